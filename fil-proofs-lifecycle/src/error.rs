@@ -0,0 +1,51 @@
+use std::path::PathBuf;
+
+use storage_proofs_core::sector::SectorId;
+
+use crate::sector::SectorStep;
+
+/// The result type of the orchestrator.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The errors of the orchestrator.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// A step of a sector failed, the failure is recorded in the journal and the step is run
+    /// again on the next [`crate::SectorOrchestrator::advance`].
+    #[error("step {step:?} of sector {sector_id:?} failed")]
+    Step {
+        sector_id: SectorId,
+        step: SectorStep,
+        #[source]
+        source: filecoin_proofs::Error,
+    },
+    /// A call of the proofs API outside of a step failed.
+    #[error(transparent)]
+    Proofs(#[from] filecoin_proofs::Error),
+    /// The journal or a persisted step output can't be read or written.
+    #[error("journal error at {}", path.display())]
+    Journal {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// There is no sector with this id.
+    #[error("unknown sector {0:?}")]
+    UnknownSector(SectorId),
+    /// The arguments are invalid, retrying with the same arguments fails again.
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    /// The sector isn't in a state that allows the operation.
+    #[error("invalid state: {0}")]
+    InvalidState(String),
+}
+
+impl Error {
+    pub(crate) fn journal<P: Into<PathBuf>, E: Into<anyhow::Error>>(path: P, source: E) -> Self {
+        Error::Journal {
+            path: path.into(),
+            source: source.into(),
+        }
+    }
+}
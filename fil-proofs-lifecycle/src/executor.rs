@@ -0,0 +1,308 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::marker::PhantomData;
+use std::path::Path;
+
+use filecoin_proofs::{
+    add_piece, clear_cache, clear_synthetic_proofs, encode_into,
+    generate_empty_sector_update_proof, generate_synth_proofs,
+    pieces::sum_piece_bytes_with_alignment, seal_commit_phase1, seal_commit_phase2,
+    seal_pre_commit_phase1, seal_pre_commit_phase2, MerkleTreeTrait, PaddedBytesAmount, PieceInfo,
+    PoRepConfig, SealCommitPhase1Output, SealPreCommitOutput, SealPreCommitPhase1Output,
+    SectorUpdateConfig, TreeRHasher, UnpaddedBytesAmount,
+};
+
+use crate::{
+    error::{Error, Result},
+    journal::{
+        read_output, remove_output, write_output, StepOutput, COMMIT1_OUTPUT_FILE,
+        PRE_COMMIT1_OUTPUT_FILE,
+    },
+    sector::{PieceFile, SectorRecord, SectorStep, UpdateCommitments, UpdateRecord},
+};
+
+/// Runs the steps of the sectors for a [`crate::SectorOrchestrator`].
+///
+/// The orchestrator records in the journal when a step starts and how it ended, the executor only
+/// runs it. [`ProofsExecutor`] runs the steps with the `filecoin-proofs` API.
+pub trait StepExecutor: Send + Sync + fmt::Debug {
+    /// Runs `step` of the sector of `record`. Outputs that are too large for the journal are
+    /// persisted in the directory `dir` of the sector with [`write_output`].
+    ///
+    /// A step can be run again after it failed or was interrupted, it must not depend on what an
+    /// earlier attempt left behind.
+    fn execute(&self, dir: &Path, record: &SectorRecord, step: SectorStep) -> Result<StepOutput>;
+}
+
+/// Runs the steps with the `filecoin-proofs` API, it's the executor of
+/// [`crate::SectorOrchestrator::open`].
+#[derive(Debug)]
+pub struct ProofsExecutor<Tree> {
+    _tree: PhantomData<fn() -> Tree>,
+}
+
+impl<Tree> Default for ProofsExecutor<Tree> {
+    fn default() -> Self {
+        ProofsExecutor { _tree: PhantomData }
+    }
+}
+
+impl<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>> StepExecutor for ProofsExecutor<Tree> {
+    fn execute(&self, dir: &Path, record: &SectorRecord, step: SectorStep) -> Result<StepOutput> {
+        let spec = &record.spec;
+        let porep_config = &spec.porep_config;
+        let sector_id = spec.sector_id;
+        let api = |source| Error::Step {
+            sector_id,
+            step,
+            source,
+        };
+
+        let output = match step {
+            SectorStep::AddPieces => StepOutput::AddPieces(
+                add_pieces(&spec.pieces, &spec.staged_path, porep_config).map_err(api)?,
+            ),
+            SectorStep::PreCommit1 => {
+                prepare_replica(&spec.cache_dir, &spec.replica_path, None).map_err(api)?;
+                let output = seal_pre_commit_phase1::<_, _, _, Tree>(
+                    porep_config,
+                    &spec.cache_dir,
+                    &spec.staged_path,
+                    &spec.replica_path,
+                    spec.prover_id,
+                    sector_id,
+                    spec.ticket,
+                    &record.piece_infos,
+                )
+                .map_err(api)?;
+                write_output(dir, PRE_COMMIT1_OUTPUT_FILE, &output)?;
+                StepOutput::PreCommit1
+            }
+            SectorStep::PreCommit2 => {
+                // The replica is encoded in place, an interrupted or failed attempt may leave it
+                // partially encoded. The first attempt uses the copy of the pre-commit phase 1.
+                if is_retry(record, step) {
+                    restore_unsealed_replica(
+                        &spec.staged_path,
+                        &spec.replica_path,
+                        u64::from(porep_config.padded_bytes_amount()),
+                    )
+                    .map_err(api)?;
+                }
+                let phase1_output: SealPreCommitPhase1Output<Tree> =
+                    read_output(dir, PRE_COMMIT1_OUTPUT_FILE)?;
+                let pre_commit = seal_pre_commit_phase2(
+                    porep_config,
+                    phase1_output,
+                    &spec.cache_dir,
+                    &spec.replica_path,
+                )
+                .map_err(api)?;
+                StepOutput::PreCommit2(pre_commit)
+            }
+            SectorStep::SynthProofs => {
+                generate_synth_proofs::<_, Tree>(
+                    porep_config,
+                    spec.cache_dir.as_path(),
+                    spec.replica_path.as_path(),
+                    spec.prover_id,
+                    sector_id,
+                    spec.ticket,
+                    pre_commit(record)?,
+                    &record.piece_infos,
+                )
+                .map_err(api)?;
+                StepOutput::SynthProofs
+            }
+            SectorStep::ClearLayers => {
+                clear_cache::<Tree>(&spec.cache_dir).map_err(api)?;
+                StepOutput::ClearLayers
+            }
+            SectorStep::Commit1 => {
+                let seed = record.seed.ok_or_else(|| {
+                    Error::InvalidState(format!("sector {:?} has no seed", sector_id))
+                })?;
+                let output = seal_commit_phase1::<_, Tree>(
+                    porep_config,
+                    spec.cache_dir.as_path(),
+                    spec.replica_path.as_path(),
+                    spec.prover_id,
+                    sector_id,
+                    spec.ticket,
+                    seed,
+                    pre_commit(record)?,
+                    &record.piece_infos,
+                )
+                .map_err(api)?;
+                write_output(dir, COMMIT1_OUTPUT_FILE, &output)?;
+                StepOutput::Commit1
+            }
+            SectorStep::Commit2 => {
+                let phase1_output: SealCommitPhase1Output<Tree> =
+                    read_output(dir, COMMIT1_OUTPUT_FILE)?;
+                let output =
+                    seal_commit_phase2(porep_config, phase1_output, spec.prover_id, sector_id)
+                        .map_err(api)?;
+                StepOutput::Commit2(output.proof)
+            }
+            SectorStep::ClearCache => {
+                if record.synthetic() {
+                    clear_synthetic_proofs::<Tree>(&spec.cache_dir).map_err(api)?;
+                }
+                clear_cache::<Tree>(&spec.cache_dir).map_err(api)?;
+                remove_output(dir, PRE_COMMIT1_OUTPUT_FILE)?;
+                remove_output(dir, COMMIT1_OUTPUT_FILE)?;
+                StepOutput::ClearCache
+            }
+            SectorStep::UpdateAddPieces => {
+                let update = update(record)?;
+                StepOutput::UpdateAddPieces(
+                    add_pieces(&update.spec.pieces, &update.spec.staged_path, porep_config)
+                        .map_err(api)?,
+                )
+            }
+            SectorStep::EncodeInto => {
+                let update = update(record)?;
+                prepare_replica(
+                    &update.spec.cache_dir,
+                    &update.spec.replica_path,
+                    Some(u64::from(porep_config.sector_size)),
+                )
+                .map_err(api)?;
+                let encoded = encode_into::<Tree>(
+                    &SectorUpdateConfig::from_porep_config(porep_config),
+                    &update.spec.replica_path,
+                    &update.spec.cache_dir,
+                    &spec.replica_path,
+                    &spec.cache_dir,
+                    &update.spec.staged_path,
+                    &update.piece_infos,
+                )
+                .map_err(api)?;
+                StepOutput::EncodeInto(UpdateCommitments {
+                    comm_r_new: encoded.comm_r_new,
+                    comm_r_last_new: encoded.comm_r_last_new,
+                    comm_d_new: encoded.comm_d_new,
+                })
+            }
+            SectorStep::ProveUpdate => {
+                let update = update(record)?;
+                let encoded = update.encoded.ok_or_else(|| {
+                    Error::InvalidState(format!("sector {:?} isn't encoded", sector_id))
+                })?;
+                let proof = generate_empty_sector_update_proof::<Tree>(
+                    porep_config,
+                    pre_commit(record)?.comm_r,
+                    encoded.comm_r_new,
+                    encoded.comm_d_new,
+                    &spec.replica_path,
+                    &spec.cache_dir,
+                    &update.spec.replica_path,
+                    &update.spec.cache_dir,
+                )
+                .map_err(api)?;
+                StepOutput::ProveUpdate(proof.0)
+            }
+        };
+
+        Ok(output)
+    }
+}
+
+fn pre_commit(record: &SectorRecord) -> Result<SealPreCommitOutput> {
+    record.pre_commit.clone().ok_or_else(|| {
+        Error::InvalidState(format!(
+            "sector {:?} isn't pre-committed",
+            record.sector_id()
+        ))
+    })
+}
+
+fn update(record: &SectorRecord) -> Result<&UpdateRecord> {
+    record.update.as_ref().ok_or_else(|| {
+        Error::InvalidState(format!(
+            "no update of sector {:?} was requested",
+            record.sector_id()
+        ))
+    })
+}
+
+/// Writes the pieces to `staged_path` and fills the rest of the sector with zero pieces. The file
+/// is overwritten, so that it can run again after it was interrupted.
+fn add_pieces(
+    pieces: &[PieceFile],
+    staged_path: &Path,
+    porep_config: &PoRepConfig,
+) -> filecoin_proofs::error::Result<Vec<PieceInfo>> {
+    let mut staged = BufWriter::new(File::create(staged_path)?);
+    let mut piece_lengths = Vec::with_capacity(pieces.len());
+    let mut piece_infos = Vec::with_capacity(pieces.len());
+    for piece in pieces {
+        let source = BufReader::new(File::open(&piece.path)?).take(u64::from(piece.size));
+        let (piece_info, _) = add_piece(source, &mut staged, piece.size, &piece_lengths)?;
+        piece_lengths.push(piece.size);
+        piece_infos.push(piece_info);
+    }
+
+    // The smallest zero piece comes first, that way every piece is aligned to its size.
+    let used = PaddedBytesAmount::from(sum_piece_bytes_with_alignment(&piece_lengths));
+    let mut remaining = u64::from(porep_config.padded_bytes_amount()) - u64::from(used);
+    while remaining > 0 {
+        let padded = 1 << remaining.trailing_zeros();
+        let size = UnpaddedBytesAmount::from(PaddedBytesAmount(padded));
+        let source = io::repeat(0).take(u64::from(size));
+        let (piece_info, _) = add_piece(source, &mut staged, size, &piece_lengths)?;
+        piece_lengths.push(size);
+        piece_infos.push(piece_info);
+        remaining -= padded;
+    }
+
+    staged
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+
+    Ok(piece_infos)
+}
+
+/// Returns whether an earlier attempt of `step` was interrupted or failed.
+fn is_retry(record: &SectorRecord, step: SectorStep) -> bool {
+    record.interrupted == Some(step)
+        || record
+            .last_failure
+            .as_ref()
+            .map_or(false, |failure| failure.step == step)
+}
+
+/// Copies the unsealed data of `staged_path` into the replica, like the pre-commit phase 1 does,
+/// and extends it to `len` bytes.
+fn restore_unsealed_replica(
+    staged_path: &Path,
+    replica_path: &Path,
+    len: u64,
+) -> filecoin_proofs::error::Result<()> {
+    fs::copy(staged_path, replica_path)?;
+    let replica = OpenOptions::new().write(true).open(replica_path)?;
+    replica.set_len(len)?;
+    replica.sync_all()?;
+    Ok(())
+}
+
+/// Creates the cache directory and the replica file if they don't exist, the replica is resized
+/// to `len` if given.
+fn prepare_replica(
+    cache_dir: &Path,
+    replica_path: &Path,
+    len: Option<u64>,
+) -> filecoin_proofs::error::Result<()> {
+    fs::create_dir_all(cache_dir)?;
+    let replica = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .open(replica_path)?;
+    if let Some(len) = len {
+        replica.set_len(len)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,377 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use filecoin_proofs::{PieceInfo, SealPreCommitOutput, Ticket};
+use log::warn;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    sector::{SectorRecord, SectorSpec, SectorStep, StepFailure, UpdateCommitments, UpdateSpec},
+};
+
+/// The name of the journal within the directory of a sector.
+pub const JOURNAL_FILE: &str = "journal.jsonl";
+/// The name of the persisted output of the pre-commit phase 1.
+pub const PRE_COMMIT1_OUTPUT_FILE: &str = "pre-commit1.bin";
+/// The name of the persisted output of the commit phase 1.
+pub const COMMIT1_OUTPUT_FILE: &str = "commit1.bin";
+
+/// An entry of the journal of a sector, the first one is always `Created`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JournalEntry {
+    Created(SectorSpec),
+    Started(SectorStep),
+    Finished(StepOutput),
+    Failed(StepFailure),
+    SeedReceived(Ticket),
+    UpdateRequested(UpdateSpec),
+}
+
+/// The output of a finished step. The outputs of the phases 1 are too large for the journal, they
+/// are persisted in their own files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StepOutput {
+    AddPieces(Vec<PieceInfo>),
+    PreCommit1,
+    PreCommit2(SealPreCommitOutput),
+    SynthProofs,
+    ClearLayers,
+    Commit1,
+    Commit2(Vec<u8>),
+    ClearCache,
+    UpdateAddPieces(Vec<PieceInfo>),
+    EncodeInto(UpdateCommitments),
+    ProveUpdate(Vec<u8>),
+}
+
+impl StepOutput {
+    /// Returns the step this is the output of.
+    pub fn step(&self) -> SectorStep {
+        match self {
+            StepOutput::AddPieces(_) => SectorStep::AddPieces,
+            StepOutput::PreCommit1 => SectorStep::PreCommit1,
+            StepOutput::PreCommit2(_) => SectorStep::PreCommit2,
+            StepOutput::SynthProofs => SectorStep::SynthProofs,
+            StepOutput::ClearLayers => SectorStep::ClearLayers,
+            StepOutput::Commit1 => SectorStep::Commit1,
+            StepOutput::Commit2(_) => SectorStep::Commit2,
+            StepOutput::ClearCache => SectorStep::ClearCache,
+            StepOutput::UpdateAddPieces(_) => SectorStep::UpdateAddPieces,
+            StepOutput::EncodeInto(_) => SectorStep::EncodeInto,
+            StepOutput::ProveUpdate(_) => SectorStep::ProveUpdate,
+        }
+    }
+}
+
+/// The append-only journal of a sector, one JSON encoded [`JournalEntry`] per line.
+///
+/// Each entry is synced to disk before [`Journal::append`] returns. A process that crashes while
+/// appending leaves a partial last line, it's discarded when the journal is opened again. A failed
+/// append is cut off before the next one.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    /// The length up to the end of the last entry that was appended completely.
+    len: u64,
+    /// Whether a failed append may have left bytes after `len`.
+    partial: bool,
+}
+
+impl Journal {
+    /// Creates the journal of a new sector in `dir`, it fails if there is one already.
+    pub fn create(dir: &Path, spec: &SectorSpec) -> Result<(Self, SectorRecord)> {
+        let path = dir.join(JOURNAL_FILE);
+        if path.exists() {
+            return Err(Error::journal(
+                &path,
+                anyhow::anyhow!("journal exists already"),
+            ));
+        }
+
+        // The journal is written to a temporary file first, so that it always starts with the
+        // `Created` entry.
+        let tmp_path = dir.join(format!("{}.tmp", JOURNAL_FILE));
+        let create = || -> anyhow::Result<(File, u64)> {
+            fs::create_dir_all(dir)?;
+            let mut line = serde_json::to_vec(&JournalEntry::Created(spec.clone()))?;
+            line.push(b'\n');
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(&line)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, &path)?;
+            sync_dir(dir)?;
+            // The directory of the sector may have been created just now.
+            match dir.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => sync_dir(parent)?,
+                _ => sync_dir(Path::new("."))?,
+            }
+            let file = OpenOptions::new().append(true).open(&path)?;
+            Ok((file, line.len() as u64))
+        };
+        let (file, len) = create().map_err(|err| Error::journal(&path, err))?;
+
+        Ok((
+            Journal {
+                path,
+                file,
+                len,
+                partial: false,
+            },
+            SectorRecord::new(spec.clone()),
+        ))
+    }
+
+    /// Opens the journal in `dir` and replays it.
+    pub fn open(dir: &Path) -> Result<(Self, SectorRecord)> {
+        let path = dir.join(JOURNAL_FILE);
+        let content = fs::read(&path).map_err(|err| Error::journal(&path, err))?;
+
+        let mut record: Option<SectorRecord> = None;
+        let mut valid_len = 0;
+        for line in content.split_inclusive(|byte| *byte == b'\n') {
+            let entry = match line.last() {
+                Some(b'\n') => serde_json::from_slice::<JournalEntry>(line),
+                // Only the last line can be unterminated.
+                _ => {
+                    warn!("discarding partial last entry of {:?}", path);
+                    break;
+                }
+            };
+            let entry = entry.map_err(|err| Error::journal(&path, err))?;
+            match (&mut record, &entry) {
+                (None, JournalEntry::Created(spec)) => {
+                    record = Some(SectorRecord::new(spec.clone()))
+                }
+                (None, _) => {
+                    return Err(Error::journal(
+                        &path,
+                        anyhow::anyhow!("first entry isn't `Created`"),
+                    ))
+                }
+                (Some(record), _) => record.apply(&entry),
+            }
+            valid_len += line.len();
+        }
+        let record =
+            record.ok_or_else(|| Error::journal(&path, anyhow::anyhow!("journal is empty")))?;
+
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .map_err(|err| Error::journal(&path, err))?;
+        if valid_len < content.len() {
+            file.set_len(valid_len as u64)
+                .and_then(|_| file.sync_all())
+                .map_err(|err| Error::journal(&path, err))?;
+        }
+
+        Ok((
+            Journal {
+                path,
+                file,
+                len: valid_len as u64,
+                partial: false,
+            },
+            record,
+        ))
+    }
+
+    /// Appends an entry and syncs it to disk.
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).map_err(|err| Error::journal(&self.path, err))?;
+        line.push(b'\n');
+        self.write_line(&line)
+            .map_err(|err| Error::journal(&self.path, err))
+    }
+
+    /// Writes a line after the last complete entry. The bytes of a failed write, e.g. when the
+    /// disk is full, are cut off again, so that the next entry doesn't start in the middle of a
+    /// line.
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.partial {
+            self.file.set_len(self.len)?;
+            self.partial = false;
+        }
+        if let Err(err) = self
+            .file
+            .write_all(line)
+            .and_then(|_| self.file.sync_data())
+        {
+            self.partial = self.file.set_len(self.len).is_err();
+            return Err(err);
+        }
+        self.len += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Persists the output of a step in `dir` as `name`. It's written to a temporary file first, so
+/// that a crash never leaves a partial output behind.
+pub fn write_output<T: Serialize>(dir: &Path, name: &str, output: &T) -> Result<()> {
+    let path = dir.join(name);
+    let tmp_path = dir.join(format!("{}.tmp", name));
+    let write = || -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, output)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+        sync_dir(dir)?;
+        Ok(())
+    };
+    write().map_err(|err| Error::journal(&path, err))
+}
+
+/// Syncs the entries of `dir` to disk, so that a file that was renamed into it survives a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened as files on other platforms, the rename is only as durable as the
+/// filesystem makes it.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Reads the output of a step that was persisted with [`write_output`].
+pub fn read_output<T: DeserializeOwned>(dir: &Path, name: &str) -> Result<T> {
+    let path = dir.join(name);
+    let read = || -> anyhow::Result<T> {
+        let file = io::BufReader::new(File::open(&path)?);
+        Ok(bincode::deserialize_from(file)?)
+    };
+    read().map_err(|err| Error::journal(&path, err))
+}
+
+/// Removes a persisted output, it's not an error if it doesn't exist.
+pub fn remove_output(dir: &Path, name: &str) -> Result<()> {
+    let path = dir.join(name);
+    match fs::remove_file(&path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(Error::journal(&path, err)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_proofs::{PoRepConfig, UnpaddedBytesAmount, SECTOR_SIZE_2_KIB};
+    use storage_proofs_core::{api_version::ApiVersion, sector::SectorId};
+    use tempfile::tempdir;
+
+    use crate::sector::SectorState;
+
+    fn spec(dir: &Path) -> SectorSpec {
+        SectorSpec {
+            sector_id: SectorId::from(7),
+            porep_config: PoRepConfig::new_groth16(SECTOR_SIZE_2_KIB, [0; 32], ApiVersion::V1_1_0),
+            prover_id: [1; 32],
+            ticket: [2; 32],
+            pieces: Vec::new(),
+            staged_path: dir.join("staged"),
+            cache_dir: dir.join("cache"),
+            replica_path: dir.join("replica"),
+        }
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let dir = tempdir().expect("failed to create temp dir");
+        let sector_dir = dir.path().join("7");
+        let spec = spec(dir.path());
+
+        let (mut journal, _) = Journal::create(&sector_dir, &spec).expect("create failed");
+        assert!(Journal::create(&sector_dir, &spec).is_err());
+        let piece_info = PieceInfo::new([3; 32], UnpaddedBytesAmount(2032)).expect("invalid piece");
+        for entry in &[
+            JournalEntry::Started(SectorStep::AddPieces),
+            JournalEntry::Finished(StepOutput::AddPieces(vec![piece_info.clone()])),
+            JournalEntry::Started(SectorStep::PreCommit1),
+            JournalEntry::Failed(StepFailure {
+                step: SectorStep::PreCommit1,
+                error: "out of disk space".to_string(),
+            }),
+            JournalEntry::Started(SectorStep::PreCommit1),
+        ] {
+            journal.append(entry).expect("append failed");
+        }
+        drop(journal);
+
+        // A crash while appending leaves a partial line.
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(sector_dir.join(JOURNAL_FILE))
+            .expect("failed to open journal");
+        file.write_all(b"{\"Finished\":{\"PreCom")
+            .expect("failed to write");
+        drop(file);
+
+        let (mut journal, record) = Journal::open(&sector_dir).expect("open failed");
+        assert_eq!(record.piece_infos, vec![piece_info]);
+        assert_eq!(record.interrupted, Some(SectorStep::PreCommit1));
+        assert_eq!(
+            record.last_failure.as_ref().map(|failure| failure.step),
+            Some(SectorStep::PreCommit1)
+        );
+        assert_eq!(record.state(), SectorState::Sealing(SectorStep::PreCommit1));
+
+        journal
+            .append(&JournalEntry::Finished(StepOutput::PreCommit1))
+            .expect("append failed");
+        drop(journal);
+        let (_, record) = Journal::open(&sector_dir).expect("open failed");
+        assert_eq!(record.interrupted, None);
+        assert_eq!(record.last_failure, None);
+        assert_eq!(record.state(), SectorState::Sealing(SectorStep::PreCommit2));
+    }
+
+    #[test]
+    fn test_journal_partial_append() {
+        let dir = tempdir().expect("failed to create temp dir");
+        let sector_dir = dir.path().join("7");
+        let spec = spec(dir.path());
+
+        // A failed append whose bytes couldn't be cut off right away.
+        let (mut journal, _) = Journal::create(&sector_dir, &spec).expect("create failed");
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(sector_dir.join(JOURNAL_FILE))
+            .expect("failed to open journal");
+        file.write_all(b"{\"Started\":\"AddPi")
+            .expect("failed to write");
+        drop(file);
+        journal.partial = true;
+
+        journal
+            .append(&JournalEntry::Started(SectorStep::AddPieces))
+            .expect("append failed");
+        drop(journal);
+        let (_, record) = Journal::open(&sector_dir).expect("open failed");
+        assert_eq!(record.interrupted, Some(SectorStep::AddPieces));
+    }
+
+    #[test]
+    fn test_outputs() {
+        let dir = tempdir().expect("failed to create temp dir");
+        write_output(dir.path(), COMMIT1_OUTPUT_FILE, &vec![1u8, 2, 3]).expect("write failed");
+        let output: Vec<u8> = read_output(dir.path(), COMMIT1_OUTPUT_FILE).expect("read failed");
+        assert_eq!(output, vec![1, 2, 3]);
+        assert!(!dir.path().join("commit1.bin.tmp").exists());
+
+        remove_output(dir.path(), COMMIT1_OUTPUT_FILE).expect("remove failed");
+        remove_output(dir.path(), COMMIT1_OUTPUT_FILE).expect("remove is idempotent");
+        assert!(read_output::<Vec<u8>>(dir.path(), COMMIT1_OUTPUT_FILE).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+//! Runs the lifecycle of Filecoin sectors on top of the `filecoin-proofs` API: adding the pieces,
+//! the pre-commit phases, waiting for the seed, the commit phases, clearing the caches and
+//! updating sectors with new data (SnapDeals).
+//!
+//! The progress of each sector is persisted in a journal, so that the sectors continue where they
+//! stopped after a crash. See [`SectorOrchestrator`].
+
+#![deny(clippy::all, clippy::perf, clippy::correctness, rust_2018_idioms)]
+#![warn(clippy::unwrap_used)]
+
+mod error;
+mod executor;
+mod journal;
+mod orchestrator;
+mod pool;
+mod sector;
+
+pub use error::*;
+pub use executor::*;
+pub use journal::*;
+pub use orchestrator::*;
+pub use pool::*;
+pub use sector::*;
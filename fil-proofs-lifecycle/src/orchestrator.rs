@@ -0,0 +1,447 @@
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fs;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread::{self, JoinHandle};
+
+use filecoin_proofs::{
+    estimate_seal_resources, estimate_update_resources, pieces::sum_piece_bytes_with_alignment,
+    MerkleTreeTrait, PhaseResources, PoRepConfig, PrivateReplicaInfo, ProofPhase,
+    SectorUpdateConfig, Ticket, TreeRHasher,
+};
+use log::{info, warn};
+use storage_proofs_core::{api_version::ApiFeature, sector::SectorId};
+
+use crate::{
+    error::{Error, Result},
+    executor::{ProofsExecutor, StepExecutor},
+    journal::{Journal, JournalEntry, JOURNAL_FILE},
+    pool::{WorkerLimits, WorkerPool, WorkerPools},
+    sector::{
+        PieceFile, SectorRecord, SectorSpec, SectorState, SectorStep, StepFailure, UpdateRecord,
+        UpdateSpec,
+    },
+};
+
+/// A sector with its journal.
+#[derive(Debug)]
+struct SectorEntry {
+    dir: PathBuf,
+    journal: Mutex<Journal>,
+    record: Mutex<SectorRecord>,
+    /// Held while the steps of the sector run, so that they never run twice at the same time.
+    running: Mutex<()>,
+}
+
+impl SectorEntry {
+    fn new(dir: PathBuf, journal: Journal, record: SectorRecord) -> Self {
+        SectorEntry {
+            dir,
+            journal: Mutex::new(journal),
+            record: Mutex::new(record),
+            running: Mutex::new(()),
+        }
+    }
+
+    fn record(&self) -> SectorRecord {
+        self.lock_record().clone()
+    }
+
+    fn lock_record(&self) -> MutexGuard<'_, SectorRecord> {
+        self.record.lock().expect("sector record poisoned")
+    }
+
+    /// Appends the entry that `f` returns for the current record and applies it. Nothing is
+    /// appended if `f` returns `None`.
+    fn update<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&SectorRecord) -> Result<Option<JournalEntry>>,
+    {
+        let mut journal = self.journal.lock().expect("sector journal poisoned");
+        let mut record = self.lock_record();
+        if let Some(entry) = f(&record)? {
+            journal.append(&entry)?;
+            record.apply(&entry);
+        }
+        Ok(())
+    }
+
+    fn log(&self, entry: JournalEntry) -> Result<()> {
+        self.update(|_| Ok(Some(entry)))
+    }
+}
+
+/// Runs the lifecycle of sectors: sealing, waiting for the seed, committing, clearing the caches
+/// and optionally updating them with new data.
+///
+/// Every sector has a directory `<root>/<sector id>` with its journal and the persisted outputs of
+/// the phases 1. Each step is recorded in the journal before it starts and once it finished, a
+/// finished step is never run again. When the orchestrator is opened again after a crash, the
+/// journals are replayed and the interrupted steps run again on the next [`Self::advance`].
+///
+/// The steps run in the [`WorkerPool`]s within the [`WorkerLimits`], by a [`StepExecutor`].
+#[derive(Debug)]
+pub struct SectorOrchestrator<Tree> {
+    root: PathBuf,
+    pools: WorkerPools,
+    executor: Arc<dyn StepExecutor>,
+    sectors: RwLock<BTreeMap<SectorId, Arc<SectorEntry>>>,
+    _tree: PhantomData<fn() -> Tree>,
+}
+
+impl<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>> SectorOrchestrator<Tree> {
+    /// Opens the orchestrator in `root` and replays the journals of the sectors in it. The steps
+    /// are run with the `filecoin-proofs` API, see [`ProofsExecutor`].
+    pub fn open<P: Into<PathBuf>>(root: P, limits: WorkerLimits) -> Result<Self> {
+        Self::open_with_executor(root, limits, Arc::new(ProofsExecutor::<Tree>::default()))
+    }
+
+    /// Like [`Self::open`], but the steps are run by `executor`.
+    pub fn open_with_executor<P: Into<PathBuf>>(
+        root: P,
+        limits: WorkerLimits,
+        executor: Arc<dyn StepExecutor>,
+    ) -> Result<Self> {
+        info!("orchestrator_open:start");
+        let root = root.into();
+        for pool in &[
+            WorkerPool::AddPieces,
+            WorkerPool::PreCommit1,
+            WorkerPool::PreCommit2,
+            WorkerPool::Commit1,
+            WorkerPool::Snark,
+            WorkerPool::Encode,
+        ] {
+            if limits.limit(*pool) == 0 {
+                return Err(Error::InvalidInput(format!(
+                    "the limit of {:?} must be at least 1",
+                    pool
+                )));
+            }
+        }
+
+        fs::create_dir_all(&root).map_err(|err| Error::journal(&root, err))?;
+        let mut sectors = BTreeMap::new();
+        let dirs = fs::read_dir(&root).map_err(|err| Error::journal(&root, err))?;
+        for dir in dirs {
+            let dir = dir.map_err(|err| Error::journal(&root, err))?.path();
+            if !dir.join(JOURNAL_FILE).is_file() {
+                continue;
+            }
+            let (journal, record) = Journal::open(&dir)?;
+            if let Some(step) = record.interrupted {
+                warn!(
+                    "step {:?} of sector {:?} was interrupted, it's run again",
+                    step,
+                    record.sector_id()
+                );
+            }
+            sectors.insert(
+                record.sector_id(),
+                Arc::new(SectorEntry::new(dir, journal, record)),
+            );
+        }
+        info!("orchestrator_open:finish: {} sectors", sectors.len());
+
+        Ok(SectorOrchestrator {
+            root,
+            pools: WorkerPools::new(limits),
+            executor,
+            sectors: RwLock::new(sectors),
+            _tree: PhantomData,
+        })
+    }
+
+    /// Adds a new sector, it starts sealing on the next [`Self::advance`].
+    pub fn add_sector(&self, spec: SectorSpec) -> Result<SectorState> {
+        if spec
+            .porep_config
+            .feature_enabled(ApiFeature::NonInteractivePoRep)
+        {
+            return Err(Error::InvalidInput(
+                "non-interactive PoRep isn't supported".to_string(),
+            ));
+        }
+        check_pieces_fit(&spec.pieces, &spec.porep_config)?;
+
+        let mut sectors = self.sectors.write().expect("sectors poisoned");
+        if sectors.contains_key(&spec.sector_id) {
+            return Err(Error::InvalidState(format!(
+                "sector {:?} exists already",
+                spec.sector_id
+            )));
+        }
+        let dir = self.root.join(u64::from(spec.sector_id).to_string());
+        let (journal, record) = Journal::create(&dir, &spec)?;
+        let state = record.state();
+        sectors.insert(
+            spec.sector_id,
+            Arc::new(SectorEntry::new(dir, journal, record)),
+        );
+
+        Ok(state)
+    }
+
+    /// Sets the seed of the commit phase 1. Setting the same seed again has no effect.
+    pub fn set_seed(&self, sector_id: SectorId, seed: Ticket) -> Result<()> {
+        self.entry(sector_id)?.update(|record| match record.seed {
+            Some(current) if current == seed => Ok(None),
+            Some(_) => Err(Error::InvalidState(format!(
+                "sector {:?} has a different seed already",
+                sector_id
+            ))),
+            None => Ok(Some(JournalEntry::SeedReceived(seed))),
+        })
+    }
+
+    /// Requests to update a sector that can be proven with new data, it starts on the next
+    /// [`Self::advance`].
+    pub fn request_update(&self, sector_id: SectorId, spec: UpdateSpec) -> Result<()> {
+        let entry = self.entry(sector_id)?;
+        entry.update(|record| {
+            check_pieces_fit(&spec.pieces, &record.spec.porep_config)?;
+            if record.update.is_some() {
+                return Err(Error::InvalidState(format!(
+                    "sector {:?} is updated already",
+                    sector_id
+                )));
+            }
+            match record.state() {
+                SectorState::Proving => Ok(Some(JournalEntry::UpdateRequested(spec))),
+                state => Err(Error::InvalidState(format!(
+                    "sector {:?} can't be updated in state {:?}",
+                    sector_id, state
+                ))),
+            }
+        })
+    }
+
+    pub fn state(&self, sector_id: SectorId) -> Result<SectorState> {
+        Ok(self.entry(sector_id)?.lock_record().state())
+    }
+
+    pub fn record(&self, sector_id: SectorId) -> Result<SectorRecord> {
+        Ok(self.entry(sector_id)?.record())
+    }
+
+    /// Returns the states of all sectors.
+    pub fn sectors(&self) -> BTreeMap<SectorId, SectorState> {
+        self.sectors
+            .read()
+            .expect("sectors poisoned")
+            .iter()
+            .map(|(sector_id, entry)| (*sector_id, entry.lock_record().state()))
+            .collect()
+    }
+
+    /// Runs the steps of a sector until it waits for the seed or is done, and returns the state
+    /// it stopped in. Calling it again on a sector that waits or is done has no effect.
+    ///
+    /// A failed step is recorded in the journal and returned as [`Error::Step`], it's run again
+    /// on the next call.
+    pub fn advance(&self, sector_id: SectorId) -> Result<SectorState> {
+        let entry = self.entry(sector_id)?;
+        let _running = entry.running.lock().expect("sector lock poisoned");
+        loop {
+            let record = entry.record();
+            let state = record.state();
+            match state.next_step() {
+                Some(step) => self.run_step(&entry, &record, step)?,
+                None => return Ok(state),
+            }
+        }
+    }
+
+    /// Like [`Self::advance`], but runs on its own thread.
+    pub fn spawn_advance(self: &Arc<Self>, sector_id: SectorId) -> JoinHandle<Result<SectorState>> {
+        let orchestrator = Arc::clone(self);
+        thread::spawn(move || orchestrator.advance(sector_id))
+    }
+
+    /// Advances all sectors that have steps to run at the same time, e.g. after opening the
+    /// orchestrator again. It returns once all of them stopped.
+    pub fn advance_all(self: &Arc<Self>) -> BTreeMap<SectorId, Result<SectorState>> {
+        let handles: Vec<_> = self
+            .sectors()
+            .into_iter()
+            .filter(|(_, state)| state.next_step().is_some())
+            .map(|(sector_id, _)| (sector_id, self.spawn_advance(sector_id)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|(sector_id, handle)| {
+                let result = handle.join().expect("advancing a sector panicked");
+                (sector_id, result)
+            })
+            .collect()
+    }
+
+    /// Returns the replicas that can be proven with Winning or Window PoSt. A sector that is
+    /// being updated is proven with its sealed replica until the update is done.
+    pub fn provable_replicas(&self) -> Result<BTreeMap<SectorId, PrivateReplicaInfo<Tree>>> {
+        let sectors = self.sectors.read().expect("sectors poisoned");
+        let mut replicas = BTreeMap::new();
+        for (sector_id, entry) in sectors.iter() {
+            let record = entry.lock_record();
+            let (replica_path, comm_r, cache_dir) =
+                match (record.state(), &record.pre_commit, &record.update) {
+                    (SectorState::Proving, Some(pre_commit), _)
+                    | (SectorState::Updating(_), Some(pre_commit), _) => (
+                        &record.spec.replica_path,
+                        pre_commit.comm_r,
+                        &record.spec.cache_dir,
+                    ),
+                    (
+                        SectorState::Updated,
+                        _,
+                        Some(UpdateRecord {
+                            spec,
+                            encoded: Some(encoded),
+                            ..
+                        }),
+                    ) => (&spec.replica_path, encoded.comm_r_new, &spec.cache_dir),
+                    _ => continue,
+                };
+            let replica = PrivateReplicaInfo::new(replica_path.clone(), comm_r, cache_dir.clone())?;
+            replicas.insert(*sector_id, replica);
+        }
+
+        Ok(replicas)
+    }
+
+    fn entry(&self, sector_id: SectorId) -> Result<Arc<SectorEntry>> {
+        self.sectors
+            .read()
+            .expect("sectors poisoned")
+            .get(&sector_id)
+            .cloned()
+            .ok_or(Error::UnknownSector(sector_id))
+    }
+
+    fn run_step(&self, entry: &SectorEntry, record: &SectorRecord, step: SectorStep) -> Result<()> {
+        let sector_id = record.sector_id();
+        let memory = match self.pools.limits().max_memory {
+            Some(_) => step_memory::<Tree>(&record.spec.porep_config, step)?,
+            None => 0,
+        };
+        let _permit = WorkerPool::of(step).map(|pool| self.pools.acquire(pool, memory));
+
+        info!("{:?}:start: {:?}", step, sector_id);
+        entry.log(JournalEntry::Started(step))?;
+        match self.executor.execute(&entry.dir, record, step) {
+            Ok(output) => {
+                entry.log(JournalEntry::Finished(output))?;
+                info!("{:?}:finish: {:?}", step, sector_id);
+                Ok(())
+            }
+            Err(err) => {
+                let failure = StepFailure {
+                    step,
+                    error: error_chain(&err),
+                };
+                warn!(
+                    "{:?} of sector {:?} failed: {}",
+                    step, sector_id, failure.error
+                );
+                entry.log(JournalEntry::Failed(failure))?;
+                Err(err)
+            }
+        }
+    }
+}
+
+fn check_pieces_fit(pieces: &[PieceFile], porep_config: &PoRepConfig) -> Result<()> {
+    let sizes: Vec<_> = pieces.iter().map(|piece| piece.size).collect();
+    let total = sum_piece_bytes_with_alignment(&sizes);
+    if total > porep_config.unpadded_bytes_amount() {
+        return Err(Error::InvalidInput(format!(
+            "the pieces need {:?}, but the sector only holds {:?}",
+            total,
+            porep_config.unpadded_bytes_amount()
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the estimated peak memory of a step.
+fn step_memory<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    porep_config: &PoRepConfig,
+    step: SectorStep,
+) -> Result<u64> {
+    let phase = match step {
+        SectorStep::PreCommit1 => ProofPhase::PreCommitPhase1,
+        SectorStep::PreCommit2 => ProofPhase::PreCommitPhase2,
+        SectorStep::SynthProofs => ProofPhase::GenerateSynthProofs,
+        SectorStep::Commit1 => ProofPhase::CommitPhase1,
+        SectorStep::Commit2 => ProofPhase::CommitPhase2,
+        SectorStep::EncodeInto => ProofPhase::EncodeInto,
+        SectorStep::ProveUpdate => ProofPhase::GenerateUpdateProof,
+        SectorStep::AddPieces
+        | SectorStep::ClearLayers
+        | SectorStep::ClearCache
+        | SectorStep::UpdateAddPieces => return Ok(0),
+    };
+    let resources = match step {
+        SectorStep::EncodeInto | SectorStep::ProveUpdate => {
+            estimate_update_resources::<Tree>(&SectorUpdateConfig::from_porep_config(porep_config))?
+        }
+        _ => estimate_seal_resources::<Tree>(porep_config)?,
+    };
+
+    Ok(resources
+        .iter()
+        .find(|resources| resources.phase == phase)
+        .map(PhaseResources::peak_memory)
+        .unwrap_or(0))
+}
+
+/// Formats an error with all its causes.
+fn error_chain(err: &dyn StdError) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use filecoin_proofs::{
+        SectorShape2KiB, MAX_LEGACY_REGISTERED_SEAL_PROOF_ID, SECTOR_SIZE_2_KIB,
+    };
+    use storage_proofs_core::api_version::ApiVersion;
+
+    #[test]
+    fn test_step_memory() {
+        let mut porep_id = [0u8; 32];
+        porep_id[..8].copy_from_slice(&(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1).to_le_bytes());
+        let porep_config =
+            PoRepConfig::new_groth16(SECTOR_SIZE_2_KIB, porep_id, ApiVersion::V1_1_0);
+
+        // All steps that generate proofs are accounted, including the SNARKs.
+        for step in [
+            SectorStep::PreCommit1,
+            SectorStep::PreCommit2,
+            SectorStep::Commit1,
+            SectorStep::Commit2,
+            SectorStep::EncodeInto,
+            SectorStep::ProveUpdate,
+        ] {
+            let memory = step_memory::<SectorShape2KiB>(&porep_config, step)
+                .expect("failed to estimate memory");
+            assert!(memory > 0, "{:?}", step);
+        }
+        assert_eq!(
+            step_memory::<SectorShape2KiB>(&porep_config, SectorStep::ClearCache)
+                .expect("failed to estimate memory"),
+            0
+        );
+    }
+}
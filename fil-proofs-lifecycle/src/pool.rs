@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+use std::sync::{Condvar, Mutex};
+
+use crate::sector::SectorStep;
+
+/// A pool of workers that runs the steps of one kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum WorkerPool {
+    AddPieces,
+    PreCommit1,
+    PreCommit2,
+    Commit1,
+    /// The steps that generate SNARKs, the commit phase 2 and the update proof.
+    Snark,
+    /// The synthetic proofs and `encode_into`.
+    Encode,
+}
+
+impl WorkerPool {
+    /// Returns the pool a step runs in, `None` if it's not limited.
+    pub fn of(step: SectorStep) -> Option<Self> {
+        match step {
+            SectorStep::AddPieces | SectorStep::UpdateAddPieces => Some(WorkerPool::AddPieces),
+            SectorStep::PreCommit1 => Some(WorkerPool::PreCommit1),
+            SectorStep::PreCommit2 => Some(WorkerPool::PreCommit2),
+            SectorStep::Commit1 => Some(WorkerPool::Commit1),
+            SectorStep::Commit2 | SectorStep::ProveUpdate => Some(WorkerPool::Snark),
+            SectorStep::SynthProofs | SectorStep::EncodeInto => Some(WorkerPool::Encode),
+            SectorStep::ClearLayers | SectorStep::ClearCache => None,
+        }
+    }
+}
+
+/// The number of steps of each pool that run at the same time, and the memory all of them may
+/// use together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerLimits {
+    pub add_pieces: usize,
+    pub pre_commit1: usize,
+    pub pre_commit2: usize,
+    pub commit1: usize,
+    pub snark: usize,
+    pub encode: usize,
+    /// The memory limit in bytes, the steps are accounted with their peak memory as estimated by
+    /// [`filecoin_proofs::estimate_seal_resources`] and
+    /// [`filecoin_proofs::estimate_update_resources`]. A step that needs more than the limit runs
+    /// once nothing else runs.
+    pub max_memory: Option<u64>,
+}
+
+impl Default for WorkerLimits {
+    fn default() -> Self {
+        WorkerLimits {
+            add_pieces: 2,
+            pre_commit1: 1,
+            pre_commit2: 1,
+            commit1: 1,
+            snark: 1,
+            encode: 1,
+            max_memory: None,
+        }
+    }
+}
+
+impl WorkerLimits {
+    pub fn limit(&self, pool: WorkerPool) -> usize {
+        match pool {
+            WorkerPool::AddPieces => self.add_pieces,
+            WorkerPool::PreCommit1 => self.pre_commit1,
+            WorkerPool::PreCommit2 => self.pre_commit2,
+            WorkerPool::Commit1 => self.commit1,
+            WorkerPool::Snark => self.snark,
+            WorkerPool::Encode => self.encode,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    running: BTreeMap<WorkerPool, usize>,
+    memory: u64,
+}
+
+/// Hands out permits to run steps within the [`WorkerLimits`].
+#[derive(Debug)]
+pub(crate) struct WorkerPools {
+    limits: WorkerLimits,
+    state: Mutex<PoolState>,
+    released: Condvar,
+}
+
+impl WorkerPools {
+    pub(crate) fn new(limits: WorkerLimits) -> Self {
+        WorkerPools {
+            limits,
+            state: Mutex::new(PoolState::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn limits(&self) -> &WorkerLimits {
+        &self.limits
+    }
+
+    /// Blocks until a step of `pool` that needs `memory` bytes can run.
+    pub(crate) fn acquire(&self, pool: WorkerPool, memory: u64) -> Permit<'_> {
+        let limit = self.limits.limit(pool);
+        let fits = |state: &PoolState| {
+            let running = state.running.get(&pool).copied().unwrap_or(0);
+            let memory_fits = match self.limits.max_memory {
+                Some(max_memory) => state.memory == 0 || state.memory + memory <= max_memory,
+                None => true,
+            };
+            running < limit && memory_fits
+        };
+
+        let mut state = self.state.lock().expect("pool state poisoned");
+        while !fits(&state) {
+            state = self.released.wait(state).expect("pool state poisoned");
+        }
+        *state.running.entry(pool).or_insert(0) += 1;
+        state.memory += memory;
+
+        Permit {
+            pools: self,
+            pool,
+            memory,
+        }
+    }
+}
+
+/// A step may run as long as the permit is held.
+#[derive(Debug)]
+pub(crate) struct Permit<'a> {
+    pools: &'a WorkerPools,
+    pool: WorkerPool,
+    memory: u64,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.pools.state.lock().expect("pool state poisoned");
+        if let Some(running) = state.running.get_mut(&self.pool) {
+            *running -= 1;
+        }
+        state.memory -= self.memory;
+        self.pools.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    fn max_concurrency(pools: &WorkerPools, requests: &[(WorkerPool, u64)]) -> usize {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for (pool, memory) in requests {
+                let (running, max_running) = (&running, &max_running);
+                scope.spawn(move || {
+                    let _permit = pools.acquire(*pool, *memory);
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(50));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        max_running.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_pool_limits() {
+        let limits = WorkerLimits {
+            pre_commit1: 2,
+            ..Default::default()
+        };
+        let pools = WorkerPools::new(limits);
+        let requests = vec![(WorkerPool::PreCommit1, 0); 6];
+        assert_eq!(max_concurrency(&pools, &requests), 2);
+
+        let requests = vec![(WorkerPool::Snark, 0); 4];
+        assert_eq!(max_concurrency(&pools, &requests), 1);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let limits = WorkerLimits {
+            add_pieces: 4,
+            max_memory: Some(100),
+            ..Default::default()
+        };
+        let pools = WorkerPools::new(limits);
+        let requests = vec![(WorkerPool::AddPieces, 40); 4];
+        assert_eq!(max_concurrency(&pools, &requests), 2);
+
+        // Steps that exceed the limit on their own still run, one after the other.
+        let requests = vec![(WorkerPool::AddPieces, 150); 3];
+        assert_eq!(max_concurrency(&pools, &requests), 1);
+
+        let state = pools.state.lock().expect("pool state poisoned");
+        assert_eq!(state.memory, 0);
+        assert_eq!(state.running.get(&WorkerPool::AddPieces), Some(&0));
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use filecoin_proofs::{
+    Commitment, PieceInfo, PoRepConfig, ProverId, SealPreCommitOutput, Ticket, UnpaddedBytesAmount,
+};
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::{api_version::ApiFeature, sector::SectorId};
+
+use crate::journal::{JournalEntry, StepOutput};
+
+/// A piece that is added to a sector, it's read from `path`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceFile {
+    pub path: PathBuf,
+    /// The number of unpadded bytes that are read from the file.
+    pub size: UnpaddedBytesAmount,
+}
+
+/// Everything that is needed to seal a sector.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectorSpec {
+    pub sector_id: SectorId,
+    pub porep_config: PoRepConfig,
+    pub prover_id: ProverId,
+    pub ticket: Ticket,
+    /// The pieces in the order they're added. The rest of the sector is filled with zero pieces.
+    pub pieces: Vec<PieceFile>,
+    /// The file the pieces are written to, it's the input of the pre-commit phase 1.
+    pub staged_path: PathBuf,
+    pub cache_dir: PathBuf,
+    pub replica_path: PathBuf,
+}
+
+/// Everything that is needed to update a sector with new data (SnapDeals). The replica of the
+/// sealed sector becomes the sector key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateSpec {
+    /// The pieces in the order they're added. The rest of the sector is filled with zero pieces.
+    pub pieces: Vec<PieceFile>,
+    /// The file the pieces are written to, it's encoded into the new replica.
+    pub staged_path: PathBuf,
+    pub cache_dir: PathBuf,
+    pub replica_path: PathBuf,
+}
+
+/// A step of the lifecycle of a sector. Each step is recorded in the journal once it's finished
+/// and never run again afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SectorStep {
+    /// Writes the pieces of the sector into the staged file.
+    AddPieces,
+    PreCommit1,
+    PreCommit2,
+    /// Generates the synthetic proofs, only with synthetic PoRep.
+    SynthProofs,
+    /// Removes the layers and trees that are only needed for sealing once the synthetic proofs
+    /// exist, only with synthetic PoRep.
+    ClearLayers,
+    /// Needs the seed, see [`crate::SectorOrchestrator::set_seed`].
+    Commit1,
+    Commit2,
+    /// Removes everything that isn't needed for proving the sector.
+    ClearCache,
+    /// Writes the pieces of the update into the staged file.
+    UpdateAddPieces,
+    EncodeInto,
+    ProveUpdate,
+}
+
+impl SectorStep {
+    const SEAL: [SectorStep; 8] = [
+        SectorStep::AddPieces,
+        SectorStep::PreCommit1,
+        SectorStep::PreCommit2,
+        SectorStep::SynthProofs,
+        SectorStep::ClearLayers,
+        SectorStep::Commit1,
+        SectorStep::Commit2,
+        SectorStep::ClearCache,
+    ];
+    const UPDATE: [SectorStep; 3] = [
+        SectorStep::UpdateAddPieces,
+        SectorStep::EncodeInto,
+        SectorStep::ProveUpdate,
+    ];
+}
+
+/// Where a sector is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SectorState {
+    /// The sector is being sealed, the step is the next one to run.
+    Sealing(SectorStep),
+    /// The sector is pre-committed and waits for the seed of the commit phase 1.
+    WaitingSeed,
+    /// The sector is sealed and can be proven.
+    Proving,
+    /// The sector is being updated, the step is the next one to run. The sealed replica can still
+    /// be proven.
+    Updating(SectorStep),
+    /// The sector is updated and the new replica can be proven.
+    Updated,
+}
+
+impl SectorState {
+    /// Returns the step to run next, `None` if the sector waits for input or is done.
+    pub fn next_step(&self) -> Option<SectorStep> {
+        match self {
+            SectorState::Sealing(step) | SectorState::Updating(step) => Some(*step),
+            _ => None,
+        }
+    }
+}
+
+/// The commitments of the updated replica.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateCommitments {
+    pub comm_r_new: Commitment,
+    pub comm_r_last_new: Commitment,
+    pub comm_d_new: Commitment,
+}
+
+/// A step that failed, the error includes its causes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepFailure {
+    pub step: SectorStep,
+    pub error: String,
+}
+
+/// The update of a sector, as it's recorded in the journal.
+#[derive(Debug, Clone)]
+pub struct UpdateRecord {
+    pub spec: UpdateSpec,
+    pub piece_infos: Vec<PieceInfo>,
+    pub encoded: Option<UpdateCommitments>,
+    pub proof: Option<Vec<u8>>,
+}
+
+/// A sector as it's recorded in the journal.
+#[derive(Debug, Clone)]
+pub struct SectorRecord {
+    pub spec: SectorSpec,
+    pub piece_infos: Vec<PieceInfo>,
+    pub pre_commit: Option<SealPreCommitOutput>,
+    pub seed: Option<Ticket>,
+    /// The proof of the commit phase 2.
+    pub proof: Option<Vec<u8>>,
+    pub update: Option<UpdateRecord>,
+    pub finished: BTreeSet<SectorStep>,
+    /// The step that was started, but neither finished nor failed, e.g. because the process
+    /// crashed. It's run again.
+    pub interrupted: Option<SectorStep>,
+    /// The last failure, it's reset once the step finishes.
+    pub last_failure: Option<StepFailure>,
+}
+
+impl SectorRecord {
+    pub(crate) fn new(spec: SectorSpec) -> Self {
+        SectorRecord {
+            spec,
+            piece_infos: Vec::new(),
+            pre_commit: None,
+            seed: None,
+            proof: None,
+            update: None,
+            finished: BTreeSet::new(),
+            interrupted: None,
+            last_failure: None,
+        }
+    }
+
+    pub fn sector_id(&self) -> SectorId {
+        self.spec.sector_id
+    }
+
+    /// Returns whether the sector is sealed with synthetic PoRep.
+    pub fn synthetic(&self) -> bool {
+        self.spec
+            .porep_config
+            .feature_enabled(ApiFeature::SyntheticPoRep)
+    }
+
+    /// Derives the state from the finished steps.
+    pub fn state(&self) -> SectorState {
+        let synthetic = self.synthetic();
+        let pending = SectorStep::SEAL
+            .iter()
+            .filter(|step| {
+                synthetic || !matches!(step, SectorStep::SynthProofs | SectorStep::ClearLayers)
+            })
+            .find(|step| !self.finished.contains(step));
+        match pending {
+            Some(SectorStep::Commit1) if self.seed.is_none() => return SectorState::WaitingSeed,
+            Some(step) => return SectorState::Sealing(*step),
+            None => {}
+        }
+
+        if self.update.is_none() {
+            return SectorState::Proving;
+        }
+        match SectorStep::UPDATE
+            .iter()
+            .find(|step| !self.finished.contains(step))
+        {
+            Some(step) => SectorState::Updating(*step),
+            None => SectorState::Updated,
+        }
+    }
+
+    /// Applies an entry of the journal, `Created` is ignored.
+    pub(crate) fn apply(&mut self, entry: &JournalEntry) {
+        match entry {
+            JournalEntry::Created(_) => {}
+            JournalEntry::Started(step) => self.interrupted = Some(*step),
+            JournalEntry::Finished(output) => {
+                self.apply_output(output);
+                self.finished.insert(output.step());
+                self.interrupted = None;
+                self.last_failure = None;
+            }
+            JournalEntry::Failed(failure) => {
+                self.interrupted = None;
+                self.last_failure = Some(failure.clone());
+            }
+            JournalEntry::SeedReceived(seed) => self.seed = Some(*seed),
+            JournalEntry::UpdateRequested(spec) => {
+                self.update = Some(UpdateRecord {
+                    spec: spec.clone(),
+                    piece_infos: Vec::new(),
+                    encoded: None,
+                    proof: None,
+                })
+            }
+        }
+    }
+
+    fn apply_output(&mut self, output: &StepOutput) {
+        match output {
+            StepOutput::AddPieces(piece_infos) => self.piece_infos = piece_infos.clone(),
+            StepOutput::PreCommit2(pre_commit) => self.pre_commit = Some(pre_commit.clone()),
+            StepOutput::Commit2(proof) => self.proof = Some(proof.clone()),
+            StepOutput::UpdateAddPieces(piece_infos) => {
+                if let Some(update) = &mut self.update {
+                    update.piece_infos = piece_infos.clone();
+                }
+            }
+            StepOutput::EncodeInto(encoded) => {
+                if let Some(update) = &mut self.update {
+                    update.encoded = Some(*encoded);
+                }
+            }
+            StepOutput::ProveUpdate(proof) => {
+                if let Some(update) = &mut self.update {
+                    update.proof = Some(proof.clone());
+                }
+            }
+            StepOutput::PreCommit1
+            | StepOutput::SynthProofs
+            | StepOutput::ClearLayers
+            | StepOutput::Commit1
+            | StepOutput::ClearCache => {}
+        }
+    }
+}
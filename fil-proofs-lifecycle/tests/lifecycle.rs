@@ -0,0 +1,517 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use fil_proofs_lifecycle::{
+    read_output, write_output, Error, Journal, JournalEntry, PieceFile, ProofsExecutor,
+    SectorOrchestrator, SectorRecord, SectorSpec, SectorState, SectorStep, StepExecutor,
+    StepOutput, UpdateCommitments, UpdateSpec, WorkerLimits, PRE_COMMIT1_OUTPUT_FILE,
+};
+use filecoin_proofs::{
+    verify_empty_sector_update_proof, verify_seal, Commitment, PieceInfo, PoRepConfig,
+    SealPreCommitOutput, SectorShape2KiB, UnpaddedBytesAmount, MAX_LEGACY_REGISTERED_SEAL_PROOF_ID,
+    SECTOR_SIZE_2_KIB, TEST_SEED,
+};
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use storage_proofs_core::{
+    api_version::{ApiFeature, ApiVersion},
+    sector::SectorId,
+};
+use tempfile::{tempdir, TempDir};
+
+type Orchestrator = SectorOrchestrator<SectorShape2KiB>;
+
+fn porep_config() -> PoRepConfig {
+    let mut porep_id = [0u8; 32];
+    porep_id[..8].copy_from_slice(&(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1).to_le_bytes());
+    PoRepConfig::new_groth16(SECTOR_SIZE_2_KIB, porep_id, ApiVersion::V1_1_0)
+}
+
+/// Writes a piece that fills half of a 2KiB sector.
+fn piece_file(rng: &mut XorShiftRng, dir: &Path, name: &str) -> Result<PieceFile> {
+    let size = UnpaddedBytesAmount(1016);
+    let path = dir.join(name);
+    let bytes: Vec<u8> = (0..size.0).map(|_| rng.gen()).collect();
+    let mut file = fs::File::create(&path)?;
+    file.write_all(&bytes)?;
+    file.sync_all()?;
+    Ok(PieceFile { path, size })
+}
+
+fn sector_spec(rng: &mut XorShiftRng, dir: &TempDir, sector_id: u64) -> Result<SectorSpec> {
+    let sector_dir = dir.path().join(format!("sector-{}", sector_id));
+    fs::create_dir_all(&sector_dir)?;
+    Ok(SectorSpec {
+        sector_id: SectorId::from(sector_id),
+        porep_config: porep_config(),
+        prover_id: rng.gen(),
+        ticket: rng.gen(),
+        pieces: vec![piece_file(rng, &sector_dir, "piece")?],
+        staged_path: sector_dir.join("staged"),
+        cache_dir: sector_dir.join("cache"),
+        replica_path: sector_dir.join("replica"),
+    })
+}
+
+/// Runs the steps without generating any proofs. The steps in `failures` fail once.
+#[derive(Debug, Default)]
+struct StubExecutor {
+    executed: Mutex<Vec<SectorStep>>,
+    failures: Mutex<BTreeSet<SectorStep>>,
+}
+
+impl StubExecutor {
+    fn failing(steps: &[SectorStep]) -> Self {
+        StubExecutor {
+            executed: Default::default(),
+            failures: Mutex::new(steps.iter().copied().collect()),
+        }
+    }
+
+    fn take_executed(&self) -> Vec<SectorStep> {
+        std::mem::take(&mut *self.executed.lock().expect("executed poisoned"))
+    }
+}
+
+impl StepExecutor for StubExecutor {
+    fn execute(
+        &self,
+        dir: &Path,
+        record: &SectorRecord,
+        step: SectorStep,
+    ) -> fil_proofs_lifecycle::Result<StepOutput> {
+        self.executed.lock().expect("executed poisoned").push(step);
+        if self
+            .failures
+            .lock()
+            .expect("failures poisoned")
+            .remove(&step)
+        {
+            return Err(Error::Step {
+                sector_id: record.sector_id(),
+                step,
+                source: filecoin_proofs::Error::Internal(anyhow!("stub failure")),
+            });
+        }
+
+        let piece_info =
+            PieceInfo::new([1; 32], UnpaddedBytesAmount(2032)).expect("invalid piece info");
+        Ok(match step {
+            SectorStep::AddPieces => StepOutput::AddPieces(vec![piece_info]),
+            SectorStep::PreCommit1 => {
+                write_output(dir, PRE_COMMIT1_OUTPUT_FILE, &record.piece_infos)?;
+                StepOutput::PreCommit1
+            }
+            SectorStep::PreCommit2 => {
+                // The output of the phase 1 was persisted by an earlier step.
+                let piece_infos: Vec<PieceInfo> = read_output(dir, PRE_COMMIT1_OUTPUT_FILE)?;
+                assert_eq!(piece_infos, record.piece_infos);
+                StepOutput::PreCommit2(SealPreCommitOutput {
+                    comm_r: [2; 32],
+                    comm_d: [3; 32],
+                })
+            }
+            SectorStep::SynthProofs => StepOutput::SynthProofs,
+            SectorStep::ClearLayers => StepOutput::ClearLayers,
+            SectorStep::Commit1 => StepOutput::Commit1,
+            SectorStep::Commit2 => StepOutput::Commit2(vec![4; 192]),
+            SectorStep::ClearCache => StepOutput::ClearCache,
+            SectorStep::UpdateAddPieces => StepOutput::UpdateAddPieces(vec![piece_info]),
+            SectorStep::EncodeInto => StepOutput::EncodeInto(UpdateCommitments {
+                comm_r_new: [5; 32],
+                comm_r_last_new: [6; 32],
+                comm_d_new: [7; 32],
+            }),
+            SectorStep::ProveUpdate => StepOutput::ProveUpdate(vec![8; 192]),
+        })
+    }
+}
+
+/// Runs the steps with the `filecoin-proofs` API. The steps in `interruptions` fail once after they
+/// ran, like a step that is interrupted right before it's recorded as finished.
+#[derive(Debug, Default)]
+struct InterruptingExecutor {
+    inner: ProofsExecutor<SectorShape2KiB>,
+    interruptions: Mutex<BTreeSet<SectorStep>>,
+}
+
+impl StepExecutor for InterruptingExecutor {
+    fn execute(
+        &self,
+        dir: &Path,
+        record: &SectorRecord,
+        step: SectorStep,
+    ) -> fil_proofs_lifecycle::Result<StepOutput> {
+        let output = self.inner.execute(dir, record, step)?;
+        if self
+            .interruptions
+            .lock()
+            .expect("interruptions poisoned")
+            .remove(&step)
+        {
+            return Err(Error::Step {
+                sector_id: record.sector_id(),
+                step,
+                source: filecoin_proofs::Error::Internal(anyhow!("interrupted")),
+            });
+        }
+        Ok(output)
+    }
+}
+
+#[test]
+fn test_lifecycle_replay_and_resume() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let dir = tempdir()?;
+    let root = dir.path().join("sectors");
+    let spec = sector_spec(&mut rng, &dir, 3)?;
+    let sector_id = spec.sector_id;
+    let open = |executor: &Arc<StubExecutor>| {
+        Orchestrator::open_with_executor(&root, WorkerLimits::default(), executor.clone())
+    };
+
+    // A failed step is recorded and run again on the next advance, the finished ones aren't.
+    let executor = Arc::new(StubExecutor::failing(&[SectorStep::PreCommit2]));
+    let orchestrator = open(&executor)?;
+    orchestrator.add_sector(spec)?;
+    assert!(matches!(
+        orchestrator.advance(sector_id),
+        Err(Error::Step {
+            step: SectorStep::PreCommit2,
+            ..
+        })
+    ));
+    let record = orchestrator.record(sector_id)?;
+    assert_eq!(
+        record.last_failure.map(|failure| failure.step),
+        Some(SectorStep::PreCommit2)
+    );
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::WaitingSeed);
+    assert_eq!(
+        executor.take_executed(),
+        vec![
+            SectorStep::AddPieces,
+            SectorStep::PreCommit1,
+            SectorStep::PreCommit2,
+            SectorStep::PreCommit2,
+        ]
+    );
+    orchestrator.set_seed(sector_id, rng.gen())?;
+    drop(orchestrator);
+
+    // Simulate a crash during the commit phase 1.
+    let (mut journal, _) = Journal::open(&root.join("3"))?;
+    journal.append(&JournalEntry::Started(SectorStep::Commit1))?;
+    drop(journal);
+
+    // The journal is replayed, the sector resumes with the interrupted step.
+    let executor = Arc::new(StubExecutor::default());
+    let orchestrator = Arc::new(open(&executor)?);
+    let record = orchestrator.record(sector_id)?;
+    assert_eq!(record.interrupted, Some(SectorStep::Commit1));
+    assert_eq!(record.last_failure, None);
+    assert_eq!(record.piece_infos.len(), 1);
+    assert!(record.pre_commit.is_some());
+    assert!(record.seed.is_some());
+
+    let results = orchestrator.advance_all();
+    assert_eq!(
+        results[&sector_id].as_ref().ok(),
+        Some(&SectorState::Proving)
+    );
+    assert_eq!(
+        executor.take_executed(),
+        vec![
+            SectorStep::Commit1,
+            SectorStep::Commit2,
+            SectorStep::ClearCache
+        ]
+    );
+    assert_eq!(orchestrator.record(sector_id)?.proof, Some(vec![4; 192]));
+
+    let update_dir = dir.path().join("update");
+    let update = UpdateSpec {
+        pieces: Vec::new(),
+        staged_path: update_dir.join("staged"),
+        cache_dir: update_dir.join("cache"),
+        replica_path: update_dir.join("replica"),
+    };
+    orchestrator.request_update(sector_id, update)?;
+    drop(orchestrator);
+
+    // The requested update survives the orchestrator being opened again.
+    let executor = Arc::new(StubExecutor::failing(&[SectorStep::EncodeInto]));
+    let orchestrator = open(&executor)?;
+    assert_eq!(
+        orchestrator.state(sector_id)?,
+        SectorState::Updating(SectorStep::UpdateAddPieces)
+    );
+    assert!(orchestrator.advance(sector_id).is_err());
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::Updated);
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::Updated);
+    assert_eq!(
+        executor.take_executed(),
+        vec![
+            SectorStep::UpdateAddPieces,
+            SectorStep::EncodeInto,
+            SectorStep::EncodeInto,
+            SectorStep::ProveUpdate,
+        ]
+    );
+    let record = orchestrator.record(sector_id)?;
+    let update = record.update.expect("update missing");
+    assert_eq!(
+        update.encoded.map(|encoded| encoded.comm_r_new),
+        Some([5; 32])
+    );
+    assert_eq!(update.proof, Some(vec![8; 192]));
+
+    Ok(())
+}
+
+#[test]
+fn test_lifecycle_recovery() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let dir = tempdir()?;
+    let root = dir.path().join("sectors");
+    let spec = sector_spec(&mut rng, &dir, 1)?;
+    let sector_id = spec.sector_id;
+
+    {
+        let orchestrator = Orchestrator::open(&root, WorkerLimits::default())?;
+        assert_eq!(
+            orchestrator.add_sector(spec.clone())?,
+            SectorState::Sealing(SectorStep::AddPieces)
+        );
+        assert!(orchestrator.add_sector(spec).is_err());
+    }
+
+    // Simulate a crash while the pieces were added.
+    let sector_dir = root.join("1");
+    let (mut journal, _) = Journal::open(&sector_dir)?;
+    journal.append(&JournalEntry::Started(SectorStep::AddPieces))?;
+    drop(journal);
+
+    let orchestrator = Arc::new(Orchestrator::open(&root, WorkerLimits::default())?);
+    let record = orchestrator.record(sector_id)?;
+    assert_eq!(record.interrupted, Some(SectorStep::AddPieces));
+
+    let results = orchestrator.advance_all();
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[&sector_id].as_ref().ok(),
+        Some(&SectorState::WaitingSeed)
+    );
+
+    let record = orchestrator.record(sector_id)?;
+    assert_eq!(record.interrupted, None);
+    assert!(record.pre_commit.is_some());
+    // The piece and a zero piece that fills the rest of the sector.
+    assert_eq!(record.piece_infos.len(), 2);
+    assert_eq!(record.piece_infos[0].size, UnpaddedBytesAmount(1016));
+    assert_eq!(record.piece_infos[1].size, UnpaddedBytesAmount(1016));
+    assert!(orchestrator.provable_replicas()?.is_empty());
+
+    // Advancing a sector that waits has no effect.
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::WaitingSeed);
+    let seed = rng.gen();
+    orchestrator.set_seed(sector_id, seed)?;
+    orchestrator.set_seed(sector_id, seed)?;
+    assert!(orchestrator.set_seed(sector_id, [0; 32]).is_err());
+    assert_eq!(
+        orchestrator.state(sector_id)?,
+        SectorState::Sealing(SectorStep::Commit1)
+    );
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_lifecycle_seal_and_update_2kib() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let dir = tempdir()?;
+    let root = dir.path().join("sectors");
+    let spec = sector_spec(&mut rng, &dir, 2)?;
+    let sector_id = spec.sector_id;
+
+    let orchestrator = Orchestrator::open(&root, WorkerLimits::default())?;
+    orchestrator.add_sector(spec.clone())?;
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::WaitingSeed);
+
+    // The sector continues after the orchestrator was opened again.
+    drop(orchestrator);
+    let orchestrator = Orchestrator::open(
+        &root,
+        WorkerLimits {
+            max_memory: Some(1 << 30),
+            ..Default::default()
+        },
+    )?;
+    let seed = rng.gen();
+    orchestrator.set_seed(sector_id, seed)?;
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::Proving);
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::Proving);
+
+    let record = orchestrator.record(sector_id)?;
+    let pre_commit = record
+        .pre_commit
+        .clone()
+        .expect("pre-commit output missing");
+    let proof = record.proof.expect("proof missing");
+    assert!(verify_seal::<SectorShape2KiB>(
+        &spec.porep_config,
+        pre_commit.comm_r,
+        pre_commit.comm_d,
+        spec.prover_id,
+        sector_id,
+        spec.ticket,
+        seed,
+        &proof,
+    )?);
+    assert!(!root.join("2").join("pre-commit1.bin").exists());
+    assert!(!root.join("2").join("commit1.bin").exists());
+    let replicas = orchestrator.provable_replicas()?;
+    assert_eq!(
+        replicas[&sector_id].replica_path(),
+        spec.replica_path.as_path()
+    );
+
+    let update_dir = dir.path().join("update");
+    fs::create_dir_all(&update_dir)?;
+    let update = UpdateSpec {
+        pieces: vec![piece_file(&mut rng, &update_dir, "piece")?],
+        staged_path: update_dir.join("staged"),
+        cache_dir: update_dir.join("cache"),
+        replica_path: update_dir.join("replica"),
+    };
+    orchestrator.request_update(sector_id, update.clone())?;
+    assert!(orchestrator
+        .request_update(sector_id, update.clone())
+        .is_err());
+    assert_eq!(orchestrator.advance(sector_id)?, SectorState::Updated);
+
+    let record = orchestrator.record(sector_id)?;
+    let update_record = record.update.expect("update missing");
+    let encoded = update_record.encoded.expect("update isn't encoded");
+    assert!(verify_empty_sector_update_proof::<SectorShape2KiB>(
+        &spec.porep_config,
+        &update_record.proof.expect("update proof missing"),
+        pre_commit.comm_r,
+        encoded.comm_r_new,
+        encoded.comm_d_new,
+    )?);
+    let replicas = orchestrator.provable_replicas()?;
+    assert_eq!(
+        replicas[&sector_id].replica_path(),
+        update.replica_path.as_path()
+    );
+
+    Ok(())
+}
+
+/// Seals and updates a sector with synthetic PoRep, returns its comm_r and the comm_r of the
+/// update. The steps in `interruptions` are run twice.
+fn seal_and_update_2kib(interruptions: &[SectorStep]) -> Result<(Commitment, Commitment)> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let dir = tempdir()?;
+    let root = dir.path().join("sectors");
+    let mut spec = sector_spec(&mut rng, &dir, 4)?;
+    spec.porep_config = PoRepConfig::new_groth16_with_features(
+        SECTOR_SIZE_2_KIB,
+        spec.porep_config.porep_id,
+        ApiVersion::V1_2_0,
+        vec![ApiFeature::SyntheticPoRep],
+    )?;
+    let sector_id = spec.sector_id;
+
+    let executor = Arc::new(InterruptingExecutor {
+        interruptions: Mutex::new(interruptions.iter().copied().collect()),
+        ..Default::default()
+    });
+    let orchestrator =
+        Orchestrator::open_with_executor(root, WorkerLimits::default(), executor.clone())?;
+    orchestrator.add_sector(spec.clone())?;
+    let advance = || loop {
+        match orchestrator.advance(sector_id) {
+            Err(Error::Step { step, .. }) if interruptions.contains(&step) => continue,
+            result => return result,
+        }
+    };
+    assert_eq!(advance()?, SectorState::WaitingSeed);
+    let seed = rng.gen();
+    orchestrator.set_seed(sector_id, seed)?;
+    assert_eq!(advance()?, SectorState::Proving);
+
+    let record = orchestrator.record(sector_id)?;
+    let pre_commit = record.pre_commit.expect("pre-commit output missing");
+    assert!(verify_seal::<SectorShape2KiB>(
+        &spec.porep_config,
+        pre_commit.comm_r,
+        pre_commit.comm_d,
+        spec.prover_id,
+        sector_id,
+        spec.ticket,
+        seed,
+        &record.proof.expect("proof missing"),
+    )?);
+
+    let update_dir = dir.path().join("update");
+    fs::create_dir_all(&update_dir)?;
+    let update = UpdateSpec {
+        pieces: vec![piece_file(&mut rng, &update_dir, "piece")?],
+        staged_path: update_dir.join("staged"),
+        cache_dir: update_dir.join("cache"),
+        replica_path: update_dir.join("replica"),
+    };
+    orchestrator.request_update(sector_id, update)?;
+    assert_eq!(advance()?, SectorState::Updated);
+
+    let update_record = orchestrator
+        .record(sector_id)?
+        .update
+        .expect("update missing");
+    let encoded = update_record.encoded.expect("update isn't encoded");
+    assert!(verify_empty_sector_update_proof::<SectorShape2KiB>(
+        &spec.porep_config,
+        &update_record.proof.expect("update proof missing"),
+        pre_commit.comm_r,
+        encoded.comm_r_new,
+        encoded.comm_d_new,
+    )?);
+    assert!(executor
+        .interruptions
+        .lock()
+        .expect("interruptions poisoned")
+        .is_empty());
+
+    Ok((pre_commit.comm_r, encoded.comm_r_new))
+}
+
+#[test]
+#[ignore]
+fn test_lifecycle_retry_2kib() -> Result<()> {
+    fil_logger::maybe_init();
+
+    // Steps that ran before they were interrupted lead to the same sector when they run again.
+    let clean = seal_and_update_2kib(&[])?;
+    let retried = seal_and_update_2kib(&[
+        SectorStep::PreCommit2,
+        SectorStep::SynthProofs,
+        SectorStep::ClearLayers,
+        SectorStep::EncodeInto,
+    ])?;
+    assert_eq!(retried, clean);
+
+    Ok(())
+}
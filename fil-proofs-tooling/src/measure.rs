@@ -9,14 +9,15 @@ pub struct FuncMeasurement<T> {
     pub return_value: T,
 }
 
-pub fn measure<T, F>(f: F) -> Result<FuncMeasurement<T>>
+pub fn measure<T, E, F>(f: F) -> Result<FuncMeasurement<T>>
 where
-    F: FnOnce() -> Result<T>,
+    F: FnOnce() -> std::result::Result<T, E>,
+    E: Into<anyhow::Error>,
 {
     let cpu_time_start = ProcessTime::now();
     let wall_start_time = Instant::now();
 
-    let x = f()?;
+    let x = f().map_err(Into::into)?;
 
     Ok(FuncMeasurement {
         cpu_time: cpu_time_start.elapsed(),
@@ -225,7 +225,7 @@ pub fn create_replicas<Tree: 'static + MerkleTreeTrait>(
                         comm_d: phase1.comm_d,
                     })
                 })
-                .collect::<Result<Vec<_>, _>>()
+                .collect::<Result<Vec<_>, anyhow::Error>>()
         } else {
             let phase1s = cache_dirs
                 .par_iter()
@@ -288,7 +288,7 @@ pub fn create_replicas<Tree: 'static + MerkleTreeTrait>(
 
                     Ok(res)
                 })
-                .collect::<Result<Vec<_>, _>>()
+                .collect::<Result<Vec<_>, anyhow::Error>>()
         }
     })
     .expect("seal_pre_commit produced an error");
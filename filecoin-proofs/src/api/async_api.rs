@@ -0,0 +1,599 @@
+//! Future based counterparts of the blocking proving APIs.
+//!
+//! The blocking calls run on a [`BlockingPool`], a fixed number of threads that is separate from
+//! the threads of an async runtime. Each call returns a [`BlockingTask`], a future that resolves
+//! once the call finished. Dropping the future cancels the call: a call that is still queued is
+//! never started, a running call is cancelled through its [`SealContext`] if it supports
+//! cancellation, otherwise it runs to completion and its result is discarded.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex,
+};
+use std::task::{Context as TaskContext, Poll};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Context};
+use futures_channel::oneshot;
+use log::{trace, warn};
+use storage_proofs_core::sector::SectorId;
+
+use crate::{
+    api::{
+        decode_from, encode_into_with_context, generate_empty_sector_update_proof,
+        generate_window_post, generate_winning_post, seal_commit_phase1,
+        seal_commit_phase2_with_context, seal_pre_commit_phase1_with_context,
+        seal_pre_commit_phase2_with_context, verify_empty_sector_update_proof, verify_window_post,
+        verify_winning_post,
+    },
+    error::{ensure_input, Error, Result},
+    types::{
+        CancellationToken, ChallengeSeed, Commitment, EmptySectorUpdateEncoded,
+        EmptySectorUpdateProof, MerkleTreeTrait, PieceInfo, PoRepConfig, PoStConfig,
+        PrivateReplicaInfo, ProofsContext, ProverId, PublicReplicaInfo, SealCommitOutput,
+        SealCommitPhase1Output, SealContext, SealPreCommitOutput, SealPreCommitPhase1Output,
+        SectorUpdateConfig, SnarkProof, Ticket, TreeRHasher,
+    },
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A queued call, with the cancellation of its task.
+struct QueuedJob {
+    run: Job,
+    cancellation: CancellationToken,
+}
+
+/// The state that is shared between a pool and its threads.
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<VecDeque<QueuedJob>>,
+    job_available: Condvar,
+    active_jobs: AtomicUsize,
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut queue = self.queue.lock().expect("queue poisoned");
+                loop {
+                    if self.shutdown.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    if let Some(job) = queue.pop_front() {
+                        break job.run;
+                    }
+                    queue = self.job_available.wait(queue).expect("queue poisoned");
+                }
+            };
+
+            self.active_jobs.fetch_add(1, Ordering::SeqCst);
+            // A panic drops the sender of the result, which fails the task.
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                warn!("blocking task panicked");
+            }
+            self.active_jobs.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A fixed size pool of threads that runs the blocking calls.
+///
+/// Dropping the pool waits for the running calls, calls that are still queued fail.
+pub struct BlockingPool {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl BlockingPool {
+    pub fn new(num_threads: usize) -> Result<Self> {
+        ensure_input!(num_threads > 0, "a pool needs at least one thread");
+
+        let shared = Arc::new(Shared::default());
+        let threads = (0..num_threads)
+            .map(|i| {
+                let shared = Arc::clone(&shared);
+                thread::Builder::new()
+                    .name(format!("proofs-blocking-{}", i))
+                    .spawn(move || shared.run())
+                    .context("failed to spawn pool thread")
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(BlockingPool { shared, threads })
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Returns the number of calls that wait for a free thread. Calls whose task was dropped
+    /// aren't counted, they are skipped once they are dequeued.
+    pub fn queue_depth(&self) -> usize {
+        self.shared
+            .queue
+            .lock()
+            .expect("queue poisoned")
+            .iter()
+            .filter(|job| !job.cancellation.is_cancelled())
+            .count()
+    }
+
+    /// Returns the number of calls that are currently running.
+    pub fn active_jobs(&self) -> usize {
+        self.shared.active_jobs.load(Ordering::SeqCst)
+    }
+
+    /// Queues a blocking call. The call gets a [`SealContext`] that is cancelled once the returned
+    /// task is dropped, and it runs with the [`ProofsContext`] that is current when it's queued.
+    pub fn spawn<F, T>(&self, f: F) -> BlockingTask<T>
+    where
+        F: FnOnce(&SealContext) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let cancellation = CancellationToken::new();
+        let ctx = SealContext::new()
+            .with_cancellation(cancellation.clone())
+            .with_proofs_context(ProofsContext::current());
+
+        let run: Job = Box::new(move || {
+            if ctx.is_cancelled() {
+                trace!("skipping cancelled blocking task");
+                return;
+            }
+            let _guard = ctx.enter_proofs_context();
+            // The receiver is gone if the task was dropped in the meantime.
+            let _ = sender.send(f(&ctx));
+        });
+        self.shared
+            .queue
+            .lock()
+            .expect("queue poisoned")
+            .push_back(QueuedJob {
+                run,
+                cancellation: cancellation.clone(),
+            });
+        self.shared.job_available.notify_one();
+
+        BlockingTask {
+            result: receiver,
+            cancellation,
+        }
+    }
+}
+
+impl Drop for BlockingPool {
+    fn drop(&mut self) {
+        {
+            // Holding the lock makes sure that no thread misses the notification.
+            let _queue = self.shared.queue.lock().expect("queue poisoned");
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+            self.shared.job_available.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                warn!("pool thread panicked");
+            }
+        }
+    }
+}
+
+/// The result of a call running on a [`BlockingPool`]. Dropping it cancels the call.
+pub struct BlockingTask<T> {
+    result: oneshot::Receiver<Result<T>>,
+    cancellation: CancellationToken,
+}
+
+impl<T> Future for BlockingTask<T> {
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.result).poll(cx).map(|result| {
+            result.unwrap_or_else(|_| {
+                Err(Error::Internal(anyhow!(
+                    "blocking task panicked or pool was dropped"
+                )))
+            })
+        })
+    }
+}
+
+impl<T> Drop for BlockingTask<T> {
+    fn drop(&mut self) {
+        self.cancellation.cancel();
+    }
+}
+
+/// Async counterpart of [`seal_pre_commit_phase1_with_context`].
+#[allow(clippy::too_many_arguments)]
+pub async fn seal_pre_commit_phase1_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    porep_config: PoRepConfig,
+    cache_path: PathBuf,
+    in_path: PathBuf,
+    out_path: PathBuf,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    piece_infos: Vec<PieceInfo>,
+) -> Result<SealPreCommitPhase1Output<Tree>> {
+    pool.spawn(move |ctx| {
+        seal_pre_commit_phase1_with_context::<_, _, _, Tree>(
+            &porep_config,
+            cache_path,
+            in_path,
+            out_path,
+            prover_id,
+            sector_id,
+            ticket,
+            &piece_infos,
+            ctx,
+        )
+    })
+    .await
+}
+
+/// Async counterpart of [`seal_pre_commit_phase2_with_context`].
+pub async fn seal_pre_commit_phase2_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    porep_config: PoRepConfig,
+    phase1_output: SealPreCommitPhase1Output<Tree>,
+    cache_path: PathBuf,
+    replica_path: PathBuf,
+) -> Result<SealPreCommitOutput> {
+    pool.spawn(move |ctx| {
+        seal_pre_commit_phase2_with_context::<_, _, Tree>(
+            &porep_config,
+            phase1_output,
+            cache_path,
+            replica_path,
+            ctx,
+        )
+    })
+    .await
+}
+
+/// Async counterpart of [`seal_commit_phase1`], it can only be cancelled before it started.
+#[allow(clippy::too_many_arguments)]
+pub async fn seal_commit_phase1_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    porep_config: PoRepConfig,
+    cache_path: PathBuf,
+    replica_path: PathBuf,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    seed: Ticket,
+    pre_commit: SealPreCommitOutput,
+    piece_infos: Vec<PieceInfo>,
+) -> Result<SealCommitPhase1Output<Tree>> {
+    pool.spawn(move |_ctx| {
+        seal_commit_phase1::<_, Tree>(
+            &porep_config,
+            cache_path,
+            replica_path,
+            prover_id,
+            sector_id,
+            ticket,
+            seed,
+            pre_commit,
+            &piece_infos,
+        )
+    })
+    .await
+}
+
+/// Async counterpart of [`seal_commit_phase2_with_context`].
+pub async fn seal_commit_phase2_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    porep_config: PoRepConfig,
+    phase1_output: SealCommitPhase1Output<Tree>,
+    prover_id: ProverId,
+    sector_id: SectorId,
+) -> Result<SealCommitOutput> {
+    pool.spawn(move |ctx| {
+        seal_commit_phase2_with_context::<Tree>(
+            &porep_config,
+            phase1_output,
+            prover_id,
+            sector_id,
+            ctx,
+        )
+    })
+    .await
+}
+
+/// Async counterpart of [`generate_winning_post`], it can only be cancelled before it started.
+pub async fn generate_winning_post_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    post_config: PoStConfig,
+    randomness: ChallengeSeed,
+    replicas: Vec<(SectorId, PrivateReplicaInfo<Tree>)>,
+    prover_id: ProverId,
+) -> Result<SnarkProof> {
+    pool.spawn(move |_ctx| {
+        generate_winning_post::<Tree>(&post_config, &randomness, &replicas, prover_id)
+    })
+    .await
+}
+
+/// Async counterpart of [`verify_winning_post`], it can only be cancelled before it started.
+pub async fn verify_winning_post_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    post_config: PoStConfig,
+    randomness: ChallengeSeed,
+    replicas: Vec<(SectorId, PublicReplicaInfo)>,
+    prover_id: ProverId,
+    proof: Vec<u8>,
+) -> Result<bool> {
+    pool.spawn(move |_ctx| {
+        verify_winning_post::<Tree>(&post_config, &randomness, &replicas, prover_id, &proof)
+    })
+    .await
+}
+
+/// Async counterpart of [`generate_window_post`], it can only be cancelled before it started.
+pub async fn generate_window_post_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    post_config: PoStConfig,
+    randomness: ChallengeSeed,
+    replicas: BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+) -> Result<SnarkProof> {
+    pool.spawn(move |_ctx| {
+        generate_window_post::<Tree>(&post_config, &randomness, &replicas, prover_id)
+    })
+    .await
+}
+
+/// Async counterpart of [`verify_window_post`], it can only be cancelled before it started.
+pub async fn verify_window_post_async<Tree: 'static + MerkleTreeTrait>(
+    pool: &BlockingPool,
+    post_config: PoStConfig,
+    randomness: ChallengeSeed,
+    replicas: BTreeMap<SectorId, PublicReplicaInfo>,
+    prover_id: ProverId,
+    proof: Vec<u8>,
+) -> Result<bool> {
+    pool.spawn(move |_ctx| {
+        verify_window_post::<Tree>(&post_config, &randomness, &replicas, prover_id, &proof)
+    })
+    .await
+}
+
+/// Async counterpart of [`encode_into_with_context`].
+#[allow(clippy::too_many_arguments)]
+pub async fn encode_into_async<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    pool: &BlockingPool,
+    config: SectorUpdateConfig,
+    new_replica_path: PathBuf,
+    new_cache_path: PathBuf,
+    sector_key_path: PathBuf,
+    sector_key_cache_path: PathBuf,
+    staged_data_path: PathBuf,
+    piece_infos: Vec<PieceInfo>,
+) -> Result<EmptySectorUpdateEncoded> {
+    pool.spawn(move |ctx| {
+        encode_into_with_context::<Tree>(
+            &config,
+            &new_replica_path,
+            &new_cache_path,
+            &sector_key_path,
+            &sector_key_cache_path,
+            &staged_data_path,
+            &piece_infos,
+            ctx,
+        )
+    })
+    .await
+}
+
+/// Async counterpart of [`decode_from`], it can only be cancelled before it started.
+pub async fn decode_from_async<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    pool: &BlockingPool,
+    config: SectorUpdateConfig,
+    out_data_path: PathBuf,
+    replica_path: PathBuf,
+    sector_key_path: PathBuf,
+    sector_key_cache_path: PathBuf,
+    comm_d_new: Commitment,
+) -> Result<()> {
+    pool.spawn(move |_ctx| {
+        decode_from::<Tree>(
+            config,
+            &out_data_path,
+            &replica_path,
+            &sector_key_path,
+            &sector_key_cache_path,
+            comm_d_new,
+        )
+    })
+    .await
+}
+
+/// Async counterpart of [`generate_empty_sector_update_proof`], it can only be cancelled before
+/// it started.
+#[allow(clippy::too_many_arguments)]
+pub async fn generate_empty_sector_update_proof_async<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    pool: &BlockingPool,
+    porep_config: PoRepConfig,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+    sector_key_path: PathBuf,
+    sector_key_cache_path: PathBuf,
+    replica_path: PathBuf,
+    replica_cache_path: PathBuf,
+) -> Result<EmptySectorUpdateProof> {
+    pool.spawn(move |_ctx| {
+        generate_empty_sector_update_proof::<Tree>(
+            &porep_config,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+            &sector_key_path,
+            &sector_key_cache_path,
+            &replica_path,
+            &replica_cache_path,
+        )
+    })
+    .await
+}
+
+/// Async counterpart of [`verify_empty_sector_update_proof`], it can only be cancelled before
+/// it started.
+pub async fn verify_empty_sector_update_proof_async<
+    Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+>(
+    pool: &BlockingPool,
+    porep_config: PoRepConfig,
+    proof_bytes: Vec<u8>,
+    comm_r_old: Commitment,
+    comm_r_new: Commitment,
+    comm_d_new: Commitment,
+) -> Result<bool> {
+    pool.spawn(move |_ctx| {
+        verify_empty_sector_update_proof::<Tree>(
+            &porep_config,
+            &proof_bytes,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+        )
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::task::{Wake, Waker};
+    use std::time::Duration;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Polls a future on the current thread until it's ready.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = TaskContext::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_blocking_pool() {
+        let pool = BlockingPool::new(2).expect("failed to create pool");
+        let tasks: Vec<_> = (0..10u64).map(|i| pool.spawn(move |_| Ok(i * i))).collect();
+        let results: Vec<u64> = tasks
+            .into_iter()
+            .map(|task| block_on(task).expect("task failed"))
+            .collect();
+        assert_eq!(results, (0..10).map(|i| i * i).collect::<Vec<_>>());
+
+        let task = pool.spawn(|_| -> Result<()> { panic!("task panics") });
+        assert!(block_on(task).is_err());
+        assert_eq!(
+            block_on(pool.spawn(|_| Ok(1))).expect("pool survives panics"),
+            1
+        );
+    }
+
+    #[test]
+    fn test_blocking_pool_queue_depth_and_cancellation() {
+        let pool = BlockingPool::new(1).expect("failed to create pool");
+
+        // Block the only thread until the running task is cancelled.
+        let (started_tx, started_rx) = mpsc::channel();
+        let running = pool.spawn(move |ctx| {
+            started_tx.send(()).expect("send failed");
+            while !ctx.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok(ctx.check_cancelled()?)
+        });
+        started_rx.recv().expect("task didn't start");
+
+        let (ran_tx, ran_rx) = mpsc::channel();
+        let queued = pool.spawn(move |_| {
+            ran_tx.send(()).expect("send failed");
+            Ok(())
+        });
+        let next = pool.spawn(|_| Ok(42));
+        assert_eq!(pool.active_jobs(), 1);
+        assert_eq!(pool.queue_depth(), 2);
+
+        // Neither the running nor the queued task complete.
+        drop(queued);
+        assert_eq!(pool.queue_depth(), 1);
+        drop(running);
+        assert_eq!(block_on(next).expect("task failed"), 42);
+        assert!(ran_rx.try_recv().is_err());
+        assert_eq!(pool.queue_depth(), 0);
+    }
+
+    #[test]
+    fn test_blocking_pool_proofs_context() {
+        use storage_proofs_core::settings::{self, Settings};
+
+        let pool = BlockingPool::new(1).expect("failed to create pool");
+        let rows_to_discard = settings::SETTINGS.rows_to_discard + 1;
+        let context = ProofsContext::new(Settings {
+            rows_to_discard,
+            ..Default::default()
+        });
+
+        let task = context.scope(|| pool.spawn(|_| Ok(settings::current().rows_to_discard)));
+        assert_eq!(block_on(task).expect("task failed"), rows_to_discard);
+        let task = pool.spawn(|_| Ok(settings::current().rows_to_discard));
+        assert_eq!(
+            block_on(task).expect("task failed"),
+            settings::SETTINGS.rows_to_discard
+        );
+    }
+
+    #[test]
+    fn test_dropped_pool_fails_queued_tasks() {
+        let pool = BlockingPool::new(1).expect("failed to create pool");
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let running = pool.spawn(move |_| {
+            started_tx.send(()).expect("send failed");
+            release_rx.recv().expect("recv failed");
+            Ok(1)
+        });
+        started_rx.recv().expect("task didn't start");
+        let queued = pool.spawn(|_| Ok(2));
+
+        let dropper = thread::spawn(move || drop(pool));
+        // Give the pool time to shut down before the running task finishes.
+        thread::sleep(Duration::from_millis(50));
+        release_tx.send(()).expect("send failed");
+        dropper.join().expect("dropping the pool panicked");
+
+        assert_eq!(block_on(running).expect("running task completes"), 1);
+        assert!(block_on(queued).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+use filecoin_hashers::{HashFunction, Hasher};
+use log::{info, trace};
+use merkletree::store::StoreConfig;
+use sha2::{Digest, Sha256};
+use storage_proofs_core::{
+    api_version::ApiFeature, cache_key::CacheKey, merkle::get_base_tree_count,
+};
+use storage_proofs_porep::stacked::{
+    SYNTHETIC_POREP_VANILLA_PROOFS_EXT, SYNTHETIC_POREP_VANILLA_PROOFS_KEY,
+};
+use typenum::Unsigned;
+
+use crate::{
+    api::{diagnose_sector_cache, util},
+    constants::LAYERS,
+    error::{ensure_input, Error, Result},
+    types::{
+        Commitment, MerkleTreeTrait, PoRepConfig, SectorBundleFile, SectorBundleManifest,
+        SectorOperation, SECTOR_BUNDLE_VERSION,
+    },
+};
+
+const MANIFEST_PATH: &str = "manifest.json";
+const REPLICA_PATH: &str = "replica";
+const CACHE_DIR: &str = "cache";
+
+/// Computes the SHA-256 digest of everything that is read through it.
+struct DigestReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> DigestReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn finish(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn tree_shape<Tree: MerkleTreeTrait>() -> String {
+    format!(
+        "{}-{}-{}",
+        Tree::Arity::to_usize(),
+        Tree::SubTreeArity::to_usize(),
+        Tree::TopTreeArity::to_usize()
+    )
+}
+
+/// Returns the files of the cache directory that are needed for `operation`.
+fn bundle_cache_files<Tree: MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: &Path,
+    operation: SectorOperation,
+    has_synthetic_proofs: bool,
+) -> Result<Vec<PathBuf>> {
+    let sector_bytes = u64::from(porep_config.sector_size);
+    let num_layers = *LAYERS
+        .read()
+        .expect("LAYERS poisoned")
+        .get(&sector_bytes)
+        .context("unknown sector size")?;
+    let tree_count = get_base_tree_count::<Tree>();
+    let split_tree = |key: CacheKey| -> Vec<PathBuf> {
+        if tree_count == 1 {
+            vec![StoreConfig::data_path(cache_path, &key.to_string())]
+        } else {
+            (0..tree_count)
+                .map(|i| StoreConfig::data_path(cache_path, &format!("{}-{}", key, i)))
+                .collect()
+        }
+    };
+
+    let mut aux = vec![cache_path.join(CacheKey::PAux.to_string())];
+    // There is no `t_aux` with the `fixed-rows-to-discard` feature.
+    let t_aux_path = cache_path.join(CacheKey::TAux.to_string());
+    if t_aux_path.exists() {
+        aux.push(t_aux_path);
+    }
+    let mut labels: Vec<PathBuf> = (1..=num_layers)
+        .map(|layer| StoreConfig::data_path(cache_path, &CacheKey::label_layer(layer)))
+        .collect();
+    labels.push(StoreConfig::data_path(
+        cache_path,
+        &CacheKey::CommDTree.to_string(),
+    ));
+
+    let files = match operation {
+        SectorOperation::PreCommitPhase2 => labels,
+        SectorOperation::CommitPhase1 => {
+            let mut files = aux;
+            files.extend(split_tree(CacheKey::CommRLastTree));
+            if porep_config.feature_enabled(ApiFeature::SyntheticPoRep) && has_synthetic_proofs {
+                files.push(cache_path.join(format!(
+                    "{}.{}",
+                    SYNTHETIC_POREP_VANILLA_PROOFS_KEY, SYNTHETIC_POREP_VANILLA_PROOFS_EXT
+                )));
+            } else {
+                files.extend(labels);
+                files.extend(split_tree(CacheKey::CommCTree));
+            }
+            files
+        }
+        SectorOperation::PoSt => {
+            let mut files = aux;
+            files.extend(split_tree(CacheKey::CommRLastTree));
+            files
+        }
+        SectorOperation::EncodeInto => aux,
+    };
+    Ok(files)
+}
+
+/// Returns the commitments stored in `p_aux`: CommR, CommC and CommRLast.
+fn p_aux_commitments<Tree: MerkleTreeTrait>(
+    cache_path: &Path,
+) -> Result<(Commitment, Commitment, Commitment)> {
+    let p_aux = util::get_p_aux::<Tree>(cache_path)?;
+    let comm_r = <Tree::Hasher as Hasher>::Function::hash2(&p_aux.comm_c, &p_aux.comm_r_last);
+    let to_commitment = |domain: &<Tree::Hasher as Hasher>::Domain| {
+        let mut commitment = [0; 32];
+        commitment.copy_from_slice(domain.as_ref());
+        commitment
+    };
+    Ok((
+        to_commitment(&comm_r),
+        to_commitment(&p_aux.comm_c),
+        to_commitment(&p_aux.comm_r_last),
+    ))
+}
+
+fn append_file<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    file_path: &Path,
+) -> Result<SectorBundleFile> {
+    trace!("adding {:?} as {}", file_path, path);
+    let file = File::open(file_path).with_context(|| format!("could not open {:?}", file_path))?;
+    let size = file.metadata()?.len();
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_cksum();
+    let mut reader = DigestReader::new(file);
+    builder
+        .append_data(&mut header, path, &mut reader)
+        .with_context(|| format!("could not add {:?} to the bundle", file_path))?;
+
+    Ok(SectorBundleFile {
+        path: path.to_string(),
+        size,
+        sha256: reader.finish(),
+    })
+}
+
+/// Writes a sector bundle, a tar archive with the replica and the cache files that are needed to
+/// run `operation` on another host.
+///
+/// The archive is written in a single pass, it contains the replica as `replica`, the cache files
+/// within `cache/` and a JSON manifest as `manifest.json` at the end. The cache is checked with
+/// [`diagnose_sector_cache`] first, it fails if the operation isn't possible with the files that
+/// are there. For [`SectorOperation::PreCommitPhase2`] the replica is the staged sector.
+pub fn export_sector_bundle<R, T, W, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    replica_path: T,
+    operation: SectorOperation,
+    writer: W,
+) -> Result<SectorBundleManifest>
+where
+    R: AsRef<Path>,
+    T: AsRef<Path>,
+    W: Write,
+{
+    info!("export_sector_bundle:start");
+
+    let cache_path = cache_path.as_ref();
+    let report = diagnose_sector_cache::<_, _, Tree>(porep_config, cache_path, &replica_path)?;
+    ensure_input!(
+        report.is_possible(operation),
+        "sector cache can't be used for {:?}: {:?}",
+        operation,
+        report
+    );
+
+    let (comm_r, comm_c, comm_r_last) = if operation == SectorOperation::PreCommitPhase2 {
+        (None, None, None)
+    } else {
+        let (comm_r, comm_c, comm_r_last) = p_aux_commitments::<Tree>(cache_path)?;
+        (Some(comm_r), Some(comm_c), Some(comm_r_last))
+    };
+
+    let mut builder = tar::Builder::new(writer);
+    let mut files = vec![append_file(
+        &mut builder,
+        REPLICA_PATH,
+        replica_path.as_ref(),
+    )?];
+    for file_path in bundle_cache_files::<Tree>(
+        porep_config,
+        cache_path,
+        operation,
+        report.has_synthetic_proofs,
+    )? {
+        let file_name = file_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context("invalid cache file name")?;
+        let path = format!("{}/{}", CACHE_DIR, file_name);
+        files.push(append_file(&mut builder, &path, &file_path)?);
+    }
+
+    let manifest = SectorBundleManifest {
+        version: SECTOR_BUNDLE_VERSION,
+        sector_size: porep_config.sector_size,
+        tree_shape: tree_shape::<Tree>(),
+        porep_id: porep_config.porep_id,
+        api_version: porep_config.api_version,
+        operation,
+        comm_r,
+        comm_c,
+        comm_r_last,
+        files,
+    };
+    let manifest_bytes =
+        serde_json::to_vec_pretty(&manifest).context("failed to serialize the manifest")?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, MANIFEST_PATH, &manifest_bytes[..])?;
+    builder.into_inner()?.flush()?;
+
+    info!("export_sector_bundle:finish");
+    Ok(manifest)
+}
+
+/// Returns the destination of a file of the bundle, or `None` for the manifest.
+fn unpack_path(path: &Path, cache_path: &Path, replica_path: &Path) -> Result<Option<PathBuf>> {
+    let components: Vec<_> = path.components().collect();
+    match components.as_slice() {
+        [Component::Normal(name)] if *name == MANIFEST_PATH => Ok(None),
+        [Component::Normal(name)] if *name == REPLICA_PATH => Ok(Some(replica_path.to_path_buf())),
+        [Component::Normal(dir), Component::Normal(name)] if *dir == CACHE_DIR => {
+            Ok(Some(cache_path.join(name)))
+        }
+        _ => Err(Error::InvalidInput(format!(
+            "unexpected file in sector bundle: {:?}",
+            path
+        ))),
+    }
+}
+
+/// Unpacks a sector bundle written by [`export_sector_bundle`].
+///
+/// The replica is written to `replica_path` and the cache files into `cache_path`. The sizes and
+/// digests of all files are verified against the manifest, as well as the sector configuration.
+/// The paths within `t_aux` are updated to the new cache directory. Finally the commitments in
+/// `p_aux` and the roots of the trees are checked, and that the unpacked files are sufficient for
+/// the operation the bundle was created for.
+///
+/// The bundle is unpacked into staging locations next to `cache_path` and `replica_path` first,
+/// the files are only moved to their destinations once all the checks passed. Nothing in
+/// `cache_path` or at `replica_path` is touched if the bundle turns out to be invalid.
+pub fn import_sector_bundle<R, T, S, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    reader: S,
+    cache_path: R,
+    replica_path: T,
+) -> Result<SectorBundleManifest>
+where
+    R: AsRef<Path>,
+    T: AsRef<Path>,
+    S: Read,
+{
+    info!("import_sector_bundle:start");
+
+    let cache_path = cache_path.as_ref();
+    let replica_path = replica_path.as_ref();
+    ensure_input!(
+        fs::metadata(cache_path)?.is_dir(),
+        "cache_path must be a directory"
+    );
+    let replica_dir = match replica_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    // The staging locations are on the same file systems as the destinations, so that the files
+    // can be renamed into place. They are removed when dropped.
+    let staging_cache = tempfile::Builder::new()
+        .prefix(".import-")
+        .tempdir_in(cache_path)
+        .with_context(|| format!("could not create a staging directory in {:?}", cache_path))?;
+    let staging_replica = tempfile::Builder::new()
+        .prefix(".import-")
+        .tempfile_in(replica_dir)
+        .with_context(|| format!("could not create a staging file in {:?}", replica_dir))?;
+
+    let mut manifest = None;
+    let mut unpacked = BTreeMap::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.into_owned();
+        match unpack_path(&path, staging_cache.path(), staging_replica.path())? {
+            Some(dest) => {
+                trace!("unpacking {:?} to {:?}", path, dest);
+                let mut reader = DigestReader::new(entry);
+                let mut file =
+                    File::create(&dest).with_context(|| format!("could not create {:?}", dest))?;
+                let size = io::copy(&mut reader, &mut file)
+                    .with_context(|| format!("could not write {:?}", dest))?;
+                file.sync_all()?;
+                let path = path.to_string_lossy().into_owned();
+                unpacked.insert(path.clone(), (size, reader.finish()));
+            }
+            None => {
+                let parsed: SectorBundleManifest = serde_json::from_reader(entry)
+                    .map_err(|err| Error::InvalidInput(format!("invalid manifest: {}", err)))?;
+                manifest = Some(parsed);
+            }
+        }
+    }
+    let manifest =
+        manifest.ok_or_else(|| Error::InvalidInput("sector bundle has no manifest".to_string()))?;
+
+    ensure_input!(
+        manifest.version == SECTOR_BUNDLE_VERSION,
+        "unsupported sector bundle version {}",
+        manifest.version
+    );
+    ensure_input!(
+        manifest.sector_size == porep_config.sector_size
+            && manifest.porep_id == porep_config.porep_id
+            && manifest.api_version == porep_config.api_version
+            && manifest.tree_shape == tree_shape::<Tree>(),
+        "sector bundle doesn't match the PoRep config"
+    );
+    ensure_input!(
+        manifest.files.len() == unpacked.len(),
+        "sector bundle contains files that are not in the manifest"
+    );
+    for file in &manifest.files {
+        let (size, sha256) = unpacked
+            .get(&file.path)
+            .with_context(|| format!("{} is missing in the sector bundle", file.path))?;
+        ensure_input!(
+            *size == file.size && *sha256 == file.sha256,
+            "{} doesn't match the manifest",
+            file.path
+        );
+    }
+
+    if manifest.comm_r.is_some() {
+        let (comm_r, comm_c, comm_r_last) = p_aux_commitments::<Tree>(staging_cache.path())?;
+        ensure_input!(
+            manifest.comm_r == Some(comm_r)
+                && manifest.comm_c == Some(comm_c)
+                && manifest.comm_r_last == Some(comm_r_last),
+            "p_aux doesn't match the commitments of the manifest"
+        );
+    }
+    let report = diagnose_sector_cache::<_, _, Tree>(
+        porep_config,
+        staging_cache.path(),
+        staging_replica.path(),
+    )?;
+    ensure_input!(
+        report.root_mismatches.is_empty() && report.is_possible(manifest.operation),
+        "unpacked sector can't be used for {:?}: {:?}",
+        manifest.operation,
+        report
+    );
+
+    for file in &manifest.files {
+        if let Some(name) = file.path.strip_prefix(&format!("{}/", CACHE_DIR)) {
+            let from = staging_cache.path().join(name);
+            let to = cache_path.join(name);
+            trace!("moving {:?} to {:?}", from, to);
+            fs::rename(&from, &to)
+                .with_context(|| format!("could not move {:?} to {:?}", from, to))?;
+        }
+    }
+    util::sync_dir(cache_path).with_context(|| format!("could not sync {:?}", cache_path))?;
+    staging_replica
+        .persist(replica_path)
+        .with_context(|| format!("could not move the replica to {:?}", replica_path))?;
+    util::sync_dir(replica_dir).with_context(|| format!("could not sync {:?}", replica_dir))?;
+
+    #[cfg(not(feature = "fixed-rows-to-discard"))]
+    if cache_path.join(CacheKey::TAux.to_string()).exists() {
+        // Reading `t_aux` sets its paths to the cache directory it's read from.
+        let t_aux = util::get_t_aux::<Tree>(cache_path, u64::from(porep_config.sector_size))?;
+        util::persist_t_aux::<Tree>(&t_aux, cache_path)?;
+    }
+
+    info!("import_sector_bundle:finish");
+    Ok(manifest)
+}
@@ -1,7 +1,6 @@
 use std::fs::File;
 use std::path::Path;
 
-use anyhow::Result;
 use filecoin_hashers::{Domain, Hasher};
 use rand::{thread_rng, Rng};
 use storage_proofs_core::merkle::MerkleTreeTrait;
@@ -10,6 +9,7 @@ use storage_proofs_porep::stacked::StackedDrg;
 use crate::{
     api::util,
     constants::DefaultPieceHasher,
+    error::Result,
     types::{Commitment, PoRepConfig},
 };
 
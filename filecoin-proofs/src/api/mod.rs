@@ -3,15 +3,15 @@ use std::fs::{File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
 
-use anyhow::{ensure, Context, Result};
+use anyhow::Context;
 use filecoin_hashers::Hasher;
-use fr32::{write_unpadded, Fr32Reader};
+use fr32::{Fr32Reader, Fr32UnpadReader};
 use log::{info, trace};
 use memmap2::MmapOptions;
 use merkletree::store::{DiskStore, LevelCacheStore, StoreConfig};
 use storage_proofs_core::{
     cache_key::CacheKey,
-    measurements::{measure_op, Operation},
+    measurements::{measure_op_with, OpDetails, Operation},
     merkle::get_base_tree_count,
     pieces::generate_piece_commitment_bytes_from_source,
     sector::SectorId,
@@ -25,6 +25,8 @@ use crate::{
         DefaultBinaryTree, DefaultOctTree, DefaultPieceDomain, DefaultPieceHasher,
         MINIMUM_RESERVED_BYTES_FOR_PIECE_IN_FULLY_ALIGNED_SECTOR as MINIMUM_PIECE_SIZE,
     },
+    error::{ensure_cache_file, ensure_input, Result},
+    parallel_commitment::{parallel_piece_commitment, PieceCommitmentConfig},
     parameters::public_params,
     pieces::{get_piece_alignment, sum_piece_bytes_with_alignment},
     types::{
@@ -33,17 +35,39 @@ use crate::{
     },
 };
 
+#[cfg(feature = "async")]
+mod async_api;
+mod bundle;
 mod fake_seal;
+mod piece_inclusion;
+mod post_batch;
 mod post_util;
+mod preflight;
+mod repair;
+mod resources;
 mod seal;
+mod sector_cache;
+mod sector_check;
+mod storage;
 mod update;
 mod util;
 mod window_post;
 mod winning_post;
 
+#[cfg(feature = "async")]
+pub use async_api::*;
+pub use bundle::*;
 pub use fake_seal::*;
+pub use piece_inclusion::*;
+pub use post_batch::*;
 pub use post_util::*;
+pub use preflight::*;
+pub use repair::*;
+pub use resources::*;
 pub use seal::*;
+pub use sector_cache::*;
+pub use sector_check::*;
+pub use storage::*;
 pub use update::*;
 pub use util::*;
 pub use window_post::*;
@@ -61,7 +85,7 @@ pub fn clear_cache<Tree>(cache_dir: &Path) -> Result<()> {
 
     info!("clear_cache:finish");
 
-    result
+    Ok(result?)
 }
 
 // TODO vmx 2023-09-26: The `Tree` generic is not needed, it's only there in order to not breaking
@@ -91,7 +115,7 @@ pub fn clear_layer_data<Tree>(cache_dir: &Path) -> Result<()> {
 
     info!("clear_layer_data:finish");
 
-    result
+    Ok(result?)
 }
 
 // TODO vmx 2023-09-26: The `Tree` generic is not needed, it's only there in order to not breaking
@@ -104,7 +128,7 @@ pub fn clear_synthetic_proofs<Tree>(cache_dir: &Path) -> Result<()> {
 
     info!("clear_synthetic_proofs:finish");
 
-    result
+    Ok(result?)
 }
 
 /// Unseals the sector at `sealed_path` and returns the bytes for a piece
@@ -198,7 +222,7 @@ where
     Tree: 'static + MerkleTreeTrait,
 {
     info!("unseal_range:start");
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure_input!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
 
     let comm_d =
         as_safe_commitment::<<DefaultPieceHasher as Hasher>::Domain, _>(&comm_d, "comm_d")?;
@@ -265,7 +289,7 @@ where
     Tree: 'static + MerkleTreeTrait,
 {
     info!("unseal_range_mapped:start");
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure_input!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
 
     let comm_d =
         as_safe_commitment::<<DefaultPieceHasher as Hasher>::Domain, _>(&comm_d, "comm_d")?;
@@ -349,13 +373,14 @@ where
     let end = start + usize::from(num_bytes_padded);
     let unsealed = &data[start..end];
 
-    // If the call to `extract_range` was successful, the `unsealed` vector must
-    // have a length which equals `num_bytes_padded`. The byte at its 0-index
-    // byte will be the byte at index `offset_padded` in the sealed sector.
-    let written = write_unpadded(unsealed, &mut unsealed_output, 0, num_bytes.into())
-        .context("write_unpadded failed")?;
+    // The `unsealed` slice has a length which equals `num_bytes_padded`. The byte at its 0-index
+    // byte is the byte at index `offset_padded` in the sealed sector. It's unpadded while it's
+    // streamed into the output.
+    let mut unpadded = Fr32UnpadReader::new(unsealed).take(num_bytes.into());
+    let written =
+        io::copy(&mut unpadded, &mut unsealed_output).context("failed to write unsealed data")?;
 
-    let amount = UnpaddedBytesAmount(written as u64);
+    let amount = UnpaddedBytesAmount(written);
 
     trace!("unseal_range_inner:finish");
     Ok(amount)
@@ -375,25 +400,70 @@ pub fn generate_piece_commitment<T: Read>(
 ) -> Result<PieceInfo> {
     trace!("generate_piece_commitment:start");
 
-    let result = measure_op(Operation::GeneratePieceCommitment, || {
-        ensure_piece_size(piece_size)?;
+    let result = measure_op_with(
+        Operation::GeneratePieceCommitment,
+        OpDetails::default().with_bytes(u64::from(piece_size)),
+        || {
+            ensure_piece_size(piece_size)?;
 
-        // send the source through the preprocessor
-        let source = BufReader::new(source);
-        let mut fr32_reader = Fr32Reader::new(source);
+            // send the source through the preprocessor
+            let source = BufReader::new(source);
+            let mut fr32_reader = Fr32Reader::new(source);
 
-        let commitment = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
-            &mut fr32_reader,
-            PaddedBytesAmount::from(piece_size).into(),
-        )?;
+            let commitment = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+                &mut fr32_reader,
+                PaddedBytesAmount::from(piece_size).into(),
+            )?;
 
-        PieceInfo::new(commitment, piece_size)
-    });
+            PieceInfo::new(commitment, piece_size)
+        },
+    );
 
     trace!("generate_piece_commitment:finish");
     result
 }
 
+/// Generates a piece commitment for the provided byte source on multiple threads.
+///
+/// The result is the same as the one of [`generate_piece_commitment`], which is faster for small
+/// pieces. The number of threads and the memory used for buffering the piece are limited by
+/// `config`.
+///
+/// # Arguments
+///
+/// * `source` - a readable source of unprocessed piece bytes.
+/// * `piece_size` - the number of unpadded user-bytes which can be read from source before EOF.
+/// * `config` - the resources that may be used for generating the commitment.
+pub fn generate_piece_commitment_parallel<T: Read + Send>(
+    source: T,
+    piece_size: UnpaddedBytesAmount,
+    config: &PieceCommitmentConfig,
+) -> Result<PieceInfo> {
+    trace!("generate_piece_commitment_parallel:start");
+
+    let result = measure_op_with(
+        Operation::GeneratePieceCommitment,
+        OpDetails::default().with_bytes(u64::from(piece_size)),
+        || {
+            ensure_piece_size(piece_size)?;
+
+            let source = BufReader::new(source);
+            let fr32_reader = Fr32Reader::new(source);
+
+            let commitment = parallel_piece_commitment(
+                fr32_reader,
+                u64::from(PaddedBytesAmount::from(piece_size)) as usize,
+                config,
+            )?;
+
+            PieceInfo::new(commitment, piece_size)
+        },
+    );
+
+    trace!("generate_piece_commitment_parallel:finish");
+    result
+}
+
 /// Computes a NUL-byte prefix and/or suffix for `source` using the provided
 /// `piece_lengths` and `piece_size` (such that the `source`, after
 /// preprocessing, will occupy a subtree of a merkle tree built using the bytes
@@ -425,58 +495,62 @@ where
 {
     trace!("add_piece:start");
 
-    let result = measure_op(Operation::AddPiece, || {
-        ensure_piece_size(piece_size)?;
+    let result = measure_op_with(
+        Operation::AddPiece,
+        OpDetails::default().with_bytes(u64::from(piece_size)),
+        || {
+            ensure_piece_size(piece_size)?;
 
-        let source = BufReader::new(source);
-        let mut target = BufWriter::new(target);
+            let source = BufReader::new(source);
+            let mut target = BufWriter::new(target);
 
-        let written_bytes = sum_piece_bytes_with_alignment(piece_lengths);
-        let piece_alignment = get_piece_alignment(written_bytes, piece_size);
-        let fr32_reader = Fr32Reader::new(source);
+            let written_bytes = sum_piece_bytes_with_alignment(piece_lengths);
+            let piece_alignment = get_piece_alignment(written_bytes, piece_size);
+            let fr32_reader = Fr32Reader::new(source);
 
-        // write left alignment
-        for _ in 0..usize::from(PaddedBytesAmount::from(piece_alignment.left_bytes)) {
-            target.write_all(&[0u8][..])?;
-        }
+            // write left alignment
+            for _ in 0..usize::from(PaddedBytesAmount::from(piece_alignment.left_bytes)) {
+                target.write_all(&[0u8][..])?;
+            }
 
-        let mut commitment_reader = CommitmentReader::new(fr32_reader);
-        let n = io::copy(&mut commitment_reader, &mut target)
-            .context("failed to write and preprocess bytes")?;
+            let mut commitment_reader = CommitmentReader::new(fr32_reader);
+            let n = io::copy(&mut commitment_reader, &mut target)
+                .context("failed to write and preprocess bytes")?;
 
-        ensure!(n != 0, "add_piece: read 0 bytes before EOF from source");
-        let n = PaddedBytesAmount(n);
-        let n: UnpaddedBytesAmount = n.into();
+            ensure_input!(n != 0, "add_piece: read 0 bytes before EOF from source");
+            let n = PaddedBytesAmount(n);
+            let n: UnpaddedBytesAmount = n.into();
 
-        ensure!(n == piece_size, "add_piece: invalid bytes amount written");
+            ensure_input!(n == piece_size, "add_piece: invalid bytes amount written");
 
-        // write right alignment
-        for _ in 0..usize::from(PaddedBytesAmount::from(piece_alignment.right_bytes)) {
-            target.write_all(&[0u8][..])?;
-        }
+            // write right alignment
+            for _ in 0..usize::from(PaddedBytesAmount::from(piece_alignment.right_bytes)) {
+                target.write_all(&[0u8][..])?;
+            }
 
-        let commitment = commitment_reader.finish()?;
-        let mut comm = [0u8; 32];
-        comm.copy_from_slice(commitment.as_ref());
+            let commitment = commitment_reader.finish()?;
+            let mut comm = [0u8; 32];
+            comm.copy_from_slice(commitment.as_ref());
 
-        let written = piece_alignment.left_bytes + piece_alignment.right_bytes + piece_size;
+            let written = piece_alignment.left_bytes + piece_alignment.right_bytes + piece_size;
 
-        Ok((PieceInfo::new(comm, n)?, written))
-    });
+            Ok((PieceInfo::new(comm, n)?, written))
+        },
+    );
 
     trace!("add_piece:finish");
     result
 }
 
 fn ensure_piece_size(piece_size: UnpaddedBytesAmount) -> Result<()> {
-    ensure!(
+    ensure_input!(
         piece_size >= UnpaddedBytesAmount(MINIMUM_PIECE_SIZE),
         "Piece must be at least {} bytes",
         MINIMUM_PIECE_SIZE
     );
 
     let padded_piece_size: PaddedBytesAmount = piece_size.into();
-    ensure!(
+    ensure_input!(
         u64::from(padded_piece_size).is_power_of_two(),
         "Bit-padded piece size must be a power of 2 ({:?})",
         padded_piece_size,
@@ -512,7 +586,7 @@ where
 }
 
 // Verifies if a DiskStore specified by a config (or set of 'required_configs' is consistent).
-fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) -> Result<()> {
+fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) -> anyhow::Result<()> {
     let store_path = StoreConfig::data_path(&config.path, &config.id);
     if !Path::new(&store_path).exists() {
         // Configs may have split due to sector size, so we need to
@@ -544,10 +618,10 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
             }
         }
 
-        ensure!(
+        ensure_cache_file!(
             configs.len() == required_configs,
-            "Missing store file (or associated split paths): {}",
-            store_path.display()
+            &store_path,
+            "Missing store file (or associated split paths)"
         );
 
         let store_len = config.size.expect("disk store size not configured");
@@ -558,10 +632,10 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
                 &data_path,
                 std::fs::metadata(&data_path)?.len()
             );
-            ensure!(
+            ensure_cache_file!(
                 DiskStore::<DefaultPieceDomain>::is_consistent(store_len, arity, config,)?,
-                "Store is inconsistent: {:?}",
-                &data_path
+                &data_path,
+                "Store is inconsistent"
             );
         }
     } else {
@@ -570,14 +644,14 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
             &store_path,
             std::fs::metadata(&store_path)?.len()
         );
-        ensure!(
+        ensure_cache_file!(
             DiskStore::<DefaultPieceDomain>::is_consistent(
                 config.size.expect("disk store size not configured"),
                 arity,
                 config,
             )?,
-            "Store is inconsistent: {:?}",
-            store_path
+            &store_path,
+            "Store is inconsistent"
         );
     }
 
@@ -585,7 +659,7 @@ fn verify_store(config: &StoreConfig, arity: usize, required_configs: usize) ->
 }
 
 // Verifies if a LevelCacheStore specified by a config is consistent.
-fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Result<()> {
+fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> anyhow::Result<()> {
     let store_path = StoreConfig::data_path(&config.path, &config.id);
     if !Path::new(&store_path).exists() {
         let required_configs = get_base_tree_count::<Tree>();
@@ -619,10 +693,10 @@ fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Resu
             }
         }
 
-        ensure!(
+        ensure_cache_file!(
             configs.len() == required_configs,
-            "Missing store file (or associated split paths): {}",
-            store_path.display()
+            &store_path,
+            "Missing store file (or associated split paths)"
         );
 
         let store_len = config.size.expect("disk store size not configured");
@@ -633,14 +707,14 @@ fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Resu
                 &data_path,
                 std::fs::metadata(&data_path)?.len()
             );
-            ensure!(
+            ensure_cache_file!(
                 LevelCacheStore::<DefaultPieceDomain, File>::is_consistent(
                     store_len,
                     Tree::Arity::to_usize(),
                     config,
                 )?,
-                "Store is inconsistent: {:?}",
-                &data_path
+                &data_path,
+                "Store is inconsistent"
             );
         }
     } else {
@@ -649,14 +723,14 @@ fn verify_level_cache_store<Tree: MerkleTreeTrait>(config: &StoreConfig) -> Resu
             &store_path,
             std::fs::metadata(&store_path)?.len()
         );
-        ensure!(
+        ensure_cache_file!(
             LevelCacheStore::<DefaultPieceDomain, File>::is_consistent(
                 config.size.expect("disk store size not configured"),
                 Tree::Arity::to_usize(),
                 config,
             )?,
-            "Store is inconsistent: {:?}",
-            store_path
+            &store_path,
+            "Store is inconsistent"
         );
     }
 
@@ -675,7 +749,7 @@ where
 {
     info!("validate_cache_for_precommit_phase2:start");
 
-    ensure!(
+    ensure_input!(
         replica_path.as_ref().exists(),
         "Missing replica: {}",
         replica_path.as_ref().to_path_buf().display()
@@ -703,7 +777,7 @@ where
     );
 
     info!("validate_cache_for_precommit_phase2:finish");
-    result
+    Ok(result?)
 }
 
 // Checks for the existence of the replica data and t_aux, which in
@@ -720,14 +794,14 @@ where
     info!("validate_cache_for_commit:start");
 
     // Verify that the replica exists and is not empty.
-    ensure!(
+    ensure_input!(
         replica_path.as_ref().exists(),
         "Missing replica: {}",
         replica_path.as_ref().to_path_buf().display()
     );
 
     let metadata = File::open(&replica_path)?.metadata()?;
-    ensure!(
+    ensure_input!(
         metadata.len() > 0,
         "Replica {} exists, but is empty!",
         replica_path.as_ref().to_path_buf().display()
@@ -0,0 +1,262 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use anyhow::Context;
+use log::{info, trace};
+use merkletree::{
+    merkle::get_merkle_tree_len,
+    store::{DiskStore, Store, StoreConfig},
+};
+use storage_proofs_core::{cache_key::CacheKey, error::Error, util::NODE_SIZE};
+
+use crate::{
+    constants::DefaultPieceDomain,
+    error::{ensure_input, Result},
+    pieces::{get_piece_offsets, piece_hash},
+    types::{
+        Commitment, PaddedBytesAmount, PieceInclusionProof, PieceInfo, SectorSize,
+        UnpaddedByteIndex, UnpaddedBytesAmount, BINARY_ARITY,
+    },
+};
+
+/// A node of tree-d, identified by its level (the leaves are at level 0) and its index within
+/// that level.
+type NodePosition = (usize, usize);
+
+/// Returns the height of a binary tree with `leafs` leaves.
+fn tree_height(leafs: u64) -> usize {
+    leafs.trailing_zeros() as usize
+}
+
+/// Returns the position of the piece commitment within tree-d.
+fn piece_node(piece_size: PaddedBytesAmount, offset: PaddedBytesAmount) -> NodePosition {
+    let level = tree_height(u64::from(piece_size) / NODE_SIZE as u64);
+    let index = u64::from(offset) / u64::from(piece_size);
+    (level, index as usize)
+}
+
+/// Returns the positions of the siblings on the path from `node` up to the root.
+fn sibling_nodes(node: NodePosition, height: usize) -> impl Iterator<Item = NodePosition> {
+    let (level, index) = node;
+    (level..height).map(move |l| (l, (index >> (l - level)) ^ 1))
+}
+
+/// Returns the positions of the piece commitments within tree-d of a sector.
+fn piece_nodes(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Result<Vec<NodePosition>> {
+    let offsets = get_piece_offsets(sector_size, piece_infos)?;
+    Ok(piece_infos
+        .iter()
+        .zip(offsets)
+        .map(|(piece_info, offset)| {
+            piece_node(
+                piece_info.size.into(),
+                UnpaddedBytesAmount::from(offset).into(),
+            )
+        })
+        .collect())
+}
+
+/// Builds the inclusion proofs from the nodes of tree-d and makes sure that they verify against
+/// its root.
+fn build_piece_inclusion_proofs<F>(
+    sector_size: SectorSize,
+    piece_infos: &[PieceInfo],
+    mut read_node: F,
+) -> Result<Vec<PieceInclusionProof>>
+where
+    F: FnMut(NodePosition) -> Result<Commitment>,
+{
+    let height = tree_height(u64::from(sector_size) / NODE_SIZE as u64);
+    let comm_d = read_node((height, 0))?;
+    let offsets = get_piece_offsets(sector_size, piece_infos)?;
+
+    piece_infos
+        .iter()
+        .zip(offsets)
+        .map(|(piece_info, offset)| {
+            let node = piece_node(
+                piece_info.size.into(),
+                UnpaddedBytesAmount::from(offset).into(),
+            );
+            ensure_input!(
+                read_node(node)? == piece_info.commitment,
+                "{}",
+                Error::BadPieceCommitment
+            );
+
+            let path = sibling_nodes(node, height)
+                .map(&mut read_node)
+                .collect::<Result<Vec<_>>>()?;
+            let proof = PieceInclusionProof { path };
+            ensure_input!(
+                verify_piece_inclusion_proof(
+                    sector_size,
+                    &comm_d,
+                    &piece_info.commitment,
+                    piece_info.size,
+                    offset,
+                    &proof,
+                )?,
+                "{}",
+                Error::BadPieceCommitment
+            );
+
+            Ok(proof)
+        })
+        .collect()
+}
+
+/// Generates inclusion proofs for all pieces of a sector from the tree-d stored in `cache_path`.
+///
+/// The proofs are returned in the order of `piece_infos`. The pieces are expected at the offsets
+/// returned by [`get_piece_offsets`], which is how they are laid out by `add_piece`.
+pub fn generate_piece_inclusion_proofs<P: AsRef<Path>>(
+    sector_size: SectorSize,
+    cache_path: P,
+    piece_infos: &[PieceInfo],
+) -> Result<Vec<PieceInclusionProof>> {
+    info!("generate_piece_inclusion_proofs:start");
+
+    let leafs = u64::from(sector_size) as usize / NODE_SIZE;
+    let tree_size = get_merkle_tree_len(leafs, BINARY_ARITY)?;
+    let config = StoreConfig {
+        path: cache_path.as_ref().to_path_buf(),
+        id: CacheKey::CommDTree.to_string(),
+        size: Some(tree_size),
+        rows_to_discard: 0,
+    };
+    let store: DiskStore<DefaultPieceDomain> =
+        DiskStore::new_from_disk(tree_size, BINARY_ARITY, &config)
+            .context("failed to open tree-d")?;
+
+    let proofs = build_piece_inclusion_proofs(sector_size, piece_infos, |(level, index)| {
+        // The levels are stored one after another, starting with the leaves.
+        let level_start: usize = (0..level).map(|l| leafs >> l).sum();
+        let node = store.read_at(level_start + index)?;
+        let mut commitment = [0; 32];
+        commitment.copy_from_slice(node.as_ref());
+        Ok(commitment)
+    })?;
+
+    info!("generate_piece_inclusion_proofs:finish");
+    Ok(proofs)
+}
+
+/// Generates inclusion proofs for all pieces of a sector from its unsealed data.
+///
+/// `unsealed` must contain the whole (bit padded) unsealed sector. It's read exactly once, without
+/// building tree-d on disk or in memory. See [`generate_piece_inclusion_proofs`] for details.
+pub fn generate_piece_inclusion_proofs_from_unsealed<R: Read>(
+    sector_size: SectorSize,
+    unsealed: R,
+    piece_infos: &[PieceInfo],
+) -> Result<Vec<PieceInclusionProof>> {
+    info!("generate_piece_inclusion_proofs_from_unsealed:start");
+
+    let leafs = u64::from(sector_size) / NODE_SIZE as u64;
+    let height = tree_height(leafs);
+    let wanted: HashSet<NodePosition> = piece_nodes(sector_size, piece_infos)?
+        .into_iter()
+        .flat_map(|node| std::iter::once(node).chain(sibling_nodes(node, height)))
+        .chain(std::iter::once((height, 0)))
+        .collect();
+    trace!("collecting {} nodes of tree-d", wanted.len());
+
+    // Tree-d is built bottom up, keeping only the nodes that don't have a sibling yet.
+    let mut nodes = HashMap::with_capacity(wanted.len());
+    let mut pending: Vec<(usize, Commitment)> = Vec::with_capacity(height + 1);
+    let mut level_counts = vec![0usize; height + 1];
+    let mut reader = BufReader::with_capacity(1 << 20, unsealed);
+    for _ in 0..leafs {
+        let mut node = [0u8; NODE_SIZE];
+        reader
+            .read_exact(&mut node)
+            .context("unsealed data is smaller than the sector")?;
+
+        let mut level = 0;
+        loop {
+            let position = (level, level_counts[level]);
+            level_counts[level] += 1;
+            if wanted.contains(&position) {
+                nodes.insert(position, node);
+            }
+
+            match pending.last() {
+                Some((pending_level, left)) if *pending_level == level => {
+                    let parent = piece_hash(left, &node);
+                    node.copy_from_slice(parent.as_ref());
+                    pending.pop();
+                    level += 1;
+                }
+                _ => {
+                    pending.push((level, node));
+                    break;
+                }
+            }
+        }
+    }
+
+    let proofs = build_piece_inclusion_proofs(sector_size, piece_infos, |position| {
+        Ok(nodes
+            .get(&position)
+            .copied()
+            .with_context(|| format!("missing node {:?} of tree-d", position))?)
+    })?;
+
+    info!("generate_piece_inclusion_proofs_from_unsealed:finish");
+    Ok(proofs)
+}
+
+/// Verifies that the piece with commitment `comm_p` and size `piece_size` is stored at `offset`
+/// within the sector with the given `comm_d`.
+///
+/// Pieces are always aligned to their size, it returns false for unaligned offsets.
+pub fn verify_piece_inclusion_proof(
+    sector_size: SectorSize,
+    comm_d: &Commitment,
+    comm_p: &Commitment,
+    piece_size: UnpaddedBytesAmount,
+    offset: UnpaddedByteIndex,
+    proof: &PieceInclusionProof,
+) -> Result<bool> {
+    let padded_piece_size = PaddedBytesAmount::from(piece_size);
+    ensure_input!(
+        u64::from(padded_piece_size).is_power_of_two()
+            && u64::from(padded_piece_size) >= 2 * NODE_SIZE as u64,
+        "Piece size ({:?}) must be a power of 2.",
+        padded_piece_size
+    );
+    ensure_input!(
+        padded_piece_size <= PaddedBytesAmount::from(sector_size),
+        "Piece is larger than sector."
+    );
+
+    // Only offsets at a multiple of the piece size can be represented as unpadded bytes.
+    if u64::from(offset) % u64::from(piece_size) != 0 {
+        return Ok(false);
+    }
+    let padded_offset = PaddedBytesAmount::from(UnpaddedBytesAmount::from(offset));
+    if padded_offset >= PaddedBytesAmount::from(sector_size) {
+        return Ok(false);
+    }
+
+    let height = tree_height(u64::from(sector_size) / NODE_SIZE as u64);
+    let (level, mut index) = piece_node(padded_piece_size, padded_offset);
+    if proof.path.len() != height - level {
+        return Ok(false);
+    }
+
+    let mut node = *comm_p;
+    for sibling in &proof.path {
+        let parent = if index & 1 == 0 {
+            piece_hash(&node, sibling)
+        } else {
+            piece_hash(sibling, &node)
+        };
+        node.copy_from_slice(parent.as_ref());
+        index >>= 1;
+    }
+
+    Ok(&node == comm_d)
+}
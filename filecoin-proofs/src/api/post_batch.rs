@@ -0,0 +1,194 @@
+use anyhow::Context;
+use bellperson::groth16::{self, verify_proofs_batch};
+use blstrs::{Bls12, Scalar as Fr};
+use filecoin_hashers::Hasher;
+use log::{info, warn};
+use rand::rngs::OsRng;
+use rayon::prelude::*;
+use storage_proofs_core::{
+    compound_proof::{self, CompoundProof},
+    merkle::MerkleTreeTrait,
+    multi_proof::MultiProof,
+    proof::ProofScheme,
+};
+use storage_proofs_post::fallback::{self, FallbackPoSt, FallbackPoStCompound};
+
+use crate::{
+    api::{
+        get_partitions_for_window_post,
+        window_post::{window_post_public_inputs, window_post_public_params},
+        winning_post::{winning_post_public_inputs, winning_post_public_params},
+    },
+    caches::{get_post_verifying_key, Bls12PreparedVerifyingKey},
+    error::{ensure_input, Result},
+    types::{PoStConfig, WindowPoStBatchItem, WinningPoStBatchItem},
+    PoStType,
+};
+
+/// The circuit proofs of a single PoSt together with their public inputs.
+struct PreparedPoSt {
+    proofs: Vec<groth16::Proof<Bls12>>,
+    inputs: Vec<Vec<Fr>>,
+}
+
+/// Verifies many Window PoSts of the same [`PoStConfig`] at once.
+///
+/// The circuit proofs of all partitions of all items are checked with a single randomized batch
+/// verification. If that fails, each item is verified on its own. Returns whether each item is
+/// valid, in the order of `items`. Malformed items, e.g. with proof bytes that don't match the
+/// number of partitions, are invalid.
+pub fn verify_window_post_batch<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    items: &[WindowPoStBatchItem<'_>],
+) -> Result<Vec<bool>> {
+    info!("verify_window_post_batch:start");
+    ensure_input!(
+        post_config.typ == PoStType::Window,
+        "invalid post config type"
+    );
+
+    let verifying_key = get_post_verifying_key::<Tree>(post_config)?;
+    let prepared: Vec<_> = items
+        .par_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let prepare = || -> Result<Option<PreparedPoSt>> {
+                let partitions = get_partitions_for_window_post(item.replicas.len(), post_config);
+                let pub_params = window_post_public_params::<Tree>(post_config, partitions)?;
+                let pub_inputs = window_post_public_inputs::<Tree>(
+                    item.randomness,
+                    item.replicas,
+                    item.prover_id,
+                )?;
+                let multi_proof =
+                    MultiProof::new_from_bytes(partitions, item.proof, &verifying_key)?;
+                prepare_post(post_config, &pub_params, &pub_inputs, multi_proof)
+            };
+            prepare().unwrap_or_else(|err| {
+                warn!("window post {} is malformed: {:?}", index, err);
+                None
+            })
+        })
+        .collect();
+
+    let valid = verify_prepared(&verifying_key, &prepared)?;
+
+    info!("verify_window_post_batch:finish");
+    Ok(valid)
+}
+
+/// Verifies many Winning PoSts of the same [`PoStConfig`] at once.
+///
+/// See [`verify_window_post_batch`] for how the batch is verified.
+pub fn verify_winning_post_batch<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    items: &[WinningPoStBatchItem<'_>],
+) -> Result<Vec<bool>> {
+    info!("verify_winning_post_batch:start");
+    ensure_input!(
+        post_config.typ == PoStType::Winning,
+        "invalid post config type"
+    );
+
+    let verifying_key = get_post_verifying_key::<Tree>(post_config)?;
+    let prepared: Vec<_> = items
+        .par_iter()
+        .enumerate()
+        .map(|(index, item)| {
+            let prepare = || -> Result<Option<PreparedPoSt>> {
+                ensure_input!(
+                    post_config.sector_count == item.replicas.len(),
+                    "invalid amount of replicas provided"
+                );
+                let pub_params = winning_post_public_params::<Tree>(post_config)?;
+                let pub_inputs = winning_post_public_inputs::<Tree>(
+                    item.randomness,
+                    item.replicas,
+                    item.prover_id,
+                    pub_params.vanilla_params.sector_count,
+                )?;
+                let multi_proof = MultiProof::new_from_reader(None, item.proof, &verifying_key)?;
+                prepare_post(post_config, &pub_params, &pub_inputs, multi_proof)
+            };
+            prepare().unwrap_or_else(|err| {
+                warn!("winning post {} is malformed: {:?}", index, err);
+                None
+            })
+        })
+        .collect();
+
+    let valid = verify_prepared(&verifying_key, &prepared)?;
+
+    info!("verify_winning_post_batch:finish");
+    Ok(valid)
+}
+
+/// Generates the public inputs of all partitions. Returns `None` if the proof can't be valid,
+/// like [`CompoundProof::verify`] does.
+fn prepare_post<'a, Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    pub_params: &compound_proof::PublicParams<'a, FallbackPoSt<'a, Tree>>,
+    pub_inputs: &fallback::PublicInputs<<Tree::Hasher as Hasher>::Domain>,
+    multi_proof: MultiProof<'_>,
+) -> Result<Option<PreparedPoSt>> {
+    let partition_count = FallbackPoStCompound::<Tree>::partition_count(pub_params);
+    if multi_proof.circuit_proofs.len() != partition_count {
+        return Ok(None);
+    }
+    let requirements = fallback::ChallengeRequirements {
+        minimum_challenge_count: post_config.challenge_count * post_config.sector_count,
+    };
+    if !FallbackPoSt::<Tree>::satisfies_requirements(
+        &pub_params.vanilla_params,
+        &requirements,
+        partition_count,
+    ) {
+        return Ok(None);
+    }
+
+    let inputs = (0..partition_count)
+        .map(|k| {
+            FallbackPoStCompound::<Tree>::generate_public_inputs(
+                pub_inputs,
+                &pub_params.vanilla_params,
+                Some(k),
+            )
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(Some(PreparedPoSt {
+        proofs: multi_proof.circuit_proofs,
+        inputs,
+    }))
+}
+
+/// Verifies all prepared proofs in a single batch, falls back to verifying them one by one if
+/// the batch is invalid.
+fn verify_prepared(
+    verifying_key: &Bls12PreparedVerifyingKey,
+    prepared: &[Option<PreparedPoSt>],
+) -> Result<Vec<bool>> {
+    let verify = |posts: &[&PreparedPoSt]| -> Result<bool> {
+        let proofs: Vec<_> = posts.iter().flat_map(|post| post.proofs.iter()).collect();
+        let inputs: Vec<_> = posts
+            .iter()
+            .flat_map(|post| post.inputs.iter().cloned())
+            .collect();
+        let valid = verify_proofs_batch(verifying_key, &mut OsRng, &proofs, &inputs)
+            .context("batch verification failed")?;
+        Ok(valid)
+    };
+
+    let well_formed: Vec<_> = prepared.iter().flatten().collect();
+    if !well_formed.is_empty() && verify(&well_formed)? {
+        return Ok(prepared.iter().map(Option::is_some).collect());
+    }
+
+    prepared
+        .par_iter()
+        .map(|post| match post {
+            Some(post) => verify(&[post]),
+            None => Ok(false),
+        })
+        .collect()
+}
@@ -1,18 +1,26 @@
 use std::collections::BTreeMap;
 
-use anyhow::{anyhow, ensure, Context, Result};
-use filecoin_hashers::Hasher;
-use log::{debug, info};
-use storage_proofs_core::{merkle::MerkleTreeTrait, proof::ProofScheme, sector::SectorId};
+use anyhow::{anyhow, Context};
+use filecoin_hashers::{HashFunction, Hasher};
+use log::{debug, info, warn};
+use storage_proofs_core::{
+    merkle::{MerkleProofTrait, MerkleTreeTrait},
+    proof::ProofScheme,
+    proofs_span,
+    sector::SectorId,
+    settings,
+    util::NODE_SIZE,
+};
 use storage_proofs_post::fallback::{
     self, generate_leaf_challenge, get_challenge_index, FallbackPoSt, SectorProof,
 };
 
 use crate::{
-    api::as_safe_commitment,
+    api::{as_safe_commitment, storage::open_tree_r_last_files},
+    error::{ensure_input, ensure_verified, Result},
     types::{
-        ChallengeSeed, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo, ProverId,
-        VanillaProof,
+        ChallengeSeed, Commitment, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo,
+        ProverId, VanillaProof,
     },
     PartitionSnarkProof, PoStType, SnarkProof, SINGLE_PARTITION_PROOF_LEN,
 };
@@ -26,7 +34,7 @@ pub fn generate_fallback_sector_challenges<Tree: 'static + MerkleTreeTrait>(
     _prover_id: ProverId,
 ) -> Result<BTreeMap<SectorId, Vec<u64>>> {
     info!("generate_sector_challenges:start");
-    ensure!(
+    ensure_input!(
         post_config.typ == PoStType::Window || post_config.typ == PoStType::Winning,
         "invalid post config type"
     );
@@ -91,6 +99,13 @@ pub fn generate_fallback_sector_challenges<Tree: 'static + MerkleTreeTrait>(
 
 /// Generates a single vanilla proof required for either Window proof-of-spacetime
 /// or Winning proof-of-spacetime.
+///
+/// For Winning PoSt the data the proof needs is prefetched with concurrent reads if
+/// `winning_post_max_outstanding_reads` is set, by default it's read while the proof is generated.
+/// Replicas that are kept in a [`SectorStorage`] are always read that way, only the challenged
+/// replica segments and tree-r-last nodes are read from the storage.
+///
+/// [`SectorStorage`]: storage_proofs_core::storage::SectorStorage
 pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
     post_config: &PoStConfig,
     sector_id: SectorId,
@@ -98,7 +113,55 @@ pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
     challenges: &[u64],
 ) -> Result<FallbackPoStSectorProof<Tree>> {
     info!("generate_single_vanilla_proof:start: {:?}", sector_id);
+    let _span = proofs_span!(
+        "generate_single_vanilla_proof",
+        sector_id = u64::from(sector_id)
+    );
+
+    let comm_r = replica.safe_comm_r().with_context(|| {
+        format!(
+            "generate_single_vanilla_poof: safe_comm_r failed: {:?}",
+            sector_id
+        )
+    })?;
+
+    let max_outstanding_reads = settings::current().winning_post_max_outstanding_reads;
+    let vanilla_proof = if post_config.typ == PoStType::Winning && max_outstanding_reads > 0 {
+        prefetched_vanilla_proof(
+            post_config,
+            sector_id,
+            replica,
+            challenges,
+            max_outstanding_reads,
+        )?
+    } else if replica.storage().is_some() {
+        prefetched_vanilla_proof(
+            post_config,
+            sector_id,
+            replica,
+            challenges,
+            max_outstanding_reads.max(1),
+        )?
+    } else {
+        tree_vanilla_proof(post_config, sector_id, replica, challenges)?
+    };
+
+    info!("generate_single_vanilla_proof:finish: {:?}", sector_id);
+
+    Ok(FallbackPoStSectorProof {
+        sector_id,
+        comm_r,
+        vanilla_proof,
+    })
+}
 
+/// Generates the vanilla proof by reading through the merkle tree of the replica.
+fn tree_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    sector_id: SectorId,
+    replica: &PrivateReplicaInfo<Tree>,
+    challenges: &[u64],
+) -> Result<VanillaProof<Tree>> {
     let tree = &replica
         .merkle_tree(post_config.sector_size)
         .with_context(|| {
@@ -107,12 +170,6 @@ pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
                 sector_id
             )
         })?;
-    let comm_r = replica.safe_comm_r().with_context(|| {
-        format!(
-            "generate_single_vanilla_poof: safe_comm_r failed: {:?}",
-            sector_id
-        )
-    })?;
     let comm_c = replica.safe_comm_c();
     let comm_r_last = replica.safe_comm_r_last();
 
@@ -134,13 +191,131 @@ pub fn generate_single_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
             )
         })?;
 
-    info!("generate_single_vanilla_proof:finish: {:?}", sector_id);
+    Ok(vanilla_proof)
+}
 
-    Ok(FallbackPoStSectorProof {
+/// Generates the vanilla proof from the replica segments and tree-r-last nodes of the
+/// challenges, which are read upfront with at most `max_outstanding_reads` reads in flight.
+fn prefetched_vanilla_proof<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    sector_id: SectorId,
+    replica: &PrivateReplicaInfo<Tree>,
+    challenges: &[u64],
+    max_outstanding_reads: usize,
+) -> Result<VanillaProof<Tree>> {
+    let layout = fallback::TreeRLastLayout::new::<Tree>(u64::from(post_config.sector_size))?;
+    let reads = layout.plan_reads(challenges)?;
+    let (replica_file, tree_r_last) = open_tree_r_last_files(replica, post_config.sector_size)?;
+    debug!(
+        "prefetching {} reads for sector {:?}",
+        reads.len(),
+        sector_id
+    );
+    let prefetched =
+        fallback::prefetch_reads(&*replica_file, &tree_r_last, &reads, max_outstanding_reads)
+            .with_context(|| {
+                format!(
+                    "generate_single_vanilla_proof: prefetch_reads failed: {:?}",
+                    sector_id
+                )
+            })?;
+
+    let vanilla_proof = fallback::vanilla_proof_from_reads::<Tree>(
         sector_id,
-        comm_r,
-        vanilla_proof,
-    })
+        &layout,
+        &prefetched,
+        replica.safe_comm_c(),
+        replica.safe_comm_r_last(),
+        challenges,
+    )
+    .with_context(|| {
+        format!(
+            "generate_single_vanilla_proof: vanilla_proof failed: {:?}",
+            sector_id
+        )
+    })?;
+
+    Ok(vanilla_proof)
+}
+
+/// Verifies a single vanilla proof, as returned by [`generate_single_vanilla_proof`], before it's
+/// used for generating a SNARK.
+///
+/// The challenges are derived from `randomness` and `pub_sectors`, which are all sectors of the
+/// PoSt in the order that was used for [`generate_fallback_sector_challenges`]. It checks that
+/// comm_c and comm_r_last of the proof lead to `comm_r` and that the inclusion proofs are valid for
+/// those challenges.
+pub fn verify_vanilla_post_sector_proof<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    pub_sectors: &[SectorId],
+    prover_id: ProverId,
+    comm_r: &Commitment,
+    proof: &FallbackPoStSectorProof<Tree>,
+) -> Result<bool> {
+    info!(
+        "verify_vanilla_post_sector_proof:start: {:?}",
+        proof.sector_id
+    );
+    ensure_input!(
+        pub_sectors.contains(&proof.sector_id),
+        "sector {:?} is not part of the PoSt",
+        proof.sector_id
+    );
+    let comm_r_safe: <Tree::Hasher as Hasher>::Domain = as_safe_commitment(comm_r, "comm_r")?;
+
+    let challenges = generate_fallback_sector_challenges::<Tree>(
+        post_config,
+        randomness,
+        pub_sectors,
+        prover_id,
+    )?;
+    let is_valid = sector_proof_is_valid(
+        post_config,
+        &comm_r_safe,
+        &challenges[&proof.sector_id],
+        proof,
+    );
+    if !is_valid {
+        warn!("invalid vanilla proof for sector {:?}", proof.sector_id);
+    }
+
+    info!(
+        "verify_vanilla_post_sector_proof:finish: {:?}",
+        proof.sector_id
+    );
+    Ok(is_valid)
+}
+
+/// Checks that comm_c and comm_r_last of the proof lead to `comm_r` and that the inclusion proofs
+/// are valid for `challenges`.
+pub(crate) fn sector_proof_is_valid<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    comm_r: &<Tree::Hasher as Hasher>::Domain,
+    challenges: &[u64],
+    proof: &FallbackPoStSectorProof<Tree>,
+) -> bool {
+    match &proof.vanilla_proof.sectors[..] {
+        [sector_proof] => {
+            let comm_r_last = sector_proof.comm_r_last;
+            let sector_leafs = u64::from(post_config.sector_size) as usize / NODE_SIZE;
+            let hashed_comm_r =
+                <Tree::Hasher as Hasher>::Function::hash2(&sector_proof.comm_c, &comm_r_last);
+
+            proof.comm_r == *comm_r
+                && hashed_comm_r == *comm_r
+                && sector_proof.inclusion_proofs.len() == challenges.len()
+                && sector_proof.inclusion_proofs.iter().zip(challenges).all(
+                    |(inclusion_proof, challenge)| {
+                        inclusion_proof.root() == comm_r_last
+                            && inclusion_proof.path().len()
+                                == inclusion_proof.expected_len(sector_leafs)
+                            && inclusion_proof.validate(*challenge as usize)
+                    },
+                )
+        }
+        _ => false,
+    }
 }
 
 // Partition a flat vector of vanilla sector proofs.  The post_config
@@ -154,7 +329,7 @@ pub fn partition_vanilla_proofs<Tree: MerkleTreeTrait>(
     vanilla_proofs: &[FallbackPoStSectorProof<Tree>],
 ) -> Result<Vec<VanillaProof<Tree>>> {
     info!("partition_vanilla_proofs:start");
-    ensure!(
+    ensure_input!(
         post_config.typ == PoStType::Window || post_config.typ == PoStType::Winning,
         "invalid post config type"
     );
@@ -162,7 +337,7 @@ pub fn partition_vanilla_proofs<Tree: MerkleTreeTrait>(
     let num_sectors_per_chunk = pub_params.sector_count;
     let num_sectors = pub_inputs.sectors.len();
 
-    ensure!(
+    ensure_input!(
         num_sectors <= partition_count * num_sectors_per_chunk,
         "cannot prove the provided number of sectors: {} > {} * {}",
         num_sectors,
@@ -206,7 +381,7 @@ pub fn partition_vanilla_proofs<Tree: MerkleTreeTrait>(
 
     info!("partition_vanilla_proofs:finish");
 
-    ensure!(
+    ensure_verified!(
         FallbackPoSt::<Tree>::verify_all_partitions(pub_params, pub_inputs, &partition_proofs)?,
         "partitioned vanilla proofs failed to verify"
     );
@@ -234,18 +409,18 @@ pub fn single_partition_vanilla_proofs<Tree: MerkleTreeTrait>(
     vanilla_proofs: &[FallbackPoStSectorProof<Tree>],
 ) -> Result<VanillaProof<Tree>> {
     info!("single_partition_vanilla_proofs:start");
-    ensure!(pub_inputs.k.is_some(), "must have a partition index");
+    ensure_input!(pub_inputs.k.is_some(), "must have a partition index");
     let partition_index = pub_inputs.k.expect("prechecked");
 
     debug!("processing partition: {}", partition_index);
-    ensure!(
+    ensure_input!(
         post_config.typ == PoStType::Window || post_config.typ == PoStType::Winning,
         "invalid post config type"
     );
 
     let num_sectors_per_chunk = pub_params.sector_count;
     let num_sectors = pub_inputs.sectors.len();
-    ensure!(
+    ensure_input!(
         num_sectors <= num_sectors_per_chunk,
         "can only prove a single partition"
     );
@@ -282,17 +457,17 @@ pub fn single_partition_vanilla_proofs<Tree: MerkleTreeTrait>(
         PoStType::Winning => {
             let sectors_chunk = vanilla_proofs;
             // Sanity check incoming structure
-            ensure!(
+            ensure_input!(
                 sectors_chunk.len() == 1,
                 "Invalid sector chunk for Winning PoSt"
             );
-            ensure!(
+            ensure_input!(
                 sectors_chunk[0].vanilla_proof.sectors.len() == 1,
                 "Invalid sector count for Winning PoSt chunk"
             );
 
             // Winning post sector_count is winning post challenges per sector
-            ensure!(
+            ensure_input!(
                 post_config.sector_count
                     == sectors_chunk[partition_index].vanilla_proof.sectors.len(),
                 "invalid number of sector proofs for Winning PoSt"
@@ -318,7 +493,7 @@ pub fn single_partition_vanilla_proofs<Tree: MerkleTreeTrait>(
             }
 
             // Winning post Challenge count is the total winning post challenges
-            ensure!(
+            ensure_input!(
                 sector_proofs.len() == post_config.challenge_count,
                 "invalid number of partition proofs based on Winning PoSt challenges"
             );
@@ -331,7 +506,7 @@ pub fn single_partition_vanilla_proofs<Tree: MerkleTreeTrait>(
 
     info!("single_partition_vanilla_proofs:finish");
 
-    ensure!(
+    ensure_verified!(
         FallbackPoSt::<Tree>::verify(pub_params, pub_inputs, &partition_proof)?,
         "partitioned vanilla proofs failed to verify"
     );
@@ -0,0 +1,470 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use fs2::available_space;
+use log::{info, trace};
+use storage_proofs_core::{
+    error::Error as CoreError,
+    merkle::MerkleTreeTrait,
+    parameter_cache::{get_parameter_data_from_id, verify_production_entry},
+    proof::ProofScheme,
+};
+use storage_proofs_porep::stacked::{verify_parent_cache_digest, StackedDrg};
+
+use crate::{
+    api::{estimate_seal_resources, estimate_update_resources, resources::update_parameters_path},
+    constants::DefaultPieceHasher,
+    error::{Error, Result},
+    parameters::setup_params,
+    types::{
+        FileLocation, PhaseResources, PoRepConfig, PreflightFailure, ProofPhase,
+        SectorUpdateConfig, TreeRHasher,
+    },
+};
+
+/// The file descriptors that are needed besides the ones of the sector files, e.g. for the
+/// parameters, the parent cache and the staged data.
+const FD_HEADROOM: u64 = 64;
+
+/// Checks that the prerequisites of a sealing phase are met, so that it doesn't fail hours after
+/// it was started.
+///
+/// It checks that
+///  - the filesystems of `cache_path` and `replica_path` have space for the files the phase
+///    writes, see [`estimate_seal_resources`],
+///  - the soft file descriptor limit covers the number of files of the sector, it's only queried
+///    and never raised,
+///  - the SDR parent cache exists for PreCommit Phase 1 and the parameters for Commit Phase 2.
+///
+/// With `verify_digests`, the parent cache and the parameters are hashed and compared with their
+/// manifests, which takes a while for production sector sizes. All failures are returned at once
+/// as an [`Error::Preflight`].
+///
+/// The entry points of the phases check the disk space, the file descriptor limit and that the
+/// parameters exist themselves. The parent cache isn't checked by them, a missing one is
+/// generated by PreCommit Phase 1 instead of failing it, and verifying the digests takes too long
+/// to run before every phase. Call this function to check them up front.
+pub fn preflight_seal<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase: ProofPhase,
+    cache_path: &Path,
+    replica_path: &Path,
+    verify_digests: bool,
+) -> Result<()> {
+    info!("preflight_seal:start: {:?}", phase);
+    let mut failures = seal_failures::<Tree>(porep_config, phase, cache_path, replica_path)?;
+    match phase {
+        ProofPhase::PreCommitPhase1 => {
+            let path = parent_cache_path::<Tree>(porep_config)?;
+            failures.extend(check_parent_cache(&path, verify_digests)?);
+        }
+        ProofPhase::CommitPhase2 => {
+            let path = porep_config.get_cache_params_path::<Tree>()?;
+            failures.extend(check_parameters(&path, verify_digests)?);
+        }
+        _ => {}
+    }
+    info!("preflight_seal:finish: {:?}", phase);
+
+    into_result(failures)
+}
+
+/// Checks that the prerequisites of `encode_into` or of generating the update proof are met.
+///
+/// `cache_path` and `replica_path` are the ones of the new replica. The same checks as for
+/// [`preflight_seal`] are run, the parameters are checked for [`ProofPhase::GenerateUpdateProof`].
+pub fn preflight_update<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    phase: ProofPhase,
+    cache_path: &Path,
+    replica_path: &Path,
+    verify_digests: bool,
+) -> Result<()> {
+    info!("preflight_update:start: {:?}", phase);
+    let mut failures = update_failures::<Tree>(config, phase, cache_path, replica_path)?;
+    if phase == ProofPhase::GenerateUpdateProof {
+        let path = update_parameters_path::<Tree>(config.sector_size);
+        failures.extend(check_parameters(&path, verify_digests)?);
+    }
+    info!("preflight_update:finish: {:?}", phase);
+
+    into_result(failures)
+}
+
+/// Checks the disk space and the file descriptor limit before a sealing phase is started.
+///
+/// The parent cache isn't checked and the parameters are checked by [`check_parameters_exist`],
+/// see [`preflight_seal`].
+pub(crate) fn check_seal_phase<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase: ProofPhase,
+    cache_path: &Path,
+    replica_path: &Path,
+) -> Result<()> {
+    trace!("checking the prerequisites of {:?}", phase);
+    into_result(seal_failures::<Tree>(
+        porep_config,
+        phase,
+        cache_path,
+        replica_path,
+    )?)
+}
+
+/// Checks the disk space and the file descriptor limit before an update phase is started.
+///
+/// The parameters are checked by [`check_parameters_exist`], see [`preflight_update`].
+pub(crate) fn check_update_phase<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    phase: ProofPhase,
+    cache_path: &Path,
+    replica_path: &Path,
+) -> Result<()> {
+    trace!("checking the prerequisites of {:?}", phase);
+    into_result(update_failures::<Tree>(
+        config,
+        phase,
+        cache_path,
+        replica_path,
+    )?)
+}
+
+/// Checks that the Groth parameters at `path` exist before a phase that needs them is started,
+/// without verifying their digest.
+pub(crate) fn check_parameters_exist(path: &Path) -> Result<()> {
+    trace!("checking that the parameters {:?} exist", path);
+    into_result(check_parameters(path, false)?.into_iter().collect())
+}
+
+/// Checks that the filesystem of `staging_dir` has space for `bytes` of staged files.
+pub(crate) fn check_staging_space(staging_dir: &Path, bytes: u64) -> Result<()> {
+    trace!("checking the space for {} staged bytes", bytes);
+    let path = existing_ancestor(staging_dir).to_path_buf();
+    let available = available_space(&path)?;
+    if available < bytes {
+        return Err(Error::Preflight(vec![
+            PreflightFailure::InsufficientSpace {
+                path,
+                required: bytes,
+                available,
+            },
+        ]));
+    }
+    Ok(())
+}
+
+fn seal_failures<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase: ProofPhase,
+    cache_path: &Path,
+    replica_path: &Path,
+) -> Result<Vec<PreflightFailure>> {
+    let phases = estimate_seal_resources::<Tree>(porep_config)?;
+    let resources = find_phase(&phases, phase)?;
+
+    let parent_cache_path = if phase == ProofPhase::PreCommitPhase1 {
+        Some(parent_cache_path::<Tree>(porep_config)?)
+    } else {
+        None
+    };
+    let mut failures = check_disk_space(
+        resources,
+        cache_path,
+        replica_path,
+        parent_cache_path.as_deref(),
+    )?;
+    failures.extend(check_fd_limit(count_sector_files(&phases)));
+
+    Ok(failures)
+}
+
+fn update_failures<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    phase: ProofPhase,
+    cache_path: &Path,
+    replica_path: &Path,
+) -> Result<Vec<PreflightFailure>> {
+    let phases = estimate_update_resources::<Tree>(config)?;
+    let resources = find_phase(&phases, phase)?;
+
+    let mut failures = check_disk_space(resources, cache_path, replica_path, None)?;
+    // The files of the sector key are open at the same time as the new ones.
+    failures.extend(check_fd_limit(2 * count_sector_files(&phases)));
+
+    Ok(failures)
+}
+
+fn find_phase(phases: &[PhaseResources], phase: ProofPhase) -> Result<&PhaseResources> {
+    phases
+        .iter()
+        .find(|resources| resources.phase == phase)
+        .ok_or_else(|| Error::InvalidInput(format!("{:?} isn't a phase of this config", phase)))
+}
+
+fn into_result(failures: Vec<PreflightFailure>) -> Result<()> {
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Preflight(failures))
+    }
+}
+
+fn parent_cache_path<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+) -> Result<PathBuf> {
+    let public_params = <StackedDrg<'_, Tree, DefaultPieceHasher> as ProofScheme<'_>>::setup(
+        &setup_params(porep_config)?,
+    )?;
+    Ok(public_params.graph.parent_cache_path())
+}
+
+/// Returns the number of files in the cache directory and the replica.
+fn count_sector_files(phases: &[PhaseResources]) -> u64 {
+    phases
+        .iter()
+        .flat_map(|resources| &resources.files)
+        .filter(|file| file.location != FileLocation::ParentCacheDir)
+        .count() as u64
+}
+
+/// Returns the id of the filesystem of `path`, if the platform provides one.
+#[cfg(unix)]
+fn filesystem_id(path: &Path) -> Result<Option<u64>> {
+    use std::os::unix::fs::MetadataExt;
+
+    Ok(Some(fs::metadata(path)?.dev()))
+}
+
+#[cfg(not(unix))]
+fn filesystem_id(_path: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Returns `path` or the closest of its parents that exists.
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+fn check_disk_space(
+    resources: &PhaseResources,
+    cache_path: &Path,
+    replica_path: &Path,
+    parent_cache_path: Option<&Path>,
+) -> Result<Vec<PreflightFailure>> {
+    // The bytes that are still needed per filesystem, files that exist already only grow.
+    let mut required: Vec<(Option<u64>, PathBuf, u64)> = Vec::new();
+    for file in &resources.files {
+        let path = match file.location {
+            FileLocation::CacheDir => cache_path.join(&file.name),
+            FileLocation::Replica => replica_path.to_path_buf(),
+            FileLocation::ParentCacheDir => match parent_cache_path {
+                Some(path) => path.to_path_buf(),
+                None => continue,
+            },
+        };
+        let existing = fs::metadata(&path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+        let bytes = file.bytes.saturating_sub(existing);
+        if bytes == 0 {
+            continue;
+        }
+
+        let dir = existing_ancestor(&path);
+        let id = filesystem_id(dir)?;
+        match required
+            .iter_mut()
+            .find(|(other_id, ..)| id.is_some() && *other_id == id)
+        {
+            Some((_, _, total)) => *total += bytes,
+            None => required.push((id, dir.to_path_buf(), bytes)),
+        }
+    }
+
+    let mut failures = Vec::new();
+    for (_, path, required) in required {
+        let available = available_space(&path)?;
+        trace!(
+            "{} bytes are required on {:?}, {} are available",
+            required,
+            path,
+            available
+        );
+        if available < required {
+            failures.push(PreflightFailure::InsufficientSpace {
+                path,
+                required,
+                available,
+            });
+        }
+    }
+
+    Ok(failures)
+}
+
+/// Returns the soft limit of open file descriptors, `None` on platforms where it can't be queried.
+///
+/// The limit is only read, raising it is a process wide side effect that is up to the caller.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // `rlim_t` isn't `u64` on every platform.
+fn fd_limit() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid `rlimit` that outlives the call.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    Some(limit.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+fn fd_limit() -> Option<u64> {
+    None
+}
+
+fn check_fd_limit(sector_files: u64) -> Option<PreflightFailure> {
+    let required = sector_files + FD_HEADROOM;
+    match fd_limit() {
+        Some(available) if available < required => Some(PreflightFailure::FileDescriptorLimit {
+            required,
+            available,
+        }),
+        _ => None,
+    }
+}
+
+fn check_parent_cache(path: &Path, verify_digest: bool) -> Result<Option<PreflightFailure>> {
+    if !path.exists() {
+        return Ok(Some(PreflightFailure::MissingParentCache {
+            path: path.to_path_buf(),
+        }));
+    }
+    // Parent caches that aren't in the manifest can't be verified.
+    if verify_digest && verify_parent_cache_digest(path)? == Some(false) {
+        return Ok(Some(PreflightFailure::CorruptParentCache {
+            path: path.to_path_buf(),
+        }));
+    }
+    Ok(None)
+}
+
+fn check_parameters(path: &Path, verify_digest: bool) -> Result<Option<PreflightFailure>> {
+    if !path.exists() {
+        return Ok(Some(PreflightFailure::MissingParameters {
+            path: path.to_path_buf(),
+        }));
+    }
+    if verify_digest {
+        let cache_key = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .to_string();
+        // Parameters that aren't in `parameters.json` can't be verified.
+        if get_parameter_data_from_id(&cache_key).is_none() {
+            return Ok(None);
+        }
+        if let Err(err) = verify_production_entry(path, cache_key, get_parameter_data_from_id) {
+            return match err.downcast::<CoreError>() {
+                Ok(CoreError::InvalidParameters(_)) => {
+                    Ok(Some(PreflightFailure::CorruptParameters {
+                        path: path.to_path_buf(),
+                    }))
+                }
+                Ok(err) => Err(anyhow::Error::from(err).into()),
+                Err(err) => Err(err.into()),
+            };
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::types::{FileEstimate, FileLifetime};
+
+    fn resources(files: Vec<FileEstimate>) -> PhaseResources {
+        PhaseResources {
+            phase: ProofPhase::PreCommitPhase1,
+            memory: 0,
+            parameters: None,
+            files,
+        }
+    }
+
+    fn file(location: FileLocation, name: &str, bytes: u64) -> FileEstimate {
+        FileEstimate {
+            location,
+            name: name.to_string(),
+            bytes,
+            lifetime: FileLifetime::Scratch,
+        }
+    }
+
+    #[test]
+    fn test_check_disk_space() {
+        let cache_dir = tempfile::tempdir().expect("tempdir failed");
+        let replica_path = cache_dir.path().join("replica");
+        let available = available_space(cache_dir.path()).expect("available space failed");
+
+        let fits = resources(vec![
+            file(FileLocation::CacheDir, "layer", 1024),
+            file(FileLocation::Replica, "replica", 1024),
+        ]);
+        let failures =
+            check_disk_space(&fits, cache_dir.path(), &replica_path, None).expect("check failed");
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        // The files are on the same filesystem, their sizes add up.
+        let too_large = resources(vec![
+            file(FileLocation::CacheDir, "layer", available),
+            file(FileLocation::Replica, "replica", available),
+            file(FileLocation::ParentCacheDir, "parent cache", u64::MAX),
+        ]);
+        let failures = check_disk_space(&too_large, cache_dir.path(), &replica_path, None)
+            .expect("check failed");
+        assert_eq!(failures.len(), 1);
+        match &failures[0] {
+            PreflightFailure::InsufficientSpace { path, required, .. } => {
+                assert_eq!(path, cache_dir.path());
+                assert_eq!(*required, 2 * available);
+            }
+            failure => panic!("unexpected failure {:?}", failure),
+        }
+
+        // Files that exist already only need to grow.
+        std::fs::write(&replica_path, vec![0u8; 1024]).expect("write failed");
+        let existing = resources(vec![file(FileLocation::Replica, "replica", 1024)]);
+        let failures = check_disk_space(&existing, Path::new("/nonexistent"), &replica_path, None)
+            .expect("check failed");
+        assert!(failures.is_empty(), "{:?}", failures);
+
+        let failures = check_disk_space(&too_large, cache_dir.path(), &replica_path, None)
+            .expect("check failed");
+        let err = into_result(failures).expect_err("must fail");
+        assert!(matches!(err, Error::Preflight(_)));
+        assert!(err.to_string().starts_with("preflight checks failed: "));
+    }
+
+    #[test]
+    fn test_check_fd_limit() {
+        let limit = fd_limit();
+        assert_eq!(check_fd_limit(0), None);
+        // Checking the limit doesn't change it.
+        assert_eq!(fd_limit(), limit);
+        if let Some(available) = limit {
+            assert_eq!(
+                check_fd_limit(available),
+                Some(PreflightFailure::FileDescriptorLimit {
+                    required: available + FD_HEADROOM,
+                    available,
+                })
+            );
+        }
+    }
+}
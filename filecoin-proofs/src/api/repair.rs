@@ -0,0 +1,213 @@
+use std::fs::{self, File};
+use std::path::Path;
+
+use anyhow::Context;
+use blstrs::Scalar as Fr;
+use log::{info, trace, warn};
+use memmap2::MmapOptions;
+use merkletree::store::StoreConfig;
+use storage_proofs_core::{
+    cache_key::CacheKey,
+    merkle::{create_base_merkle_tree, get_base_tree_count, split_config, BinaryMerkleTree},
+    util::{default_rows_to_discard, NODE_SIZE},
+};
+use typenum::Unsigned;
+
+use crate::{
+    api::{
+        commitment_from_fr, generate_tree_c, get_base_tree_leafs, get_base_tree_size,
+        seal::generate_tree_r_last_with_rows_to_discard, util,
+    },
+    constants::{DefaultBinaryTree, DefaultPieceHasher, LAYERS},
+    error::{ensure_input, Result},
+    types::{Commitment, MerkleTreeTrait, PoRepConfig},
+};
+
+/// Builds a tree within a staging directory inside the cache directory and moves its files into
+/// the cache directory once `build` succeeded.
+///
+/// The staging directory is on the same file system as the cache directory, hence each file is
+/// replaced atomically. Each file is synced before it's renamed and the cache directory after, so
+/// that a crash can't leave a name pointing to a partial file. The existing files are left
+/// untouched if `build` fails.
+fn replace_tree_files<F>(cache_path: &Path, tree_ids: &[String], build: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let staging_path = cache_path.join(format!(".rebuild-{}", tree_ids[0]));
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path)
+            .with_context(|| format!("could not remove {:?}", staging_path))?;
+    }
+    fs::create_dir(&staging_path)
+        .with_context(|| format!("could not create {:?}", staging_path))?;
+
+    let result = build(&staging_path).and_then(|()| {
+        for id in tree_ids {
+            let from = StoreConfig::data_path(&staging_path, id);
+            let to = StoreConfig::data_path(cache_path, id);
+            trace!("moving {:?} to {:?}", from, to);
+            File::open(&from)
+                .and_then(|file| file.sync_all())
+                .with_context(|| format!("could not sync {:?}", from))?;
+            fs::rename(&from, &to)
+                .with_context(|| format!("could not move {:?} to {:?}", from, to))?;
+        }
+        util::sync_dir(cache_path).with_context(|| format!("could not sync {:?}", cache_path))?;
+        Ok(())
+    });
+
+    if let Err(err) = fs::remove_dir_all(&staging_path) {
+        warn!("failed to remove {:?}: {}", staging_path, err);
+    }
+    result
+}
+
+/// Returns the ids of the stores a tree with the given id is split into.
+fn split_tree_ids(cache_path: &Path, id: CacheKey, count: usize) -> Result<Vec<String>> {
+    let config = StoreConfig::new(cache_path, id.to_string(), 0);
+    Ok(split_config(config, count)?
+        .into_iter()
+        .map(|config| config.id)
+        .collect())
+}
+
+/// Rebuilds tree-r-last of a sealed sector from its replica.
+///
+/// The root of the new tree is verified against the `comm_r_last` stored in `p_aux`, before the
+/// files in the cache directory are replaced. The number of rows to discard is taken from `t_aux`
+/// if it exists.
+pub fn rebuild_tree_r_last<R, T, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    replica_path: T,
+) -> Result<()>
+where
+    R: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    info!("rebuild_tree_r_last:start");
+
+    let cache_path = cache_path.as_ref();
+    let sector_bytes = u64::from(porep_config.sector_size);
+    let p_aux = util::get_p_aux::<Tree>(cache_path)?;
+
+    let tree_count = get_base_tree_count::<Tree>();
+    let t_aux_path = cache_path.join(CacheKey::TAux.to_string());
+    let rows_to_discard = if cfg!(feature = "fixed-rows-to-discard") || t_aux_path.exists() {
+        util::get_t_aux::<Tree>(cache_path, sector_bytes)?
+            .tree_r_last_config
+            .rows_to_discard
+    } else {
+        let base_tree_leafs = sector_bytes as usize / NODE_SIZE / tree_count;
+        default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize())
+    };
+
+    let tree_ids = split_tree_ids(cache_path, CacheKey::CommRLastTree, tree_count)?;
+    replace_tree_files(cache_path, &tree_ids, |staging_path| {
+        let root = generate_tree_r_last_with_rows_to_discard::<_, _, Tree>(
+            sector_bytes,
+            replica_path,
+            staging_path,
+            rows_to_discard,
+        )?;
+        ensure_input!(
+            root == p_aux.comm_r_last,
+            "rebuilt tree-r-last doesn't match comm_r_last of p_aux"
+        );
+        Ok(())
+    })?;
+
+    info!("rebuild_tree_r_last:finish");
+    Ok(())
+}
+
+/// Rebuilds tree-c of a sector from the labels of all layers in its cache directory.
+///
+/// The root of the new tree is verified against the `comm_c` stored in `p_aux`, before the files
+/// in the cache directory are replaced.
+pub fn rebuild_tree_c<R, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+) -> Result<()>
+where
+    R: AsRef<Path>,
+{
+    info!("rebuild_tree_c:start");
+
+    let cache_path = cache_path.as_ref();
+    let sector_bytes = u64::from(porep_config.sector_size);
+    let p_aux = util::get_p_aux::<Tree>(cache_path)?;
+    let num_layers = *LAYERS
+        .read()
+        .expect("LAYERS poisoned")
+        .get(&sector_bytes)
+        .context("unknown sector size")?;
+
+    let tree_count = get_base_tree_count::<Tree>();
+    let tree_ids = split_tree_ids(cache_path, CacheKey::CommCTree, tree_count)?;
+    replace_tree_files(cache_path, &tree_ids, |staging_path| {
+        let root =
+            generate_tree_c::<_, _, Tree>(sector_bytes, cache_path, staging_path, num_layers)?;
+        ensure_input!(
+            root == p_aux.comm_c,
+            "rebuilt tree-c doesn't match comm_c of p_aux"
+        );
+        Ok(())
+    })?;
+
+    info!("rebuild_tree_c:finish");
+    Ok(())
+}
+
+/// Rebuilds tree-d of a sector from its unsealed data.
+///
+/// `unsealed_path` must contain the whole (bit padded) unsealed sector. The root of the new tree
+/// is verified against `comm_d`, before the file in the cache directory is replaced.
+pub fn rebuild_tree_d<R, T>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    unsealed_path: T,
+    comm_d: &Commitment,
+) -> Result<()>
+where
+    R: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    info!("rebuild_tree_d:start");
+
+    let cache_path = cache_path.as_ref();
+    let unsealed_path = unsealed_path.as_ref();
+    let f_data = File::open(unsealed_path)
+        .with_context(|| format!("could not open unsealed_path={:?}", unsealed_path))?;
+    ensure_input!(
+        f_data.metadata()?.len() == u64::from(porep_config.sector_size),
+        "unsealed data must be as large as the sector"
+    );
+    let data = unsafe {
+        MmapOptions::new()
+            .map(&f_data)
+            .with_context(|| format!("could not mmap unsealed_path={:?}", unsealed_path))?
+    };
+
+    let tree_ids = [CacheKey::CommDTree.to_string()];
+    replace_tree_files(cache_path, &tree_ids, |staging_path| {
+        let base_tree_size = get_base_tree_size::<DefaultBinaryTree>(porep_config.sector_size)?;
+        let base_tree_leafs = get_base_tree_leafs::<DefaultBinaryTree>(base_tree_size)?;
+        let config = StoreConfig::new(staging_path, CacheKey::CommDTree.to_string(), 0);
+        let data_tree = create_base_merkle_tree::<BinaryMerkleTree<DefaultPieceHasher>>(
+            Some(config),
+            base_tree_leafs,
+            &data,
+        )?;
+        let root: Fr = data_tree.root().into();
+        ensure_input!(
+            &commitment_from_fr(root) == comm_d,
+            "rebuilt tree-d doesn't match comm_d"
+        );
+        Ok(())
+    })?;
+
+    info!("rebuild_tree_d:finish");
+    Ok(())
+}
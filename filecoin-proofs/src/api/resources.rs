@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Context;
+use bellperson::{util_cs::bench_cs::BenchCS, Circuit};
+use blstrs::Scalar as Fr;
+use lazy_static::lazy_static;
+use log::{info, trace};
+use merkletree::{
+    merkle::{get_merkle_tree_cache_size, get_merkle_tree_leafs, get_merkle_tree_len},
+    store::StoreConfig,
+};
+use storage_proofs_core::{
+    api_version::ApiFeature,
+    cache_key::CacheKey,
+    compound_proof::{CompoundProof, MAX_GROTH16_BATCH_SIZE},
+    drgraph::BASE_DEGREE,
+    merkle::{get_base_tree_count, split_config, MerkleTreeTrait},
+    parameter_cache::{parameter_cache_params_path, CacheableParameters},
+    settings,
+    util::{default_rows_to_discard, NODE_SIZE},
+};
+use storage_proofs_porep::stacked::{
+    challenge_proof_size, synth_proofs_file_size, StackedCompound, StackedDrg, EXP_DEGREE,
+    SYNTHETIC_POREP_VANILLA_PROOFS_EXT, SYNTHETIC_POREP_VANILLA_PROOFS_KEY,
+};
+use storage_proofs_update::{
+    circuit::EmptySectorUpdateCircuit, compound::EmptySectorUpdateCompound, constants::TreeRHasher,
+    PublicParams,
+};
+use typenum::Unsigned;
+
+use crate::{
+    constants::{DefaultPieceHasher, LAYERS},
+    error::{ensure_input, Result},
+    parameters::public_params,
+    types::{
+        FileEstimate, FileLifetime, FileLocation, PhaseResources, PoRepConfig, PoStConfig,
+        PoStType, ProofPhase, SectorSize, SectorUpdateConfig, BINARY_ARITY,
+    },
+};
+
+/// The number of parents of each node, as stored in the SDR parent cache.
+const DEGREE: usize = BASE_DEGREE + EXP_DEGREE;
+
+/// The bytes of a single node within the SDR parent cache, a `u32` per parent.
+const PARENT_CACHE_NODE_BYTES: usize = DEGREE * 4;
+
+/// `p_aux` holds comm_c and comm_r_last.
+const P_AUX_BYTES: u64 = 2 * NODE_SIZE as u64;
+
+/// The bytes of a field element, the assignments and the polynomials of a circuit are vectors of
+/// them.
+const SCALAR_BYTES: u64 = 32;
+
+lazy_static! {
+    /// The number of constraints and inputs of each circuit, keyed by the cache identifier of its
+    /// parameters. Counting them takes a while for large sectors, hence it's done only once.
+    static ref CIRCUIT_SIZES: Mutex<HashMap<String, (usize, usize)>> = Default::default();
+}
+
+/// Returns the memory of proving `partitions` circuits of the same shape as `blank_circuit`.
+///
+/// The circuits are synthesized in batches of at most `MAX_GROTH16_BATCH_SIZE`. Each circuit of a
+/// batch keeps its input and auxiliary assignments, there is about one auxiliary variable per
+/// constraint, and the evaluations of the A, B and C polynomials, which are padded to the next
+/// power of two for the FFTs.
+fn circuit_memory<C, F>(
+    cache_identifier: String,
+    partitions: usize,
+    blank_circuit: F,
+) -> Result<u64>
+where
+    C: Circuit<Fr>,
+    F: FnOnce() -> C,
+{
+    let mut sizes = CIRCUIT_SIZES.lock().expect("CIRCUIT_SIZES poisoned");
+    let (constraints, inputs) = match sizes.get(&cache_identifier) {
+        Some(size) => *size,
+        None => {
+            trace!("counting the constraints of {}", cache_identifier);
+            let mut cs = BenchCS::<Fr>::new();
+            blank_circuit()
+                .synthesize(&mut cs)
+                .context("failed to synthesize blank circuit")?;
+            let size = (cs.num_constraints(), cs.num_inputs());
+            sizes.insert(cache_identifier, size);
+            size
+        }
+    };
+
+    // bellperson adds a constraint for each input.
+    let domain = (constraints + inputs).next_power_of_two() as u64;
+    let circuit_bytes = (3 * domain + (constraints + inputs) as u64) * SCALAR_BYTES;
+    Ok(partitions.min(MAX_GROTH16_BATCH_SIZE) as u64 * circuit_bytes)
+}
+
+/// The shape of the trees of a sector.
+pub(crate) struct SectorShape {
+    sector_bytes: u64,
+    sector_nodes: usize,
+    pub(crate) num_layers: usize,
+    tree_count: usize,
+    base_tree_leafs: usize,
+    base_tree_len: usize,
+    arity: usize,
+    sub_tree_arity: usize,
+    top_tree_arity: usize,
+}
+
+impl SectorShape {
+    pub(crate) fn new<Tree: MerkleTreeTrait>(sector_size: SectorSize) -> Result<Self> {
+        let sector_bytes = u64::from(sector_size);
+        let sector_nodes = sector_bytes as usize / NODE_SIZE;
+        let num_layers = *LAYERS
+            .read()
+            .expect("LAYERS poisoned")
+            .get(&sector_bytes)
+            .context("unknown sector size")?;
+        let tree_count = get_base_tree_count::<Tree>();
+        let base_tree_leafs = sector_nodes / tree_count;
+        let arity = Tree::Arity::to_usize();
+        let base_tree_len = get_merkle_tree_len(base_tree_leafs, arity)?;
+
+        Ok(SectorShape {
+            sector_bytes,
+            sector_nodes,
+            num_layers,
+            tree_count,
+            base_tree_leafs,
+            base_tree_len,
+            arity,
+            sub_tree_arity: Tree::SubTreeArity::to_usize(),
+            top_tree_arity: Tree::TopTreeArity::to_usize(),
+        })
+    }
+
+    fn base_tree_bytes(&self) -> u64 {
+        (self.base_tree_len * NODE_SIZE) as u64
+    }
+
+    /// The size of a single tree-r-last file, only the rows above the discarded ones are stored.
+    fn tree_r_last_bytes(&self) -> Result<u64> {
+        let rows_to_discard = default_rows_to_discard(self.base_tree_leafs, self.arity);
+        let cache_size =
+            get_merkle_tree_cache_size(self.base_tree_leafs, self.arity, rows_to_discard)?;
+        Ok((cache_size * NODE_SIZE) as u64)
+    }
+
+    fn tree_d_bytes(&self) -> Result<u64> {
+        let tree_d_len = get_merkle_tree_len(self.sector_nodes, BINARY_ARITY)?;
+        Ok((tree_d_len * NODE_SIZE) as u64)
+    }
+
+    /// Returns the files of a tree that is split into one file per base tree.
+    fn tree_files(
+        &self,
+        key: CacheKey,
+        bytes: u64,
+        lifetime: FileLifetime,
+    ) -> Result<Vec<FileEstimate>> {
+        Ok(self
+            .tree_file_names(key)?
+            .into_iter()
+            .map(|name| cache_file(name, bytes, lifetime))
+            .collect())
+    }
+
+    /// Returns the names of the files of a tree that is split into one file per base tree.
+    pub(crate) fn tree_file_names(&self, key: CacheKey) -> Result<Vec<String>> {
+        let configs = split_config(
+            StoreConfig::new(PathBuf::new(), key.to_string(), 0),
+            self.tree_count,
+        )?;
+        Ok(configs
+            .iter()
+            .map(|config| data_file_name(&config.id))
+            .collect())
+    }
+
+    /// Returns the names of the files that are only needed for sealing, they are the layers,
+    /// tree-c and tree-d.
+    pub(crate) fn sealing_file_names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<_> = (1..=self.num_layers)
+            .map(|layer| data_file_name(&CacheKey::label_layer(layer)))
+            .collect();
+        names.extend(self.tree_file_names(CacheKey::CommCTree)?);
+        names.push(data_file_name(&CacheKey::CommDTree.to_string()));
+        Ok(names)
+    }
+
+    /// The number of leafs of tree-r-last, that are read from the replica in order to generate
+    /// the inclusion proof of a single leaf. The segments are aligned to their width.
+    pub(crate) fn tree_r_last_segment_width(&self) -> Result<usize> {
+        let rows_to_discard = default_rows_to_discard(self.base_tree_leafs, self.arity);
+        let cache_size =
+            get_merkle_tree_cache_size(self.base_tree_leafs, self.arity, rows_to_discard)?;
+        let cached_leafs = get_merkle_tree_leafs(cache_size, self.arity)?;
+        Ok(self.base_tree_leafs / cached_leafs)
+    }
+
+    /// The memory of generating the inclusion proof of a single leaf of tree-r-last: the segment
+    /// of the replica that is read and the rows that are rebuilt from it.
+    fn tree_r_last_read_bytes(&self) -> Result<u64> {
+        let segment_width = self.tree_r_last_segment_width()?;
+        let segment_tree_len = get_merkle_tree_len(segment_width, self.arity)?;
+        Ok(((segment_width + segment_tree_len) * NODE_SIZE) as u64)
+    }
+
+    /// The size of a single inclusion proof of tree-r-last, the leaf, the root and the siblings of
+    /// the path.
+    fn inclusion_proof_bytes(&self) -> u64 {
+        let mut siblings = 0;
+        let mut row_len = self.base_tree_leafs;
+        while row_len > 1 {
+            siblings += self.arity - 1;
+            row_len /= self.arity;
+        }
+        for arity in [self.sub_tree_arity, self.top_tree_arity] {
+            siblings += arity.saturating_sub(1);
+        }
+        ((2 + siblings) * NODE_SIZE) as u64
+    }
+
+    pub(crate) fn sector_bytes(&self) -> u64 {
+        self.sector_bytes
+    }
+}
+
+fn data_file_name(id: &str) -> String {
+    StoreConfig::data_path(Path::new(""), id)
+        .display()
+        .to_string()
+}
+
+fn cache_file(name: String, bytes: u64, lifetime: FileLifetime) -> FileEstimate {
+    FileEstimate {
+        location: FileLocation::CacheDir,
+        name,
+        bytes,
+        lifetime,
+    }
+}
+
+fn replica_file(bytes: u64) -> FileEstimate {
+    FileEstimate {
+        location: FileLocation::Replica,
+        name: "replica".to_string(),
+        bytes,
+        lifetime: FileLifetime::Final,
+    }
+}
+
+/// Returns the path of the Groth parameters of the update proof.
+pub(crate) fn update_parameters_path<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    sector_size: SectorSize,
+) -> PathBuf {
+    let public_params = PublicParams::from_sector_size(u64::from(sector_size));
+    let parameters_id = <EmptySectorUpdateCompound<Tree> as CacheableParameters<
+        EmptySectorUpdateCircuit<Tree>,
+        _,
+    >>::cache_identifier(&public_params);
+    parameter_cache_params_path(&parameters_id)
+}
+
+/// Returns the size of the parameter file at `path`, if it's in the parameter cache.
+fn parameters_size(path: PathBuf) -> Option<u64> {
+    let size = fs::metadata(&path).ok().map(|metadata| metadata.len());
+    if size.is_none() {
+        trace!("parameters not found at {:?}", path);
+    }
+    size
+}
+
+/// Estimates the memory and disk space of each sealing phase of a sector with the given config.
+///
+/// The memory is the one of the buffers that scale with the sector size, based on the current
+/// settings, plus the Groth parameters if they're in the parameter cache. For the SNARKs it's the
+/// vanilla proofs and the synthesized circuits, based on the number of constraints and partitions.
+/// The constraints are counted by synthesizing a blank circuit on the first estimate of a config,
+/// which takes a while for large sectors. Memory mapped files that are read or written
+/// sequentially aren't included. The files are the ones each phase writes, `t_aux` is left out as
+/// its size depends on the length of the cache path.
+pub fn estimate_seal_resources<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+) -> Result<Vec<PhaseResources>> {
+    info!("estimate_seal_resources:start");
+    let shape = SectorShape::new::<Tree>(porep_config.sector_size)?;
+    let settings = settings::current();
+
+    // The labels of the current and the previous layer, and two windows of the parent cache.
+    let parent_cache_bytes = (shape.sector_nodes * PARENT_CACHE_NODE_BYTES) as u64;
+    let parent_cache_window = (settings.sdr_parents_cache_size as usize * PARENT_CACHE_NODE_BYTES)
+        .min(parent_cache_bytes as usize);
+    let mut pc1_memory = 2 * shape.sector_bytes + 2 * parent_cache_window as u64;
+    if cfg!(feature = "multicore-sdr") && settings.use_multicore_sdr {
+        // The ring buffer of the parents that were read ahead, one SHA-256 block is added per
+        // node.
+        pc1_memory += (settings.multicore_sdr_lookahead * (DEGREE * NODE_SIZE + 64)) as u64;
+    }
+    let mut pc1_files: Vec<_> = (1..=shape.num_layers)
+        .map(|layer| {
+            cache_file(
+                data_file_name(&CacheKey::label_layer(layer)),
+                shape.sector_bytes,
+                FileLifetime::Scratch,
+            )
+        })
+        .collect();
+    pc1_files.push(cache_file(
+        data_file_name(&CacheKey::CommDTree.to_string()),
+        shape.tree_d_bytes()?,
+        FileLifetime::Scratch,
+    ));
+    // The data is copied to the replica, it's encoded in place by PC2.
+    pc1_files.push(replica_file(shape.sector_bytes));
+    pc1_files.push(FileEstimate {
+        location: FileLocation::ParentCacheDir,
+        name: "SDR parent cache".to_string(),
+        bytes: parent_cache_bytes,
+        lifetime: FileLifetime::Shared,
+    });
+
+    // The data that is encoded and one base tree that is built in memory.
+    let pc2_memory = shape.sector_bytes + shape.base_tree_bytes();
+    let mut pc2_files = shape.tree_files(
+        CacheKey::CommCTree,
+        shape.base_tree_bytes(),
+        FileLifetime::Scratch,
+    )?;
+    pc2_files.extend(shape.tree_files(
+        CacheKey::CommRLastTree,
+        shape.tree_r_last_bytes()?,
+        FileLifetime::Final,
+    )?);
+    pc2_files.push(cache_file(
+        CacheKey::PAux.to_string(),
+        P_AUX_BYTES,
+        FileLifetime::Final,
+    ));
+
+    let mut phases = vec![
+        PhaseResources {
+            phase: ProofPhase::PreCommitPhase1,
+            memory: pc1_memory,
+            parameters: None,
+            files: pc1_files,
+        },
+        PhaseResources {
+            phase: ProofPhase::PreCommitPhase2,
+            memory: pc2_memory,
+            parameters: None,
+            files: pc2_files,
+        },
+    ];
+
+    if porep_config.feature_enabled(ApiFeature::SyntheticPoRep) {
+        // All synthetic proofs are generated in memory before they are written.
+        let synth_proofs_bytes =
+            synth_proofs_file_size::<Tree>(shape.sector_nodes, shape.num_layers);
+        phases.push(PhaseResources {
+            phase: ProofPhase::GenerateSynthProofs,
+            memory: synth_proofs_bytes,
+            parameters: None,
+            files: vec![cache_file(
+                format!(
+                    "{}.{}",
+                    SYNTHETIC_POREP_VANILLA_PROOFS_KEY, SYNTHETIC_POREP_VANILLA_PROOFS_EXT
+                ),
+                synth_proofs_bytes,
+                FileLifetime::Scratch,
+            )],
+        });
+    }
+
+    // The vanilla proofs of all challenges of all partitions, they are the output of C1 and kept
+    // in memory by C2 until the circuits are created.
+    let public_params = public_params::<Tree>(porep_config)?;
+    let partitions = usize::from(porep_config.partitions);
+    let challenge_count = partitions * public_params.challenges.num_challenges_per_partition();
+    let vanilla_proofs_memory = (challenge_count
+        * challenge_proof_size::<Tree>(shape.sector_nodes, shape.num_layers))
+        as u64;
+    let circuits_memory = circuit_memory(
+        porep_config.get_cache_identifier::<Tree>()?,
+        partitions,
+        || {
+            <StackedCompound<Tree, DefaultPieceHasher> as CompoundProof<
+                StackedDrg<'_, Tree, DefaultPieceHasher>,
+                _,
+            >>::blank_circuit(&public_params)
+        },
+    )?;
+
+    phases.push(PhaseResources {
+        phase: ProofPhase::CommitPhase1,
+        memory: vanilla_proofs_memory,
+        parameters: None,
+        files: Vec::new(),
+    });
+    phases.push(PhaseResources {
+        phase: ProofPhase::CommitPhase2,
+        memory: vanilla_proofs_memory + circuits_memory,
+        parameters: parameters_size(porep_config.get_cache_params_path::<Tree>()?),
+        files: Vec::new(),
+    });
+    info!("estimate_seal_resources:finish");
+
+    Ok(phases)
+}
+
+/// Estimates the memory of a Winning or Window PoSt over `sector_count` sectors.
+///
+/// The files of the sectors are only read. For each challenge a segment of the replica is read
+/// into memory and the rows of tree-r-last below the cached ones are rebuilt from it, the
+/// challenges are proven concurrently on the threads of the rayon pool. The inclusion proofs of
+/// all challenges are kept until the SNARK is generated.
+pub fn estimate_post_resources<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    sector_count: usize,
+) -> Result<PhaseResources> {
+    info!("estimate_post_resources:start");
+    ensure_input!(sector_count > 0, "sector_count must be greater than zero");
+
+    let shape = SectorShape::new::<Tree>(post_config.sector_size)?;
+    let challenge_count = (sector_count * post_config.challenge_count) as u64;
+    let concurrent_reads = challenge_count.min(rayon::current_num_threads() as u64);
+    let memory = concurrent_reads * shape.tree_r_last_read_bytes()?
+        + challenge_count * shape.inclusion_proof_bytes();
+
+    let resources = PhaseResources {
+        phase: match post_config.typ {
+            PoStType::Winning => ProofPhase::WinningPost,
+            PoStType::Window => ProofPhase::WindowPost,
+        },
+        memory,
+        parameters: parameters_size(post_config.get_cache_params_path::<Tree>()?),
+        files: Vec::new(),
+    };
+    info!("estimate_post_resources:finish");
+
+    Ok(resources)
+}
+
+/// Estimates the memory and disk space of `encode_into` and of generating the update proof for
+/// a sector with the given config.
+///
+/// The same rules as for [`estimate_seal_resources`] apply.
+pub fn estimate_update_resources<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+) -> Result<Vec<PhaseResources>> {
+    info!("estimate_update_resources:start");
+    let shape = SectorShape::new::<Tree>(config.sector_size)?;
+
+    let mut encode_files = vec![cache_file(
+        data_file_name(&CacheKey::CommDTree.to_string()),
+        shape.tree_d_bytes()?,
+        FileLifetime::Scratch,
+    )];
+    encode_files.extend(shape.tree_files(
+        CacheKey::CommRLastTree,
+        shape.tree_r_last_bytes()?,
+        FileLifetime::Final,
+    )?);
+    encode_files.push(cache_file(
+        CacheKey::PAux.to_string(),
+        P_AUX_BYTES,
+        FileLifetime::Final,
+    ));
+    encode_files.push(replica_file(shape.sector_bytes));
+
+    let public_params = PublicParams::from_sector_size(shape.sector_bytes);
+    let cache_identifier = <EmptySectorUpdateCompound<Tree> as CacheableParameters<
+        EmptySectorUpdateCircuit<Tree>,
+        _,
+    >>::cache_identifier(&public_params);
+    let update_circuits_memory = circuit_memory(
+        cache_identifier,
+        usize::from(config.update_partitions),
+        || EmptySectorUpdateCompound::<Tree>::blank_circuit(&public_params),
+    )?;
+
+    let phases = vec![
+        PhaseResources {
+            phase: ProofPhase::EncodeInto,
+            // One base tree of the new tree-r-last is built in memory.
+            memory: shape.base_tree_bytes(),
+            parameters: None,
+            files: encode_files,
+        },
+        PhaseResources {
+            phase: ProofPhase::GenerateUpdateProof,
+            memory: update_circuits_memory,
+            parameters: parameters_size(update_parameters_path::<Tree>(config.sector_size)),
+            files: Vec::new(),
+        },
+    ];
+    info!("estimate_update_resources:finish");
+
+    Ok(phases)
+}
@@ -1,7 +1,7 @@
 use std::fs::{self, metadata, OpenOptions};
 use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, ensure, Context, Result};
+use anyhow::{anyhow, Context};
 use bellperson::groth16;
 use blstrs::Scalar as Fr;
 use filecoin_hashers::{Domain, Hasher};
@@ -20,8 +20,11 @@ use storage_proofs_core::{
         create_base_merkle_tree, get_base_tree_count, split_config, BinaryMerkleTree,
         MerkleTreeTrait,
     },
+    metrics::{OpDetails, OpTimer},
     multi_proof::MultiProof,
+    progress::{Progress, SealContext, TreeKind},
     proof::ProofScheme,
+    proofs_span,
     sector::SectorId,
     util::{default_rows_to_discard, NODE_SIZE},
     Data,
@@ -34,6 +37,7 @@ use storage_proofs_update::vanilla::prepare_tree_r_data;
 use typenum::{Unsigned, U11, U2};
 
 use crate::{
+    api::preflight::{check_parameters_exist, check_seal_phase},
     api::util::{get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
     api::{as_safe_commitment, commitment_from_fr, get_base_tree_leafs, get_base_tree_size, util},
     caches::{
@@ -45,12 +49,13 @@ use crate::{
         FIP92_MAX_NI_POREP_AGGREGATION_PROOFS, FIP92_MIN_NI_POREP_AGGREGATION_PROOFS,
         SINGLE_PARTITION_PROOF_LEN,
     },
+    error::{ensure_input, ensure_verified, Result},
     parameters::setup_params,
     pieces::{self, verify_pieces},
     types::{
-        AggregateSnarkProof, Commitment, PieceInfo, PoRepConfig, ProverId, SealCommitOutput,
-        SealCommitPhase1Output, SealPreCommitOutput, SealPreCommitPhase1Output, SectorSize, Ticket,
-        BINARY_ARITY,
+        AggregateSnarkProof, Commitment, PieceInfo, PoRepConfig, ProofPhase, ProverId,
+        SealCommitOutput, SealCommitPhase1Output, SealPreCommitOutput, SealPreCommitPhase1Output,
+        SectorSize, Ticket, BINARY_ARITY,
     },
 };
 
@@ -65,12 +70,55 @@ pub fn seal_pre_commit_phase1<R, S, T, Tree: 'static + MerkleTreeTrait>(
     ticket: Ticket,
     piece_infos: &[PieceInfo],
 ) -> Result<SealPreCommitPhase1Output<Tree>>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    seal_pre_commit_phase1_with_context(
+        porep_config,
+        cache_path,
+        in_path,
+        out_path,
+        prover_id,
+        sector_id,
+        ticket,
+        piece_infos,
+        &SealContext::default(),
+    )
+}
+
+/// Like [`seal_pre_commit_phase1`], but reports the progress to `ctx` and stops with a
+/// `Cancelled` error if the cancellation was requested.
+///
+/// The layers that are already generated and the checkpoint of the current layer are kept in
+/// `cache_path`, calling it again with the same arguments continues where it stopped.
+#[allow(clippy::too_many_arguments)]
+pub fn seal_pre_commit_phase1_with_context<R, S, T, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    in_path: S,
+    out_path: T,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    piece_infos: &[PieceInfo],
+    ctx: &SealContext,
+) -> Result<SealPreCommitPhase1Output<Tree>>
 where
     R: AsRef<Path>,
     S: AsRef<Path>,
     T: AsRef<Path>,
 {
     info!("seal_pre_commit_phase1:start: {:?}", sector_id);
+    let _span = proofs_span!("seal_pre_commit_phase1", sector_id = u64::from(sector_id));
+    let _proofs_context = ctx.enter_proofs_context();
+    let timer = OpTimer::start(
+        Operation::SealPreCommitPhase1,
+        OpDetails::sector_size(u64::from(porep_config.sector_size))
+            .with_bytes(u64::from(porep_config.sector_size)),
+    );
+    ctx.check_cancelled()?;
 
     let in_path_is_dev_zero = in_path.as_ref() == Path::new("/dev/zero");
     if in_path_is_dev_zero {
@@ -81,18 +129,24 @@ where
     //
     // In the special case where `in_path` is `/dev/zero`, `.is_file()` is `false` as `/dev/zero` is
     // not a "normal" unix file.
-    ensure!(
+    ensure_input!(
         in_path_is_dev_zero || metadata(in_path.as_ref())?.is_file(),
         "in_path must be a file or /dev/zero",
     );
-    ensure!(
+    ensure_input!(
         metadata(out_path.as_ref())?.is_file(),
         "out_path must be a file"
     );
-    ensure!(
+    ensure_input!(
         metadata(cache_path.as_ref())?.is_dir(),
         "cache_path must be a directory"
     );
+    check_seal_phase::<Tree>(
+        porep_config,
+        ProofPhase::PreCommitPhase1,
+        cache_path.as_ref(),
+        out_path.as_ref(),
+    )?;
 
     let sector_bytes = usize::from(porep_config.padded_bytes_amount());
     fs::metadata(&in_path)
@@ -145,7 +199,7 @@ where
     let (config, comm_d) = measure_op(Operation::CommD, || -> Result<_> {
         let base_tree_size = get_base_tree_size::<DefaultBinaryTree>(porep_config.sector_size)?;
         let base_tree_leafs = get_base_tree_leafs::<DefaultBinaryTree>(base_tree_size)?;
-        ensure!(
+        ensure_input!(
             compound_public_params.vanilla_params.graph.size() == base_tree_leafs,
             "graph size and leaf size don't match"
         );
@@ -174,10 +228,13 @@ where
 
         Ok((config, comm_d))
     })?;
+    ctx.report(Progress::TreeBuilt {
+        tree: TreeKind::TreeD,
+    });
 
     trace!("verifying pieces");
 
-    ensure!(
+    ensure_input!(
         verify_pieces(&comm_d, piece_infos, porep_config.sector_size)?,
         "pieces and comm_d do not match"
     );
@@ -190,10 +247,11 @@ where
         &porep_config.porep_id,
     );
 
-    let (labels, _) = StackedDrg::<Tree, DefaultPieceHasher>::replicate_phase1(
+    let (labels, _) = StackedDrg::<Tree, DefaultPieceHasher>::replicate_phase1_with_context(
         &compound_public_params.vanilla_params,
         &replica_id,
         &config.path,
+        ctx,
     )?;
 
     let out = SealPreCommitPhase1Output {
@@ -202,6 +260,7 @@ where
         comm_d,
     };
 
+    timer.finish();
     info!("seal_pre_commit_phase1:finish: {:?}", sector_id);
     Ok(out)
 }
@@ -213,21 +272,63 @@ pub fn seal_pre_commit_phase2<R, S, Tree: 'static + MerkleTreeTrait>(
     cache_path: S,
     replica_path: R,
 ) -> Result<SealPreCommitOutput>
+where
+    R: AsRef<Path>,
+    S: AsRef<Path>,
+{
+    seal_pre_commit_phase2_with_context(
+        porep_config,
+        phase1_output,
+        cache_path,
+        replica_path,
+        &SealContext::default(),
+    )
+}
+
+/// Like [`seal_pre_commit_phase2`], but reports the built trees to `ctx` and stops with a
+/// `Cancelled` error if the cancellation was requested.
+///
+/// The output of phase 1 in `cache_path` is left untouched, the trees are built from scratch when
+/// it is called again.
+pub fn seal_pre_commit_phase2_with_context<R, S, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: SealPreCommitPhase1Output<Tree>,
+    cache_path: S,
+    replica_path: R,
+    ctx: &SealContext,
+) -> Result<SealPreCommitOutput>
 where
     R: AsRef<Path>,
     S: AsRef<Path>,
 {
     info!("seal_pre_commit_phase2:start");
+    let _span = proofs_span!(
+        "seal_pre_commit_phase2",
+        replica = %replica_path.as_ref().display()
+    );
+    let _proofs_context = ctx.enter_proofs_context();
+    let timer = OpTimer::start(
+        Operation::SealPreCommitPhase2,
+        OpDetails::sector_size(u64::from(porep_config.sector_size))
+            .with_bytes(u64::from(porep_config.sector_size)),
+    );
+    ctx.check_cancelled()?;
 
     // Sanity check all input path types.
-    ensure!(
+    ensure_input!(
         metadata(cache_path.as_ref())?.is_dir(),
         "cache_path must be a directory"
     );
-    ensure!(
+    ensure_input!(
         metadata(replica_path.as_ref())?.is_file(),
         "replica_path must be a file"
     );
+    check_seal_phase::<Tree>(
+        porep_config,
+        ProofPhase::PreCommitPhase2,
+        cache_path.as_ref(),
+        replica_path.as_ref(),
+    )?;
 
     let SealPreCommitPhase1Output {
         mut labels,
@@ -289,14 +390,16 @@ where
 
     // Silence Clippy warning for the case where `t_aux` is not written.
     #[allow(unused_variables)]
-    let (tau, (p_aux, t_aux)) = StackedDrg::<Tree, DefaultPieceHasher>::replicate_phase2(
-        &compound_public_params.vanilla_params,
-        labels,
-        data,
-        Some(data_tree),
-        cache_path.as_ref().to_path_buf(),
-        replica_path.as_ref().to_path_buf(),
-    )?;
+    let (tau, (p_aux, t_aux)) =
+        StackedDrg::<Tree, DefaultPieceHasher>::replicate_phase2_with_context(
+            &compound_public_params.vanilla_params,
+            labels,
+            data,
+            Some(data_tree),
+            cache_path.as_ref().to_path_buf(),
+            replica_path.as_ref().to_path_buf(),
+            ctx,
+        )?;
 
     let comm_r = commitment_from_fr(tau.comm_r.into());
 
@@ -307,6 +410,7 @@ where
 
     let out = SealPreCommitOutput { comm_r, comm_d };
 
+    timer.finish();
     info!("seal_pre_commit_phase2:finish");
     Ok(out)
 }
@@ -323,11 +427,18 @@ pub fn generate_synth_proofs<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
     pre_commit: SealPreCommitOutput,
     piece_infos: &[PieceInfo],
 ) -> Result<()> {
-    ensure!(
+    ensure_input!(
         porep_config.feature_enabled(ApiFeature::SyntheticPoRep),
         "synth-porep must be enabled to generate synthetic proofs",
     );
     info!("seal_gen_synth_proofs:start: {:?}", sector_id);
+    let _span = proofs_span!("seal_gen_synth_proofs", sector_id = u64::from(sector_id));
+    check_seal_phase::<Tree>(
+        porep_config,
+        ProofPhase::GenerateSynthProofs,
+        cache_path.as_ref(),
+        replica_path.as_ref(),
+    )?;
     // Ignore C1 output as it contains no vanilla proofs (they are stored on disk, rather than
     // in memory) and a bogus porep challenge seed.
     seal_commit_phase1_inner::<T, Tree>(
@@ -361,6 +472,17 @@ pub fn seal_commit_phase1<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
     piece_infos: &[PieceInfo],
 ) -> Result<SealCommitPhase1Output<Tree>> {
     info!("seal_commit_phase1:start: {:?}", sector_id);
+    let _span = proofs_span!("seal_commit_phase1", sector_id = u64::from(sector_id));
+    let timer = OpTimer::start(
+        Operation::SealCommitPhase1,
+        OpDetails::sector_size(u64::from(porep_config.sector_size)),
+    );
+    check_seal_phase::<Tree>(
+        porep_config,
+        ProofPhase::CommitPhase1,
+        cache_path.as_ref(),
+        replica_path.as_ref(),
+    )?;
 
     let skip_labels = porep_config.feature_enabled(ApiFeature::SyntheticPoRep);
     let out = seal_commit_phase1_inner::<T, Tree>(
@@ -375,6 +497,7 @@ pub fn seal_commit_phase1<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>(
         piece_infos,
         skip_labels,
     )?;
+    timer.finish();
     info!("seal_commit_phase1:finish: {:?}", sector_id);
     Ok(out)
 }
@@ -396,25 +519,25 @@ pub fn seal_commit_phase1_inner<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>
     trace!("seal_commit_phase1_inner:start: {:?}", sector_id);
 
     // Sanity check all input path types.
-    ensure!(
+    ensure_input!(
         metadata(cache_path.as_ref())?.is_dir(),
         "cache_path must be a directory"
     );
-    ensure!(
+    ensure_input!(
         metadata(replica_path.as_ref())?.is_file(),
         "replica_path must be a file"
     );
 
-    ensure!(
+    ensure_input!(
         seed.is_some() || porep_config.feature_enabled(ApiFeature::SyntheticPoRep),
         "porep challenge seed must be set for non-synthetic proving",
     );
 
     let SealPreCommitOutput { comm_d, comm_r } = pre_commit;
 
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
-    ensure!(
+    ensure_input!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure_input!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure_input!(
         verify_pieces(&comm_d, piece_infos, porep_config.sector_size)?,
         "pieces and comm_d do not match"
     );
@@ -477,7 +600,7 @@ pub fn seal_commit_phase1_inner<T: AsRef<Path>, Tree: 'static + MerkleTreeTrait>
         &public_inputs,
         &vanilla_proofs,
     )?;
-    ensure!(sanity_check, "Invalid vanilla proof generated");
+    ensure_verified!(sanity_check, "Invalid vanilla proof generated");
 
     let out = SealCommitPhase1Output {
         vanilla_proofs,
@@ -508,8 +631,26 @@ pub fn seal_commit_phase2_circuit_proofs<Tree: 'static + MerkleTreeTrait>(
     porep_config: &PoRepConfig,
     phase1_output: SealCommitPhase1Output<Tree>,
     sector_id: SectorId,
+) -> Result<SealCommitOutput> {
+    seal_commit_phase2_circuit_proofs_inner(
+        porep_config,
+        phase1_output,
+        sector_id,
+        &SealContext::default(),
+    )
+}
+
+fn seal_commit_phase2_circuit_proofs_inner<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: SealCommitPhase1Output<Tree>,
+    sector_id: SectorId,
+    ctx: &SealContext,
 ) -> Result<SealCommitOutput> {
     info!("seal_commit_phase2_circuit_proofs:start: {:?}", sector_id);
+    let _span = proofs_span!(
+        "seal_commit_phase2_circuit_proofs",
+        sector_id = u64::from(sector_id)
+    );
 
     let SealCommitPhase1Output {
         vanilla_proofs,
@@ -520,10 +661,10 @@ pub fn seal_commit_phase2_circuit_proofs<Tree: 'static + MerkleTreeTrait>(
         ticket: _,
     } = phase1_output;
 
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
-    ensure!(seed != [0; 32], "Invalid porep challenge seed");
-    ensure!(
+    ensure_input!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure_input!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure_input!(seed != [0; 32], "Invalid porep challenge seed");
+    ensure_input!(
         !vanilla_proofs.is_empty()
             && vanilla_proofs
                 .iter()
@@ -563,12 +704,13 @@ pub fn seal_commit_phase2_circuit_proofs<Tree: 'static + MerkleTreeTrait>(
     >>::setup(&compound_setup_params)?;
 
     trace!("snark_proof:start");
-    let groth_proofs = StackedCompound::<Tree, DefaultPieceHasher>::circuit_proofs(
+    let groth_proofs = StackedCompound::<Tree, DefaultPieceHasher>::circuit_proofs_with_context(
         &public_inputs,
         vanilla_proofs,
         &compound_public_params.vanilla_params,
         &groth_params,
         compound_public_params.priority,
+        ctx,
     )?;
     trace!("snark_proof:finish");
 
@@ -592,8 +734,34 @@ pub fn seal_commit_phase2<Tree: 'static + MerkleTreeTrait>(
     phase1_output: SealCommitPhase1Output<Tree>,
     prover_id: ProverId,
     sector_id: SectorId,
+) -> Result<SealCommitOutput> {
+    seal_commit_phase2_with_context(
+        porep_config,
+        phase1_output,
+        prover_id,
+        sector_id,
+        &SealContext::default(),
+    )
+}
+
+/// Like [`seal_commit_phase2`], but reports the proven partitions to `ctx` and stops with a
+/// `Cancelled` error if the cancellation was requested. The cache directory isn't modified by
+/// this phase.
+pub fn seal_commit_phase2_with_context<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    phase1_output: SealCommitPhase1Output<Tree>,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ctx: &SealContext,
 ) -> Result<SealCommitOutput> {
     info!("seal_commit_phase2:start: {:?}", sector_id);
+    let _span = proofs_span!("seal_commit_phase2", sector_id = u64::from(sector_id));
+    let _proofs_context = ctx.enter_proofs_context();
+    let timer = OpTimer::start(
+        Operation::SealCommitPhase2,
+        OpDetails::sector_size(u64::from(porep_config.sector_size)),
+    );
+    check_parameters_exist(&porep_config.get_cache_params_path::<Tree>()?)?;
 
     let SealCommitPhase1Output {
         vanilla_proofs: _,
@@ -604,12 +772,16 @@ pub fn seal_commit_phase2<Tree: 'static + MerkleTreeTrait>(
         ticket,
     } = phase1_output;
 
-    let seal_commit_output =
-        seal_commit_phase2_circuit_proofs::<Tree>(porep_config, phase1_output, sector_id)?;
+    let seal_commit_output = seal_commit_phase2_circuit_proofs_inner::<Tree>(
+        porep_config,
+        phase1_output,
+        sector_id,
+        ctx,
+    )?;
 
     // Non-interactive PoRep is an aggregated proof, hence we use that as the returned buffer.
     let buf = if porep_config.feature_enabled(ApiFeature::NonInteractivePoRep) {
-        ensure!(
+        ensure_input!(
             porep_config.api_version >= ApiFeature::NonInteractivePoRep.first_supported_version(),
             "API version does not support NonInteractivePoRep"
         );
@@ -638,8 +810,9 @@ pub fn seal_commit_phase2<Tree: 'static + MerkleTreeTrait>(
         &buf,
     )
     .context("post-seal verification sanity check failed")?;
-    ensure!(is_valid, "post seal aggregation verifies");
+    ensure_verified!(is_valid, "post seal aggregation verifies");
 
+    timer.finish();
     info!("seal_commit_phase2:finish: {:?}", sector_id);
     Ok(SealCommitOutput { proof: buf })
 }
@@ -671,8 +844,8 @@ pub fn get_seal_inputs<Tree: 'static + MerkleTreeTrait>(
 ) -> Result<Vec<Vec<Fr>>> {
     trace!("get_seal_inputs:start");
 
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure_input!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure_input!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
 
     let replica_id = generate_replica_id::<Tree::Hasher, _>(
         &prover_id,
@@ -721,7 +894,7 @@ pub fn get_seal_inputs<Tree: 'static + MerkleTreeTrait>(
                 Some(k),
             )
         })
-        .collect::<Result<_>>()?;
+        .collect::<anyhow::Result<_>>()?;
 
     trace!("get_seal_inputs:finish");
 
@@ -746,7 +919,7 @@ pub fn aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
 ) -> Result<AggregateSnarkProof> {
     info!("aggregate_seal_commit_proofs:start");
 
-    ensure!(
+    ensure_input!(
         !commit_outputs.is_empty(),
         "cannot aggregate with empty outputs"
     );
@@ -757,7 +930,7 @@ pub fn aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
     // there are multiple NI-PoRep commit_outputs that are to be
     // aggregated together.
     if porep_config.feature_enabled(ApiFeature::NonInteractivePoRep) && commit_outputs.len() > 1 {
-        ensure!(
+        ensure_input!(
             commit_outputs.len() >= FIP92_MIN_NI_POREP_AGGREGATION_PROOFS
                 && commit_outputs.len() <= FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
             "{} proofs is outside of FIP-0090 specified NI-PoRep aggregation bounds",
@@ -789,7 +962,7 @@ pub fn aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
     );
 
     let target_proofs_len = get_aggregate_target_len(proofs.len());
-    ensure!(
+    ensure_input!(
         target_proofs_len > 1,
         "cannot aggregate less than two proofs"
     );
@@ -862,9 +1035,9 @@ pub fn verify_aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
 
     let aggregated_proofs_len = aggregate_proof.tmipp.gipa.nproofs as usize;
 
-    ensure!(aggregated_proofs_len != 0, "cannot verify zero proofs");
-    ensure!(!commit_inputs.is_empty(), "cannot verify with empty inputs");
-    ensure!(
+    ensure_input!(aggregated_proofs_len != 0, "cannot verify zero proofs");
+    ensure_input!(!commit_inputs.is_empty(), "cannot verify with empty inputs");
+    ensure_input!(
         comm_rs.len() == seeds.len(),
         "invalid comm_rs and seeds len mismatch"
     );
@@ -874,11 +1047,11 @@ pub fn verify_aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
         aggregated_proofs_len,
     );
 
-    ensure!(
+    ensure_input!(
         aggregated_proofs_len > 1,
         "cannot verify less than two proofs"
     );
-    ensure!(
+    ensure_input!(
         aggregated_proofs_len == aggregated_proofs_len.next_power_of_two(),
         "cannot verify non-pow2 aggregate seal proofs"
     );
@@ -886,7 +1059,7 @@ pub fn verify_aggregate_seal_commit_proofs<Tree: 'static + MerkleTreeTrait>(
     let num_inputs = commit_inputs.len();
     let num_inputs_per_proof = get_aggregate_target_len(num_inputs) / aggregated_proofs_len;
     let target_inputs_len = aggregated_proofs_len * num_inputs_per_proof;
-    ensure!(
+    ensure_input!(
         target_inputs_len % aggregated_proofs_len == 0,
         "invalid number of inputs provided",
     );
@@ -979,6 +1152,7 @@ pub fn verify_seal<Tree: 'static + MerkleTreeTrait>(
     proof_vec: &[u8],
 ) -> Result<bool> {
     info!("verify_seal:start: {:?}", sector_id);
+    let _span = proofs_span!("verify_seal", sector_id = u64::from(sector_id));
 
     // Non-interactive PoReps are aggregated, but it should be possible to use the usual PoRep
     // APIs, hence branch out here and not one layer higher.
@@ -1002,9 +1176,9 @@ pub fn verify_seal<Tree: 'static + MerkleTreeTrait>(
         );
     }
 
-    ensure!(comm_d_in != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r_in != [0; 32], "Invalid all zero commitment (comm_r)");
-    ensure!(!proof_vec.is_empty(), "Invalid proof bytes (empty vector)");
+    ensure_input!(comm_d_in != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure_input!(comm_r_in != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure_input!(!proof_vec.is_empty(), "Invalid proof bytes (empty vector)");
 
     let comm_r: <Tree::Hasher as Hasher>::Domain = as_safe_commitment(&comm_r_in, "comm_r")?;
     let comm_d: DefaultPieceDomain = as_safe_commitment(&comm_d_in, "comm_d")?;
@@ -1062,7 +1236,7 @@ pub fn verify_seal<Tree: 'static + MerkleTreeTrait>(
     };
 
     info!("verify_seal:finish: {:?}", sector_id);
-    result
+    Ok(result?)
 }
 
 /// Verifies a batch of outputs of some previously-run seal operations.
@@ -1089,30 +1263,30 @@ pub fn verify_batch_seal<Tree: 'static + MerkleTreeTrait>(
     proof_vecs: &[&[u8]],
 ) -> Result<bool> {
     info!("verify_batch_seal:start");
-    ensure!(!comm_r_ins.is_empty(), "Cannot prove empty batch");
+    ensure_input!(!comm_r_ins.is_empty(), "Cannot prove empty batch");
     let l = comm_r_ins.len();
-    ensure!(l == comm_d_ins.len(), "Inconsistent inputs");
-    ensure!(l == prover_ids.len(), "Inconsistent inputs");
-    ensure!(l == prover_ids.len(), "Inconsistent inputs");
-    ensure!(l == sector_ids.len(), "Inconsistent inputs");
-    ensure!(l == tickets.len(), "Inconsistent inputs");
-    ensure!(l == seeds.len(), "Inconsistent inputs");
-    ensure!(l == proof_vecs.len(), "Inconsistent inputs");
+    ensure_input!(l == comm_d_ins.len(), "Inconsistent inputs");
+    ensure_input!(l == prover_ids.len(), "Inconsistent inputs");
+    ensure_input!(l == prover_ids.len(), "Inconsistent inputs");
+    ensure_input!(l == sector_ids.len(), "Inconsistent inputs");
+    ensure_input!(l == tickets.len(), "Inconsistent inputs");
+    ensure_input!(l == seeds.len(), "Inconsistent inputs");
+    ensure_input!(l == proof_vecs.len(), "Inconsistent inputs");
 
     for comm_d_in in comm_d_ins {
-        ensure!(
+        ensure_input!(
             comm_d_in != &[0; 32],
             "Invalid all zero commitment (comm_d)"
         );
     }
     for comm_r_in in comm_r_ins {
-        ensure!(
+        ensure_input!(
             comm_r_in != &[0; 32],
             "Invalid all zero commitment (comm_r)"
         );
     }
     for proofs in proof_vecs {
-        ensure!(!proofs.is_empty(), "Invalid proof (empty bytes) found");
+        ensure_input!(!proofs.is_empty(), "Invalid proof (empty bytes) found");
     }
 
     let sector_bytes = porep_config.padded_bytes_amount();
@@ -1196,11 +1370,10 @@ where
     let base_tree_count = get_base_tree_count::<TreeR>();
     let base_tree_leafs = leaf_count / base_tree_count;
 
-    let size = get_base_tree_size::<TreeR>(SectorSize(sector_size))?;
-    let tree_r_last_config = StoreConfig {
-        path: PathBuf::from(output_dir.as_ref()),
-        id: CacheKey::CommRLastTree.to_string(),
-        size: Some(size),
+    generate_tree_r_last_with_rows_to_discard::<_, _, TreeR>(
+        sector_size,
+        replica_path,
+        output_dir,
         // A default 'rows_to_discard' value will be chosen for tree_r_last, unless the
         // `fixed-rows-to-discard` feature is not enabled and the user overrides this value via
         // the environment setting (FIL_PROOFS_ROWS_TO_DISCARD). If this value is specified, no
@@ -1208,7 +1381,32 @@ where
         // It must be noted that if/when this unchecked value is passed through merkle_light,
         // merkle_light now does a check that does not allow us to discard more rows than is
         // possible to discard.
-        rows_to_discard: default_rows_to_discard(base_tree_leafs, TreeR::Arity::to_usize()),
+        default_rows_to_discard(base_tree_leafs, TreeR::Arity::to_usize()),
+    )
+}
+
+/// Same as [`generate_tree_r_last`], but with the given number of rows to discard instead of the
+/// default one.
+pub(crate) fn generate_tree_r_last_with_rows_to_discard<O, R, TreeR: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    replica_path: R,
+    output_dir: O,
+    rows_to_discard: usize,
+) -> Result<<TreeR::Hasher as Hasher>::Domain>
+where
+    O: AsRef<Path>,
+    R: AsRef<Path>,
+{
+    let leaf_count = sector_size as usize / NODE_SIZE;
+    let base_tree_count = get_base_tree_count::<TreeR>();
+    let base_tree_leafs = leaf_count / base_tree_count;
+
+    let size = get_base_tree_size::<TreeR>(SectorSize(sector_size))?;
+    let tree_r_last_config = StoreConfig {
+        path: PathBuf::from(output_dir.as_ref()),
+        id: CacheKey::CommRLastTree.to_string(),
+        size: Some(size),
+        rows_to_discard,
     };
 
     let replica_base_tree_size = get_base_tree_size::<DefaultBinaryTree>(sector_size.into())?;
@@ -1288,7 +1486,7 @@ where
             configs,
             &labels_cache,
         )?,
-        _ => return Err(anyhow!("Unsupported column arity")),
+        _ => return Err(anyhow!("Unsupported column arity").into()),
     };
 
     Ok(tree_c.root())
@@ -0,0 +1,286 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use filecoin_hashers::Domain;
+use log::{info, trace};
+use merkletree::{
+    merkle::{get_merkle_tree_cache_size, get_merkle_tree_len},
+    store::StoreConfig,
+};
+use storage_proofs_core::{
+    api_version::ApiFeature,
+    cache_key::CacheKey,
+    merkle::{
+        create_disk_tree, create_lc_tree, get_base_tree_count, split_config,
+        split_config_and_replica, MerkleTreeTrait,
+    },
+    util::{default_rows_to_discard, NODE_SIZE},
+};
+use storage_proofs_porep::stacked::{
+    create_label::checkpoint::checkpoint_path, SYNTHETIC_POREP_VANILLA_PROOFS_EXT,
+    SYNTHETIC_POREP_VANILLA_PROOFS_KEY,
+};
+use typenum::Unsigned;
+
+use crate::{
+    api::util,
+    constants::LAYERS,
+    error::{ensure_input, Result},
+    types::{
+        Commitment, PoRepConfig, RootMismatch, SectorCacheReport, SectorOperation, TreeKind,
+        UnreadableFile, WrongFileSize, BINARY_ARITY,
+    },
+};
+
+/// Collects the findings while walking the cache directory.
+struct Diagnosis {
+    report: SectorCacheReport,
+    /// All files that belong to the sector, whether they exist or not.
+    known_files: HashSet<PathBuf>,
+}
+
+impl Diagnosis {
+    /// Checks that a file exists and has the expected size. Returns true if it does.
+    fn check_file(&mut self, path: &Path, expected_size: Option<u64>) -> bool {
+        self.known_files.insert(path.to_path_buf());
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                self.report.missing_files.push(path.to_path_buf());
+                return false;
+            }
+        };
+        match expected_size {
+            Some(expected) if metadata.len() != expected => {
+                self.report.wrong_sizes.push(WrongFileSize {
+                    path: path.to_path_buf(),
+                    expected,
+                    actual: metadata.len(),
+                });
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn unreadable<E: Into<anyhow::Error>>(&mut self, path: &Path, error: E) {
+        self.report.unreadable_files.push(UnreadableFile {
+            path: path.to_path_buf(),
+            error: format!("{:#}", error.into()),
+        });
+    }
+
+    /// Compares the root of a tree on disk with the one stored in `p_aux`. Returns true if they
+    /// match.
+    fn check_root<D: Domain>(
+        &mut self,
+        tree: TreeKind,
+        path: &Path,
+        expected: D,
+        actual: anyhow::Result<D>,
+    ) -> bool {
+        match actual {
+            Ok(actual) if actual == expected => true,
+            Ok(actual) => {
+                self.report.root_mismatches.push(RootMismatch {
+                    tree,
+                    expected: domain_to_commitment(expected),
+                    actual: domain_to_commitment(actual),
+                });
+                false
+            }
+            Err(err) => {
+                self.unreadable(path, err);
+                false
+            }
+        }
+    }
+}
+
+fn domain_to_commitment<D: Domain>(domain: D) -> Commitment {
+    let mut commitment = [0; 32];
+    commitment.copy_from_slice(domain.as_ref());
+    commitment
+}
+
+/// Walks a sector cache directory and reports on the state of its files.
+///
+/// All files that are expected for a sector of the given shape are checked: `p_aux`, `t_aux`,
+/// the layers, tree-d, tree-c, tree-r-last and the synthetic proofs. Files that are missing,
+/// have the wrong size or can't be read are listed, as well as the roots of tree-c and
+/// tree-r-last that don't match `p_aux`. Based on that, it reports which operations can still be
+/// run on the sector.
+///
+/// The `replica_path` is the sealed sector, it's needed for all operations except
+/// `seal_pre_commit_phase2`, where it's the staged sector.
+pub fn diagnose_sector_cache<R, T, Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    cache_path: R,
+    replica_path: T,
+) -> Result<SectorCacheReport>
+where
+    R: AsRef<Path>,
+    T: AsRef<Path>,
+{
+    info!("diagnose_sector_cache:start");
+
+    let cache_path = cache_path.as_ref();
+    let replica_path = replica_path.as_ref();
+    ensure_input!(
+        fs::metadata(cache_path)?.is_dir(),
+        "cache_path must be a directory"
+    );
+
+    let sector_bytes = u64::from(porep_config.sector_size);
+    let sector_nodes = sector_bytes as usize / NODE_SIZE;
+    let num_layers = *LAYERS
+        .read()
+        .expect("LAYERS poisoned")
+        .get(&sector_bytes)
+        .context("unknown sector size")?;
+    let tree_count = get_base_tree_count::<Tree>();
+    let base_tree_leafs = sector_nodes / tree_count;
+    let base_tree_len = get_merkle_tree_len(base_tree_leafs, Tree::Arity::to_usize())?;
+
+    let mut diagnosis = Diagnosis {
+        report: SectorCacheReport {
+            cache_path: cache_path.to_path_buf(),
+            missing_files: Vec::new(),
+            extra_files: Vec::new(),
+            wrong_sizes: Vec::new(),
+            unreadable_files: Vec::new(),
+            root_mismatches: Vec::new(),
+            has_synthetic_proofs: false,
+            possible_operations: Vec::new(),
+        },
+        known_files: HashSet::new(),
+    };
+
+    let replica_ok = diagnosis.check_file(replica_path, Some(sector_bytes));
+
+    let p_aux_path = cache_path.join(CacheKey::PAux.to_string());
+    let p_aux = if diagnosis.check_file(&p_aux_path, None) {
+        util::get_p_aux::<Tree>(cache_path)
+            .map_err(|err| diagnosis.unreadable(&p_aux_path, err))
+            .ok()
+    } else {
+        None
+    };
+
+    // With `fixed-rows-to-discard`, a missing `t_aux` is replaced by the default values.
+    let t_aux_path = cache_path.join(CacheKey::TAux.to_string());
+    let t_aux =
+        if cfg!(feature = "fixed-rows-to-discard") || diagnosis.check_file(&t_aux_path, None) {
+            diagnosis.known_files.insert(t_aux_path.clone());
+            util::get_t_aux::<Tree>(cache_path, sector_bytes)
+                .map_err(|err| diagnosis.unreadable(&t_aux_path, err))
+                .ok()
+        } else {
+            None
+        };
+
+    let mut layers_ok = true;
+    for layer in 1..=num_layers {
+        let config = StoreConfig::new(cache_path.to_path_buf(), CacheKey::label_layer(layer), 0);
+        // Checkpoints of partially generated layers are expected while sealing.
+        diagnosis.known_files.insert(checkpoint_path(&config));
+        let layer_path = StoreConfig::data_path(&config.path, &config.id);
+        layers_ok &= diagnosis.check_file(&layer_path, Some(sector_bytes));
+    }
+
+    let tree_d_path = StoreConfig::data_path(cache_path, &CacheKey::CommDTree.to_string());
+    let tree_d_len = get_merkle_tree_len(sector_nodes, BINARY_ARITY)?;
+    let tree_d_ok = diagnosis.check_file(&tree_d_path, Some((tree_d_len * NODE_SIZE) as u64));
+
+    let tree_c_configs = split_config(
+        StoreConfig {
+            path: cache_path.to_path_buf(),
+            id: CacheKey::CommCTree.to_string(),
+            size: Some(base_tree_len),
+            rows_to_discard: 0,
+        },
+        tree_count,
+    )?;
+    let mut tree_c_ok = true;
+    for config in &tree_c_configs {
+        let path = StoreConfig::data_path(&config.path, &config.id);
+        tree_c_ok &= diagnosis.check_file(&path, Some((base_tree_len * NODE_SIZE) as u64));
+    }
+    if let (true, Some(p_aux)) = (tree_c_ok, &p_aux) {
+        trace!("checking the root of tree-c");
+        let root = create_disk_tree::<Tree>(base_tree_len, &tree_c_configs).map(|tree| tree.root());
+        let path = StoreConfig::data_path(cache_path, &tree_c_configs[0].id);
+        tree_c_ok = diagnosis.check_root(TreeKind::TreeC, &path, p_aux.comm_c, root);
+    }
+
+    let rows_to_discard = t_aux
+        .as_ref()
+        .map(|t_aux| t_aux.tree_r_last_config.rows_to_discard)
+        .unwrap_or_else(|| default_rows_to_discard(base_tree_leafs, Tree::Arity::to_usize()));
+    let (tree_r_last_configs, replica_config) = split_config_and_replica(
+        StoreConfig {
+            path: cache_path.to_path_buf(),
+            id: CacheKey::CommRLastTree.to_string(),
+            size: Some(base_tree_len),
+            rows_to_discard,
+        },
+        replica_path.to_path_buf(),
+        base_tree_leafs,
+        tree_count,
+    )?;
+    let tree_r_last_bytes =
+        get_merkle_tree_cache_size(base_tree_leafs, Tree::Arity::to_usize(), rows_to_discard)?
+            * NODE_SIZE;
+    let mut tree_r_last_ok = true;
+    for config in &tree_r_last_configs {
+        let path = StoreConfig::data_path(&config.path, &config.id);
+        tree_r_last_ok &= diagnosis.check_file(&path, Some(tree_r_last_bytes as u64));
+    }
+    // The base layer of tree-r-last is read from the replica.
+    if let (true, true, Some(p_aux)) = (tree_r_last_ok, replica_ok, &p_aux) {
+        trace!("checking the root of tree-r-last");
+        let root = create_lc_tree::<Tree>(base_tree_len, &tree_r_last_configs, &replica_config)
+            .map(|tree| tree.root());
+        let path = StoreConfig::data_path(cache_path, &tree_r_last_configs[0].id);
+        tree_r_last_ok = diagnosis.check_root(TreeKind::TreeRLast, &path, p_aux.comm_r_last, root);
+    }
+
+    let synth_proofs_path = cache_path.join(format!(
+        "{}.{}",
+        SYNTHETIC_POREP_VANILLA_PROOFS_KEY, SYNTHETIC_POREP_VANILLA_PROOFS_EXT
+    ));
+    diagnosis.known_files.insert(synth_proofs_path.clone());
+    diagnosis.report.has_synthetic_proofs = synth_proofs_path.exists();
+
+    for entry in fs::read_dir(cache_path)? {
+        let path = entry?.path();
+        if !diagnosis.known_files.contains(&path) {
+            diagnosis.report.extra_files.push(path);
+        }
+    }
+    diagnosis.report.extra_files.sort();
+
+    let aux_ok = p_aux.is_some() && t_aux.is_some();
+    // Synthetic PoRep doesn't need the labels anymore once the synthetic proofs are generated.
+    let labels_ok = layers_ok && tree_d_ok && tree_c_ok
+        || porep_config.feature_enabled(ApiFeature::SyntheticPoRep)
+            && diagnosis.report.has_synthetic_proofs;
+    let operations = &mut diagnosis.report.possible_operations;
+    if replica_ok && layers_ok && tree_d_ok {
+        operations.push(SectorOperation::PreCommitPhase2);
+    }
+    if replica_ok && aux_ok && tree_r_last_ok && labels_ok {
+        operations.push(SectorOperation::CommitPhase1);
+    }
+    if replica_ok && aux_ok && tree_r_last_ok {
+        operations.push(SectorOperation::PoSt);
+    }
+    if replica_ok && aux_ok {
+        operations.push(SectorOperation::EncodeInto);
+    }
+
+    info!("diagnose_sector_cache:finish");
+    Ok(diagnosis.report)
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use filecoin_hashers::{HashFunction, Hasher};
+use log::{info, warn};
+use rayon::prelude::*;
+use storage_proofs_core::{
+    cache_key::CacheKey,
+    merkle::{MerkleProofTrait, MerkleTreeTrait},
+    sector::SectorId,
+    settings,
+};
+use storage_proofs_post::fallback;
+
+use crate::{
+    api::{
+        generate_fallback_sector_challenges, resources::SectorShape,
+        storage::open_tree_r_last_files,
+    },
+    error::{ensure_input, Result},
+    types::{
+        ChallengeSeed, PoStConfig, PrivateReplicaInfo, ProofsContext, ProverId, SectorCheck,
+        SectorStatus,
+    },
+    PoStType,
+};
+
+/// Checks which sectors can be proven for a Window or Winning PoSt, without generating any
+/// proofs.
+///
+/// The challenges are derived the same way as for the PoSt. Only the challenged leafs and their
+/// Merkle paths are read from tree-r-last and the replica, and validated against comm_r_last of
+/// p_aux, after checking that comm_c and comm_r_last of p_aux hash to comm_r. The sectors are
+/// checked in parallel, the result is in the order of the sector ids.
+///
+/// As for [`crate::generate_winning_post`], a Winning PoSt must have exactly
+/// `post_config.sector_count` replicas.
+pub fn check_sectors_provable<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+) -> Result<Vec<SectorCheck>> {
+    info!("check_sectors_provable:start");
+    ensure_input!(
+        post_config.typ == PoStType::Window || post_config.typ == PoStType::Winning,
+        "invalid post config type"
+    );
+    ensure_input!(
+        post_config.typ != PoStType::Winning || replicas.len() == post_config.sector_count,
+        "invalid amount of replicas"
+    );
+
+    let shape = SectorShape::new::<Tree>(post_config.sector_size)?;
+    let tree_r_last_names = shape.tree_file_names(CacheKey::CommRLastTree)?;
+
+    let sector_ids: Vec<_> = replicas.keys().copied().collect();
+    let challenges = generate_fallback_sector_challenges::<Tree>(
+        post_config,
+        randomness,
+        &sector_ids,
+        prover_id,
+    )?;
+
+    let context = ProofsContext::current();
+    let checks = replicas
+        .par_iter()
+        .map(|(sector_id, replica)| {
+            let _context = context.enter();
+            let start = Instant::now();
+            let sector_challenges = challenges
+                .get(sector_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let status = check_sector(post_config, replica, &tree_r_last_names, sector_challenges);
+            if status != SectorStatus::Provable {
+                warn!("sector {:?} is not provable: {:?}", sector_id, status);
+            }
+            SectorCheck {
+                sector_id: *sector_id,
+                status,
+                duration: start.elapsed(),
+            }
+        })
+        .collect();
+
+    info!("check_sectors_provable:finish");
+    Ok(checks)
+}
+
+fn check_sector<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    replica: &PrivateReplicaInfo<Tree>,
+    tree_r_last_names: &[String],
+    challenges: &[u64],
+) -> SectorStatus {
+    let mut paths: Vec<PathBuf> = tree_r_last_names
+        .iter()
+        .map(|name| replica.cache_dir_path().join(name))
+        .collect();
+    paths.push(replica.replica_path().to_path_buf());
+    let missing = paths.into_iter().find(|path| match replica.storage() {
+        Some(storage) => storage.size(path).is_err(),
+        None => !path.is_file(),
+    });
+    if let Some(path) = missing {
+        return SectorStatus::MissingFile { path };
+    }
+
+    // The inclusion proofs are checked against comm_r_last of p_aux, which has to belong to the
+    // comm_r of the sector.
+    let comm_r_last = replica.safe_comm_r_last();
+    let hashed_comm_r =
+        <Tree::Hasher as Hasher>::Function::hash2(&replica.safe_comm_c(), &comm_r_last);
+    if replica.safe_comm_r().ok() != Some(hashed_comm_r) {
+        return SectorStatus::CommRMismatch;
+    }
+
+    let io_error = |err: anyhow::Error| SectorStatus::Io {
+        error: format!("{:#}", err),
+    };
+
+    let (layout, reads) = match read_challenged_nodes(post_config, replica, challenges) {
+        Ok(reads) => reads,
+        Err(err) => return io_error(err),
+    };
+
+    for &challenge in challenges {
+        let proof = match fallback::inclusion_proof_from_reads::<Tree>(&layout, &reads, challenge) {
+            Ok(proof) => proof,
+            Err(err) => return io_error(err),
+        };
+        if !proof.validate(challenge as usize) || proof.root() != comm_r_last {
+            return SectorStatus::RootMismatch { challenge };
+        }
+    }
+
+    SectorStatus::Provable
+}
+
+/// Reads the replica segments and tree-r-last nodes the inclusion proofs of the challenges need.
+fn read_challenged_nodes<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    replica: &PrivateReplicaInfo<Tree>,
+    challenges: &[u64],
+) -> anyhow::Result<(fallback::TreeRLastLayout, fallback::PrefetchedReads)> {
+    let layout = fallback::TreeRLastLayout::new::<Tree>(u64::from(post_config.sector_size))?;
+    let planned = layout.plan_reads(challenges)?;
+    let (replica_file, tree_r_last) = open_tree_r_last_files(replica, post_config.sector_size)?;
+    let max_outstanding_reads = settings::current().winning_post_max_outstanding_reads;
+    let reads = fallback::prefetch_reads(
+        &*replica_file,
+        &tree_r_last,
+        &planned,
+        max_outstanding_reads.max(1),
+    )?;
+    Ok((layout, reads))
+}
@@ -0,0 +1,191 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use log::{info, trace};
+use storage_proofs_core::{
+    api_version::ApiFeature,
+    cache_key::CacheKey,
+    merkle::MerkleTreeTrait,
+    sector::SectorId,
+    storage::{copy_to_local, ReadAt, SectorStorage},
+};
+
+use crate::{
+    api::{preflight::check_staging_space, resources::SectorShape, seal_commit_phase1},
+    error::{Error, Result},
+    types::{
+        PieceInfo, PoRepConfig, PrivateReplicaInfo, ProverId, SealCommitPhase1Output,
+        SealPreCommitOutput, SectorSize, Ticket,
+    },
+};
+
+/// Copies the cache directory and the replica of a sector into `storage`. All files directly
+/// within `cache_path` are copied into `dest_cache_path`.
+pub fn store_sector_files(
+    storage: &dyn SectorStorage,
+    cache_path: &Path,
+    replica_path: &Path,
+    dest_cache_path: &Path,
+    dest_replica_path: &Path,
+) -> Result<()> {
+    info!("store_sector_files:start");
+
+    for entry in fs::read_dir(cache_path).map_err(|err| Error::cache_file(cache_path, err))? {
+        let entry = entry.map_err(|err| Error::cache_file(cache_path, err))?;
+        if entry.file_type()?.is_file() {
+            store_file(
+                storage,
+                &entry.path(),
+                &dest_cache_path.join(entry.file_name()),
+            )?;
+        }
+    }
+    store_file(storage, replica_path, dest_replica_path)?;
+
+    info!("store_sector_files:finish");
+    Ok(())
+}
+
+fn store_file(storage: &dyn SectorStorage, path: &Path, dest: &Path) -> Result<()> {
+    trace!("storing {:?} as {:?}", path, dest);
+    let mut file = File::open(path).map_err(|err| Error::cache_file(path, err))?;
+    let mut writer = storage
+        .create(dest)
+        .with_context(|| format!("could not create {:?} in storage", dest))?;
+    io::copy(&mut file, &mut writer).with_context(|| format!("could not store {:?}", path))?;
+    writer
+        .flush()
+        .with_context(|| format!("could not store {:?}", path))?;
+    Ok(())
+}
+
+/// Generates the commit phase 1 output of a sector that is kept in `storage`.
+///
+/// The files the proof needs are staged into a temporary directory within `staging_dir` first,
+/// which is removed afterwards. For synthetic PoRep that are the synthetic proofs and tree-r-last
+/// only, otherwise it's the whole cache directory and the replica, which for production sector
+/// sizes is many times the sector size. The free space of `staging_dir` is checked before
+/// anything is copied.
+#[allow(clippy::too_many_arguments)]
+pub fn seal_commit_phase1_from_storage<Tree: 'static + MerkleTreeTrait>(
+    porep_config: &PoRepConfig,
+    storage: &dyn SectorStorage,
+    cache_path: &Path,
+    replica_path: &Path,
+    staging_dir: &Path,
+    prover_id: ProverId,
+    sector_id: SectorId,
+    ticket: Ticket,
+    seed: Ticket,
+    pre_commit: SealPreCommitOutput,
+    piece_infos: &[PieceInfo],
+) -> Result<SealCommitPhase1Output<Tree>> {
+    info!("seal_commit_phase1_from_storage:start: {:?}", sector_id);
+
+    let shape = SectorShape::new::<Tree>(porep_config.sector_size)?;
+    let synthetic = porep_config.feature_enabled(ApiFeature::SyntheticPoRep);
+    let skipped = if synthetic {
+        shape.sealing_file_names()?
+    } else {
+        Vec::new()
+    };
+    let mut staged = Vec::new();
+    for path in storage
+        .list(cache_path)
+        .with_context(|| format!("could not list {:?} in storage", cache_path))?
+    {
+        let name = match path.file_name() {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        if skipped.iter().any(|skip| name == skip.as_str()) {
+            continue;
+        }
+        staged.push((path, name));
+    }
+
+    // The replica of synthetic PoRep is a sparse file, it doesn't take up space.
+    let mut staged_bytes = if synthetic {
+        0
+    } else {
+        storage_size(storage, replica_path)?
+    };
+    for (path, _) in &staged {
+        staged_bytes += storage_size(storage, path)?;
+    }
+    check_staging_space(staging_dir, staged_bytes)?;
+
+    let dir =
+        tempfile::tempdir_in(staging_dir).map_err(|err| Error::cache_file(staging_dir, err))?;
+    let local_cache = dir.path().join("cache");
+    let local_replica = dir.path().join("replica");
+    fs::create_dir(&local_cache)?;
+
+    for (path, name) in &staged {
+        stage_file(storage, path, &local_cache.join(name))?;
+    }
+    if synthetic {
+        // Tree-r-last is opened with the replica, though only the synthetic proofs are read.
+        create_sparse_file(&local_replica, shape.sector_bytes())?;
+    } else {
+        stage_file(storage, replica_path, &local_replica)?;
+    }
+
+    let out = seal_commit_phase1::<_, Tree>(
+        porep_config,
+        &local_cache,
+        &local_replica,
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        pre_commit,
+        piece_infos,
+    )?;
+
+    info!("seal_commit_phase1_from_storage:finish: {:?}", sector_id);
+    Ok(out)
+}
+
+/// Opens the replica and the tree-r-last files of a sector for random reads, either locally or
+/// from its [`SectorStorage`]. The tree-r-last files are in the order of their base tree index.
+pub(crate) fn open_tree_r_last_files<Tree: 'static + MerkleTreeTrait>(
+    replica: &PrivateReplicaInfo<Tree>,
+    sector_size: SectorSize,
+) -> Result<(Box<dyn ReadAt>, Vec<Box<dyn ReadAt>>)> {
+    let open = |path: &Path| {
+        match replica.storage() {
+            Some(storage) => storage.open_read(path),
+            None => File::open(path).map(|file| Box::new(file) as Box<dyn ReadAt>),
+        }
+        .map_err(|err| Error::cache_file(path, err))
+    };
+
+    let replica_file = open(replica.replica_path())?;
+    let tree_r_last = SectorShape::new::<Tree>(sector_size)?
+        .tree_file_names(CacheKey::CommRLastTree)?
+        .iter()
+        .map(|name| open(&replica.cache_dir_path().join(name)))
+        .collect::<Result<_>>()?;
+    Ok((replica_file, tree_r_last))
+}
+
+fn storage_size(storage: &dyn SectorStorage, path: &Path) -> Result<u64> {
+    storage
+        .size(path)
+        .map_err(|err| Error::cache_file(path, err))
+}
+
+fn stage_file(storage: &dyn SectorStorage, path: &Path, dest: &Path) -> Result<()> {
+    trace!("staging {:?} as {:?}", path, dest);
+    copy_to_local(storage, path, dest).map_err(|err| Error::cache_file(path, err))?;
+    Ok(())
+}
+
+fn create_sparse_file(path: &Path, len: u64) -> Result<()> {
+    let file = File::create(path)?;
+    file.set_len(len)?;
+    Ok(())
+}
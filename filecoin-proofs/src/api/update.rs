@@ -3,7 +3,7 @@ use std::fs;
 use std::io::{Read, Write};
 use std::path::Path;
 
-use anyhow::{ensure, Context, Result};
+use anyhow::Context;
 use bellperson::groth16;
 use blstrs::Scalar as Fr;
 use ff::PrimeField;
@@ -18,9 +18,14 @@ use sha2::{Digest, Sha256};
 use storage_proofs_core::{
     api_version::ApiVersion,
     compound_proof::{self, CompoundProof},
+    measurements::Operation,
     merkle::{get_base_tree_count, MerkleTreeTrait},
+    metrics::{OpDetails, OpTimer},
     multi_proof::MultiProof,
+    progress::SealContext,
     proof::ProofScheme,
+    proofs_span,
+    spans::ShortId,
     util::NODE_SIZE,
 };
 use storage_proofs_porep::stacked::TemporaryAux;
@@ -33,6 +38,8 @@ use storage_proofs_update::{
 };
 
 use crate::{
+    api::preflight::{check_parameters_exist, check_update_phase},
+    api::resources::update_parameters_path,
     api::util::{self, get_aggregate_target_len, pad_inputs_to_target, pad_proofs_to_target},
     caches::{
         get_empty_sector_update_params, get_empty_sector_update_verifying_key, get_stacked_srs_key,
@@ -40,10 +47,11 @@ use crate::{
     },
     chunk_iter::ChunkIterator,
     constants::{DefaultPieceDomain, DefaultPieceHasher},
+    error::{ensure_input, Result},
     pieces::verify_pieces,
     types::{
         AggregateSnarkProof, Commitment, EmptySectorUpdateEncoded, EmptySectorUpdateProof,
-        PieceInfo, PoRepConfig, SectorUpdateConfig, SectorUpdateProofInputs,
+        PieceInfo, PoRepConfig, ProofPhase, SectorUpdateConfig, SectorUpdateProofInputs,
     },
 };
 
@@ -99,17 +107,56 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     sector_key_cache_path: &Path,
     staged_data_path: &Path,
     piece_infos: &[PieceInfo],
+) -> Result<EmptySectorUpdateEncoded> {
+    encode_into_with_context::<Tree>(
+        config,
+        new_replica_path,
+        new_cache_path,
+        sector_key_path,
+        sector_key_cache_path,
+        staged_data_path,
+        piece_infos,
+        &SealContext::default(),
+    )
+}
+
+/// Like [`encode_into`], but reports the built trees to `ctx` and stops with a `Cancelled` error
+/// if the cancellation was requested. The partially written `new_replica_path` and
+/// `new_cache_path` are overwritten when encoding again.
+#[allow(clippy::too_many_arguments)]
+pub fn encode_into_with_context<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    config: &SectorUpdateConfig,
+    new_replica_path: &Path,
+    new_cache_path: &Path,
+    sector_key_path: &Path,
+    sector_key_cache_path: &Path,
+    staged_data_path: &Path,
+    piece_infos: &[PieceInfo],
+    ctx: &SealContext,
 ) -> Result<EmptySectorUpdateEncoded> {
     info!("encode_into:start");
+    let _span = proofs_span!("encode_into", replica = %new_replica_path.display());
+    let _proofs_context = ctx.enter_proofs_context();
+    let timer = OpTimer::start(
+        Operation::EncodeInto,
+        OpDetails::sector_size(u64::from(config.sector_size))
+            .with_bytes(u64::from(config.sector_size)),
+    );
 
-    ensure!(
+    ensure_input!(
         fs::metadata(sector_key_cache_path)?.is_dir(),
         "sector_key_cache_path must be a directory",
     );
+    check_update_phase::<Tree>(
+        config,
+        ProofPhase::EncodeInto,
+        new_cache_path,
+        new_replica_path,
+    )?;
     let p_aux = util::get_p_aux::<Tree>(sector_key_cache_path)?;
     let t_aux = util::get_t_aux::<Tree>(sector_key_cache_path, u64::from(config.sector_size))?;
 
-    ensure!(
+    ensure_input!(
         fs::metadata(new_cache_path)?.is_dir(),
         "new_cache_path must be a directory"
     );
@@ -117,7 +164,7 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
         get_new_configs_from_t_aux_old::<Tree>(&t_aux, new_cache_path, config.nodes_count)?;
 
     let (comm_r_domain, comm_r_last_domain, comm_d_domain) =
-        EmptySectorUpdate::<Tree>::encode_into(
+        EmptySectorUpdate::<Tree>::encode_into_with_context(
             config.nodes_count,
             tree_d_new_config,
             tree_r_last_new_config,
@@ -127,6 +174,7 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
             sector_key_path,
             staged_data_path,
             h_default(config.nodes_count),
+            ctx,
         )?;
 
     let mut comm_d = [0; 32];
@@ -141,13 +189,13 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     // commitments, but given that this check exists during the
     // sealing process and may have historically been hit, this is
     // considered a consistency check
-    ensure!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
-    ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
-    ensure!(
+    ensure_input!(comm_d != [0; 32], "Invalid all zero commitment (comm_d)");
+    ensure_input!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+    ensure_input!(
         comm_r_last != [0; 32],
         "Invalid all zero commitment (comm_r)"
     );
-    ensure!(
+    ensure_input!(
         verify_pieces(&comm_d, piece_infos, config.sector_size)?,
         "pieces and comm_d do not match"
     );
@@ -159,6 +207,7 @@ pub fn encode_into<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     #[cfg(not(feature = "fixed-rows-to-discard"))]
     util::persist_t_aux::<Tree>(&t_aux, new_cache_path)?;
 
+    timer.finish();
     info!("encode_into:finish");
 
     Ok(EmptySectorUpdateEncoded {
@@ -216,11 +265,11 @@ pub fn decode_from_range<R: Read, S: Read, W: Write>(
         // If the bytes that still need to be read is smaller then the chunk size, then use that
         // size.
         let current_chunk_size = cmp::min(bytes_length - chunk_offset, chunk_size);
-        ensure!(
+        ensure_input!(
             current_chunk_size <= input_chunk.len(),
             "not enough bytes in input",
         );
-        ensure!(
+        ensure_input!(
             current_chunk_size <= sector_key_chunk.len(),
             "not enough bytes in sector key",
         );
@@ -258,6 +307,7 @@ pub fn decode_from<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
     comm_d_new: Commitment,
 ) -> Result<()> {
     info!("decode_from:start");
+    let _span = proofs_span!("decode_from", replica = %replica_path.display());
 
     let p_aux = util::get_p_aux::<Tree>(sector_key_cache_path)?;
 
@@ -289,6 +339,7 @@ pub fn remove_encoded_data<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>
     comm_d_new: Commitment,
 ) -> Result<()> {
     info!("remove_data:start");
+    let _span = proofs_span!("remove_data", replica = %replica_path.display());
 
     let p_aux = util::get_p_aux::<Tree>(replica_cache_path)?;
     let t_aux = util::get_t_aux::<Tree>(replica_cache_path, u64::from(config.sector_size))?;
@@ -335,6 +386,10 @@ pub fn generate_single_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher =
     replica_cache_path: &Path,
 ) -> Result<PartitionProof<Tree>> {
     info!("generate_single_partition_proof:start");
+    let _span = proofs_span!(
+        "generate_single_partition_proof",
+        partition = partition_index
+    );
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -347,7 +402,7 @@ pub fn generate_single_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher =
     let p_aux_old = util::get_p_aux::<Tree>(sector_key_cache_path)?;
 
     let partitions = usize::from(config.update_partitions);
-    ensure!(partition_index < partitions, "invalid partition index");
+    ensure_input!(partition_index < partitions, "invalid partition index");
 
     let public_inputs: storage_proofs_update::PublicInputs = PublicInputs {
         k: partition_index,
@@ -400,7 +455,7 @@ pub fn verify_single_partition_proof<Tree: 'static + MerkleTreeTrait<Hasher = Tr
         PublicParams::from_sector_size(u64::from(config.sector_size));
 
     let partitions = usize::from(config.update_partitions);
-    ensure!(partition_index < partitions, "invalid partition index");
+    ensure_input!(partition_index < partitions, "invalid partition index");
 
     let public_inputs: storage_proofs_update::PublicInputs = PublicInputs {
         k: partition_index,
@@ -430,6 +485,10 @@ pub fn generate_partition_proofs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRH
     replica_cache_path: &Path,
 ) -> Result<Vec<PartitionProof<Tree>>> {
     info!("generate_partition_proofs:start");
+    let _span = proofs_span!(
+        "generate_partition_proofs",
+        comm_r_new = %ShortId(&comm_r_new)
+    );
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -520,6 +579,14 @@ pub fn generate_empty_sector_update_proof_with_vanilla<
     comm_d_new: Commitment,
 ) -> Result<EmptySectorUpdateProof> {
     info!("generate_empty_sector_update_proof_with_vanilla:start");
+    let _span = proofs_span!(
+        "generate_empty_sector_update_proof_with_vanilla",
+        comm_r_new = %ShortId(&comm_r_new)
+    );
+    let timer = OpTimer::start(
+        Operation::GenerateEmptySectorUpdateProof,
+        OpDetails::sector_size(u64::from(porep_config.sector_size)),
+    );
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
@@ -554,6 +621,7 @@ pub fn generate_empty_sector_update_proof_with_vanilla<
         &groth_params,
     )?;
 
+    timer.finish();
     info!("generate_empty_sector_update_proof_with_vanilla:finish");
 
     let proofs_bytes = util::proofs_to_bytes(&proofs)?;
@@ -572,14 +640,29 @@ pub fn generate_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher
     replica_cache_path: &Path,
 ) -> Result<EmptySectorUpdateProof> {
     info!("generate_empty_sector_update_proof:start");
+    let _span = proofs_span!(
+        "generate_empty_sector_update_proof",
+        comm_r_new = %ShortId(&comm_r_new)
+    );
+    let timer = OpTimer::start(
+        Operation::GenerateEmptySectorUpdateProof,
+        OpDetails::sector_size(u64::from(porep_config.sector_size)),
+    );
+
+    let config = SectorUpdateConfig::from_porep_config(porep_config);
+    check_update_phase::<Tree>(
+        &config,
+        ProofPhase::GenerateUpdateProof,
+        replica_cache_path,
+        replica_path,
+    )?;
+    check_parameters_exist(&update_parameters_path::<Tree>(config.sector_size))?;
 
     let comm_r_old_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_old)?;
     let comm_r_new_safe = <TreeRHasher as Hasher>::Domain::try_from_bytes(&comm_r_new)?;
 
     let comm_d_new_safe = DefaultPieceDomain::try_from_bytes(&comm_d_new)?;
 
-    let config = SectorUpdateConfig::from_porep_config(porep_config);
-
     let p_aux_old = util::get_p_aux::<Tree>(sector_key_cache_path)?;
 
     let partitions = usize::from(config.update_partitions);
@@ -622,6 +705,7 @@ pub fn generate_empty_sector_update_proof<Tree: 'static + MerkleTreeTrait<Hasher
         &groth_params,
     )?;
 
+    timer.finish();
     info!("generate_empty_sector_update_proof:finish");
 
     let proofs_bytes = util::proofs_to_bytes(&proofs)?;
@@ -695,15 +779,15 @@ pub fn get_sector_update_inputs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHa
     // commitments, but given that this check exists during the
     // sealing process and may have historically been hit, this is
     // considered a consistency check
-    ensure!(
+    ensure_input!(
         comm_r_old != [0; 32],
         "Invalid all zero commitment (comm_r_old)"
     );
-    ensure!(
+    ensure_input!(
         comm_r_new != [0; 32],
         "Invalid all zero commitment (comm_r_new)"
     );
-    ensure!(
+    ensure_input!(
         comm_d_new != [0; 32],
         "Invalid all zero commitment (comm_d_new)"
     );
@@ -742,7 +826,7 @@ pub fn get_sector_update_inputs<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHa
                 Some(k),
             )
         })
-        .collect::<Result<_>>()?;
+        .collect::<anyhow::Result<_>>()?;
 
     trace!("get_sector_update_inputs:finish");
 
@@ -779,22 +863,22 @@ pub fn aggregate_empty_sector_update_proofs<
         "aggregate_empty_sector_update_proofs using API Version {}",
         porep_config.api_version
     );
-    ensure!(
+    ensure_input!(
         porep_config.api_version >= ApiVersion::V1_2_0,
         "Empty Sector Update proof aggregation is supported in ApiVersion 1.2.0 or later"
     );
-    ensure!(
+    ensure_input!(
         aggregate_version == groth16::aggregate::AggregateVersion::V2,
         "Empty sector update aggregation requires SnarkPackV2"
     );
-    ensure!(
+    ensure_input!(
         !sector_update_inputs.is_empty(),
         "cannot aggregate with empty sector_update_inputs"
     );
 
     let h = sector_update_inputs[0].h;
     for sector_update_input in sector_update_inputs {
-        ensure!(
+        ensure_input!(
             h == sector_update_input.h,
             "mismatched h values in sector update aggregation inputs!"
         );
@@ -819,7 +903,7 @@ pub fn aggregate_empty_sector_update_proofs<
     // proofs since the multi proof type takes the partitions into
     // account
     let target_proofs_len = get_aggregate_target_len(proofs.len());
-    ensure!(
+    ensure_input!(
         target_proofs_len > 1,
         "cannot aggregate less than two proofs"
     );
@@ -862,11 +946,11 @@ pub fn verify_aggregate_sector_update_proofs<
         "verify_aggregate_sector_update_proofs using API Version {}",
         porep_config.api_version
     );
-    ensure!(
+    ensure_input!(
         porep_config.api_version >= ApiVersion::V1_2_0,
         "Empty Sector Update proof aggregation is supported in ApiVersion 1.2.0 or later"
     );
-    ensure!(
+    ensure_input!(
         aggregate_version == groth16::aggregate::AggregateVersion::V2,
         "Empty sector update aggregate verification requires SnarkPackV2"
     );
@@ -876,18 +960,18 @@ pub fn verify_aggregate_sector_update_proofs<
 
     let aggregated_proofs_len = aggregate_proof.tmipp.gipa.nproofs as usize;
 
-    ensure!(aggregated_proofs_len != 0, "cannot verify zero proofs");
-    ensure!(
+    ensure_input!(aggregated_proofs_len != 0, "cannot verify zero proofs");
+    ensure_input!(
         !sector_update_inputs.is_empty(),
         "cannot verify with empty inputs"
     );
-    ensure!(
+    ensure_input!(
         !inputs.is_empty(),
         "cannot verify with empty sector_update_inputs"
     );
     let h = inputs[0].h;
     for input in inputs {
-        ensure!(
+        ensure_input!(
             h == input.h,
             "mismatched h values in sector update verify aggregation inputs!"
         );
@@ -898,11 +982,11 @@ pub fn verify_aggregate_sector_update_proofs<
         aggregated_proofs_len,
     );
 
-    ensure!(
+    ensure_input!(
         aggregated_proofs_len > 1,
         "cannot verify less than two proofs"
     );
-    ensure!(
+    ensure_input!(
         aggregated_proofs_len == aggregated_proofs_len.next_power_of_two(),
         "cannot verify non-pow2 aggregate seal proofs"
     );
@@ -913,7 +997,7 @@ pub fn verify_aggregate_sector_update_proofs<
     // each vector in 'sector_update_inputs' are the public inputs to
     // one Groth16 proof
     let num_inputs_per_proof = get_aggregate_target_len(num_inputs) / aggregated_proofs_len;
-    ensure!(num_inputs_per_proof == 1, "num_inputs per proof mismatch");
+    ensure_input!(num_inputs_per_proof == 1, "num_inputs per proof mismatch");
     let target_inputs_len = aggregated_proofs_len * num_inputs_per_proof;
 
     trace!(
@@ -923,7 +1007,7 @@ pub fn verify_aggregate_sector_update_proofs<
         target_inputs_len,
     );
 
-    ensure!(
+    ensure_input!(
         target_inputs_len % aggregated_proofs_len == 0,
         "invalid number of inputs provided",
     );
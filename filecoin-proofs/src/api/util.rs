@@ -1,6 +1,11 @@
-use std::{fs, mem::size_of, path::Path};
+use std::{
+    fs::{self, File},
+    io,
+    mem::size_of,
+    path::Path,
+};
 
-use anyhow::{ensure, Context, Result};
+use anyhow::Context;
 use bellperson::groth16::{self, Proof};
 use blstrs::{Bls12, Scalar as Fr};
 use filecoin_hashers::{Domain, Hasher};
@@ -17,6 +22,7 @@ use typenum::Unsigned;
 
 use crate::{
     constants::DefaultPieceHasher,
+    error::{ensure_input, Error, Result},
     types::{Commitment, PoRepConfig, SectorSize, SectorUpdateConfig},
 };
 
@@ -24,9 +30,13 @@ pub fn as_safe_commitment<H: Domain, T: AsRef<str>>(
     comm: &[u8; 32],
     commitment_name: T,
 ) -> Result<H> {
-    bytes_into_fr(comm)
-        .map(Into::into)
-        .with_context(|| format!("Invalid commitment ({})", commitment_name.as_ref(),))
+    bytes_into_fr(comm).map(Into::into).map_err(|err| {
+        Error::InvalidInput(format!(
+            "Invalid commitment ({}): {}",
+            commitment_name.as_ref(),
+            err
+        ))
+    })
 }
 
 pub fn commitment_from_fr(fr: Fr) -> Commitment {
@@ -42,11 +52,17 @@ pub fn get_base_tree_size<Tree: MerkleTreeTrait>(sector_size: SectorSize) -> Res
         / size_of::<<Tree::Hasher as Hasher>::Domain>()
         / get_base_tree_count::<Tree>();
 
-    get_merkle_tree_len(base_tree_leaves, Tree::Arity::to_usize())
+    Ok(get_merkle_tree_len(
+        base_tree_leaves,
+        Tree::Arity::to_usize(),
+    )?)
 }
 
 pub fn get_base_tree_leafs<Tree: MerkleTreeTrait>(base_tree_size: usize) -> Result<usize> {
-    get_merkle_tree_leafs(base_tree_size, Tree::Arity::to_usize())
+    Ok(get_merkle_tree_leafs(
+        base_tree_size,
+        Tree::Arity::to_usize(),
+    )?)
 }
 
 pub(crate) fn proofs_to_bytes(proofs: &[Proof<Bls12>]) -> Result<Vec<u8>> {
@@ -63,10 +79,11 @@ pub(crate) fn persist_p_aux<Tree: MerkleTreeTrait>(
     cache_path: &Path,
 ) -> Result<()> {
     let p_aux_path = cache_path.join(CacheKey::PAux.to_string());
-    let p_aux_bytes = bincode::serialize(&p_aux)?;
+    let p_aux_bytes = bincode::serialize(&p_aux).context("could not serialize p_aux")?;
 
     fs::write(&p_aux_path, p_aux_bytes)
-        .with_context(|| format!("could not write to file p_aux={:?}", p_aux_path))
+        .with_context(|| format!("could not write to file p_aux={:?}", p_aux_path))?;
+    Ok(())
 }
 
 /// Instantiates p_aux from the specified cache_dir for access to comm_c and comm_r_last.
@@ -74,12 +91,9 @@ pub(crate) fn get_p_aux<Tree: MerkleTreeTrait>(
     cache_path: &Path,
 ) -> Result<PersistentAux<<Tree::Hasher as Hasher>::Domain>> {
     let p_aux_path = cache_path.join(CacheKey::PAux.to_string());
-    let p_aux_bytes = fs::read(&p_aux_path)
-        .with_context(|| format!("could not read file p_aux={:?}", p_aux_path))?;
+    let p_aux_bytes = fs::read(&p_aux_path).map_err(|err| Error::cache_file(&p_aux_path, err))?;
 
-    let p_aux = bincode::deserialize(&p_aux_bytes)?;
-
-    Ok(p_aux)
+    bincode::deserialize(&p_aux_bytes).map_err(|err| Error::cache_file(p_aux_path, err))
 }
 
 fn read_t_aux_file<Tree: MerkleTreeTrait>(
@@ -87,10 +101,10 @@ fn read_t_aux_file<Tree: MerkleTreeTrait>(
 ) -> Result<TemporaryAux<Tree, DefaultPieceHasher>> {
     let t_aux_path = cache_path.join(CacheKey::TAux.to_string());
     trace!("Instantiating TemporaryAux from {:?}", cache_path);
-    let t_aux_bytes = fs::read(&t_aux_path)
-        .with_context(|| format!("could not read file t_aux={:?}", t_aux_path))?;
+    let t_aux_bytes = fs::read(&t_aux_path).map_err(|err| Error::cache_file(&t_aux_path, err))?;
 
-    let mut res: TemporaryAux<Tree, DefaultPieceHasher> = bincode::deserialize(&t_aux_bytes)?;
+    let mut res: TemporaryAux<Tree, DefaultPieceHasher> =
+        bincode::deserialize(&t_aux_bytes).map_err(|err| Error::cache_file(t_aux_path, err))?;
     res.set_cache_path(cache_path);
     trace!("Set TemporaryAux cache_path to {:?}", cache_path);
 
@@ -152,10 +166,24 @@ pub(crate) fn persist_t_aux<Tree: MerkleTreeTrait>(
     cache_path: &Path,
 ) -> Result<()> {
     let t_aux_path = cache_path.join(CacheKey::TAux.to_string());
-    let t_aux_bytes = bincode::serialize(&t_aux)?;
+    let t_aux_bytes = bincode::serialize(&t_aux).context("could not serialize t_aux")?;
 
     fs::write(&t_aux_path, t_aux_bytes)
-        .with_context(|| format!("could not write to file t_aux={:?}", t_aux_path))
+        .with_context(|| format!("could not write to file t_aux={:?}", t_aux_path))?;
+    Ok(())
+}
+
+/// Syncs the entries of `dir` to disk, so that the files renamed into it survive a crash.
+#[cfg(unix)]
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened as files on other platforms, the renames are only as durable as
+/// the filesystem makes them.
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
 }
 
 /// Given a value, get one suitable for aggregation.
@@ -178,11 +206,11 @@ pub(crate) fn pad_proofs_to_target(
         target_len,
         proofs.len()
     );
-    ensure!(
+    ensure_input!(
         target_len >= proofs.len(),
         "target len must be greater than actual num proofs"
     );
-    ensure!(
+    ensure_input!(
         proofs.last().is_some(),
         "invalid last proof for duplication"
     );
@@ -196,11 +224,11 @@ pub(crate) fn pad_proofs_to_target(
         .collect();
     proofs.append(&mut padding);
 
-    ensure!(
+    ensure_input!(
         proofs.len().next_power_of_two() == proofs.len(),
         "proof count must be a power of 2 for aggregation"
     );
-    ensure!(
+    ensure_input!(
         proofs.len() <= SRS_MAX_PROOFS_TO_AGGREGATE,
         "proof count for aggregation is larger than the max supported value"
     );
@@ -214,7 +242,7 @@ pub(crate) fn pad_inputs_to_target(
     num_inputs_per_proof: usize,
     target_len: usize,
 ) -> Result<Vec<Vec<Fr>>> {
-    ensure!(
+    ensure_input!(
         !fr_inputs.is_empty(),
         "cannot aggregate with empty public inputs"
     );
@@ -223,7 +251,7 @@ pub(crate) fn pad_inputs_to_target(
     let mut new_inputs = fr_inputs.to_owned();
 
     if target_len != num_inputs {
-        ensure!(
+        ensure_input!(
             target_len > num_inputs,
             "target len must be greater than actual num inputs"
         );
@@ -233,7 +261,7 @@ pub(crate) fn pad_inputs_to_target(
         while target_len != num_inputs {
             new_inputs.extend_from_slice(duplicate_inputs);
             num_inputs += num_inputs_per_proof;
-            ensure!(
+            ensure_input!(
                 num_inputs <= target_len,
                 "num_inputs extended beyond target"
             );
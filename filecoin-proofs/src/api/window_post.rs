@@ -1,13 +1,16 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-use anyhow::{ensure, Context, Result};
+use anyhow::{anyhow, Context};
 use filecoin_hashers::Hasher;
-use log::info;
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use log::{info, warn};
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use storage_proofs_core::{
     compound_proof::{self, CompoundProof},
+    measurements::Operation,
     merkle::MerkleTreeTrait,
+    metrics::{OpDetails, OpTimer},
     multi_proof::MultiProof,
+    proofs_span,
     sector::SectorId,
 };
 use storage_proofs_post::fallback::{
@@ -16,14 +19,16 @@ use storage_proofs_post::fallback::{
 
 use crate::{
     api::{
-        as_safe_commitment, get_partitions_for_window_post, partition_vanilla_proofs,
+        as_safe_commitment, generate_fallback_sector_challenges, generate_single_vanilla_proof,
+        get_partitions_for_window_post, partition_vanilla_proofs, sector_proof_is_valid,
         single_partition_vanilla_proofs, util,
     },
     caches::{get_post_params, get_post_verifying_key},
+    error::{ensure_input, Error, Result},
     parameters::window_post_setup_params,
     types::{
-        ChallengeSeed, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo, ProverId,
-        PublicReplicaInfo, SnarkProof,
+        ChallengeSeed, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo, ProofsContext,
+        ProverId, PublicReplicaInfo, SnarkProof, WindowPoStOutput,
     },
     PartitionSnarkProof, PoStType,
 };
@@ -36,11 +41,34 @@ pub fn generate_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
     vanilla_proofs: Vec<FallbackPoStSectorProof<Tree>>,
 ) -> Result<SnarkProof> {
     info!("generate_window_post_with_vanilla:start");
-    ensure!(
+    let _span = proofs_span!(
+        "generate_window_post_with_vanilla",
+        sectors = vanilla_proofs.len()
+    );
+    let timer = OpTimer::start(
+        Operation::GenerateWindowPost,
+        OpDetails::sector_size(u64::from(post_config.sector_size)),
+    );
+    ensure_input!(
         post_config.typ == PoStType::Window,
         "invalid post config type"
     );
 
+    let proof = prove_window_post_with_vanilla(post_config, randomness, prover_id, vanilla_proofs)?;
+
+    timer.finish();
+    info!("generate_window_post_with_vanilla:finish");
+
+    Ok(proof)
+}
+
+/// Generates the SNARK of a Window proof-of-spacetime from the vanilla proofs of all sectors.
+fn prove_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    prover_id: ProverId,
+    vanilla_proofs: Vec<FallbackPoStSectorProof<Tree>>,
+) -> Result<SnarkProof> {
     let randomness_safe: <Tree::Hasher as Hasher>::Domain =
         as_safe_commitment(randomness, "randomness")?;
     let prover_id_safe: <Tree::Hasher as Hasher>::Domain =
@@ -91,8 +119,6 @@ pub fn generate_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
         &groth_params,
     )?;
 
-    info!("generate_window_post_with_vanilla:finish");
-
     util::proofs_to_bytes(&proofs)
 }
 
@@ -104,11 +130,30 @@ pub fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
 ) -> Result<SnarkProof> {
     info!("generate_window_post:start");
-    ensure!(
+    let _span = proofs_span!("generate_window_post", sectors = replicas.len());
+    let timer = OpTimer::start(
+        Operation::GenerateWindowPost,
+        OpDetails::sector_size(u64::from(post_config.sector_size)),
+    );
+    ensure_input!(
         post_config.typ == PoStType::Window,
         "invalid post config type"
     );
 
+    // Replicas in a storage are proven from the data their challenges read, instead of their
+    // whole trees.
+    if replicas.values().any(|replica| replica.storage().is_some()) {
+        let vanilla_proofs =
+            window_post_vanilla_proofs(post_config, randomness, replicas, prover_id)?;
+        let proof =
+            prove_window_post_with_vanilla(post_config, randomness, prover_id, vanilla_proofs)?;
+
+        timer.finish();
+        info!("generate_window_post:finish");
+
+        return Ok(proof);
+    }
+
     let randomness_safe = as_safe_commitment(randomness, "randomness")?;
     let prover_id_safe = as_safe_commitment(&prover_id, "prover_id")?;
 
@@ -126,16 +171,18 @@ pub fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
         FallbackPoStCompound::setup(&setup_params)?;
     let groth_params = get_post_params::<Tree>(post_config)?;
 
+    let context = ProofsContext::current();
     let trees: Vec<_> = replicas
         .par_iter()
         .map(|(sector_id, replica)| {
+            let _context = context.enter();
             replica
                 .merkle_tree(post_config.sector_size)
                 .with_context(|| {
                     format!("generate_window_post: merkle_tree failed: {:?}", sector_id)
                 })
         })
-        .collect::<Result<_>>()?;
+        .collect::<anyhow::Result<_>>()?;
 
     let mut pub_sectors = Vec::with_capacity(sector_count);
     let mut priv_sectors = Vec::with_capacity(sector_count);
@@ -172,11 +219,154 @@ pub fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
     let proofs =
         FallbackPoStCompound::prove(&pub_params, &pub_inputs, &priv_inputs, &groth_params)?;
 
+    timer.finish();
     info!("generate_window_post:finish");
 
     util::proofs_to_bytes(&proofs)
 }
 
+/// Generates the vanilla proofs of all sectors of a Window PoSt, in the order of their sector id.
+fn window_post_vanilla_proofs<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+) -> Result<Vec<FallbackPoStSectorProof<Tree>>> {
+    let sector_ids: Vec<_> = replicas.keys().copied().collect();
+    let challenges = generate_fallback_sector_challenges::<Tree>(
+        post_config,
+        randomness,
+        &sector_ids,
+        prover_id,
+    )?;
+
+    let context = ProofsContext::current();
+    replicas
+        .par_iter()
+        .map(|(sector_id, replica)| {
+            let _context = context.enter();
+            let sector_challenges = challenges
+                .get(sector_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            generate_single_vanilla_proof(post_config, *sector_id, replica, sector_challenges)
+        })
+        .collect()
+}
+
+/// Generates a Window proof-of-spacetime over the sectors that can be proven.
+///
+/// The vanilla proofs are generated per sector, faulty sectors are skipped and the SNARK is
+/// generated over the remaining sectors with the same randomness. A sector is faulty if its
+/// vanilla proof can't be generated or doesn't lead to its comm_r, e.g. because of a corrupt
+/// p_aux. With [`ApiVersion::V1_0_0`] the
+/// challenges of a sector depend on its position, so skipping a sector changes the challenges of
+/// the sectors after it; their vanilla proofs are generated again, which may reveal new faults.
+/// The vanilla proofs of all other sectors are reused. It fails with [`Error::FaultySectors`] if
+/// all sectors are faulty.
+///
+/// [`ApiVersion::V1_0_0`]: storage_proofs_core::api_version::ApiVersion::V1_0_0
+pub fn generate_window_post_skipping_faults<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PrivateReplicaInfo<Tree>>,
+    prover_id: ProverId,
+) -> Result<WindowPoStOutput> {
+    info!("generate_window_post_skipping_faults:start");
+    let _span = proofs_span!(
+        "generate_window_post_skipping_faults",
+        sectors = replicas.len()
+    );
+    ensure_input!(
+        post_config.typ == PoStType::Window,
+        "invalid post config type"
+    );
+
+    let context = ProofsContext::current();
+    let mut skipped = BTreeSet::new();
+    // The vanilla proofs that were generated, with the challenges they were generated for.
+    let mut proven: BTreeMap<SectorId, (Vec<u64>, FallbackPoStSectorProof<Tree>)> = BTreeMap::new();
+    loop {
+        let remaining: Vec<_> = replicas
+            .keys()
+            .filter(|sector_id| !skipped.contains(*sector_id))
+            .copied()
+            .collect();
+        if remaining.is_empty() {
+            return Err(Error::FaultySectors {
+                sectors: skipped.into_iter().collect(),
+                source: anyhow!("all sectors are faulty"),
+            });
+        }
+
+        let challenges = generate_fallback_sector_challenges::<Tree>(
+            post_config,
+            randomness,
+            &remaining,
+            prover_id,
+        )?;
+        proven.retain(|sector_id, (proven_challenges, _)| {
+            challenges.get(sector_id) == Some(proven_challenges)
+        });
+        let results: Vec<_> = challenges
+            .into_iter()
+            .filter(|(sector_id, _)| !proven.contains_key(sector_id))
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(sector_id, challenges)| {
+                let _context = context.enter();
+                let replica = &replicas[&sector_id];
+                let proof = generate_single_vanilla_proof::<Tree>(
+                    post_config,
+                    sector_id,
+                    replica,
+                    &challenges,
+                )
+                .and_then(|proof| {
+                    let comm_r = replica.safe_comm_r()?;
+                    if sector_proof_is_valid(post_config, &comm_r, &challenges, &proof) {
+                        Ok(proof)
+                    } else {
+                        Err(Error::Internal(anyhow!(
+                            "vanilla proof of sector {:?} doesn't match its comm_r",
+                            sector_id
+                        )))
+                    }
+                });
+                (sector_id, challenges, proof)
+            })
+            .collect();
+
+        let skipped_count = skipped.len();
+        for (sector_id, challenges, proof) in results {
+            match proof {
+                Ok(proof) => {
+                    proven.insert(sector_id, (challenges, proof));
+                }
+                Err(err @ Error::InvalidInput(_)) => return Err(err),
+                Err(err) => {
+                    warn!("skipping faulty sector {:?}: {:?}", sector_id, err);
+                    skipped.insert(sector_id);
+                }
+            }
+        }
+        if skipped.len() == skipped_count {
+            break;
+        }
+    }
+
+    let vanilla_proofs = proven.into_values().map(|(_, proof)| proof).collect();
+    let proof =
+        generate_window_post_with_vanilla(post_config, randomness, prover_id, vanilla_proofs)?;
+
+    info!("generate_window_post_skipping_faults:finish");
+
+    Ok(WindowPoStOutput {
+        proof,
+        skipped: skipped.into_iter().collect(),
+    })
+}
+
 /// Verifies a window proof-of-spacetime.
 pub fn verify_window_post<Tree: 'static + MerkleTreeTrait>(
     post_config: &PoStConfig,
@@ -187,24 +377,57 @@ pub fn verify_window_post<Tree: 'static + MerkleTreeTrait>(
 ) -> Result<bool> {
     info!("verify_window_post:start");
 
-    ensure!(
+    ensure_input!(
         post_config.typ == PoStType::Window,
         "invalid post config type"
     );
 
-    let randomness_safe = as_safe_commitment(randomness, "randomness")?;
-    let prover_id_safe = as_safe_commitment(&prover_id, "prover_id")?;
-
-    let vanilla_params = window_post_setup_params(post_config);
     let partitions = get_partitions_for_window_post(replicas.len(), post_config);
+    let pub_params = window_post_public_params::<Tree>(post_config, partitions)?;
+    let pub_inputs = window_post_public_inputs::<Tree>(randomness, replicas, prover_id)?;
+
+    let is_valid = {
+        let verifying_key = get_post_verifying_key::<Tree>(post_config)?;
+        let multi_proof = MultiProof::new_from_bytes(partitions, proof, &verifying_key)?;
+
+        FallbackPoStCompound::verify(
+            &pub_params,
+            &pub_inputs,
+            &multi_proof,
+            &fallback::ChallengeRequirements {
+                minimum_challenge_count: post_config.challenge_count * post_config.sector_count,
+            },
+        )?
+    };
+    if !is_valid {
+        return Ok(false);
+    }
+
+    info!("verify_window_post:finish");
 
+    Ok(true)
+}
+
+pub(crate) fn window_post_public_params<'a, Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    partitions: Option<usize>,
+) -> Result<compound_proof::PublicParams<'a, FallbackPoSt<'a, Tree>>> {
     let setup_params = compound_proof::SetupParams {
-        vanilla_params,
+        vanilla_params: window_post_setup_params(post_config),
         partitions,
         priority: false,
     };
-    let pub_params: compound_proof::PublicParams<'_, FallbackPoSt<'_, Tree>> =
-        FallbackPoStCompound::setup(&setup_params)?;
+    Ok(FallbackPoStCompound::setup(&setup_params)?)
+}
+
+/// Returns the public inputs for verifying a Window PoSt over `replicas`.
+pub(crate) fn window_post_public_inputs<Tree: 'static + MerkleTreeTrait>(
+    randomness: &ChallengeSeed,
+    replicas: &BTreeMap<SectorId, PublicReplicaInfo>,
+    prover_id: ProverId,
+) -> Result<fallback::PublicInputs<<Tree::Hasher as Hasher>::Domain>> {
+    let randomness_safe = as_safe_commitment(randomness, "randomness")?;
+    let prover_id_safe = as_safe_commitment(&prover_id, "prover_id")?;
 
     let pub_sectors: Vec<_> = replicas
         .iter()
@@ -219,33 +442,12 @@ pub fn verify_window_post<Tree: 'static + MerkleTreeTrait>(
         })
         .collect::<Result<_>>()?;
 
-    let pub_inputs = fallback::PublicInputs {
+    Ok(fallback::PublicInputs {
         randomness: randomness_safe,
         prover_id: prover_id_safe,
         sectors: pub_sectors,
         k: None,
-    };
-
-    let is_valid = {
-        let verifying_key = get_post_verifying_key::<Tree>(post_config)?;
-        let multi_proof = MultiProof::new_from_bytes(partitions, proof, &verifying_key)?;
-
-        FallbackPoStCompound::verify(
-            &pub_params,
-            &pub_inputs,
-            &multi_proof,
-            &fallback::ChallengeRequirements {
-                minimum_challenge_count: post_config.challenge_count * post_config.sector_count,
-            },
-        )?
-    };
-    if !is_valid {
-        return Ok(false);
-    }
-
-    info!("verify_window_post:finish");
-
-    Ok(true)
+    })
 }
 
 /// Generates a Window proof-of-spacetime with provided vanilla proofs of a single partition.
@@ -257,7 +459,11 @@ pub fn generate_single_window_post_with_vanilla<Tree: 'static + MerkleTreeTrait>
     partition_index: usize,
 ) -> Result<PartitionSnarkProof> {
     info!("generate_single_window_post_with_vanilla:start");
-    ensure!(
+    let _span = proofs_span!(
+        "generate_single_window_post_with_vanilla",
+        partition = partition_index
+    );
+    ensure_input!(
         post_config.typ == PoStType::Window,
         "invalid post config type"
     );
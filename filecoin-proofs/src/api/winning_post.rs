@@ -1,11 +1,15 @@
-use anyhow::{ensure, Context, Result};
+use anyhow::{anyhow, Context};
 use filecoin_hashers::Hasher;
-use log::info;
+use log::{info, warn};
 use storage_proofs_core::{
     compound_proof::{self, CompoundProof},
+    measurements::Operation,
     merkle::MerkleTreeTrait,
+    metrics::{OpDetails, OpTimer},
     multi_proof::MultiProof,
+    proofs_span,
     sector::SectorId,
+    settings,
 };
 use storage_proofs_post::fallback::{
     self, generate_sector_challenges, FallbackPoSt, FallbackPoStCompound, PrivateSector,
@@ -13,8 +17,12 @@ use storage_proofs_post::fallback::{
 };
 
 use crate::{
-    api::{as_safe_commitment, partition_vanilla_proofs, util},
+    api::{
+        as_safe_commitment, generate_fallback_sector_challenges, generate_single_vanilla_proof,
+        partition_vanilla_proofs, util,
+    },
     caches::{get_post_params, get_post_verifying_key},
+    error::{ensure_input, Error, Result},
     parameters::winning_post_setup_params,
     types::{
         ChallengeSeed, Commitment, FallbackPoStSectorProof, PoStConfig, PrivateReplicaInfo,
@@ -31,16 +39,40 @@ pub fn generate_winning_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
     vanilla_proofs: Vec<FallbackPoStSectorProof<Tree>>,
 ) -> Result<SnarkProof> {
     info!("generate_winning_post_with_vanilla:start");
-    ensure!(
+    let _span = proofs_span!(
+        "generate_winning_post_with_vanilla",
+        sectors = vanilla_proofs.len()
+    );
+    let timer = OpTimer::start(
+        Operation::GenerateWinningPost,
+        OpDetails::sector_size(u64::from(post_config.sector_size)),
+    );
+    ensure_input!(
         post_config.typ == PoStType::Winning,
         "invalid post config type"
     );
 
-    ensure!(
+    ensure_input!(
         vanilla_proofs.len() == post_config.sector_count,
         "invalid amount of vanilla proofs"
     );
 
+    let proof =
+        prove_winning_post_with_vanilla(post_config, randomness, prover_id, &vanilla_proofs)?;
+
+    timer.finish();
+    info!("generate_winning_post_with_vanilla:finish");
+
+    Ok(proof)
+}
+
+/// Generates the SNARK of a Winning proof-of-spacetime from the vanilla proofs of all sectors.
+fn prove_winning_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    prover_id: ProverId,
+    vanilla_proofs: &[FallbackPoStSectorProof<Tree>],
+) -> Result<SnarkProof> {
     let randomness_safe: <Tree::Hasher as Hasher>::Domain =
         as_safe_commitment(randomness, "randomness")?;
     let prover_id_safe: <Tree::Hasher as Hasher>::Domain =
@@ -58,7 +90,7 @@ pub fn generate_winning_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
     let groth_params = get_post_params::<Tree>(post_config)?;
 
     let mut pub_sectors = Vec::with_capacity(vanilla_proofs.len());
-    for vanilla_proof in &vanilla_proofs {
+    for vanilla_proof in vanilla_proofs {
         pub_sectors.push(PublicSector {
             id: vanilla_proof.sector_id,
             comm_r: vanilla_proof.comm_r,
@@ -78,7 +110,7 @@ pub fn generate_winning_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
         &pub_params.vanilla_params,
         &pub_inputs,
         partitions,
-        &vanilla_proofs,
+        vanilla_proofs,
     )?;
 
     let proofs = FallbackPoStCompound::prove_with_vanilla(
@@ -88,11 +120,54 @@ pub fn generate_winning_post_with_vanilla<Tree: 'static + MerkleTreeTrait>(
         &groth_params,
     )?;
 
-    info!("generate_winning_post_with_vanilla:finish");
-
     util::proofs_to_bytes(&proofs)
 }
 
+/// Generates the vanilla proofs of all sectors of a Winning proof-of-spacetime, see
+/// [`generate_single_vanilla_proof`].
+///
+/// Fails with [`Error::FaultySectors`] if the proofs of some sectors couldn't be generated.
+fn winning_post_vanilla_proofs<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    replicas: &[(SectorId, PrivateReplicaInfo<Tree>)],
+    prover_id: ProverId,
+) -> Result<Vec<FallbackPoStSectorProof<Tree>>> {
+    let sector_ids: Vec<_> = replicas.iter().map(|(sector_id, _)| *sector_id).collect();
+    let challenges = generate_fallback_sector_challenges::<Tree>(
+        post_config,
+        randomness,
+        &sector_ids,
+        prover_id,
+    )?;
+
+    let mut vanilla_proofs = Vec::with_capacity(replicas.len());
+    let mut faulty_sectors = Vec::new();
+    let mut errors = Vec::new();
+    for (sector_id, replica) in replicas {
+        let sector_challenges = challenges
+            .get(sector_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        match generate_single_vanilla_proof(post_config, *sector_id, replica, sector_challenges) {
+            Ok(vanilla_proof) => vanilla_proofs.push(vanilla_proof),
+            Err(err) => {
+                warn!("sector {:?} is faulty: {:?}", sector_id, err);
+                faulty_sectors.push(*sector_id);
+                errors.push(format!("{:#}", anyhow::Error::new(err)));
+            }
+        }
+    }
+    if !faulty_sectors.is_empty() {
+        return Err(Error::FaultySectors {
+            sectors: faulty_sectors,
+            source: anyhow!(errors.join("; ")),
+        });
+    }
+
+    Ok(vanilla_proofs)
+}
+
 /// Generates a Winning proof-of-spacetime.
 pub fn generate_winning_post<Tree: 'static + MerkleTreeTrait>(
     post_config: &PoStConfig,
@@ -101,16 +176,39 @@ pub fn generate_winning_post<Tree: 'static + MerkleTreeTrait>(
     prover_id: ProverId,
 ) -> Result<SnarkProof> {
     info!("generate_winning_post:start");
-    ensure!(
+    let _span = proofs_span!("generate_winning_post", sectors = replicas.len());
+    let timer = OpTimer::start(
+        Operation::GenerateWinningPost,
+        OpDetails::sector_size(u64::from(post_config.sector_size)),
+    );
+    ensure_input!(
         post_config.typ == PoStType::Winning,
         "invalid post config type"
     );
 
-    ensure!(
+    ensure_input!(
         replicas.len() == post_config.sector_count,
         "invalid amount of replicas"
     );
 
+    // Replicas in a storage are proven from the data their challenges read, instead of their
+    // whole trees.
+    if settings::current().winning_post_max_outstanding_reads > 0
+        || replicas
+            .iter()
+            .any(|(_, replica)| replica.storage().is_some())
+    {
+        let vanilla_proofs =
+            winning_post_vanilla_proofs(post_config, randomness, replicas, prover_id)?;
+        let proof =
+            prove_winning_post_with_vanilla(post_config, randomness, prover_id, &vanilla_proofs)?;
+
+        timer.finish();
+        info!("generate_winning_post:finish");
+
+        return Ok(proof);
+    }
+
     let randomness_safe: <Tree::Hasher as Hasher>::Domain =
         as_safe_commitment(randomness, "randomness")?;
     let prover_id_safe: <Tree::Hasher as Hasher>::Domain =
@@ -137,7 +235,7 @@ pub fn generate_winning_post<Tree: 'static + MerkleTreeTrait>(
                     format!("generate_winning_post: merkle_tree failed: {:?}", sector_id)
                 })
         })
-        .collect::<Result<Vec<_>>>()?;
+        .collect::<anyhow::Result<Vec<_>>>()?;
 
     let mut pub_sectors = Vec::with_capacity(param_sector_count);
     let mut priv_sectors = Vec::with_capacity(param_sector_count);
@@ -176,6 +274,7 @@ pub fn generate_winning_post<Tree: 'static + MerkleTreeTrait>(
     let proofs =
         FallbackPoStCompound::<Tree>::prove(&pub_params, &pub_inputs, &priv_inputs, &groth_params)?;
 
+    timer.finish();
     info!("generate_winning_post:finish");
 
     util::proofs_to_bytes(&proofs)
@@ -192,8 +291,8 @@ pub fn generate_winning_post_sector_challenge<Tree: MerkleTreeTrait>(
     prover_id: Commitment,
 ) -> Result<Vec<u64>> {
     info!("generate_winning_post_sector_challenge:start");
-    ensure!(sector_set_size != 0, "empty sector set is invalid");
-    ensure!(
+    ensure_input!(sector_set_size != 0, "empty sector set is invalid");
+    ensure_input!(
         post_config.typ == PoStType::Winning,
         "invalid post config type"
     );
@@ -212,7 +311,7 @@ pub fn generate_winning_post_sector_challenge<Tree: MerkleTreeTrait>(
 
     info!("generate_winning_post_sector_challenge:finish");
 
-    result
+    Ok(result?)
 }
 
 /// Verifies a winning proof-of-spacetime.
@@ -229,50 +328,22 @@ pub fn verify_winning_post<Tree: 'static + MerkleTreeTrait>(
 ) -> Result<bool> {
     info!("verify_winning_post:start");
 
-    ensure!(
+    ensure_input!(
         post_config.typ == PoStType::Winning,
         "invalid post config type"
     );
-    ensure!(
+    ensure_input!(
         post_config.sector_count == replicas.len(),
         "invalid amount of replicas provided"
     );
 
-    let randomness_safe: <Tree::Hasher as Hasher>::Domain =
-        as_safe_commitment(randomness, "randomness")?;
-    let prover_id_safe: <Tree::Hasher as Hasher>::Domain =
-        as_safe_commitment(&prover_id, "prover_id")?;
-
-    let vanilla_params = winning_post_setup_params(post_config)?;
-    let param_sector_count = vanilla_params.sector_count;
-
-    let setup_params = compound_proof::SetupParams {
-        vanilla_params,
-        partitions: None,
-        priority: false,
-    };
-    let pub_params: compound_proof::PublicParams<'_, FallbackPoSt<'_, Tree>> =
-        FallbackPoStCompound::setup(&setup_params)?;
-
-    let mut pub_sectors = Vec::with_capacity(param_sector_count);
-    for _ in 0..param_sector_count {
-        for (sector_id, replica) in replicas.iter() {
-            let comm_r = replica.safe_comm_r().with_context(|| {
-                format!("verify_winning_post: safe_comm_r failed: {:?}", sector_id)
-            })?;
-            pub_sectors.push(PublicSector {
-                id: *sector_id,
-                comm_r,
-            });
-        }
-    }
-
-    let pub_inputs = fallback::PublicInputs {
-        randomness: randomness_safe,
-        prover_id: prover_id_safe,
-        sectors: pub_sectors,
-        k: None,
-    };
+    let pub_params = winning_post_public_params::<Tree>(post_config)?;
+    let pub_inputs = winning_post_public_inputs::<Tree>(
+        randomness,
+        replicas,
+        prover_id,
+        pub_params.vanilla_params.sector_count,
+    )?;
 
     let is_valid = {
         let verifying_key = get_post_verifying_key::<Tree>(post_config)?;
@@ -300,3 +371,48 @@ pub fn verify_winning_post<Tree: 'static + MerkleTreeTrait>(
 
     Ok(true)
 }
+
+pub(crate) fn winning_post_public_params<'a, Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+) -> Result<compound_proof::PublicParams<'a, FallbackPoSt<'a, Tree>>> {
+    let setup_params = compound_proof::SetupParams {
+        vanilla_params: winning_post_setup_params(post_config)?,
+        partitions: None,
+        priority: false,
+    };
+    Ok(FallbackPoStCompound::setup(&setup_params)?)
+}
+
+/// Returns the public inputs for verifying a Winning PoSt over `replicas`. The replicas are
+/// repeated `param_sector_count` times.
+pub(crate) fn winning_post_public_inputs<Tree: 'static + MerkleTreeTrait>(
+    randomness: &ChallengeSeed,
+    replicas: &[(SectorId, PublicReplicaInfo)],
+    prover_id: ProverId,
+    param_sector_count: usize,
+) -> Result<fallback::PublicInputs<<Tree::Hasher as Hasher>::Domain>> {
+    let randomness_safe: <Tree::Hasher as Hasher>::Domain =
+        as_safe_commitment(randomness, "randomness")?;
+    let prover_id_safe: <Tree::Hasher as Hasher>::Domain =
+        as_safe_commitment(&prover_id, "prover_id")?;
+
+    let mut pub_sectors = Vec::with_capacity(param_sector_count);
+    for _ in 0..param_sector_count {
+        for (sector_id, replica) in replicas.iter() {
+            let comm_r = replica.safe_comm_r().with_context(|| {
+                format!("verify_winning_post: safe_comm_r failed: {:?}", sector_id)
+            })?;
+            pub_sectors.push(PublicSector {
+                id: *sector_id,
+                comm_r,
+            });
+        }
+    }
+
+    Ok(fallback::PublicInputs {
+        randomness: randomness_safe,
+        prover_id: prover_id_safe,
+        sectors: pub_sectors,
+        k: None,
+    })
+}
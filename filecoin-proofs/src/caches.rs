@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
@@ -9,7 +10,9 @@ use log::{info, trace};
 use once_cell::sync::OnceCell;
 use rand::rngs::OsRng;
 use storage_proofs_core::{
-    compound_proof::CompoundProof, merkle::MerkleTreeTrait, parameter_cache::Bls12GrothParams,
+    compound_proof::CompoundProof,
+    merkle::MerkleTreeTrait,
+    parameter_cache::{parameter_cache_dir, Bls12GrothParams},
 };
 use storage_proofs_porep::stacked::{StackedCompound, StackedDrg};
 use storage_proofs_post::fallback::{FallbackPoSt, FallbackPoStCircuit, FallbackPoStCompound};
@@ -20,6 +23,7 @@ use storage_proofs_update::{
 
 use crate::{
     constants::{DefaultPieceHasher, SUPPORTED_SECTOR_SIZES},
+    error::Error,
     parameters::{public_params, window_post_public_params, winning_post_public_params},
     types::{PoRepConfig, PoStConfig, PoStType},
 };
@@ -28,7 +32,9 @@ pub type Bls12PreparedVerifyingKey = groth16::PreparedVerifyingKey<Bls12>;
 type Bls12ProverSRSKey = groth16::aggregate::ProverSRS<Bls12>;
 type Bls12VerifierSRSKey = groth16::aggregate::VerifierSRS<Bls12>;
 
-type Cache<G> = HashMap<String, Arc<G>>;
+/// Entries are keyed by the parameter cache directory they were loaded from and their identifier,
+/// so that contexts with different parameter caches don't share them.
+type Cache<G> = HashMap<(PathBuf, String), Arc<G>>;
 type GrothMemCache = Cache<Bls12GrothParams>;
 type VerifyingKeyMemCache = Cache<Bls12PreparedVerifyingKey>;
 
@@ -113,22 +119,23 @@ where
     G: Send + Sync,
 {
     info!("trying parameters memory cache for: {}", &identifier);
+    let key = (parameter_cache_dir(), identifier);
     {
         let cache = (*cache_ref).lock().expect("poisoned cache");
 
-        if let Some(entry) = cache.get(&identifier) {
-            info!("found params in memory cache for {}", &identifier);
+        if let Some(entry) = cache.get(&key) {
+            info!("found params in memory cache for {}", key.1);
             return Ok(entry.clone());
         }
     }
 
-    info!("no params in memory cache for {}", &identifier);
+    info!("no params in memory cache for {}", key.1);
 
-    let new_entry = Arc::new(generator()?);
+    let new_entry = Arc::new(generator().map_err(Error::ParameterCache)?);
     let res = new_entry.clone();
     {
         let cache = &mut (*cache_ref).lock().expect("poisoned cache");
-        cache.insert(identifier, new_entry);
+        cache.insert(key, new_entry);
     }
 
     Ok(res)
@@ -144,7 +151,10 @@ where
     G: Send + Sync,
 {
     trace!("srs_cache_lookup looking up {}", identifier);
-    if let Some(entry) = cache_ref.get_or_init(&identifier, generator)? {
+    if let Some(entry) = cache_ref
+        .get_or_init(&identifier, generator)
+        .map_err(Error::ParameterCache)?
+    {
         return Ok(entry.clone());
     }
 
@@ -436,3 +446,30 @@ pub(crate) fn get_empty_sector_update_verifying_key<
         vk_generator,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage_proofs_core::settings::{ProofsContext, Settings};
+
+    #[test]
+    fn test_cache_lookup_per_parameter_cache() {
+        let cache: Mutex<Cache<u32>> = Default::default();
+        let context = |parameter_cache: &str| {
+            ProofsContext::new(Settings {
+                parameter_cache: parameter_cache.to_string(),
+                ..Default::default()
+            })
+        };
+        let lookup = |value| {
+            cache_lookup(&cache, "STACKED[2048]".to_string(), || Ok(value))
+                .map(|entry| *entry)
+                .expect("lookup failed")
+        };
+
+        assert_eq!(context("/tmp/first-parameters/").scope(|| lookup(1)), 1);
+        assert_eq!(context("/tmp/second-parameters/").scope(|| lookup(2)), 2);
+        assert_eq!(context("/tmp/first-parameters/").scope(|| lookup(3)), 1);
+    }
+}
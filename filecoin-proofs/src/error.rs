@@ -0,0 +1,190 @@
+use std::io;
+use std::path::PathBuf;
+
+use storage_proofs_core::{error::Error as CoreError, sector::SectorId};
+
+use crate::types::PreflightFailure;
+
+/// The result type of the public API.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The errors of the public API.
+///
+/// The variants that wrap an [`anyhow::Error`] keep the whole chain of causes, it's available
+/// through [`std::error::Error::source`].
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The arguments of the call are invalid, retrying with the same arguments fails again.
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    /// A file in a cache directory is missing or can't be decoded.
+    #[error("missing or corrupt cache file {}", path.display())]
+    CacheFile {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// The Groth parameters, verifying keys or SRS keys can't be loaded.
+    #[error("parameter cache error")]
+    ParameterCache(#[source] anyhow::Error),
+    /// The replicas of these sectors can't be proven, see
+    /// [`storage_proofs_core::error::Error::FaultySectors`].
+    #[error("faulty sectors {sectors:?}")]
+    FaultySectors {
+        sectors: Vec<SectorId>,
+        #[source]
+        source: anyhow::Error,
+    },
+    /// A proof that was just generated doesn't verify.
+    #[error("verification failed: {0}")]
+    VerificationFailed(String),
+    /// The operation was cancelled through its [`crate::types::SealContext`].
+    #[error("operation was cancelled")]
+    Cancelled(#[source] anyhow::Error),
+    /// The prerequisites of a phase aren't met, nothing was written. See
+    /// [`crate::preflight_seal`].
+    #[error("preflight checks failed: {}", join_failures(.0))]
+    Preflight(Vec<PreflightFailure>),
+    #[error(transparent)]
+    Internal(anyhow::Error),
+}
+
+impl From<anyhow::Error> for Error {
+    /// Classifies the error by the errors in its chain.
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<Error>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+
+        let core_error = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<CoreError>());
+        match core_error {
+            Some(CoreError::FaultySectors(sectors)) => Error::FaultySectors {
+                sectors: sectors.clone(),
+                source: err,
+            },
+            Some(CoreError::Cancelled) => Error::Cancelled(err),
+            Some(CoreError::InvalidParameters(_)) => Error::ParameterCache(err),
+            _ => Error::Internal(err),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    /// A missing file is a [`Error::CacheFile`] without a path, the other I/O errors are internal.
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => Error::cache_file(PathBuf::new(), err),
+            _ => Error::Internal(err.into()),
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn cache_file<P: Into<PathBuf>, E: Into<anyhow::Error>>(path: P, source: E) -> Self {
+        Error::CacheFile {
+            path: path.into(),
+            source: source.into(),
+        }
+    }
+}
+
+fn join_failures(failures: &[PreflightFailure]) -> String {
+    failures
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Like [`anyhow::ensure!`], but fails with an [`Error::InvalidInput`]. It can be used in
+/// functions that return an [`anyhow::Result`] as well.
+macro_rules! ensure_input {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::Error::InvalidInput(format!($($arg)+)).into());
+        }
+    };
+}
+pub(crate) use ensure_input;
+
+/// Like [`anyhow::ensure!`], but fails with an [`Error::CacheFile`] for `path`.
+macro_rules! ensure_cache_file {
+    ($cond:expr, $path:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::Error::cache_file($path, ::anyhow::anyhow!($($arg)+)).into());
+        }
+    };
+}
+pub(crate) use ensure_cache_file;
+
+/// Like [`anyhow::ensure!`], but fails with an [`Error::VerificationFailed`].
+macro_rules! ensure_verified {
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            return Err($crate::Error::VerificationFailed(format!($($arg)+)).into());
+        }
+    };
+}
+pub(crate) use ensure_verified;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_error_classification() {
+        let err: Error = anyhow::Error::from(CoreError::FaultySectors(vec![SectorId::from(3)]))
+            .context("while proving")
+            .into();
+        match &err {
+            Error::FaultySectors { sectors, source } => {
+                assert_eq!(sectors, &[SectorId::from(3)]);
+                assert_eq!(source.to_string(), "while proving");
+            }
+            _ => panic!("unexpected error {:?}", err),
+        }
+        let source = std::error::Error::source(&err).expect("source is kept");
+        assert_eq!(source.to_string(), "while proving");
+
+        let err: Error = anyhow::Error::from(CoreError::Cancelled).into();
+        assert!(matches!(err, Error::Cancelled(_)));
+        assert!(crate::types::is_cancelled_error(&err.into()));
+
+        let err: Error = anyhow::Error::from(Error::InvalidInput("bad".to_string())).into();
+        assert!(matches!(err, Error::InvalidInput(_)));
+
+        let err: Error = anyhow!("something else").into();
+        assert!(matches!(err, Error::Internal(_)));
+        assert_eq!(err.to_string(), "something else");
+
+        let err: Error = io::Error::from(io::ErrorKind::NotFound).into();
+        assert!(matches!(err, Error::CacheFile { .. }));
+        let err: Error = io::Error::from(io::ErrorKind::PermissionDenied).into();
+        assert!(matches!(err, Error::Internal(_)));
+    }
+
+    #[test]
+    fn test_ensure_input() {
+        fn typed(value: u64) -> Result<u64> {
+            ensure_input!(value > 0, "value must be positive, got {}", value);
+            Ok(value)
+        }
+        fn untyped(value: u64) -> anyhow::Result<u64> {
+            ensure_input!(value > 0, "value must be positive");
+            Ok(value)
+        }
+
+        assert!(matches!(typed(0), Err(Error::InvalidInput(msg)) if msg.ends_with("got 0")));
+        let err = untyped(0).expect_err("must fail");
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::InvalidInput(_))
+        ));
+    }
+}
@@ -11,16 +11,24 @@ compile_error!(
 pub mod caches;
 pub mod chunk_iter;
 pub mod constants;
+pub mod error;
 pub mod param;
 pub mod parameters;
 pub mod pieces;
+#[cfg(feature = "split-prover")]
+pub mod split_prover;
 pub mod types;
 
 mod api;
 mod commitment_reader;
+mod parallel_commitment;
 
 pub use api::*;
 pub use chunk_iter::ChunkIterator;
 pub use commitment_reader::*;
 pub use constants::*;
+pub use error::Error;
+pub use parallel_commitment::PieceCommitmentConfig;
+pub use storage_proofs_core::metrics;
+pub use storage_proofs_core::storage;
 pub use types::*;
@@ -0,0 +1,258 @@
+use std::cmp::max;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{ensure, Context, Result};
+use lazy_static::lazy_static;
+use rayon::{
+    prelude::{ParallelIterator, ParallelSlice},
+    ThreadPool, ThreadPoolBuilder,
+};
+use sha2raw::Sha256;
+use storage_proofs_core::util::NODE_SIZE;
+
+use crate::{pieces::piece_hash, types::Commitment};
+
+/// The smallest subtree that is hashed as a unit, it's two leaves.
+const MIN_SUBTREE_SIZE: usize = 2 * NODE_SIZE;
+
+lazy_static! {
+    /// The thread pools that hash pieces, keyed by their number of threads. They are shared by
+    /// all calls, so that concurrent calls don't each spawn their own threads. There is at most
+    /// one per number of threads up to the available parallelism.
+    static ref THREAD_POOLS: Mutex<HashMap<usize, Arc<ThreadPool>>> = Default::default();
+}
+
+/// Resource limits of [`crate::generate_piece_commitment_parallel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceCommitmentConfig {
+    /// The number of threads that hash the data, zero means one thread per CPU. It's capped at the
+    /// available parallelism. There is one thread pool per number of threads, it's shared by all
+    /// calls with the same number and kept for the lifetime of the process.
+    pub num_threads: usize,
+    /// The maximum number of bytes of padded data that are kept in memory. Half of it is read
+    /// while the other half is hashed.
+    pub max_memory: usize,
+}
+
+impl Default for PieceCommitmentConfig {
+    fn default() -> Self {
+        PieceCommitmentConfig {
+            num_threads: 0,
+            max_memory: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Returns the thread pool with `num_threads` threads, capped at the available parallelism. It's
+/// built on first use.
+fn thread_pool(num_threads: usize) -> Result<Arc<ThreadPool>> {
+    let max_threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let num_threads = match num_threads {
+        0 => max_threads,
+        n => n.min(max_threads),
+    };
+
+    let mut pools = THREAD_POOLS.lock().expect("THREAD_POOLS poisoned");
+    if let Some(pool) = pools.get(&num_threads) {
+        return Ok(pool.clone());
+    }
+    let pool = Arc::new(
+        ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|index| format!("piece-commitment-{}", index))
+            .build()
+            .context("failed to create thread pool")?,
+    );
+    pools.insert(num_threads, pool.clone());
+    Ok(pool)
+}
+
+/// Returns the largest power of two that is smaller than or equal to `x`.
+fn prev_power_of_two(x: usize) -> usize {
+    1 << (usize::BITS - 1 - x.leading_zeros())
+}
+
+/// Hashes two nodes the same way as `DefaultPieceHasher`, but with `sha2raw`.
+fn hash_nodes(left: &[u8], right: &[u8]) -> [u8; 32] {
+    let mut hash = Sha256::digest(&[left, right]);
+    // Keep in sync with `Sha256Domain::trim_to_fr32`.
+    hash[31] &= 0b0011_1111;
+    hash
+}
+
+/// Returns the root of the tree whose leaves are the nodes of `data`.
+fn subtree_root(data: &[u8]) -> [u8; 32] {
+    let mut row: Vec<[u8; 32]> = data
+        .chunks_exact(2 * NODE_SIZE)
+        .map(|pair| hash_nodes(&pair[..NODE_SIZE], &pair[NODE_SIZE..]))
+        .collect();
+    while row.len() > 1 {
+        for i in 0..row.len() / 2 {
+            row[i] = hash_nodes(&row[2 * i], &row[2 * i + 1]);
+        }
+        row.truncate(row.len() / 2);
+    }
+    row[0]
+}
+
+/// Fills the whole buffer, unlike `read_exact` it doesn't give up on interrupted reads.
+fn read_full<R: Read>(source: &mut R, buf: &mut [u8]) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match source.read(&mut buf[filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Calculates the piece commitment of bit padded data on multiple threads.
+///
+/// The data is read in batches. Each batch is split into subtrees of equal size, that are hashed
+/// in parallel while the next batch is read. The roots of the subtrees are then merged into the
+/// piece commitment. The result is the same as the one of `CommitmentReader`.
+pub(crate) fn parallel_piece_commitment<R: Read + Send>(
+    mut source: R,
+    padded_piece_size: usize,
+    config: &PieceCommitmentConfig,
+) -> Result<Commitment> {
+    ensure!(
+        padded_piece_size.is_power_of_two() && padded_piece_size >= MIN_SUBTREE_SIZE,
+        "piece size must be a power of two"
+    );
+
+    let pool = thread_pool(config.num_threads)?;
+    let batch_size =
+        prev_power_of_two(max(config.max_memory / 2, MIN_SUBTREE_SIZE)).min(padded_piece_size);
+    let subtree_size = prev_power_of_two(max(
+        batch_size / pool.current_num_threads(),
+        MIN_SUBTREE_SIZE,
+    ));
+    let num_batches = padded_piece_size / batch_size;
+
+    pool.install(|| -> Result<Commitment> {
+        let mut roots = Vec::with_capacity(padded_piece_size / subtree_size);
+        let mut current = vec![0u8; batch_size];
+        let mut next = vec![0u8; batch_size];
+        read_full(&mut source, &mut current).context("failed to read piece")?;
+
+        for batch in 0..num_batches {
+            let is_last = batch + 1 == num_batches;
+            let (batch_roots, read) = rayon::join(
+                || {
+                    current
+                        .par_chunks(subtree_size)
+                        .map(subtree_root)
+                        .collect::<Vec<_>>()
+                },
+                || {
+                    if is_last {
+                        Ok(())
+                    } else {
+                        read_full(&mut source, &mut next)
+                    }
+                },
+            );
+            read.context("failed to read piece")?;
+            roots.extend(batch_roots);
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        while roots.len() > 1 {
+            roots = roots
+                .par_chunks(2)
+                .map(|pair| {
+                    let mut node = [0; 32];
+                    node.copy_from_slice(piece_hash(&pair[0], &pair[1]).as_ref());
+                    node
+                })
+                .collect();
+        }
+        Ok(roots[0])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use fr32::Fr32Reader;
+    use rand::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+    use storage_proofs_core::pieces::generate_piece_commitment_bytes_from_source;
+
+    use crate::{
+        constants::{DefaultPieceHasher, TEST_SEED},
+        types::{PaddedBytesAmount, UnpaddedBytesAmount},
+    };
+
+    #[test]
+    fn test_parallel_piece_commitment() {
+        let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+
+        for padded_piece_size in [64, 128, 2048, 1 << 16] {
+            let piece_size = UnpaddedBytesAmount::from(PaddedBytesAmount(padded_piece_size));
+            let mut source = vec![0u8; u64::from(piece_size) as usize];
+            rng.fill_bytes(&mut source);
+
+            let expected = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+                &mut Fr32Reader::new(Cursor::new(&source)),
+                padded_piece_size as usize,
+            )
+            .expect("failed to generate piece commitment");
+
+            for (num_threads, max_memory) in [(1, 0), (4, 0), (3, 1024), (0, 1 << 20)] {
+                let config = PieceCommitmentConfig {
+                    num_threads,
+                    max_memory,
+                };
+                let commitment = parallel_piece_commitment(
+                    Fr32Reader::new(Cursor::new(&source)),
+                    padded_piece_size as usize,
+                    &config,
+                )
+                .expect("failed to generate piece commitment in parallel");
+                assert_eq!(commitment, expected, "{:?}", config);
+            }
+        }
+    }
+
+    #[test]
+    fn test_thread_pools_are_reused() {
+        let max_threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let pool = thread_pool(1).expect("failed to create thread pool");
+        assert_eq!(pool.current_num_threads(), 1);
+        assert!(Arc::ptr_eq(
+            &pool,
+            &thread_pool(1).expect("failed to create thread pool")
+        ));
+
+        // Larger numbers of threads share the pool with the available parallelism.
+        let pool = thread_pool(0).expect("failed to create thread pool");
+        assert_eq!(pool.current_num_threads(), max_threads);
+        assert!(Arc::ptr_eq(
+            &pool,
+            &thread_pool(max_threads + 1).expect("failed to create thread pool")
+        ));
+    }
+
+    #[test]
+    fn test_parallel_piece_commitment_short_source() {
+        let source = vec![0u8; 127 * 4];
+        assert!(parallel_piece_commitment(
+            Fr32Reader::new(Cursor::new(&source)),
+            1024,
+            &PieceCommitmentConfig::default(),
+        )
+        .is_err());
+    }
+}
@@ -1,10 +1,10 @@
-use std::cmp::min;
+use std::cmp::{min, Reverse};
 use std::collections::HashMap;
 use std::io::{self, Cursor, Read};
 use std::iter::Iterator;
 use std::sync::Mutex;
 
-use anyhow::{ensure, Context, Result};
+use anyhow::Context;
 use filecoin_hashers::{HashFunction, Hasher};
 use fr32::Fr32Reader;
 use lazy_static::lazy_static;
@@ -17,9 +17,10 @@ use crate::{
         DefaultPieceHasher,
         MINIMUM_RESERVED_BYTES_FOR_PIECE_IN_FULLY_ALIGNED_SECTOR as MINIMUM_PIECE_SIZE,
     },
+    error::{ensure_input, Result},
     types::{
-        Commitment, PaddedBytesAmount, PieceInfo, SectorSize, UnpaddedByteIndex,
-        UnpaddedBytesAmount,
+        Commitment, LayoutPiece, LayoutPieceKind, PackingStrategy, PaddedBytesAmount, PieceInfo,
+        PieceLayout, SectorSize, UnpaddedByteIndex, UnpaddedBytesAmount,
     },
 };
 
@@ -90,7 +91,7 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
 
     let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
 
-    ensure!(
+    ensure_input!(
         piece_infos.len() as u64 <= u64::from(unpadded_sector) / MINIMUM_PIECE_SIZE,
         "Too many pieces"
     );
@@ -101,7 +102,7 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
         .map(|info| u64::from(PaddedBytesAmount::from(info.size)))
         .sum();
 
-    ensure!(
+    ensure_input!(
         piece_size <= u64::from(sector_size),
         "Piece is larger than sector."
     );
@@ -112,7 +113,7 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
         .first()
         .expect("unreachable: !is_empty()")
         .clone();
-    ensure!(
+    ensure_input!(
         u64::from(PaddedBytesAmount::from(first.size)).is_power_of_two(),
         "Piece size ({:?}) must be a power of 2.",
         PaddedBytesAmount::from(first.size)
@@ -120,7 +121,7 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
     stack.shift(first);
 
     for piece_info in piece_infos.iter().skip(1) {
-        ensure!(
+        ensure_input!(
             u64::from(PaddedBytesAmount::from(piece_info.size)).is_power_of_two(),
             "Piece size ({:?}) must be a power of 2.",
             PaddedBytesAmount::from(piece_info.size)
@@ -137,13 +138,138 @@ pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Res
         stack.shift_reduce(zero_padding(stack.peek().size)?)?;
     }
 
-    ensure!(stack.len() == 1, "Stack size ({}) must be 1.", stack.len());
+    ensure_input!(stack.len() == 1, "Stack size ({}) must be 1.", stack.len());
 
     let comm_d_calculated = stack.pop()?.commitment;
 
     Ok(comm_d_calculated)
 }
 
+/// Returns the offsets of the pieces within a sector, as they are laid out by [`compute_comm_d`].
+///
+/// Each piece starts where [`get_piece_start_byte`] places it after the pieces before it, the gaps
+/// are filled with zero padding.
+pub fn get_piece_offsets(
+    sector_size: SectorSize,
+    piece_infos: &[PieceInfo],
+) -> Result<Vec<UnpaddedByteIndex>> {
+    let mut offsets = Vec::with_capacity(piece_infos.len());
+    let mut piece_lengths = Vec::with_capacity(piece_infos.len());
+    for piece_info in piece_infos {
+        ensure_input!(
+            u64::from(PaddedBytesAmount::from(piece_info.size)).is_power_of_two(),
+            "Piece size ({:?}) must be a power of 2.",
+            PaddedBytesAmount::from(piece_info.size)
+        );
+
+        let offset = get_piece_start_byte(&piece_lengths, piece_info.size);
+        let end = UnpaddedBytesAmount::from(offset) + piece_info.size;
+        ensure_input!(
+            u64::from(PaddedBytesAmount::from(end)) <= u64::from(sector_size),
+            "Pieces are larger than sector."
+        );
+        piece_lengths.push(piece_info.size);
+        offsets.push(offset);
+    }
+
+    Ok(offsets)
+}
+
+/// Plans the layout of a sector from pieces that must be included and optional candidates.
+///
+/// The candidates are added while they fit, in the order given by `strategy`. The pieces are laid
+/// out from the largest to the smallest, which aligns each one to its size without any padding in
+/// between. The space left at the end of the sector is filled with [`zero_padding`] pieces, so
+/// adding all pieces of the layout in order fills the whole sector.
+pub fn plan_piece_layout(
+    sector_size: SectorSize,
+    required: &[PieceInfo],
+    candidates: &[PieceInfo],
+    strategy: PackingStrategy,
+) -> Result<PieceLayout> {
+    let padded_size = |piece_info: &PieceInfo| -> Result<u64> {
+        let size = PaddedBytesAmount::from(piece_info.size);
+        ensure_input!(
+            u64::from(size).is_power_of_two() && u64::from(piece_info.size) >= MINIMUM_PIECE_SIZE,
+            "Piece size ({:?}) must be a power of 2.",
+            size
+        );
+        Ok(u64::from(size))
+    };
+
+    let sector_bytes = u64::from(sector_size);
+    let mut free = sector_bytes;
+    let mut selected = Vec::with_capacity(required.len() + candidates.len());
+    for (i, piece_info) in required.iter().enumerate() {
+        let size = padded_size(piece_info)?;
+        ensure_input!(size <= free, "Required pieces are larger than sector.");
+        free -= size;
+        selected.push((LayoutPieceKind::Required(i), piece_info, size));
+    }
+
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    if strategy == PackingStrategy::BestFit {
+        order.sort_by_key(|&i| Reverse(candidates[i].size));
+    }
+    let mut excluded = Vec::new();
+    for i in order {
+        let size = padded_size(&candidates[i])?;
+        if size <= free {
+            free -= size;
+            selected.push((LayoutPieceKind::Candidate(i), &candidates[i], size));
+        } else {
+            excluded.push(i);
+        }
+    }
+    excluded.sort_unstable();
+    trace!(
+        "planned {} pieces, {} excluded, {} bytes free",
+        selected.len(),
+        excluded.len(),
+        free
+    );
+
+    // The sort is stable, pieces of the same size keep their order.
+    selected.sort_by_key(|(_, _, size)| Reverse(*size));
+    let mut pieces = Vec::with_capacity(selected.len());
+    let mut written = Vec::with_capacity(selected.len());
+    for (kind, piece_info, _) in selected {
+        pieces.push(LayoutPiece {
+            kind,
+            piece_info: piece_info.clone(),
+            offset: get_piece_start_byte(&written, piece_info.size),
+        });
+        written.push(piece_info.size);
+    }
+
+    // Each filler is the largest piece that fits without any left alignment.
+    let unpadded_sector = UnpaddedBytesAmount::from(sector_size);
+    let mut written_bytes = sum_piece_bytes_with_alignment(&written);
+    while written_bytes < unpadded_sector {
+        let mut size = unpadded_sector;
+        while written_bytes + size > unpadded_sector
+            || get_piece_alignment(written_bytes, size).left_bytes != UnpaddedBytesAmount(0)
+        {
+            size = PaddedBytesAmount(u64::from(PaddedBytesAmount::from(size)) / 2).into();
+        }
+        pieces.push(LayoutPiece {
+            kind: LayoutPieceKind::Filler,
+            piece_info: zero_padding(size)?,
+            offset: UnpaddedByteIndex::from(written_bytes),
+        });
+        written.push(size);
+        written_bytes = sum_piece_bytes_with_alignment(&written);
+    }
+
+    let mut layout = PieceLayout {
+        pieces,
+        excluded,
+        comm_d: [0; 32],
+    };
+    layout.comm_d = compute_comm_d(sector_size, &layout.piece_infos())?;
+    Ok(layout)
+}
+
 /// Stack used for piece reduction.
 struct Stack(Vec<PieceInfo>);
 
@@ -170,7 +296,7 @@ impl Stack {
 
     /// Pop the last element of the stack.
     fn pop(&mut self) -> Result<PieceInfo> {
-        self.0.pop().context("empty stack popped")
+        Ok(self.0.pop().context("empty stack popped")?)
     }
 
     fn reduce1(&mut self) -> Result<bool> {
@@ -220,7 +346,7 @@ pub fn zero_padding(size: UnpaddedBytesAmount) -> Result<PieceInfo> {
         hashed_size *= 2;
     }
 
-    ensure!(
+    ensure_input!(
         hashed_size == u64::from(padded_size),
         "Hashed size must equal padded size"
     );
@@ -230,7 +356,7 @@ pub fn zero_padding(size: UnpaddedBytesAmount) -> Result<PieceInfo> {
 
 /// Join two equally sized `PieceInfo`s together, by hashing them and adding their sizes.
 fn join_piece_infos(mut left: PieceInfo, right: PieceInfo) -> Result<PieceInfo> {
-    ensure!(
+    ensure_input!(
         left.size == right.size,
         "Piece sizes must be equal (left: {:?}, right: {:?})",
         left.size,
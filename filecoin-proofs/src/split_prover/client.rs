@@ -0,0 +1,303 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+
+use anyhow::anyhow;
+use filecoin_hashers::Hasher;
+use log::{info, warn};
+use storage_proofs_core::{merkle::MerkleTreeTrait, sector::SectorId};
+
+use crate::{
+    api::{
+        generate_empty_sector_update_proof_with_vanilla, generate_fallback_sector_challenges,
+        generate_window_post_with_vanilla, generate_winning_post_with_vanilla,
+        sector_proof_is_valid, verify_partition_proofs,
+    },
+    error::{ensure_input, Error, Result},
+    split_prover::{
+        read_message, write_message, PoStVanillaRequest, Request, Response, SectorChallenges,
+        UpdateVanillaRequest, MAX_REQUEST_LEN, MAX_RESPONSE_LEN,
+    },
+    types::{
+        ChallengeSeed, Commitment, EmptySectorUpdateProof, FallbackPoStSectorProof, PartitionProof,
+        PoRepConfig, PoStConfig, ProverId, PublicReplicaInfo, SectorUpdateConfig, SnarkProof,
+        TreeRHasher, VanillaProofBytes,
+    },
+    PoStType,
+};
+
+/// Fetches vanilla proofs from a [`super::VanillaProofServer`] and turns them into
+/// SNARKs locally.
+#[derive(Debug)]
+pub struct VanillaProofClient<S: Read + Write> {
+    stream: S,
+}
+
+impl VanillaProofClient<TcpStream> {
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+#[cfg(unix)]
+impl VanillaProofClient<UnixStream> {
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(UnixStream::connect(path)?))
+    }
+}
+
+impl<S: Read + Write> VanillaProofClient<S> {
+    pub fn new(stream: S) -> Self {
+        VanillaProofClient { stream }
+    }
+
+    /// Sends a single request and waits for its response.
+    pub fn request(&mut self, request: &Request) -> Result<Response> {
+        write_message(&mut self.stream, request, MAX_REQUEST_LEN)?;
+        read_message(&mut self.stream, MAX_RESPONSE_LEN)?
+            .ok_or_else(|| Error::Internal(anyhow!("vanilla proof server closed the connection")))
+    }
+
+    /// Fetches the vanilla proofs of the given sectors, in the same order.
+    ///
+    /// Each proof is verified against the comm_r of its sector in `replicas` and its challenges
+    /// before it's returned. Fails with [`Error::FaultySectors`] if the server couldn't generate
+    /// the proofs of some sectors or the proofs are invalid.
+    pub fn post_vanilla_proofs<Tree: 'static + MerkleTreeTrait>(
+        &mut self,
+        post_config: &PoStConfig,
+        sectors: Vec<SectorChallenges>,
+        replicas: &BTreeMap<SectorId, PublicReplicaInfo>,
+    ) -> Result<Vec<FallbackPoStSectorProof<Tree>>> {
+        info!("post_vanilla_proofs:start: {} sectors", sectors.len());
+        ensure_input!(post_config.sector_count > 0, "invalid sector count");
+        let comm_rs = sectors
+            .iter()
+            .map(|sector| {
+                let replica = replicas.get(&sector.sector_id).ok_or_else(|| {
+                    Error::InvalidInput(format!("no replica of sector {:?}", sector.sector_id))
+                })?;
+                replica.safe_comm_r::<<Tree::Hasher as Hasher>::Domain>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The sectors are requested one partition at a time, this bounds the size of a response.
+        let mut responses = Vec::with_capacity(sectors.len());
+        for chunk in sectors.chunks(post_config.sector_count) {
+            let request = Request::PoStVanillaProofs(PoStVanillaRequest {
+                post_config: post_config.clone(),
+                sectors: chunk.to_vec(),
+            });
+            let chunk_responses = match self.request(&request)? {
+                Response::PoStVanillaProofs(responses) => responses,
+                Response::Error(err) => return Err(err.into()),
+                other => return Err(unexpected_response(&other)),
+            };
+            if !chunk_responses
+                .iter()
+                .map(|response| response.sector_id)
+                .eq(chunk.iter().map(|sector| sector.sector_id))
+            {
+                return Err(Error::Internal(anyhow!(
+                    "vanilla proof server answered with other sectors"
+                )));
+            }
+            responses.extend(chunk_responses);
+        }
+
+        let mut proofs = Vec::with_capacity(responses.len());
+        let mut faulty_sectors = Vec::new();
+        let mut errors = Vec::new();
+        for ((response, sector), comm_r) in responses.into_iter().zip(&sectors).zip(&comm_rs) {
+            info!(
+                "vanilla proof of sector {:?} took {:?}",
+                response.sector_id, response.duration
+            );
+            let proof = response.proof.map_err(|err| err.message).and_then(|bytes| {
+                let proof = FallbackPoStSectorProof::<Tree>::from_bytes(&bytes)
+                    .map_err(|err| format!("{:#}", anyhow::Error::new(err)))?;
+                if sector_proof_is_valid(post_config, comm_r, &sector.challenges, &proof) {
+                    Ok(proof)
+                } else {
+                    Err(format!(
+                        "invalid vanilla proof of sector {:?}",
+                        sector.sector_id
+                    ))
+                }
+            });
+            match proof {
+                Ok(proof) => proofs.push(proof),
+                Err(err) => {
+                    warn!("sector {:?} is faulty: {}", response.sector_id, err);
+                    faulty_sectors.push(response.sector_id);
+                    errors.push(err);
+                }
+            }
+        }
+        if !faulty_sectors.is_empty() {
+            return Err(Error::FaultySectors {
+                sectors: faulty_sectors,
+                source: anyhow!(errors.join("; ")),
+            });
+        }
+
+        info!("post_vanilla_proofs:finish");
+        Ok(proofs)
+    }
+
+    /// Like [`crate::generate_window_post`], but the vanilla proofs are generated by the server.
+    /// `replicas` are the sectors of the PoSt, as for [`crate::verify_window_post`].
+    pub fn generate_window_post<Tree: 'static + MerkleTreeTrait>(
+        &mut self,
+        post_config: &PoStConfig,
+        randomness: &ChallengeSeed,
+        replicas: &BTreeMap<SectorId, PublicReplicaInfo>,
+        prover_id: ProverId,
+    ) -> Result<SnarkProof> {
+        ensure_input!(
+            post_config.typ == PoStType::Window,
+            "invalid post config type"
+        );
+        let sectors: Vec<_> = replicas.keys().copied().collect();
+        let challenges = sector_challenges::<Tree>(post_config, randomness, &sectors, prover_id)?;
+        let vanilla_proofs = self.post_vanilla_proofs::<Tree>(post_config, challenges, replicas)?;
+        generate_window_post_with_vanilla::<Tree>(
+            post_config,
+            randomness,
+            prover_id,
+            vanilla_proofs,
+        )
+    }
+
+    /// Like [`crate::generate_winning_post`], but the vanilla proofs are generated by the server.
+    /// `replicas` are the sectors of the PoSt, as for [`crate::verify_winning_post`].
+    pub fn generate_winning_post<Tree: 'static + MerkleTreeTrait>(
+        &mut self,
+        post_config: &PoStConfig,
+        randomness: &ChallengeSeed,
+        replicas: &[(SectorId, PublicReplicaInfo)],
+        prover_id: ProverId,
+    ) -> Result<SnarkProof> {
+        ensure_input!(
+            post_config.typ == PoStType::Winning,
+            "invalid post config type"
+        );
+        let sectors: Vec<_> = replicas.iter().map(|(sector_id, _)| *sector_id).collect();
+        let challenges = sector_challenges::<Tree>(post_config, randomness, &sectors, prover_id)?;
+        let vanilla_proofs = self.post_vanilla_proofs::<Tree>(
+            post_config,
+            challenges,
+            &replicas.iter().cloned().collect(),
+        )?;
+        generate_winning_post_with_vanilla::<Tree>(
+            post_config,
+            randomness,
+            prover_id,
+            vanilla_proofs,
+        )
+    }
+
+    /// Like [`crate::generate_empty_sector_update_proof`], but the vanilla proofs are generated by
+    /// the server.
+    ///
+    /// The partition proofs are verified before they're turned into a SNARK. Fails with
+    /// [`Error::FaultySectors`] if they are invalid or not one per partition.
+    pub fn generate_empty_sector_update_proof<
+        Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>,
+    >(
+        &mut self,
+        porep_config: &PoRepConfig,
+        sector_id: SectorId,
+        comm_r_old: Commitment,
+        comm_r_new: Commitment,
+        comm_d_new: Commitment,
+    ) -> Result<EmptySectorUpdateProof> {
+        let request = Request::UpdateVanillaProofs(UpdateVanillaRequest {
+            porep_config: porep_config.clone(),
+            sector_id,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+        });
+        let response = match self.request(&request)? {
+            Response::UpdateVanillaProofs(response) => response,
+            Response::Error(err) => return Err(err.into()),
+            other => return Err(unexpected_response(&other)),
+        };
+        info!(
+            "vanilla proofs of sector {:?} took {:?}",
+            sector_id, response.duration
+        );
+
+        let vanilla_proofs: Vec<_> = response
+            .partition_proofs
+            .iter()
+            .map(|bytes| PartitionProof::<Tree>::from_bytes(bytes))
+            .collect::<Result<_>>()?;
+        let config = SectorUpdateConfig::from_porep_config(porep_config);
+        let partitions = usize::from(config.update_partitions);
+        let invalid = if vanilla_proofs.len() != partitions {
+            Some(format!(
+                "expected {} partition proofs, got {}",
+                partitions,
+                vanilla_proofs.len()
+            ))
+        } else if !verify_partition_proofs::<Tree>(
+            config,
+            &vanilla_proofs,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+        )? {
+            Some("invalid partition proofs".to_string())
+        } else {
+            None
+        };
+        if let Some(err) = invalid {
+            warn!("sector {:?} is faulty: {}", sector_id, err);
+            return Err(Error::FaultySectors {
+                sectors: vec![sector_id],
+                source: anyhow!(err),
+            });
+        }
+
+        generate_empty_sector_update_proof_with_vanilla::<Tree>(
+            porep_config,
+            vanilla_proofs,
+            comm_r_old,
+            comm_r_new,
+            comm_d_new,
+        )
+    }
+}
+
+/// Derives the challenges of the sectors, in the order of `sectors`.
+fn sector_challenges<Tree: 'static + MerkleTreeTrait>(
+    post_config: &PoStConfig,
+    randomness: &ChallengeSeed,
+    sectors: &[SectorId],
+    prover_id: ProverId,
+) -> Result<Vec<SectorChallenges>> {
+    let challenges =
+        generate_fallback_sector_challenges::<Tree>(post_config, randomness, sectors, prover_id)?;
+    Ok(sectors
+        .iter()
+        .map(|sector_id| SectorChallenges {
+            sector_id: *sector_id,
+            challenges: challenges.get(sector_id).cloned().unwrap_or_default(),
+        })
+        .collect())
+}
+
+fn unexpected_response(response: &Response) -> Error {
+    Error::Internal(anyhow!(
+        "unexpected response from vanilla proof server: {:?}",
+        response
+    ))
+}
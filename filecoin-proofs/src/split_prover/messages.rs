@@ -0,0 +1,241 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use storage_proofs_core::sector::SectorId;
+
+use crate::{
+    error::{ensure_input, Error, Result},
+    types::{Commitment, PoRepConfig, PoStConfig},
+};
+
+/// The version of the message set, it's the first field of every frame.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The maximum size of a request frame. Requests only carry challenges, those of a full Window
+/// PoSt partition of 2349 sectors take less than 300 KiB.
+pub const MAX_REQUEST_LEN: u32 = 4 << 20;
+
+/// The maximum size of a response frame. Responses carry the vanilla proofs of at most one PoSt
+/// partition or of one sector update, those of a 64GiB Window PoSt partition take about 70 MiB.
+pub const MAX_RESPONSE_LEN: u32 = 256 << 20;
+
+/// A request from the SNARK client to the vanilla proof server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Request {
+    /// Vanilla proofs for the challenged sectors of a Window or Winning PoSt.
+    PoStVanillaProofs(PoStVanillaRequest),
+    /// Vanilla proofs for all partitions of an empty sector update.
+    UpdateVanillaProofs(UpdateVanillaRequest),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoStVanillaRequest {
+    pub post_config: PoStConfig,
+    /// The challenges of each sector, see [`crate::generate_fallback_sector_challenges`].
+    pub sectors: Vec<SectorChallenges>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectorChallenges {
+    pub sector_id: SectorId,
+    pub challenges: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateVanillaRequest {
+    pub porep_config: PoRepConfig,
+    pub sector_id: SectorId,
+    pub comm_r_old: Commitment,
+    pub comm_r_new: Commitment,
+    pub comm_d_new: Commitment,
+}
+
+/// The response of the vanilla proof server, one for each [`Request`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response {
+    /// The proofs in the order of [`PoStVanillaRequest::sectors`].
+    PoStVanillaProofs(Vec<SectorProofResponse>),
+    UpdateVanillaProofs(UpdateVanillaResponse),
+    /// The whole request failed, e.g. because it couldn't be decoded.
+    Error(RemoteError),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SectorProofResponse {
+    pub sector_id: SectorId,
+    /// The proof encoded with [`crate::VanillaProofBytes`].
+    pub proof: std::result::Result<Vec<u8>, RemoteError>,
+    /// The time it took to generate the proof.
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateVanillaResponse {
+    /// The partition proofs encoded with [`crate::VanillaProofBytes`].
+    pub partition_proofs: Vec<Vec<u8>>,
+    /// The time it took to generate all partition proofs.
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RemoteErrorKind {
+    /// The request is malformed or doesn't match the configuration of the server.
+    InvalidInput,
+    /// The server doesn't know the sector.
+    UnknownSector,
+    /// The proof generation failed, e.g. because the sector data is missing or corrupt.
+    ProofGeneration,
+}
+
+/// An error that happened on the vanilla proof server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteError {
+    pub kind: RemoteErrorKind,
+    pub message: String,
+}
+
+impl RemoteError {
+    pub(crate) fn from_error(err: Error) -> Self {
+        let kind = match err {
+            Error::InvalidInput(_) => RemoteErrorKind::InvalidInput,
+            _ => RemoteErrorKind::ProofGeneration,
+        };
+        RemoteError {
+            kind,
+            message: format!("{:#}", anyhow::Error::new(err)),
+        }
+    }
+}
+
+impl From<RemoteError> for Error {
+    fn from(err: RemoteError) -> Self {
+        match err.kind {
+            RemoteErrorKind::InvalidInput => Error::InvalidInput(err.message),
+            _ => Error::Internal(anyhow!("vanilla proof server: {}", err.message)),
+        }
+    }
+}
+
+/// Writes a single message as a frame: the length of the rest of the frame as little endian
+/// `u32`, the [`PROTOCOL_VERSION`] as little endian `u32` and the message encoded with bincode.
+/// Frames longer than `max_len` are rejected.
+pub fn write_message<W: Write, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+    max_len: u32,
+) -> Result<()> {
+    let body = bincode::serialize(message).context("failed to encode message")?;
+    let len: u32 = (body.len() + 4)
+        .try_into()
+        .ok()
+        .filter(|len| *len <= max_len)
+        .ok_or_else(|| {
+            Error::InvalidInput(format!("message of {} bytes is too large", body.len()))
+        })?;
+
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&PROTOCOL_VERSION.to_le_bytes())?;
+    writer.write_all(&body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a single message written by [`write_message`]. Returns `None` if the stream ends before
+/// a new frame starts.
+///
+/// Frames longer than `max_len` are rejected before they are read. The length prefix isn't
+/// trusted otherwise, the frame buffer only grows as the bytes arrive.
+pub fn read_message<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+    max_len: u32,
+) -> Result<Option<T>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_le_bytes(len);
+    ensure_input!((4..=max_len).contains(&len), "invalid frame length {}", len);
+
+    let mut frame = Vec::new();
+    reader.take(u64::from(len)).read_to_end(&mut frame)?;
+    if frame.len() < len as usize {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated frame").into());
+    }
+    let version = u32::from_le_bytes(frame[..4].try_into().expect("4 bytes"));
+    ensure_input!(
+        version == PROTOCOL_VERSION,
+        "unsupported protocol version {}",
+        version
+    );
+
+    let message = bincode::deserialize(&frame[4..])
+        .map_err(|err| Error::InvalidInput(format!("invalid message: {:#}", err)))?;
+    Ok(Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_message_round_trip() {
+        let request = SectorChallenges {
+            sector_id: SectorId::from(7),
+            challenges: vec![1, 2, 3],
+        };
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &request, MAX_REQUEST_LEN).expect("write failed");
+        write_message(&mut bytes, &request, MAX_REQUEST_LEN).expect("write failed");
+
+        let mut reader = Cursor::new(&bytes);
+        for _ in 0..2 {
+            let read: Option<SectorChallenges> =
+                read_message(&mut reader, MAX_REQUEST_LEN).expect("read failed");
+            assert_eq!(read.as_ref(), Some(&request));
+        }
+        let end: Option<SectorChallenges> =
+            read_message(&mut reader, MAX_REQUEST_LEN).expect("read failed");
+        assert!(end.is_none());
+
+        // Other protocol version.
+        bytes[4] ^= 1;
+        let err = read_message::<_, SectorChallenges>(&mut Cursor::new(&bytes), MAX_REQUEST_LEN)
+            .expect_err("version must not match");
+        assert!(matches!(err, Error::InvalidInput(_)));
+    }
+
+    #[test]
+    fn test_frame_limits() {
+        let request = SectorChallenges {
+            sector_id: SectorId::from(7),
+            challenges: vec![0; 64],
+        };
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &request, MAX_REQUEST_LEN).expect("write failed");
+        let err = write_message(&mut Vec::new(), &request, 64).expect_err("frame is too large");
+        assert!(matches!(err, Error::InvalidInput(_)));
+
+        // The limit of the reader applies before the frame is read.
+        let err = read_message::<_, SectorChallenges>(&mut Cursor::new(&bytes), 64)
+            .expect_err("frame is too large");
+        assert!(matches!(err, Error::InvalidInput(_)));
+
+        // A length prefix without the frame doesn't allocate the claimed length.
+        let mut reader = Cursor::new(MAX_RESPONSE_LEN.to_le_bytes());
+        let err = read_message::<_, SectorChallenges>(&mut reader, MAX_RESPONSE_LEN)
+            .expect_err("frame is truncated");
+        assert!(matches!(err, Error::Internal(_)));
+
+        bytes.truncate(bytes.len() - 1);
+        let err = read_message::<_, SectorChallenges>(&mut Cursor::new(&bytes), MAX_REQUEST_LEN)
+            .expect_err("frame is truncated");
+        assert!(matches!(err, Error::Internal(_)));
+    }
+}
@@ -0,0 +1,16 @@
+//! Generating PoSt and empty sector update proofs on two machines.
+//!
+//! The vanilla proofs need the sector data and are generated by a [`VanillaProofServer`] next to
+//! the storage. A [`VanillaProofClient`], usually on a machine with a GPU, derives the challenges,
+//! fetches the vanilla proofs and generates the SNARKs with the `*_with_vanilla` functions.
+//!
+//! Server and client talk over any byte stream, e.g. TCP or a Unix socket. Each [`Request`] is
+//! answered with exactly one [`Response`], see [`write_message`] for the framing.
+
+mod client;
+mod messages;
+mod server;
+
+pub use client::*;
+pub use messages::*;
+pub use server::*;
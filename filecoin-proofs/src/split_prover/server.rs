@@ -0,0 +1,575 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rayon::prelude::*;
+use storage_proofs_core::{merkle::MerkleTreeTrait, sector::SectorId};
+
+use crate::{
+    api::{generate_partition_proofs, generate_single_vanilla_proof},
+    constants::WINDOW_POST_SECTOR_COUNT,
+    error::{Error, Result},
+    split_prover::{
+        read_message, write_message, PoStVanillaRequest, RemoteError, RemoteErrorKind, Request,
+        Response, SectorProofResponse, UpdateVanillaRequest, UpdateVanillaResponse,
+        MAX_REQUEST_LEN, MAX_RESPONSE_LEN,
+    },
+    types::{
+        PrivateReplicaInfo, ProofsContext, SectorUpdateConfig, TreeRHasher, VanillaProofBytes,
+    },
+};
+
+/// How long accepting is paused after it failed for a lack of resources, e.g. file descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Returns the accepted connection, `None` if accepting it failed for a reason that doesn't affect
+/// further connections.
+fn accepted<S>(stream: io::Result<S>) -> io::Result<Option<S>> {
+    match stream {
+        Ok(stream) => Ok(Some(stream)),
+        Err(err) if is_connection_error(&err) => {
+            warn!("failed to accept a connection: {:?}", err);
+            Ok(None)
+        }
+        Err(err) if is_resource_error(&err) => {
+            warn!("failed to accept a connection: {:?}", err);
+            thread::sleep(ACCEPT_BACKOFF);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns whether accepting failed because of the connection itself.
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+    )
+}
+
+/// Returns whether accepting failed because the process or system ran out of resources, which
+/// are freed again once served connections are closed.
+#[cfg(unix)]
+fn is_resource_error(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    )
+}
+
+#[cfg(not(unix))]
+fn is_resource_error(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::OutOfMemory
+}
+
+/// The files of a sector that was updated with [`crate::encode_into`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateSectorPaths {
+    pub sector_key_path: PathBuf,
+    pub sector_key_cache_path: PathBuf,
+    pub replica_path: PathBuf,
+    pub replica_cache_path: PathBuf,
+}
+
+/// The limits of a [`VanillaProofServer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VanillaProofServerConfig {
+    /// PoSt requests with more sectors are rejected. The client requests one partition at a time,
+    /// so this should be at least the partition size of the PoSts that are served.
+    pub max_sectors_per_request: usize,
+    /// Further connections aren't accepted until one of the served connections is closed.
+    pub max_connections: usize,
+    /// A connection is closed when no data of a request arrives for this long, e.g. when the
+    /// client is idle between requests. `None` keeps idle connections open.
+    pub read_timeout: Option<Duration>,
+}
+
+impl Default for VanillaProofServerConfig {
+    /// Serves the largest window PoSt partition and 16 connections at a time, connections are
+    /// closed after 5 minutes without a request.
+    fn default() -> Self {
+        VanillaProofServerConfig {
+            max_sectors_per_request: WINDOW_POST_SECTOR_COUNT
+                .read()
+                .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+                .values()
+                .copied()
+                .max()
+                .unwrap_or_default(),
+            max_connections: 16,
+            read_timeout: Some(Duration::from_secs(5 * 60)),
+        }
+    }
+}
+
+/// Serves vanilla proofs of the sectors in local storage to a [`super::VanillaProofClient`].
+///
+/// Sectors can be added and removed while the server is running. Each connection is handled on its
+/// own thread, up to [`VanillaProofServerConfig::max_connections`] at a time, the requests of a
+/// connection are answered in order. The proofs use the [`ProofsContext`] of the thread that
+/// started serving.
+#[derive(Debug)]
+pub struct VanillaProofServer<Tree: MerkleTreeTrait> {
+    config: VanillaProofServerConfig,
+    replicas: RwLock<BTreeMap<SectorId, PrivateReplicaInfo<Tree>>>,
+    updates: RwLock<BTreeMap<SectorId, UpdateSectorPaths>>,
+    connections: Mutex<usize>,
+    connection_closed: Condvar,
+}
+
+impl<Tree: MerkleTreeTrait> Default for VanillaProofServer<Tree> {
+    fn default() -> Self {
+        VanillaProofServer {
+            config: Default::default(),
+            replicas: Default::default(),
+            updates: Default::default(),
+            connections: Default::default(),
+            connection_closed: Default::default(),
+        }
+    }
+}
+
+impl<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>> VanillaProofServer<Tree> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config(config: VanillaProofServerConfig) -> Self {
+        VanillaProofServer {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Makes the sector available for PoSt vanilla proofs.
+    pub fn insert_replica(&self, sector_id: SectorId, replica: PrivateReplicaInfo<Tree>) {
+        self.replicas
+            .write()
+            .expect("replicas poisoned")
+            .insert(sector_id, replica);
+    }
+
+    /// Makes the updated sector available for empty sector update vanilla proofs.
+    pub fn insert_update(&self, sector_id: SectorId, paths: UpdateSectorPaths) {
+        self.updates
+            .write()
+            .expect("updates poisoned")
+            .insert(sector_id, paths);
+    }
+
+    pub fn remove_sector(&self, sector_id: SectorId) {
+        self.replicas
+            .write()
+            .expect("replicas poisoned")
+            .remove(&sector_id);
+        self.updates
+            .write()
+            .expect("updates poisoned")
+            .remove(&sector_id);
+    }
+
+    /// Accepts connections until the listener fails. Connections that can't be accepted, e.g.
+    /// because they were aborted or the file descriptors ran out, are logged and skipped.
+    pub fn serve_tcp(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        info!("serve_tcp:start: {:?}", listener.local_addr()?);
+        let context = ProofsContext::current();
+        for stream in listener.incoming() {
+            let stream = match accepted(stream)? {
+                Some(stream) => stream,
+                None => continue,
+            };
+            if let Err(err) = stream.set_read_timeout(self.config.read_timeout) {
+                warn!("failed to set the read timeout of a connection: {:?}", err);
+                continue;
+            }
+            self.spawn_connection(stream, &context);
+        }
+        info!("serve_tcp:finish");
+        Ok(())
+    }
+
+    /// Accepts connections until the listener fails, see [`Self::serve_tcp`].
+    #[cfg(unix)]
+    pub fn serve_unix(self: Arc<Self>, listener: UnixListener) -> Result<()> {
+        info!("serve_unix:start: {:?}", listener.local_addr()?);
+        let context = ProofsContext::current();
+        for stream in listener.incoming() {
+            let stream = match accepted(stream)? {
+                Some(stream) => stream,
+                None => continue,
+            };
+            if let Err(err) = stream.set_read_timeout(self.config.read_timeout) {
+                warn!("failed to set the read timeout of a connection: {:?}", err);
+                continue;
+            }
+            self.spawn_connection(stream, &context);
+        }
+        info!("serve_unix:finish");
+        Ok(())
+    }
+
+    /// Serves the connection on its own thread, once fewer than
+    /// [`VanillaProofServerConfig::max_connections`] connections are served.
+    fn spawn_connection<S: 'static + Read + Write + Send>(
+        self: &Arc<Self>,
+        stream: S,
+        context: &ProofsContext,
+    ) {
+        let mut connections = self.connections.lock().expect("connections poisoned");
+        while *connections >= self.config.max_connections.max(1) {
+            connections = self
+                .connection_closed
+                .wait(connections)
+                .expect("connections poisoned");
+        }
+        *connections += 1;
+        drop(connections);
+
+        let server = Arc::clone(self);
+        let context = context.clone();
+        thread::spawn(move || {
+            let _closed = ConnectionClosed(&server);
+            context.scope(|| server.serve_logged(stream))
+        });
+    }
+
+    fn serve_logged<S: Read + Write>(&self, stream: S) {
+        if let Err(err) = self.serve_connection(stream) {
+            warn!("vanilla proof connection failed: {:?}", err);
+        }
+    }
+
+    /// Answers the requests of a single connection until the client closes it or a read times
+    /// out.
+    ///
+    /// A request that can't be decoded is answered with [`Response::Error`], then the connection
+    /// is closed.
+    pub fn serve_connection<S: Read + Write>(&self, mut stream: S) -> Result<()> {
+        loop {
+            let response = match read_message::<_, Request>(&mut stream, MAX_REQUEST_LEN) {
+                Ok(Some(request)) => self.handle(request),
+                Ok(None) => return Ok(()),
+                Err(err @ Error::InvalidInput(_)) => {
+                    let response = Response::Error(RemoteError {
+                        kind: RemoteErrorKind::InvalidInput,
+                        message: err.to_string(),
+                    });
+                    write_message(&mut stream, &response, MAX_RESPONSE_LEN)?;
+                    return Err(err);
+                }
+                Err(err) => return Err(err),
+            };
+            write_message(&mut stream, &response, MAX_RESPONSE_LEN)?;
+        }
+    }
+
+    /// Generates the vanilla proofs of a single request.
+    pub fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::PoStVanillaProofs(request)
+                if request.sectors.len() > self.config.max_sectors_per_request =>
+            {
+                Response::Error(RemoteError {
+                    kind: RemoteErrorKind::InvalidInput,
+                    message: format!(
+                        "{} sectors requested, at most {} are served per request",
+                        request.sectors.len(),
+                        self.config.max_sectors_per_request
+                    ),
+                })
+            }
+            Request::PoStVanillaProofs(request) => {
+                Response::PoStVanillaProofs(self.post_vanilla_proofs(request))
+            }
+            Request::UpdateVanillaProofs(request) => match self.update_vanilla_proofs(request) {
+                Ok(response) => Response::UpdateVanillaProofs(response),
+                Err(err) => Response::Error(err),
+            },
+        }
+    }
+
+    fn post_vanilla_proofs(&self, request: PoStVanillaRequest) -> Vec<SectorProofResponse> {
+        let PoStVanillaRequest {
+            post_config,
+            sectors,
+        } = request;
+        info!("post_vanilla_proofs:start: {} sectors", sectors.len());
+
+        // The lock isn't held while proving, so that sectors can be added and removed meanwhile.
+        let replicas: Vec<_> = {
+            let replicas = self.replicas.read().expect("replicas poisoned");
+            sectors
+                .iter()
+                .map(|sector| replicas.get(&sector.sector_id).cloned())
+                .collect()
+        };
+        let context = ProofsContext::current();
+        let responses = sectors
+            .into_par_iter()
+            .zip(replicas)
+            .map(|(sector, replica)| {
+                let _context = context.enter();
+                let start = Instant::now();
+                let proof = match replica {
+                    Some(replica) => generate_single_vanilla_proof::<Tree>(
+                        &post_config,
+                        sector.sector_id,
+                        &replica,
+                        &sector.challenges,
+                    )
+                    .and_then(|proof| proof.to_bytes())
+                    .map_err(RemoteError::from_error),
+                    None => Err(unknown_sector(sector.sector_id)),
+                };
+                SectorProofResponse {
+                    sector_id: sector.sector_id,
+                    proof,
+                    duration: start.elapsed(),
+                }
+            })
+            .collect();
+
+        info!("post_vanilla_proofs:finish");
+        responses
+    }
+
+    fn update_vanilla_proofs(
+        &self,
+        request: UpdateVanillaRequest,
+    ) -> std::result::Result<UpdateVanillaResponse, RemoteError> {
+        info!("update_vanilla_proofs:start: {:?}", request.sector_id);
+        let start = Instant::now();
+        let paths = self
+            .updates
+            .read()
+            .expect("updates poisoned")
+            .get(&request.sector_id)
+            .cloned()
+            .ok_or_else(|| unknown_sector(request.sector_id))?;
+
+        let partition_proofs = generate_partition_proofs::<Tree>(
+            SectorUpdateConfig::from_porep_config(&request.porep_config),
+            request.comm_r_old,
+            request.comm_r_new,
+            request.comm_d_new,
+            &paths.sector_key_path,
+            &paths.sector_key_cache_path,
+            &paths.replica_path,
+            &paths.replica_cache_path,
+        )
+        .and_then(|proofs| proofs.iter().map(VanillaProofBytes::to_bytes).collect())
+        .map_err(RemoteError::from_error)?;
+
+        info!("update_vanilla_proofs:finish: {:?}", request.sector_id);
+        Ok(UpdateVanillaResponse {
+            partition_proofs,
+            duration: start.elapsed(),
+        })
+    }
+}
+
+/// Frees the slot of a connection when its thread finishes, even if it panics.
+struct ConnectionClosed<'a, Tree: MerkleTreeTrait>(&'a VanillaProofServer<Tree>);
+
+impl<Tree: MerkleTreeTrait> Drop for ConnectionClosed<'_, Tree> {
+    fn drop(&mut self) {
+        // Not `expect`, panicking while unwinding would abort.
+        if let Ok(mut connections) = self.0.connections.lock() {
+            *connections -= 1;
+        }
+        self.0.connection_closed.notify_one();
+    }
+}
+
+fn unknown_sector(sector_id: SectorId) -> RemoteError {
+    RemoteError {
+        kind: RemoteErrorKind::UnknownSector,
+        message: format!("unknown sector {:?}", sector_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use storage_proofs_core::api_version::ApiVersion;
+
+    use crate::{
+        constants::{SectorShape2KiB, SECTOR_SIZE_2_KIB},
+        split_prover::{SectorChallenges, VanillaProofClient},
+        types::{PoStConfig, PoStType, PublicReplicaInfo},
+    };
+
+    fn post_config() -> PoStConfig {
+        PoStConfig {
+            sector_size: SECTOR_SIZE_2_KIB.into(),
+            challenge_count: 2,
+            sector_count: 2,
+            typ: PoStType::Window,
+            priority: false,
+            api_version: ApiVersion::V1_1_0,
+        }
+    }
+
+    fn sectors(count: u64) -> Vec<SectorChallenges> {
+        (1..=count)
+            .map(|sector_id| SectorChallenges {
+                sector_id: SectorId::from(sector_id),
+                challenges: vec![0, 1],
+            })
+            .collect()
+    }
+
+    fn replicas(count: u64) -> BTreeMap<SectorId, PublicReplicaInfo> {
+        (1..=count)
+            .map(|sector_id| {
+                (
+                    SectorId::from(sector_id),
+                    PublicReplicaInfo::new([1; 32]).expect("invalid comm_r"),
+                )
+            })
+            .collect()
+    }
+
+    fn serve_tcp(config: VanillaProofServerConfig) -> std::net::SocketAddr {
+        let server = Arc::new(VanillaProofServer::<SectorShape2KiB>::with_config(config));
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind");
+        let addr = listener.local_addr().expect("failed to get address");
+        thread::spawn(move || server.serve_tcp(listener));
+        addr
+    }
+
+    #[test]
+    fn test_serve_tcp() {
+        let addr = serve_tcp(VanillaProofServerConfig {
+            max_sectors_per_request: 2,
+            ..Default::default()
+        });
+
+        // The sectors are requested one partition at a time, all of them are unknown.
+        let mut client = VanillaProofClient::connect_tcp(addr).expect("failed to connect");
+        match client.post_vanilla_proofs::<SectorShape2KiB>(
+            &post_config(),
+            sectors(3),
+            &replicas(3),
+        ) {
+            Err(Error::FaultySectors { sectors, .. }) => {
+                assert_eq!(sectors, (1..=3).map(SectorId::from).collect::<Vec<_>>())
+            }
+            other => panic!("expected faulty sectors, got {:?}", other),
+        }
+
+        // More sectors than the server serves per request are rejected, whatever the client's
+        // partition size.
+        let request = Request::PoStVanillaProofs(PoStVanillaRequest {
+            post_config: PoStConfig {
+                sector_count: 3,
+                ..post_config()
+            },
+            sectors: sectors(3),
+        });
+        match client.request(&request).expect("request failed") {
+            Response::Error(err) => assert_eq!(err.kind, RemoteErrorKind::InvalidInput),
+            other => panic!("expected an error, got {:?}", other),
+        }
+
+        // A frame that is too large is answered with an error before it is read.
+        let mut stream = TcpStream::connect(addr).expect("failed to connect");
+        stream
+            .write_all(&(MAX_REQUEST_LEN + 1).to_le_bytes())
+            .expect("failed to write");
+        match read_message::<_, Response>(&mut stream, MAX_RESPONSE_LEN).expect("read failed") {
+            Some(Response::Error(err)) => assert_eq!(err.kind, RemoteErrorKind::InvalidInput),
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_accept_errors() {
+        let err = |kind| accepted::<()>(Err(io::Error::from(kind)));
+        assert!(matches!(err(io::ErrorKind::ConnectionAborted), Ok(None)));
+        assert!(matches!(err(io::ErrorKind::Interrupted), Ok(None)));
+        assert!(err(io::ErrorKind::InvalidInput).is_err());
+        assert!(matches!(accepted(Ok(1)), Ok(Some(1))));
+
+        #[cfg(unix)]
+        {
+            let os_err = |code| accepted::<()>(Err(io::Error::from_raw_os_error(code)));
+            assert!(matches!(os_err(libc::EMFILE), Ok(None)));
+            assert!(os_err(libc::EBADF).is_err());
+        }
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let addr = serve_tcp(VanillaProofServerConfig {
+            max_connections: 1,
+            ..Default::default()
+        });
+        let mut first = VanillaProofClient::connect_tcp(addr).expect("failed to connect");
+        let request = Request::PoStVanillaProofs(PoStVanillaRequest {
+            post_config: post_config(),
+            sectors: sectors(1),
+        });
+        first.request(&request).expect("request failed");
+
+        // The second connection is only served once the first one is closed.
+        let (sender, receiver) = mpsc::channel();
+        let second_request = request.clone();
+        thread::spawn(move || {
+            let mut second = VanillaProofClient::connect_tcp(addr).expect("failed to connect");
+            sender
+                .send(second.request(&second_request).is_ok())
+                .expect("failed to send");
+        });
+        assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+        drop(first);
+        assert!(receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("second connection wasn't served"));
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let addr = serve_tcp(VanillaProofServerConfig {
+            max_connections: 1,
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        });
+
+        // An idle connection is closed once the read times out and frees its slot.
+        let mut idle = TcpStream::connect(addr).expect("failed to connect");
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut client = VanillaProofClient::connect_tcp(addr).expect("failed to connect");
+            let request = Request::PoStVanillaProofs(PoStVanillaRequest {
+                post_config: post_config(),
+                sectors: sectors(1),
+            });
+            sender
+                .send(client.request(&request).is_ok())
+                .expect("failed to send");
+        });
+        assert!(receiver
+            .recv_timeout(Duration::from_secs(10))
+            .expect("second connection wasn't served"));
+        let mut buf = [0; 1];
+        assert_eq!(idle.read(&mut buf).expect("read failed"), 0);
+    }
+}
@@ -1,5 +1,9 @@
 pub use merkletree::store::StoreConfig;
 pub use storage_proofs_core::merkle::{MerkleProof, MerkleTreeTrait};
+pub use storage_proofs_core::progress::{
+    is_cancelled_error, CancellationToken, Progress, SealContext, TreeKind,
+};
+pub use storage_proofs_core::settings::{ProofsContext, ProofsContextGuard, Settings};
 pub use storage_proofs_porep::stacked::{Labels, PersistentAux, TemporaryAux};
 pub use storage_proofs_update::constants::TreeRHasher;
 
@@ -12,30 +16,48 @@ use storage_proofs_post::fallback;
 use crate::constants::DefaultPieceHasher;
 
 mod bytes_amount;
+mod piece_inclusion_proof;
 mod piece_info;
+mod piece_layout;
 mod porep_config;
 mod porep_proof_partitions;
+mod post_batch;
 mod post_config;
 mod post_proof_partitions;
+mod preflight;
 mod private_replica_info;
 mod public_replica_info;
+mod resource_estimate;
+mod sector_bundle;
+mod sector_cache_report;
+mod sector_check;
 mod sector_class;
 mod sector_size;
 mod sector_update_config;
 mod update_proof_partitions;
+mod vanilla_proof_bytes;
 
 pub use bytes_amount::*;
+pub use piece_inclusion_proof::*;
 pub use piece_info::*;
+pub use piece_layout::*;
 pub use porep_config::*;
 pub use porep_proof_partitions::*;
+pub use post_batch::*;
 pub use post_config::*;
 pub use post_proof_partitions::*;
+pub use preflight::*;
 pub use private_replica_info::*;
 pub use public_replica_info::*;
+pub use resource_estimate::*;
+pub use sector_bundle::*;
+pub use sector_cache_report::*;
+pub use sector_check::*;
 pub use sector_class::*;
 pub use sector_size::*;
 pub use sector_update_config::*;
 pub use update_proof_partitions::*;
+pub use vanilla_proof_bytes::*;
 
 pub type Commitment = [u8; 32];
 pub type ChallengeSeed = [u8; 32];
@@ -96,6 +118,14 @@ pub type AggregateSnarkProof = Vec<u8>;
 pub type VanillaProof<Tree> = fallback::Proof<<Tree as MerkleTreeTrait>::Proof>;
 pub type PartitionProof<Tree> = storage_proofs_update::vanilla::PartitionProof<Tree>;
 
+/// The output of [`crate::generate_window_post_skipping_faults`].
+#[derive(Clone, Debug)]
+pub struct WindowPoStOutput {
+    pub proof: SnarkProof,
+    /// The faulty sectors, which aren't part of the proof.
+    pub skipped: Vec<SectorId>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 #[repr(transparent)]
 pub struct EmptySectorUpdateProof(pub Vec<u8>);
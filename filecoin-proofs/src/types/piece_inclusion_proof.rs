@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::Commitment;
+
+/// Proof that a piece is stored at its aligned position within a sector, i.e. that its piece
+/// commitment is a node of the sector's tree-d.
+///
+/// It contains the sibling nodes on the path from the piece commitment up to, but excluding, the
+/// root (CommD), starting at the level of the piece commitment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceInclusionProof {
+    pub path: Vec<Commitment>,
+}
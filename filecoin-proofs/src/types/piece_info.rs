@@ -1,9 +1,11 @@
 use std::fmt::{self, Debug, Formatter};
 
-use anyhow::{ensure, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::types::{Commitment, UnpaddedBytesAmount};
+use crate::{
+    error::{ensure_input, Result},
+    types::{Commitment, UnpaddedBytesAmount},
+};
 
 #[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PieceInfo {
@@ -22,7 +24,7 @@ impl Debug for PieceInfo {
 
 impl PieceInfo {
     pub fn new(commitment: Commitment, size: UnpaddedBytesAmount) -> Result<Self> {
-        ensure!(commitment != [0; 32], "Invalid all zero commitment");
+        ensure_input!(commitment != [0; 32], "Invalid all zero commitment");
         Ok(PieceInfo { commitment, size })
     }
 }
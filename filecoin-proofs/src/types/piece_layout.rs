@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Commitment, PieceInfo, UnpaddedByteIndex};
+
+/// How the planner picks the optional pieces that go into a sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PackingStrategy {
+    /// Candidates are taken in the given order, each one that still fits is added.
+    FirstFit,
+    /// Candidates are taken from the largest to the smallest, which leaves the least space unused.
+    BestFit,
+}
+
+/// Where a piece of a [`PieceLayout`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayoutPieceKind {
+    /// The piece at this index of the must-include pieces.
+    Required(usize),
+    /// The piece at this index of the candidate pieces.
+    Candidate(usize),
+    /// A zero padding piece that fills unused space.
+    Filler,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LayoutPiece {
+    pub kind: LayoutPieceKind,
+    pub piece_info: PieceInfo,
+    pub offset: UnpaddedByteIndex,
+}
+
+/// An ordered layout of the pieces within a sector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceLayout {
+    /// The pieces in the order they are added to the sector, they cover the whole sector.
+    pub pieces: Vec<LayoutPiece>,
+    /// The indices of the candidate pieces that didn't fit.
+    pub excluded: Vec<usize>,
+    pub comm_d: Commitment,
+}
+
+impl PieceLayout {
+    /// Returns the piece infos of all pieces, including the fillers, in layout order.
+    pub fn piece_infos(&self) -> Vec<PieceInfo> {
+        self.pieces
+            .iter()
+            .map(|piece| piece.piece_info.clone())
+            .collect()
+    }
+}
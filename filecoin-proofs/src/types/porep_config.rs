@@ -1,6 +1,5 @@
 use std::path::PathBuf;
 
-use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use storage_proofs_core::{
     api_version::{ApiFeature, ApiVersion},
@@ -14,6 +13,7 @@ use storage_proofs_porep::stacked::{StackedCircuit, StackedCompound};
 
 use crate::{
     constants::{self, DefaultPieceHasher},
+    error::{Error, Result},
     parameters::public_params,
     types::{PaddedBytesAmount, PoRepProofPartitions, SectorSize, UnpaddedBytesAmount},
     POREP_PARTITIONS,
@@ -91,9 +91,9 @@ impl PoRepConfig {
     pub fn enable_feature(&mut self, feat: ApiFeature) -> Result<()> {
         for conflict in feat.conflicting_features() {
             if self.feature_enabled(*conflict) {
-                return Err(anyhow!(
+                return Err(Error::InvalidInput(format!(
                     "Cannot enable feature `{feat}` when `{conflict}` is already enabled"
-                ));
+                )));
             }
         }
 
@@ -0,0 +1,23 @@
+use std::collections::BTreeMap;
+
+use storage_proofs_core::sector::SectorId;
+
+use crate::types::{ChallengeSeed, ProverId, PublicReplicaInfo};
+
+/// A Window PoSt to verify within a batch, see [`crate::verify_window_post_batch`].
+#[derive(Debug, Clone, Copy)]
+pub struct WindowPoStBatchItem<'a> {
+    pub randomness: &'a ChallengeSeed,
+    pub replicas: &'a BTreeMap<SectorId, PublicReplicaInfo>,
+    pub prover_id: ProverId,
+    pub proof: &'a [u8],
+}
+
+/// A Winning PoSt to verify within a batch, see [`crate::verify_winning_post_batch`].
+#[derive(Debug, Clone, Copy)]
+pub struct WinningPoStBatchItem<'a> {
+    pub randomness: &'a ChallengeSeed,
+    pub replicas: &'a [(SectorId, PublicReplicaInfo)],
+    pub prover_id: ProverId,
+    pub proof: &'a [u8],
+}
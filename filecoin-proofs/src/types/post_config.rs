@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use storage_proofs_core::{
     api_version::ApiVersion,
     merkle::MerkleTreeTrait,
@@ -12,11 +12,12 @@ use storage_proofs_core::{
 use storage_proofs_post::fallback::{FallbackPoStCircuit, FallbackPoStCompound};
 
 use crate::{
+    error::Result,
     parameters::{window_post_public_params, winning_post_public_params},
     types::{PaddedBytesAmount, SectorSize, UnpaddedBytesAmount},
 };
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PoStConfig {
     pub sector_size: SectorSize,
     pub challenge_count: usize,
@@ -27,7 +28,7 @@ pub struct PoStConfig {
     pub api_version: ApiVersion,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PoStType {
     Winning,
     Window,
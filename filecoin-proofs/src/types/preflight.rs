@@ -0,0 +1,64 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// A prerequisite of a phase that isn't met, see [`crate::preflight_seal`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PreflightFailure {
+    /// The filesystem of `path` can't hold the files the phase writes.
+    InsufficientSpace {
+        path: PathBuf,
+        required: u64,
+        available: u64,
+    },
+    /// The SDR parent cache hasn't been generated yet.
+    MissingParentCache { path: PathBuf },
+    /// The digest of the SDR parent cache doesn't match the one in the manifest.
+    CorruptParentCache { path: PathBuf },
+    /// The Groth parameters aren't in the parameter cache.
+    MissingParameters { path: PathBuf },
+    /// The digest of the Groth parameters doesn't match the one in `parameters.json`.
+    CorruptParameters { path: PathBuf },
+    /// The soft file descriptor limit is lower than the number of files the phase keeps open. The
+    /// limit is only read by the checks, raising it is up to the caller.
+    FileDescriptorLimit { required: u64, available: u64 },
+}
+
+impl fmt::Display for PreflightFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightFailure::InsufficientSpace {
+                path,
+                required,
+                available,
+            } => write!(
+                f,
+                "{} bytes are required on the filesystem of {}, {} are available",
+                required,
+                path.display(),
+                available
+            ),
+            PreflightFailure::MissingParentCache { path } => {
+                write!(f, "parent cache {} is missing", path.display())
+            }
+            PreflightFailure::CorruptParentCache { path } => {
+                write!(f, "parent cache {} is corrupt", path.display())
+            }
+            PreflightFailure::MissingParameters { path } => {
+                write!(f, "parameters {} are missing", path.display())
+            }
+            PreflightFailure::CorruptParameters { path } => {
+                write!(f, "parameters {} are corrupt", path.display())
+            }
+            PreflightFailure::FileDescriptorLimit {
+                required,
+                available,
+            } => write!(
+                f,
+                "{} file descriptors are required, the limit is {}",
+                required, available
+            ),
+        }
+    }
+}
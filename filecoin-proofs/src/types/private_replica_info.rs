@@ -2,8 +2,8 @@ use std::cmp::Ordering;
 use std::hash::{Hash, Hasher as StdHasher};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::{ensure, Result};
 use filecoin_hashers::Hasher;
 use generic_array::typenum::Unsigned;
 use log::trace;
@@ -14,11 +14,13 @@ use storage_proofs_core::{
         create_tree, get_base_tree_count, split_config_and_replica, MerkleTreeTrait,
         MerkleTreeWrapper,
     },
+    storage::{read_all, SectorStorage},
     util::default_rows_to_discard,
 };
 
 use crate::{
     api::{as_safe_commitment, get_base_tree_leafs, get_base_tree_size, get_p_aux},
+    error::{ensure_cache_file, ensure_input, Error, Result},
     types::{Commitment, PersistentAux, SectorSize},
 };
 
@@ -34,6 +36,8 @@ pub struct PrivateReplicaInfo<Tree: MerkleTreeTrait> {
     aux: PersistentAux<<Tree::Hasher as Hasher>::Domain>,
     /// Contains sector-specific (e.g. merkle trees) assets
     pub cache_dir: PathBuf,
+    /// The storage the replica and cache directory are kept in, `None` if they are local.
+    storage: Option<Arc<dyn SectorStorage>>,
 
     _t: PhantomData<Tree>,
 }
@@ -45,6 +49,7 @@ impl<Tree: MerkleTreeTrait> Clone for PrivateReplicaInfo<Tree> {
             comm_r: self.comm_r,
             aux: self.aux.clone(),
             cache_dir: self.cache_dir.clone(),
+            storage: self.storage.clone(),
             _t: Default::default(),
         }
     }
@@ -84,17 +89,48 @@ impl<Tree: MerkleTreeTrait> PartialOrd for PrivateReplicaInfo<Tree> {
 
 impl<Tree: 'static + MerkleTreeTrait> PrivateReplicaInfo<Tree> {
     pub fn new(replica: PathBuf, comm_r: Commitment, cache_dir: PathBuf) -> Result<Self> {
-        ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+        ensure_input!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
 
         let aux = get_p_aux::<Tree>(&cache_dir)?;
 
-        ensure!(replica.exists(), "Sealed replica does not exist");
+        ensure_cache_file!(replica.exists(), &replica, "Sealed replica does not exist");
 
         Ok(PrivateReplicaInfo {
             replica,
             comm_r,
             aux,
             cache_dir,
+            storage: None,
+            _t: Default::default(),
+        })
+    }
+
+    /// Creates the information about a replica that is kept in `storage`. A PoSt reads only the
+    /// challenged replica and tree-r-last nodes from it, nothing is staged locally.
+    pub fn new_in_storage(
+        storage: Arc<dyn SectorStorage>,
+        replica: PathBuf,
+        comm_r: Commitment,
+        cache_dir: PathBuf,
+    ) -> Result<Self> {
+        ensure_input!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+
+        let p_aux_path = cache_dir.join(CacheKey::PAux.to_string());
+        let p_aux_bytes =
+            read_all(&*storage, &p_aux_path).map_err(|err| Error::cache_file(&p_aux_path, err))?;
+        let aux = bincode::deserialize(&p_aux_bytes)
+            .map_err(|err| Error::cache_file(&p_aux_path, err))?;
+
+        storage
+            .size(&replica)
+            .map_err(|err| Error::cache_file(&replica, err))?;
+
+        Ok(PrivateReplicaInfo {
+            replica,
+            comm_r,
+            aux,
+            cache_dir,
+            storage: Some(storage),
             _t: Default::default(),
         })
     }
@@ -107,6 +143,10 @@ impl<Tree: 'static + MerkleTreeTrait> PrivateReplicaInfo<Tree> {
         self.replica.as_path()
     }
 
+    pub fn storage(&self) -> Option<&Arc<dyn SectorStorage>> {
+        self.storage.as_ref()
+    }
+
     pub fn safe_comm_r(&self) -> Result<<Tree::Hasher as Hasher>::Domain> {
         as_safe_commitment(&self.comm_r, "comm_r")
     }
@@ -119,7 +159,8 @@ impl<Tree: 'static + MerkleTreeTrait> PrivateReplicaInfo<Tree> {
         self.aux.comm_r_last
     }
 
-    /// Generate the merkle tree of this particular replica.
+    /// Generate the merkle tree of this particular replica. It fails for a replica that is kept
+    /// in a [`SectorStorage`], its tree-r-last isn't available locally.
     pub fn merkle_tree(
         &self,
         sector_size: SectorSize,
@@ -132,6 +173,11 @@ impl<Tree: 'static + MerkleTreeTrait> PrivateReplicaInfo<Tree> {
             Tree::TopTreeArity,
         >,
     > {
+        ensure_input!(
+            self.storage.is_none(),
+            "the merkle tree of a replica in storage can't be opened locally"
+        );
+
         let base_tree_size = get_base_tree_size::<Tree>(sector_size)?;
         let base_tree_leafs = get_base_tree_leafs::<Tree>(base_tree_size)?;
         trace!(
@@ -159,6 +205,10 @@ impl<Tree: 'static + MerkleTreeTrait> PrivateReplicaInfo<Tree> {
             tree_count,
         )?;
 
-        create_tree::<Tree>(base_tree_size, &configs, Some(&replica_config))
+        Ok(create_tree::<Tree>(
+            base_tree_size,
+            &configs,
+            Some(&replica_config),
+        )?)
     }
 }
@@ -1,10 +1,13 @@
 use std::cmp::Ordering;
 use std::hash::Hash;
 
-use anyhow::{ensure, Result};
 use filecoin_hashers::Domain;
 
-use crate::{api::as_safe_commitment, types::Commitment};
+use crate::{
+    api::as_safe_commitment,
+    error::{ensure_input, Result},
+    types::Commitment,
+};
 
 /// The minimal information required about a replica, in order to be able to verify
 /// a PoSt over it.
@@ -28,7 +31,7 @@ impl PartialOrd for PublicReplicaInfo {
 
 impl PublicReplicaInfo {
     pub fn new(comm_r: Commitment) -> Result<Self> {
-        ensure!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
+        ensure_input!(comm_r != [0; 32], "Invalid all zero commitment (comm_r)");
         Ok(PublicReplicaInfo { comm_r })
     }
 
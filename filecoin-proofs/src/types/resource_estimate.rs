@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+
+/// A phase of the sealing, PoSt or update pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ProofPhase {
+    PreCommitPhase1,
+    PreCommitPhase2,
+    /// `generate_synth_proofs`, only with synthetic PoRep.
+    GenerateSynthProofs,
+    CommitPhase1,
+    CommitPhase2,
+    WinningPost,
+    WindowPost,
+    EncodeInto,
+    GenerateUpdateProof,
+}
+
+/// The directory a file is written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FileLocation {
+    /// The cache directory of the sector.
+    CacheDir,
+    /// The replica file itself, it's the only file in that location.
+    Replica,
+    /// The directory of the SDR parent cache (`FIL_PROOFS_PARENT_CACHE`).
+    ParentCacheDir,
+}
+
+/// How long a file needs to be kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FileLifetime {
+    /// It's only needed until the sector is sealed or updated, e.g. it's removed by
+    /// `clear_cache` or `clear_synthetic_proofs`.
+    Scratch,
+    /// It's needed for proving the sector.
+    Final,
+    /// It's shared by all sectors of the same size and kept across sectors.
+    Shared,
+}
+
+/// A file that is written by a phase.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEstimate {
+    pub location: FileLocation,
+    /// The file name within the cache directory, or a description for the other locations.
+    pub name: String,
+    pub bytes: u64,
+    pub lifetime: FileLifetime,
+}
+
+/// The resources a single phase needs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseResources {
+    pub phase: ProofPhase,
+    /// The memory of the buffers that scale with the sector size, of the vanilla proofs and of the
+    /// synthesized circuits.
+    pub memory: u64,
+    /// The size of the Groth parameters that are loaded, `None` if the phase doesn't need any or
+    /// they aren't in the parameter cache.
+    pub parameters: Option<u64>,
+    /// The files that are written by the phase.
+    pub files: Vec<FileEstimate>,
+}
+
+impl PhaseResources {
+    /// Returns the expected peak memory, the buffers plus the Groth parameters.
+    pub fn peak_memory(&self) -> u64 {
+        self.memory + self.parameters.unwrap_or(0)
+    }
+
+    /// Returns the disk space of the written files that can be removed once the sector is sealed
+    /// or updated.
+    pub fn scratch_disk(&self) -> u64 {
+        self.disk(FileLifetime::Scratch)
+    }
+
+    /// Returns the disk space of the written files that need to be kept for proving.
+    pub fn final_disk(&self) -> u64 {
+        self.disk(FileLifetime::Final)
+    }
+
+    fn disk(&self, lifetime: FileLifetime) -> u64 {
+        self.files
+            .iter()
+            .filter(|file| file.lifetime == lifetime)
+            .map(|file| file.bytes)
+            .sum()
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::api_version::ApiVersion;
+
+use crate::types::{Commitment, SectorOperation, SectorSize};
+
+/// The version of the sector bundle format.
+pub const SECTOR_BUNDLE_VERSION: u32 = 1;
+
+/// A file within a sector bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectorBundleFile {
+    /// The path within the bundle, either `replica` or `cache/<file name>`.
+    pub path: String,
+    pub size: u64,
+    /// The hex encoded SHA-256 digest of the file.
+    pub sha256: String,
+}
+
+/// The manifest of a sector bundle, it's stored as `manifest.json` at the end of the bundle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectorBundleManifest {
+    pub version: u32,
+    pub sector_size: SectorSize,
+    /// The arities of the tree-r-last, as `<base>-<sub>-<top>`.
+    pub tree_shape: String,
+    pub porep_id: [u8; 32],
+    pub api_version: ApiVersion,
+    /// The operation the bundle contains the files for.
+    pub operation: SectorOperation,
+    /// The commitments from `p_aux`, they are only set if the bundle contains it.
+    pub comm_r: Option<Commitment>,
+    pub comm_c: Option<Commitment>,
+    pub comm_r_last: Option<Commitment>,
+    pub files: Vec<SectorBundleFile>,
+}
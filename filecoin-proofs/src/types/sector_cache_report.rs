@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Commitment, TreeKind};
+
+/// An operation that needs some of the files of a sector cache directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SectorOperation {
+    /// `seal_pre_commit_phase2`, it needs the labels of all layers and tree-d.
+    PreCommitPhase2,
+    /// `seal_commit_phase1`, it needs all trees and either the labels or the synthetic proofs.
+    CommitPhase1,
+    /// Winning and Window PoSt, they need tree-r-last.
+    PoSt,
+    /// `encode_into` with the sector as sector key.
+    EncodeInto,
+}
+
+/// A file whose size doesn't match the sector shape.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrongFileSize {
+    pub path: PathBuf,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// A file that exists, but can't be read or deserialized.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnreadableFile {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// A tree whose root doesn't match the one stored in `p_aux`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootMismatch {
+    pub tree: TreeKind,
+    /// The root stored in `p_aux`.
+    pub expected: Commitment,
+    /// The root of the tree on disk.
+    pub actual: Commitment,
+}
+
+/// The result of [`crate::diagnose_sector_cache`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectorCacheReport {
+    pub cache_path: PathBuf,
+    pub missing_files: Vec<PathBuf>,
+    /// Files within the cache directory that don't belong to the sector.
+    pub extra_files: Vec<PathBuf>,
+    pub wrong_sizes: Vec<WrongFileSize>,
+    pub unreadable_files: Vec<UnreadableFile>,
+    pub root_mismatches: Vec<RootMismatch>,
+    pub has_synthetic_proofs: bool,
+    /// The operations that can still be run with the files that are there.
+    pub possible_operations: Vec<SectorOperation>,
+}
+
+impl SectorCacheReport {
+    /// Returns true if no files are missing, corrupted or unexpected.
+    ///
+    /// Note that a sector that was cleaned up after sealing isn't healthy, as the labels, tree-c
+    /// and tree-d are missing. Use [`SectorCacheReport::is_possible`] to check whether it can
+    /// still be used for a certain operation.
+    pub fn is_healthy(&self) -> bool {
+        self.missing_files.is_empty()
+            && self.extra_files.is_empty()
+            && self.wrong_sizes.is_empty()
+            && self.unreadable_files.is_empty()
+            && self.root_mismatches.is_empty()
+    }
+
+    pub fn is_possible(&self, operation: SectorOperation) -> bool {
+        self.possible_operations.contains(&operation)
+    }
+}
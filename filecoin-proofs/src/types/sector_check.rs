@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use storage_proofs_core::sector::SectorId;
+
+/// Whether a sector can be proven, see [`crate::check_sectors_provable`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SectorStatus {
+    /// All challenged leafs have a valid inclusion proof into tree-r-last.
+    Provable,
+    /// The replica or a tree-r-last file doesn't exist.
+    MissingFile { path: PathBuf },
+    /// comm_c and comm_r_last of p_aux don't hash to the comm_r of the sector.
+    CommRMismatch,
+    /// The replica or tree-r-last couldn't be read.
+    Io { error: String },
+    /// The inclusion proof of a challenged leaf doesn't lead to comm_r_last.
+    RootMismatch { challenge: u64 },
+}
+
+/// The result of checking a single sector.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectorCheck {
+    pub sector_id: SectorId,
+    pub status: SectorStatus,
+    /// The time it took to read and validate the challenged leafs.
+    pub duration: Duration,
+}
+
+impl SectorCheck {
+    pub fn is_provable(&self) -> bool {
+        self.status == SectorStatus::Provable
+    }
+}
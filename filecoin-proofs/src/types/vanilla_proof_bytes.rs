@@ -0,0 +1,148 @@
+use std::convert::TryInto;
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Serialize};
+use storage_proofs_core::merkle::MerkleTreeTrait;
+use typenum::Unsigned;
+
+use crate::{
+    error::{ensure_input, Error, Result},
+    types::{FallbackPoStSectorProof, PartitionProof, TreeRHasher},
+};
+
+/// The version of the binary encoding of vanilla proofs.
+pub const VANILLA_PROOF_BYTES_VERSION: u32 = 1;
+
+/// The bytes of the header, see [`VanillaProofBytes`].
+const HEADER_LEN: usize = 11;
+
+/// A versioned binary encoding of vanilla proofs, for sending them between machines.
+///
+/// The encoding is:
+///
+/// | Bytes | Content                                                                         |
+/// |-------|---------------------------------------------------------------------------------|
+/// | 4     | Magic, `FPSP` for a [`FallbackPoStSectorProof`], `SUPP` for a [`PartitionProof`] |
+/// | 4     | Version as little endian `u32`, see [`VANILLA_PROOF_BYTES_VERSION`]             |
+/// | 3     | Arity of the base, sub and top tree of tree-r-last                              |
+/// | rest  | The proof encoded with bincode, integers are fixed size little endian           |
+///
+/// Decoding fails if the magic, the version or the tree shape don't match, or if there are bytes
+/// after the proof.
+pub trait VanillaProofBytes: Sized {
+    fn to_bytes(&self) -> Result<Vec<u8>>;
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self>;
+}
+
+impl<Tree: MerkleTreeTrait> VanillaProofBytes for FallbackPoStSectorProof<Tree> {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        encode::<Tree, _>(b"FPSP", self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode::<Tree, _>(b"FPSP", bytes)
+    }
+}
+
+impl<Tree: MerkleTreeTrait<Hasher = TreeRHasher>> VanillaProofBytes for PartitionProof<Tree> {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        encode::<Tree, _>(b"SUPP", self)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        decode::<Tree, _>(b"SUPP", bytes)
+    }
+}
+
+fn header<Tree: MerkleTreeTrait>(magic: &[u8; 4]) -> [u8; HEADER_LEN] {
+    let mut header = [0; HEADER_LEN];
+    header[..4].copy_from_slice(magic);
+    header[4..8].copy_from_slice(&VANILLA_PROOF_BYTES_VERSION.to_le_bytes());
+    header[8] = Tree::Arity::to_u8();
+    header[9] = Tree::SubTreeArity::to_u8();
+    header[10] = Tree::TopTreeArity::to_u8();
+    header
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
+
+fn encode<Tree: MerkleTreeTrait, T: Serialize>(magic: &[u8; 4], proof: &T) -> Result<Vec<u8>> {
+    let mut bytes = header::<Tree>(magic).to_vec();
+    bincode_options()
+        .serialize_into(&mut bytes, proof)
+        .map_err(|err| Error::Internal(err.into()))?;
+    Ok(bytes)
+}
+
+fn decode<Tree: MerkleTreeTrait, T: DeserializeOwned>(magic: &[u8; 4], bytes: &[u8]) -> Result<T> {
+    ensure_input!(bytes.len() >= HEADER_LEN, "vanilla proof is truncated");
+    let (actual, body) = bytes.split_at(HEADER_LEN);
+    ensure_input!(&actual[..4] == magic, "not a vanilla proof of this type");
+    let version = u32::from_le_bytes(actual[4..8].try_into().expect("4 bytes"));
+    ensure_input!(
+        version == VANILLA_PROOF_BYTES_VERSION,
+        "unsupported vanilla proof version {}",
+        version
+    );
+    ensure_input!(
+        actual[8..] == header::<Tree>(magic)[8..],
+        "vanilla proof is for arities {:?}",
+        &actual[8..]
+    );
+
+    bincode_options()
+        .deserialize(body)
+        .map_err(|err| Error::InvalidInput(format!("{:#}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage_proofs_core::sector::SectorId;
+    use storage_proofs_post::fallback;
+
+    use crate::constants::{SectorShape2KiB, SectorShape32KiB};
+
+    fn proof() -> FallbackPoStSectorProof<SectorShape2KiB> {
+        FallbackPoStSectorProof {
+            sector_id: SectorId::from(7),
+            comm_r: Default::default(),
+            vanilla_proof: fallback::Proof {
+                sectors: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_vanilla_proof_bytes() {
+        let bytes = proof().to_bytes().expect("failed to encode");
+        let decoded = FallbackPoStSectorProof::<SectorShape2KiB>::from_bytes(&bytes)
+            .expect("failed to decode");
+        assert_eq!(decoded.sector_id, SectorId::from(7));
+        assert_eq!(decoded.to_bytes().expect("failed to encode"), bytes);
+
+        // Other tree shape.
+        assert!(matches!(
+            FallbackPoStSectorProof::<SectorShape32KiB>::from_bytes(&bytes),
+            Err(Error::InvalidInput(_))
+        ));
+
+        // Truncated and trailing bytes.
+        assert!(matches!(
+            FallbackPoStSectorProof::<SectorShape2KiB>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::InvalidInput(_))
+        ));
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert!(matches!(
+            FallbackPoStSectorProof::<SectorShape2KiB>::from_bytes(&trailing),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+}
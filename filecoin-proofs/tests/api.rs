@@ -2,60 +2,87 @@ use std::collections::BTreeMap;
 use std::fs::{metadata, read_dir, remove_file, File, OpenOptions};
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use anyhow::{ensure, Context, Error, Result};
-use bellperson::groth16;
-use bincode::serialize;
+use bellperson::{groth16, util_cs::bench_cs::BenchCS, Circuit};
+use bincode::{deserialize, serialize};
 use blstrs::{Bls12, Scalar as Fr};
 use ff::Field;
 use filecoin_hashers::Hasher;
 use filecoin_proofs::{
-    add_piece, aggregate_empty_sector_update_proofs, aggregate_seal_commit_proofs, clear_cache,
-    clear_synthetic_proofs, compute_comm_d, decode_from, decode_from_range, encode_into,
-    fauxrep_aux, generate_empty_sector_update_proof,
-    generate_empty_sector_update_proof_with_vanilla, generate_fallback_sector_challenges,
-    generate_partition_proofs, generate_piece_commitment, generate_single_partition_proof,
-    generate_single_vanilla_proof, generate_single_window_post_with_vanilla, generate_synth_proofs,
-    generate_tree_c, generate_tree_r_last, generate_window_post, generate_window_post_with_vanilla,
-    generate_winning_post, generate_winning_post_sector_challenge,
-    generate_winning_post_with_vanilla, get_num_partition_for_fallback_post, get_seal_inputs,
-    get_sector_update_h_select_from_porep_config, get_sector_update_inputs,
-    merge_window_post_partition_proofs, remove_encoded_data, seal_commit_phase1,
-    seal_commit_phase2, seal_commit_phase2_circuit_proofs, seal_pre_commit_phase1,
-    seal_pre_commit_phase2, unseal_range, validate_cache_for_commit,
+    add_piece, aggregate_empty_sector_update_proofs, aggregate_seal_commit_proofs,
+    check_sectors_provable, clear_cache, clear_synthetic_proofs, compute_comm_d, decode_from,
+    decode_from_range, diagnose_sector_cache, encode_into, estimate_post_resources,
+    estimate_seal_resources, estimate_update_resources, export_sector_bundle, fauxrep_aux,
+    generate_empty_sector_update_proof, generate_empty_sector_update_proof_with_vanilla,
+    generate_fallback_sector_challenges, generate_partition_proofs, generate_piece_commitment,
+    generate_piece_inclusion_proofs, generate_piece_inclusion_proofs_from_unsealed,
+    generate_single_partition_proof, generate_single_vanilla_proof,
+    generate_single_window_post_with_vanilla, generate_synth_proofs, generate_tree_c,
+    generate_tree_r_last, generate_window_post, generate_window_post_skipping_faults,
+    generate_window_post_with_vanilla, generate_winning_post,
+    generate_winning_post_sector_challenge, generate_winning_post_with_vanilla,
+    get_num_partition_for_fallback_post, get_seal_inputs,
+    get_sector_update_h_select_from_porep_config, get_sector_update_inputs, import_sector_bundle,
+    merge_window_post_partition_proofs,
+    parameters::public_params,
+    pieces::get_piece_offsets,
+    preflight_seal, rebuild_tree_c, rebuild_tree_d, rebuild_tree_r_last, remove_encoded_data,
+    seal_commit_phase1, seal_commit_phase1_from_storage, seal_commit_phase2,
+    seal_commit_phase2_circuit_proofs, seal_pre_commit_phase1, seal_pre_commit_phase1_with_context,
+    seal_pre_commit_phase2, seal_pre_commit_phase2_with_context,
+    storage::{MemoryStorage, ReadAt, SectorStorage},
+    store_sector_files, unseal_range, validate_cache_for_commit,
     validate_cache_for_precommit_phase2, verify_aggregate_seal_commit_proofs,
     verify_aggregate_sector_update_proofs, verify_empty_sector_update_proof,
-    verify_partition_proofs, verify_seal, verify_single_partition_proof, verify_window_post,
-    verify_winning_post, Commitment, DefaultTreeDomain, EmptySectorUpdateProof, MerkleTreeTrait,
-    PaddedBytesAmount, PieceInfo, PoRepConfig, PoStConfig, PoStType, PrivateReplicaInfo, ProverId,
-    PublicReplicaInfo, SealCommitOutput, SealPreCommitOutput, SealPreCommitPhase1Output,
-    SectorShape16KiB, SectorShape2KiB, SectorShape32GiB, SectorShape32KiB, SectorShape4KiB,
-    SectorUpdateConfig, SectorUpdateProofInputs, UnpaddedByteIndex, UnpaddedBytesAmount,
-    SECTOR_SIZE_16_KIB, SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_32_KIB,
-    SECTOR_SIZE_4_KIB, WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT,
-    WINNING_POST_CHALLENGE_COUNT, WINNING_POST_SECTOR_COUNT,
+    verify_partition_proofs, verify_piece_inclusion_proof, verify_seal,
+    verify_single_partition_proof, verify_vanilla_post_sector_proof, verify_window_post,
+    verify_window_post_batch, verify_winning_post, verify_winning_post_batch, CancellationToken,
+    Commitment, DefaultTreeDomain, EmptySectorUpdateProof, Error as ProofsError,
+    FallbackPoStSectorProof, FileLifetime, FileLocation, MerkleTreeTrait, PaddedBytesAmount,
+    PartitionProof, PersistentAux, PhaseResources, PieceInfo, PoRepConfig, PoStConfig, PoStType,
+    PreflightFailure, PrivateReplicaInfo, Progress, ProofPhase, ProofsContext, ProverId,
+    PublicReplicaInfo, SealCommitOutput, SealContext, SealPreCommitOutput,
+    SealPreCommitPhase1Output, SectorOperation, SectorShape16KiB, SectorShape2KiB,
+    SectorShape32GiB, SectorShape32KiB, SectorShape4KiB, SectorStatus, SectorUpdateConfig,
+    SectorUpdateProofInputs, Settings, TreeKind, UnpaddedByteIndex, UnpaddedBytesAmount,
+    VanillaProofBytes, WindowPoStBatchItem, WinningPoStBatchItem, SECTOR_SIZE_16_KIB,
+    SECTOR_SIZE_2_KIB, SECTOR_SIZE_32_GIB, SECTOR_SIZE_32_KIB, SECTOR_SIZE_4_KIB,
+    WINDOW_POST_CHALLENGE_COUNT, WINDOW_POST_SECTOR_COUNT, WINNING_POST_CHALLENGE_COUNT,
+    WINNING_POST_SECTOR_COUNT,
 };
 use fr32::bytes_into_fr;
 use log::{info, trace};
 use memmap2::MmapOptions;
-use merkletree::store::StoreConfig;
+use merkletree::{
+    merkle::{get_merkle_tree_leafs, get_merkle_tree_len},
+    store::StoreConfig,
+};
 use rand::{random, Rng, SeedableRng};
 use rand_xorshift::XorShiftRng;
 use sha2::{Digest, Sha256};
 use storage_proofs_core::{
     api_version::{ApiFeature, ApiVersion},
     cache_key::CacheKey,
+    compound_proof::CompoundProof,
     is_legacy_porep_id,
     merkle::get_base_tree_count,
     sector::SectorId,
+    settings::{self, SETTINGS},
     util::NODE_SIZE,
 };
-use storage_proofs_update::constants::TreeRHasher;
+use storage_proofs_porep::stacked::{StackedCompound, StackedDrg};
+use storage_proofs_post::fallback::{ReadSource, TreeRLastLayout};
+use storage_proofs_update::{
+    constants::TreeRHasher, EmptySectorUpdateCompound, PublicParams as UpdatePublicParams,
+};
 use tempfile::{tempdir, NamedTempFile, TempDir};
+use typenum::Unsigned;
 
 use filecoin_proofs::constants::{
-    FIP92_MAX_NI_POREP_AGGREGATION_PROOFS, FIP92_MIN_NI_POREP_AGGREGATION_PROOFS,
-    MAX_LEGACY_REGISTERED_SEAL_PROOF_ID,
+    DefaultPieceHasher, FIP92_MAX_NI_POREP_AGGREGATION_PROOFS,
+    FIP92_MIN_NI_POREP_AGGREGATION_PROOFS, MAX_LEGACY_REGISTERED_SEAL_PROOF_ID,
 };
 
 #[cfg(feature = "big-tests")]
@@ -64,6 +91,11 @@ use filecoin_proofs::{
     SECTOR_SIZE_8_MIB,
 };
 
+#[cfg(all(feature = "split-prover", unix))]
+use filecoin_proofs::split_prover::{UpdateSectorPaths, VanillaProofClient, VanillaProofServer};
+#[cfg(all(feature = "split-prover", unix))]
+use std::{net::TcpListener, os::unix::net::UnixListener, thread};
+
 #[cfg(feature = "persist-regression-proofs")]
 mod regression;
 #[cfg(feature = "persist-regression-proofs")]
@@ -1328,6 +1360,1588 @@ fn run_resumable_seal<Tree: 'static + MerkleTreeTrait>(
     }
 }
 
+#[test]
+fn test_seal_pre_commit_with_context() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let expected = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    let mut staged_sector_file = NamedTempFile::new()?;
+    piece_file.rewind()?;
+    add_piece(
+        &mut piece_file,
+        &mut staged_sector_file,
+        config.unpadded_bytes_amount(),
+        &[],
+    )?;
+
+    // Cancel after the first layer.
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let token = CancellationToken::new();
+    let ctx = {
+        let token = token.clone();
+        SealContext::new().with_progress(move |progress| {
+            if let Progress::LayerGenerated { layer: 1, .. } = progress {
+                token.cancel();
+            }
+        })
+    }
+    .with_cancellation(token);
+    let err = seal_pre_commit_phase1_with_context::<_, _, _, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        staged_sector_file.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        &piece_infos,
+        &ctx,
+    )
+    .expect_err("phase1 wasn't cancelled");
+    assert!(matches!(err, filecoin_proofs::Error::Cancelled(_)));
+    let layer_path =
+        |layer| StoreConfig::data_path(cache_dir.path(), &CacheKey::label_layer(layer));
+    assert!(layer_path(1).exists());
+    assert!(!layer_path(2).exists());
+
+    // Running it again continues where it stopped.
+    let run_phase1 = |ctx: &SealContext| {
+        seal_pre_commit_phase1_with_context::<_, _, _, SectorShape2KiB>(
+            &config,
+            cache_dir.path(),
+            staged_sector_file.path(),
+            sealed_sector_file.path(),
+            prover_id,
+            sector_id,
+            ticket,
+            &piece_infos,
+            ctx,
+        )
+    };
+    let phase1_output = run_phase1(&SealContext::default())?;
+    assert!(layer_path(2).exists());
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let err = seal_pre_commit_phase2_with_context(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        &SealContext::new().with_cancellation(token),
+    )
+    .expect_err("phase2 wasn't cancelled");
+    assert!(matches!(err, filecoin_proofs::Error::Cancelled(_)));
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let ctx = {
+        let reports = reports.clone();
+        SealContext::new()
+            .with_progress(move |progress| reports.lock().expect("poisoned").push(progress))
+    };
+    let phase1_output = run_phase1(&ctx)?;
+    let pre_commit_output = seal_pre_commit_phase2_with_context(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        &ctx,
+    )?;
+    assert_eq!(pre_commit_output.comm_d, expected.comm_d);
+    assert_eq!(pre_commit_output.comm_r, expected.comm_r);
+
+    assert_eq!(
+        *reports.lock().expect("poisoned"),
+        vec![
+            Progress::TreeBuilt {
+                tree: TreeKind::TreeD
+            },
+            Progress::LayerGenerated {
+                layer: 1,
+                layers: 2
+            },
+            Progress::LayerGenerated {
+                layer: 2,
+                layers: 2
+            },
+            Progress::TreeBuilt {
+                tree: TreeKind::TreeC
+            },
+            Progress::TreeBuilt {
+                tree: TreeKind::TreeRLast
+            },
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_piece_inclusion_proofs_from_cache() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+
+    let piece_sizes = [
+        UnpaddedBytesAmount(127 * 4),
+        UnpaddedBytesAmount(127 * 4),
+        UnpaddedBytesAmount(127 * 8),
+    ];
+    let mut staged_sector_file = NamedTempFile::new()?;
+    let mut piece_infos = Vec::with_capacity(piece_sizes.len());
+    for (i, piece_size) in piece_sizes.iter().enumerate() {
+        let mut piece_bytes = vec![0u8; u64::from(*piece_size) as usize];
+        rng.fill(&mut piece_bytes[..]);
+        let (piece_info, _) = add_piece(
+            &piece_bytes[..],
+            &mut staged_sector_file,
+            *piece_size,
+            &piece_sizes[..i],
+        )?;
+        piece_infos.push(piece_info);
+    }
+
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let phase1_output = seal_pre_commit_phase1::<_, _, _, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        staged_sector_file.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        &piece_infos,
+    )?;
+
+    let proofs =
+        generate_piece_inclusion_proofs(sector_size.into(), cache_dir.path(), &piece_infos)?;
+    let unsealed_proofs = generate_piece_inclusion_proofs_from_unsealed(
+        sector_size.into(),
+        File::open(staged_sector_file.path())?,
+        &piece_infos,
+    )?;
+    assert_eq!(proofs, unsealed_proofs);
+
+    let offsets = get_piece_offsets(sector_size.into(), &piece_infos)?;
+    for ((piece_info, offset), proof) in piece_infos.iter().zip(offsets).zip(&proofs) {
+        assert!(verify_piece_inclusion_proof(
+            sector_size.into(),
+            &phase1_output.comm_d,
+            &piece_info.commitment,
+            piece_info.size,
+            offset,
+            proof,
+        )?);
+    }
+
+    // The pieces have to be in the order they were added.
+    piece_infos.swap(1, 2);
+    assert!(
+        generate_piece_inclusion_proofs(sector_size.into(), cache_dir.path(), &piece_infos)
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_diagnose_sector_cache() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    let diagnose = || {
+        diagnose_sector_cache::<_, _, SectorShape2KiB>(
+            &config,
+            cache_dir.path(),
+            sealed_sector_file.path(),
+        )
+    };
+    let all_operations = vec![
+        SectorOperation::PreCommitPhase2,
+        SectorOperation::CommitPhase1,
+        SectorOperation::PoSt,
+        SectorOperation::EncodeInto,
+    ];
+
+    let report = diagnose()?;
+    assert!(report.is_healthy(), "{:?}", report);
+    assert!(!report.has_synthetic_proofs);
+    assert_eq!(report.possible_operations, all_operations);
+
+    // Swapping the roots in `p_aux` makes both of them mismatch.
+    let p_aux_path = cache_dir.path().join(CacheKey::PAux.to_string());
+    let p_aux = std::fs::read(&p_aux_path)?;
+    let swapped = [&p_aux[32..], &p_aux[..32]].concat();
+    std::fs::write(&p_aux_path, swapped)?;
+    let report = diagnose()?;
+    assert_eq!(
+        report
+            .root_mismatches
+            .iter()
+            .map(|mismatch| mismatch.tree)
+            .collect::<Vec<_>>(),
+        vec![TreeKind::TreeC, TreeKind::TreeRLast]
+    );
+    assert_eq!(
+        report.possible_operations,
+        vec![
+            SectorOperation::PreCommitPhase2,
+            SectorOperation::EncodeInto
+        ]
+    );
+    std::fs::write(&p_aux_path, p_aux)?;
+
+    let extra_file = cache_dir.path().join("unrelated");
+    File::create(&extra_file)?;
+    let report = diagnose()?;
+    assert_eq!(report.extra_files, vec![extra_file]);
+    assert_eq!(report.possible_operations, all_operations);
+
+    // After cleaning up, the sector can only be used for PoSt and as sector key.
+    clear_cache::<SectorShape2KiB>(cache_dir.path())?;
+    let report = diagnose()?;
+    assert_eq!(report.missing_files.len(), 2 + 1 + 1);
+    assert!(report.wrong_sizes.is_empty());
+    assert!(report.root_mismatches.is_empty());
+    assert_eq!(
+        report.possible_operations,
+        vec![SectorOperation::PoSt, SectorOperation::EncodeInto]
+    );
+
+    let tree_r_last_path =
+        StoreConfig::data_path(cache_dir.path(), &CacheKey::CommRLastTree.to_string());
+    let tree_r_last_len = metadata(&tree_r_last_path)?.len();
+    OpenOptions::new()
+        .write(true)
+        .open(&tree_r_last_path)?
+        .set_len(tree_r_last_len - 32)?;
+    let report = diagnose()?;
+    assert_eq!(report.wrong_sizes.len(), 1);
+    assert_eq!(report.wrong_sizes[0].path, tree_r_last_path);
+    assert_eq!(report.wrong_sizes[0].expected, tree_r_last_len);
+    assert_eq!(
+        report.possible_operations,
+        vec![SectorOperation::EncodeInto]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_estimate_seal_resources() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let porep_id =
+        to_porep_id_verified(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1, ApiVersion::V1_2_0);
+    let config = PoRepConfig::new_groth16_with_features(
+        sector_size,
+        porep_id,
+        ApiVersion::V1_2_0,
+        vec![ApiFeature::SyntheticPoRep],
+    )?;
+
+    let estimates = estimate_seal_resources::<SectorShape2KiB>(&config)?;
+    assert_eq!(
+        estimates
+            .iter()
+            .map(|estimate| estimate.phase)
+            .collect::<Vec<_>>(),
+        vec![
+            ProofPhase::PreCommitPhase1,
+            ProofPhase::PreCommitPhase2,
+            ProofPhase::GenerateSynthProofs,
+            ProofPhase::CommitPhase1,
+            ProofPhase::CommitPhase2,
+        ]
+    );
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+
+    // Compares the estimated sizes with the files that were written.
+    let check_files = |estimate: &PhaseResources| -> Result<()> {
+        for file in &estimate.files {
+            let path = match file.location {
+                FileLocation::CacheDir => cache_dir.path().join(&file.name),
+                FileLocation::Replica => sealed_sector_file.path().to_path_buf(),
+                FileLocation::ParentCacheDir => continue,
+            };
+            assert_eq!(
+                metadata(&path)?.len(),
+                file.bytes,
+                "size of {:?} in {:?}",
+                path,
+                estimate.phase
+            );
+        }
+        Ok(())
+    };
+
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    check_files(&estimates[0])?;
+    assert_eq!(estimates[0].files.len(), 2 + 1 + 1 + 1);
+    assert!(estimates[0].memory >= 2 * sector_size);
+
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    check_files(&estimates[1])?;
+
+    generate_synth_proofs::<_, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        pre_commit_output.clone(),
+        &piece_infos,
+    )?;
+    check_files(&estimates[2])?;
+
+    // The vanilla proofs of C1 are within a factor of two of the estimate.
+    let seed = rng.gen();
+    let commit_phase1_output = seal_commit_phase1::<_, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        pre_commit_output,
+        &piece_infos,
+    )?;
+    let vanilla_proofs_bytes = serialize(&commit_phase1_output.vanilla_proofs)?.len() as u64;
+    let vanilla_proofs_memory = estimates[3].memory;
+    assert!(
+        vanilla_proofs_memory <= vanilla_proofs_bytes
+            && 2 * vanilla_proofs_memory >= vanilla_proofs_bytes,
+        "{} bytes of vanilla proofs estimated, C1 output has {} bytes",
+        vanilla_proofs_memory,
+        vanilla_proofs_bytes
+    );
+
+    // C2 adds the circuits of all partitions, each keeps at least the evaluations of the A, B and
+    // C polynomials.
+    let public_params = public_params::<SectorShape2KiB>(&config)?;
+    let mut cs = BenchCS::<Fr>::new();
+    <StackedCompound<SectorShape2KiB, DefaultPieceHasher> as CompoundProof<
+        StackedDrg<'_, SectorShape2KiB, DefaultPieceHasher>,
+        _,
+    >>::blank_circuit(&public_params)
+    .synthesize(&mut cs)?;
+    let evaluations_bytes = (usize::from(config.partitions) * 3 * cs.num_constraints() * 32) as u64;
+    let circuits_memory = estimates[4].memory - vanilla_proofs_memory;
+    assert!(
+        circuits_memory >= evaluations_bytes && circuits_memory <= 3 * evaluations_bytes,
+        "{} bytes of circuits estimated for {} constraints",
+        circuits_memory,
+        cs.num_constraints()
+    );
+
+    // Everything that is scratch space is removed by cleaning up, the rest is kept.
+    clear_cache::<SectorShape2KiB>(cache_dir.path())?;
+    clear_synthetic_proofs::<SectorShape2KiB>(cache_dir.path())?;
+    for estimate in &estimates {
+        for file in &estimate.files {
+            if file.location == FileLocation::CacheDir {
+                assert_eq!(
+                    cache_dir.path().join(&file.name).exists(),
+                    file.lifetime == FileLifetime::Final,
+                    "{:?}",
+                    file.name
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_estimate_update_and_post_resources_2kib() -> Result<()> {
+    estimate_update_and_post_resources::<SectorShape2KiB>(SECTOR_SIZE_2_KIB)
+}
+
+#[test]
+fn test_estimate_update_and_post_resources_32kib_top_8_8_2() -> Result<()> {
+    // Tree-r-last is split into one file per base tree.
+    assert!(get_base_tree_count::<SectorShape32KiB>() > 1);
+    estimate_update_and_post_resources::<SectorShape32KiB>(SECTOR_SIZE_32_KIB)
+}
+
+fn estimate_update_and_post_resources<Tree: 'static + MerkleTreeTrait<Hasher = TreeRHasher>>(
+    sector_size: u64,
+) -> Result<()> {
+    fil_logger::maybe_init();
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let api_version = ApiVersion::V1_1_0;
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, api_version);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<Tree>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    clear_cache::<Tree>(cache_dir.path())?;
+
+    // The memory of the PoSt is based on the segments of the replica that are read for each
+    // challenge, their width follows from the rows that are cached in the tree-r-last files.
+    let tree_count = get_base_tree_count::<Tree>();
+    let base_tree_leafs = sector_size as usize / NODE_SIZE / tree_count;
+    let arity = Tree::Arity::to_usize();
+    let mut tree_r_last_files = Vec::new();
+    for entry in read_dir(cache_dir.path())? {
+        let entry = entry?;
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .contains(&CacheKey::CommRLastTree.to_string())
+        {
+            tree_r_last_files.push(entry.metadata()?.len());
+        }
+    }
+    assert_eq!(tree_r_last_files.len(), tree_count);
+    let cached_leafs = get_merkle_tree_leafs(tree_r_last_files[0] as usize / NODE_SIZE, arity)?;
+    let segment_width = base_tree_leafs / cached_leafs;
+    let segment_bytes =
+        ((segment_width + get_merkle_tree_len(segment_width, arity)?) * NODE_SIZE) as u64;
+
+    let post_config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: 1,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let estimate = estimate_post_resources::<Tree>(&post_config, 1)?;
+    assert_eq!(estimate.phase, ProofPhase::WindowPost);
+    assert!(estimate.files.is_empty());
+    let concurrent_reads =
+        (WINDOW_POST_CHALLENGE_COUNT as u64).min(rayon::current_num_threads() as u64);
+    let proofs_memory = estimate
+        .memory
+        .checked_sub(concurrent_reads * segment_bytes)
+        .expect("the reads of the challenges are missing");
+
+    // The inclusion proofs are within a factor of two of the actual vanilla proof.
+    let challenges = generate_fallback_sector_challenges::<Tree>(
+        &post_config,
+        &[1; 32],
+        &[sector_id],
+        prover_id,
+    )?;
+    let replica = PrivateReplicaInfo::<Tree>::new(
+        sealed_sector_file.path().into(),
+        pre_commit_output.comm_r,
+        cache_dir.path().into(),
+    )?;
+    let vanilla_proof = generate_single_vanilla_proof::<Tree>(
+        &post_config,
+        sector_id,
+        &replica,
+        &challenges[&sector_id],
+    )?;
+    let vanilla_proof_bytes = vanilla_proof.to_bytes()?.len() as u64;
+    assert!(
+        proofs_memory <= vanilla_proof_bytes && 2 * proofs_memory >= vanilla_proof_bytes,
+        "{} bytes of inclusion proofs estimated, the vanilla proof has {} bytes",
+        proofs_memory,
+        vanilla_proof_bytes
+    );
+
+    // Compares the estimated sizes of the update with the files that were written.
+    let update_config = SectorUpdateConfig::from_porep_config(&config);
+    let estimates = estimate_update_resources::<Tree>(&update_config)?;
+    assert_eq!(
+        estimates
+            .iter()
+            .map(|estimate| estimate.phase)
+            .collect::<Vec<_>>(),
+        vec![ProofPhase::EncodeInto, ProofPhase::GenerateUpdateProof]
+    );
+
+    let new_sealed_sector_file = NamedTempFile::new()?;
+    new_sealed_sector_file
+        .as_file()
+        .set_len(metadata(sealed_sector_file.path())?.len())?;
+    let new_cache_dir = tempdir()?;
+    let (mut new_piece_file, _new_piece_bytes) = generate_piece_file(sector_size)?;
+    let new_piece_info =
+        generate_piece_commitment(new_piece_file.as_file_mut(), config.unpadded_bytes_amount())?;
+    new_piece_file.as_file_mut().rewind()?;
+    let mut new_staged_sector_file = NamedTempFile::new()?;
+    add_piece(
+        &mut new_piece_file,
+        &mut new_staged_sector_file,
+        config.unpadded_bytes_amount(),
+        &[],
+    )?;
+    encode_into::<Tree>(
+        &update_config,
+        new_sealed_sector_file.path(),
+        new_cache_dir.path(),
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        new_staged_sector_file.path(),
+        &[new_piece_info],
+    )?;
+
+    for file in &estimates[0].files {
+        let path = match file.location {
+            FileLocation::CacheDir => new_cache_dir.path().join(&file.name),
+            FileLocation::Replica => new_sealed_sector_file.path().to_path_buf(),
+            FileLocation::ParentCacheDir => continue,
+        };
+        assert_eq!(metadata(&path)?.len(), file.bytes, "size of {:?}", path);
+    }
+    assert_eq!(
+        estimates[0]
+            .files
+            .iter()
+            .filter(|file| file.name.contains(&CacheKey::CommRLastTree.to_string()))
+            .count(),
+        tree_count
+    );
+    // One base tree is built in memory.
+    assert_eq!(
+        estimates[0].memory,
+        (get_merkle_tree_len(base_tree_leafs, arity)? * NODE_SIZE) as u64
+    );
+
+    // The update proof keeps at least the evaluations of the A, B and C polynomials of each
+    // partition.
+    let mut cs = BenchCS::<Fr>::new();
+    EmptySectorUpdateCompound::<Tree>::blank_circuit(&UpdatePublicParams::from_sector_size(
+        sector_size,
+    ))
+    .synthesize(&mut cs)?;
+    let evaluations_bytes =
+        (usize::from(update_config.update_partitions) * 3 * cs.num_constraints() * 32) as u64;
+    assert!(
+        estimates[1].memory >= evaluations_bytes && estimates[1].memory <= 3 * evaluations_bytes,
+        "{} bytes of circuits estimated for {} constraints",
+        estimates[1].memory,
+        cs.num_constraints()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_preflight_seal() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+
+    // The parent cache was generated by PC1.
+    for phase in [ProofPhase::PreCommitPhase1, ProofPhase::PreCommitPhase2] {
+        preflight_seal::<SectorShape2KiB>(
+            &config,
+            phase,
+            cache_dir.path(),
+            sealed_sector_file.path(),
+            true,
+        )?;
+    }
+
+    let err = preflight_seal::<SectorShape2KiB>(
+        &config,
+        ProofPhase::EncodeInto,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        false,
+    )
+    .expect_err("not a sealing phase");
+    assert!(matches!(err, ProofsError::InvalidInput(_)));
+
+    seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    // The parameters are only there if they were generated by another test.
+    match preflight_seal::<SectorShape2KiB>(
+        &config,
+        ProofPhase::CommitPhase2,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        false,
+    ) {
+        Ok(()) => {}
+        Err(ProofsError::Preflight(failures)) => assert!(
+            failures
+                .iter()
+                .all(|failure| matches!(failure, PreflightFailure::MissingParameters { .. })),
+            "{:?}",
+            failures
+        ),
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sector_storage() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let api_version = ApiVersion::V1_2_0;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [9u8; 32];
+    let ticket = rng.gen();
+    let seed = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let porep_id = to_porep_id_verified(MAX_LEGACY_REGISTERED_SEAL_PROOF_ID + 1, api_version);
+    let config = PoRepConfig::new_groth16_with_features(
+        sector_size,
+        porep_id,
+        api_version,
+        vec![ApiFeature::SyntheticPoRep],
+    )?;
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    generate_synth_proofs::<_, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        pre_commit_output.clone(),
+        &piece_infos,
+    )?;
+    clear_cache::<SectorShape2KiB>(cache_dir.path())?;
+
+    let storage = Arc::new(ReadRecordingStorage::default());
+    let storage_cache = Path::new("/sectors/cache");
+    let storage_replica = Path::new("/sectors/sealed");
+    store_sector_files(
+        &*storage,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        storage_cache,
+        storage_replica,
+    )?;
+
+    let local_output = seal_commit_phase1::<_, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        pre_commit_output.clone(),
+        &piece_infos,
+    )?;
+    let staging_dir = tempdir()?;
+    let storage_output = seal_commit_phase1_from_storage::<SectorShape2KiB>(
+        &config,
+        &*storage,
+        storage_cache,
+        storage_replica,
+        staging_dir.path(),
+        prover_id,
+        sector_id,
+        ticket,
+        seed,
+        pre_commit_output.clone(),
+        &piece_infos,
+    )?;
+    assert_eq!(serialize(&local_output)?, serialize(&storage_output)?);
+
+    let post_config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: 1,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let randomness = rng.gen();
+    let challenges = generate_fallback_sector_challenges::<SectorShape2KiB>(
+        &post_config,
+        &randomness,
+        &[sector_id],
+        prover_id,
+    )?;
+
+    let local_replica = PrivateReplicaInfo::<SectorShape2KiB>::new(
+        sealed_sector_file.path().to_path_buf(),
+        pre_commit_output.comm_r,
+        cache_dir.path().to_path_buf(),
+    )?;
+    let storage_replica = PrivateReplicaInfo::<SectorShape2KiB>::new_in_storage(
+        storage.clone(),
+        storage_replica.to_path_buf(),
+        pre_commit_output.comm_r,
+        storage_cache.to_path_buf(),
+    )?;
+    let local_proof = generate_single_vanilla_proof(
+        &post_config,
+        sector_id,
+        &local_replica,
+        &challenges[&sector_id],
+    )?;
+    storage.reads.lock().expect("reads poisoned").clear();
+    let storage_proof = generate_single_vanilla_proof(
+        &post_config,
+        sector_id,
+        &storage_replica,
+        &challenges[&sector_id],
+    )?;
+    assert_eq!(serialize(&local_proof)?, serialize(&storage_proof)?);
+
+    // Only the nodes of the challenged paths are read from the storage.
+    let tree_r_last = StoreConfig::data_path(storage_cache, &CacheKey::CommRLastTree.to_string());
+    let mut planned: Vec<_> = TreeRLastLayout::new::<SectorShape2KiB>(sector_size)?
+        .plan_reads(&challenges[&sector_id])?
+        .into_iter()
+        .map(|read| {
+            let path = match read.source {
+                ReadSource::Replica => storage_replica.replica_path().to_path_buf(),
+                ReadSource::TreeRLast(_) => tree_r_last.clone(),
+            };
+            (path, read.offset, read.len)
+        })
+        .collect();
+    planned.sort_unstable();
+    let mut reads = storage.reads.lock().expect("reads poisoned").clone();
+    reads.sort_unstable();
+    assert_eq!(reads, planned);
+
+    Ok(())
+}
+
+#[test]
+fn test_check_sectors_provable() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [11u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    clear_cache::<SectorShape2KiB>(cache_dir.path())?;
+    let comm_r = pre_commit_output.comm_r;
+
+    let copy_sector = || -> Result<(TempDir, PathBuf)> {
+        let dir = tempdir()?;
+        for entry in read_dir(cache_dir.path())? {
+            let entry = entry?;
+            std::fs::copy(entry.path(), dir.path().join(entry.file_name()))?;
+        }
+        let replica = dir.path().join("sealed");
+        std::fs::copy(sealed_sector_file.path(), &replica)?;
+        Ok((dir, replica))
+    };
+
+    let (missing_dir, missing_replica) = copy_sector()?;
+    let tree_r_last_path =
+        StoreConfig::data_path(missing_dir.path(), &CacheKey::CommRLastTree.to_string());
+    let (corrupt_dir, corrupt_replica) = copy_sector()?;
+    std::fs::write(&corrupt_replica, vec![0u8; sector_size as usize])?;
+    // The trees are intact, but p_aux doesn't belong to comm_r.
+    let (foreign_dir, foreign_replica) = copy_sector()?;
+    let p_aux_path = foreign_dir.path().join(CacheKey::PAux.to_string());
+    let mut p_aux: PersistentAux<DefaultTreeDomain> = deserialize(&std::fs::read(&p_aux_path)?)?;
+    p_aux.comm_c = DefaultTreeDomain::default();
+    std::fs::write(&p_aux_path, serialize(&p_aux)?)?;
+
+    let storage = Arc::new(MemoryStorage::new());
+    store_sector_files(
+        &*storage,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        Path::new("/sectors/cache"),
+        Path::new("/sectors/sealed"),
+    )?;
+
+    let mut replicas = BTreeMap::new();
+    replicas.insert(
+        SectorId::from(1),
+        PrivateReplicaInfo::<SectorShape2KiB>::new(
+            sealed_sector_file.path().to_path_buf(),
+            comm_r,
+            cache_dir.path().to_path_buf(),
+        )?,
+    );
+    replicas.insert(
+        SectorId::from(2),
+        PrivateReplicaInfo::new(missing_replica, comm_r, missing_dir.path().to_path_buf())?,
+    );
+    remove_file(&tree_r_last_path)?;
+    replicas.insert(
+        SectorId::from(3),
+        PrivateReplicaInfo::new(corrupt_replica, comm_r, corrupt_dir.path().to_path_buf())?,
+    );
+    replicas.insert(
+        SectorId::from(4),
+        PrivateReplicaInfo::new_in_storage(
+            storage,
+            PathBuf::from("/sectors/sealed"),
+            comm_r,
+            PathBuf::from("/sectors/cache"),
+        )?,
+    );
+    replicas.insert(
+        SectorId::from(5),
+        PrivateReplicaInfo::new(foreign_replica, comm_r, foreign_dir.path().to_path_buf())?,
+    );
+
+    let post_config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: replicas.len(),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_1_0,
+    };
+    let randomness = rng.gen();
+    let checks = check_sectors_provable(&post_config, &randomness, &replicas, prover_id)?;
+
+    let sector_ids: Vec<_> = checks
+        .iter()
+        .map(|check| u64::from(check.sector_id))
+        .collect();
+    assert_eq!(sector_ids, vec![1, 2, 3, 4, 5]);
+    assert_eq!(checks[0].status, SectorStatus::Provable);
+    assert_eq!(
+        checks[1].status,
+        SectorStatus::MissingFile {
+            path: tree_r_last_path
+        }
+    );
+    assert!(
+        matches!(checks[2].status, SectorStatus::RootMismatch { .. }),
+        "{:?}",
+        checks[2].status
+    );
+    assert!(checks[3].is_provable(), "{:?}", checks[3].status);
+    assert_eq!(checks[4].status, SectorStatus::CommRMismatch);
+
+    // Winning PoSt only challenges as many sectors as its config has.
+    let winning_config = PoStConfig {
+        sector_count: WINNING_POST_SECTOR_COUNT,
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        typ: PoStType::Winning,
+        ..post_config
+    };
+    assert!(matches!(
+        check_sectors_provable(&winning_config, &randomness, &replicas, prover_id),
+        Err(ProofsError::InvalidInput(_))
+    ));
+
+    Ok(())
+}
+
+/// Records the path, offset and length of all reads of files.
+#[derive(Debug, Default)]
+struct ReadRecordingStorage {
+    inner: MemoryStorage,
+    reads: Arc<Mutex<Vec<(PathBuf, u64, usize)>>>,
+}
+
+struct RecordingReader {
+    inner: Box<dyn ReadAt>,
+    path: PathBuf,
+    reads: Arc<Mutex<Vec<(PathBuf, u64, usize)>>>,
+}
+
+impl ReadAt for RecordingReader {
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.reads
+            .lock()
+            .expect("reads poisoned")
+            .push((self.path.clone(), offset, buf.len()));
+        self.inner.read_exact_at(offset, buf)
+    }
+}
+
+impl SectorStorage for ReadRecordingStorage {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadAt>> {
+        Ok(Box::new(RecordingReader {
+            inner: self.inner.open_read(path)?,
+            path: path.to_path_buf(),
+            reads: self.reads.clone(),
+        }))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+        self.inner.create(path)
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.list(dir)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.inner.size(path)
+    }
+}
+
+/// Records the `rows_to_discard` setting and whether it was read on a rayon worker, whenever the
+/// size of a file is queried.
+#[derive(Debug, Default)]
+struct SettingsRecordingStorage {
+    inner: MemoryStorage,
+    reads: Mutex<Vec<(bool, u32)>>,
+}
+
+impl SectorStorage for SettingsRecordingStorage {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadAt>> {
+        self.inner.open_read(path)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+        self.inner.create(path)
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.list(dir)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        self.reads.lock().expect("reads poisoned").push((
+            rayon::current_thread_index().is_some(),
+            settings::current().rows_to_discard,
+        ));
+        self.inner.size(path)
+    }
+}
+
+#[test]
+fn test_check_sectors_provable_in_context() -> Result<()> {
+    let storage = Arc::new(SettingsRecordingStorage::default());
+    let aux = PersistentAux {
+        comm_c: DefaultTreeDomain::default(),
+        comm_r_last: DefaultTreeDomain::default(),
+    };
+    storage
+        .create(&Path::new("/sectors/cache").join(CacheKey::PAux.to_string()))?
+        .write_all(&serialize(&aux)?)?;
+    // Only the replica exists, the sectors are checked up to the missing trees.
+    storage
+        .create(Path::new("/sectors/sealed"))?
+        .write_all(&[0; 2048])?;
+
+    let mut replicas = BTreeMap::new();
+    for sector_id in 0..8 {
+        replicas.insert(
+            SectorId::from(sector_id),
+            PrivateReplicaInfo::<SectorShape2KiB>::new_in_storage(
+                storage.clone(),
+                PathBuf::from("/sectors/sealed"),
+                [1; 32],
+                PathBuf::from("/sectors/cache"),
+            )?,
+        );
+    }
+    let post_config = PoStConfig {
+        sector_size: SECTOR_SIZE_2_KIB.into(),
+        sector_count: replicas.len(),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version: ApiVersion::V1_2_0,
+    };
+
+    let rows_to_discard = SETTINGS.rows_to_discard + 1;
+    let context = ProofsContext::new(Settings {
+        rows_to_discard,
+        ..(**SETTINGS).clone()
+    });
+    storage.reads.lock().expect("reads poisoned").clear();
+    let checks =
+        context.scope(|| check_sectors_provable(&post_config, &[2; 32], &replicas, [3; 32]))?;
+    assert!(checks
+        .iter()
+        .all(|check| matches!(check.status, SectorStatus::MissingFile { .. })));
+
+    let reads = storage.reads.lock().expect("reads poisoned");
+    assert!(reads.iter().any(|(in_worker, _)| *in_worker));
+    assert!(reads.iter().all(|(_, rows)| *rows == rows_to_discard));
+
+    Ok(())
+}
+
+#[cfg(all(feature = "split-prover", unix))]
+#[test]
+#[ignore]
+fn test_split_prover() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let api_version = ApiVersion::V1_1_0;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+    let ticket = rng.gen();
+    let sector_id = SectorId::from(1);
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, api_version);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    clear_cache::<SectorShape2KiB>(cache_dir.path())?;
+    let comm_r = pre_commit_output.comm_r;
+
+    // Update the sector into a new replica.
+    let new_sealed_sector_file = NamedTempFile::new()?;
+    new_sealed_sector_file
+        .as_file()
+        .set_len(metadata(sealed_sector_file.path())?.len())?;
+    let new_cache_dir = tempdir()?;
+    let (mut new_piece_file, _new_piece_bytes) = generate_piece_file(sector_size)?;
+    let new_piece_info =
+        generate_piece_commitment(new_piece_file.as_file_mut(), config.unpadded_bytes_amount())?;
+    new_piece_file.as_file_mut().rewind()?;
+    let mut new_staged_sector_file = NamedTempFile::new()?;
+    add_piece(
+        &mut new_piece_file,
+        &mut new_staged_sector_file,
+        config.unpadded_bytes_amount(),
+        &[],
+    )?;
+    let encoded = encode_into::<SectorShape2KiB>(
+        &SectorUpdateConfig::from_porep_config(&config),
+        new_sealed_sector_file.path(),
+        new_cache_dir.path(),
+        sealed_sector_file.path(),
+        cache_dir.path(),
+        new_staged_sector_file.path(),
+        &[new_piece_info],
+    )?;
+
+    let server = Arc::new(VanillaProofServer::<SectorShape2KiB>::new());
+    server.insert_replica(
+        sector_id,
+        PrivateReplicaInfo::new(
+            sealed_sector_file.path().to_path_buf(),
+            comm_r,
+            cache_dir.path().to_path_buf(),
+        )?,
+    );
+    server.insert_update(
+        sector_id,
+        UpdateSectorPaths {
+            sector_key_path: sealed_sector_file.path().to_path_buf(),
+            sector_key_cache_path: cache_dir.path().to_path_buf(),
+            replica_path: new_sealed_sector_file.path().to_path_buf(),
+            replica_cache_path: new_cache_dir.path().to_path_buf(),
+        },
+    );
+
+    let tcp_listener = TcpListener::bind("127.0.0.1:0")?;
+    let tcp_addr = tcp_listener.local_addr()?;
+    let tcp_server = Arc::clone(&server);
+    thread::spawn(move || tcp_server.serve_tcp(tcp_listener));
+
+    let socket_dir = tempdir()?;
+    let socket_path = socket_dir.path().join("vanilla.sock");
+    let unix_listener = UnixListener::bind(&socket_path)?;
+    let unix_server = Arc::clone(&server);
+    thread::spawn(move || unix_server.serve_unix(unix_listener));
+
+    let random_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+    let window_config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: *WINDOW_POST_SECTOR_COUNT
+            .read()
+            .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+            .get(&sector_size)
+            .expect("unknown sector size"),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let winning_config = PoStConfig {
+        sector_count: WINNING_POST_SECTOR_COUNT,
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        typ: PoStType::Winning,
+        ..window_config
+    };
+    let mut pub_replicas = BTreeMap::new();
+    pub_replicas.insert(sector_id, PublicReplicaInfo::new(comm_r)?);
+    let winning_pub_replicas = vec![(sector_id, PublicReplicaInfo::new(comm_r)?)];
+
+    let mut tcp_client = VanillaProofClient::connect_tcp(tcp_addr)?;
+    let proof = tcp_client.generate_window_post::<SectorShape2KiB>(
+        &window_config,
+        &randomness,
+        &pub_replicas,
+        prover_id,
+    )?;
+    let valid = verify_window_post::<SectorShape2KiB>(
+        &window_config,
+        &randomness,
+        &pub_replicas,
+        prover_id,
+        &proof,
+    )?;
+    assert!(valid, "window post did not verify");
+
+    // Sector 2 is unknown to the server.
+    let mut unknown_pub_replicas = pub_replicas.clone();
+    unknown_pub_replicas.insert(SectorId::from(2), PublicReplicaInfo::new(comm_r)?);
+    match tcp_client.generate_window_post::<SectorShape2KiB>(
+        &window_config,
+        &randomness,
+        &unknown_pub_replicas,
+        prover_id,
+    ) {
+        Err(ProofsError::FaultySectors { sectors, .. }) => {
+            assert_eq!(sectors, vec![SectorId::from(2)])
+        }
+        other => panic!("expected faulty sectors, got {:?}", other),
+    }
+
+    // The vanilla proof doesn't match the comm_r the client expects.
+    let mut wrong_pub_replicas = BTreeMap::new();
+    wrong_pub_replicas.insert(sector_id, PublicReplicaInfo::new([1; 32])?);
+    match tcp_client.generate_window_post::<SectorShape2KiB>(
+        &window_config,
+        &randomness,
+        &wrong_pub_replicas,
+        prover_id,
+    ) {
+        Err(ProofsError::FaultySectors { sectors, .. }) => assert_eq!(sectors, vec![sector_id]),
+        other => panic!("expected faulty sectors, got {:?}", other),
+    }
+
+    let mut unix_client = VanillaProofClient::connect_unix(&socket_path)?;
+    let proof = unix_client.generate_winning_post::<SectorShape2KiB>(
+        &winning_config,
+        &randomness,
+        &winning_pub_replicas,
+        prover_id,
+    )?;
+    let valid = verify_winning_post::<SectorShape2KiB>(
+        &winning_config,
+        &randomness,
+        &winning_pub_replicas,
+        prover_id,
+        &proof,
+    )?;
+    assert!(valid, "winning post did not verify");
+
+    let proof = unix_client.generate_empty_sector_update_proof::<SectorShape2KiB>(
+        &config,
+        sector_id,
+        comm_r,
+        encoded.comm_r_new,
+        encoded.comm_d_new,
+    )?;
+    let valid = verify_empty_sector_update_proof::<SectorShape2KiB>(
+        &config,
+        &proof.0,
+        comm_r,
+        encoded.comm_r_new,
+        encoded.comm_d_new,
+    )?;
+    assert!(valid, "empty sector update proof did not verify");
+
+    // The partition proofs don't match the comm_r_new the client expects.
+    match unix_client.generate_empty_sector_update_proof::<SectorShape2KiB>(
+        &config,
+        sector_id,
+        comm_r,
+        comm_r,
+        encoded.comm_d_new,
+    ) {
+        Err(ProofsError::FaultySectors { sectors, .. }) => assert_eq!(sectors, vec![sector_id]),
+        other => panic!("expected faulty sectors, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_rebuild_trees() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    let mut unsealed_file = NamedTempFile::new()?;
+    piece_file.rewind()?;
+    add_piece(
+        &mut piece_file,
+        &mut unsealed_file,
+        config.unpadded_bytes_amount(),
+        &[],
+    )?;
+
+    let tree_path = |key: CacheKey| StoreConfig::data_path(cache_dir.path(), &key.to_string());
+    let tree_c = std::fs::read(tree_path(CacheKey::CommCTree))?;
+    let tree_d = std::fs::read(tree_path(CacheKey::CommDTree))?;
+
+    // Damage all trees.
+    std::fs::write(tree_path(CacheKey::CommCTree), vec![0u8; tree_c.len()])?;
+    remove_file(tree_path(CacheKey::CommRLastTree))?;
+    remove_file(tree_path(CacheKey::CommDTree))?;
+
+    // A wrong CommD is detected and leaves the cache untouched.
+    let mut wrong_comm_d = pre_commit_output.comm_d;
+    wrong_comm_d[0] ^= 1;
+    assert!(rebuild_tree_d(
+        &config,
+        cache_dir.path(),
+        unsealed_file.path(),
+        &wrong_comm_d
+    )
+    .is_err());
+    assert!(!tree_path(CacheKey::CommDTree).exists());
+
+    rebuild_tree_c::<_, SectorShape2KiB>(&config, cache_dir.path())?;
+    rebuild_tree_r_last::<_, _, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    rebuild_tree_d(
+        &config,
+        cache_dir.path(),
+        unsealed_file.path(),
+        &pre_commit_output.comm_d,
+    )?;
+    assert_eq!(std::fs::read(tree_path(CacheKey::CommCTree))?, tree_c);
+    assert_eq!(std::fs::read(tree_path(CacheKey::CommDTree))?, tree_d);
+
+    let report = diagnose_sector_cache::<_, _, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+    assert!(report.is_healthy(), "{:?}", report);
+
+    // Without the labels, tree-c can't be rebuilt.
+    remove_file(StoreConfig::data_path(
+        cache_dir.path(),
+        &CacheKey::label_layer(1),
+    ))?;
+    assert!(rebuild_tree_c::<_, SectorShape2KiB>(&config, cache_dir.path()).is_err());
+    assert_eq!(std::fs::read(tree_path(CacheKey::CommCTree))?, tree_c);
+
+    Ok(())
+}
+
+#[test]
+fn test_sector_bundle() -> Result<()> {
+    fil_logger::maybe_init();
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let prover_id = [7u8; 32];
+    let ticket = rng.gen();
+    let sector_id = rng.gen::<u64>().into();
+    let config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_1_0, ApiVersion::V1_1_0);
+
+    let (mut piece_file, _piece_bytes) = generate_piece_file(sector_size)?;
+    let sealed_sector_file = NamedTempFile::new()?;
+    let cache_dir = tempdir()?;
+    let (_piece_infos, phase1_output) = run_seal_pre_commit_phase1::<SectorShape2KiB>(
+        &config,
+        prover_id,
+        sector_id,
+        ticket,
+        &cache_dir,
+        &mut piece_file,
+        &sealed_sector_file,
+    )?;
+    let pre_commit_output = seal_pre_commit_phase2(
+        &config,
+        phase1_output,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+    )?;
+
+    let mut bundle = Vec::new();
+    let exported = export_sector_bundle::<_, _, _, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        SectorOperation::PoSt,
+        &mut bundle,
+    )?;
+    assert_eq!(exported.comm_r, Some(pre_commit_output.comm_r));
+    assert_eq!(
+        exported
+            .files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>(),
+        vec![
+            "replica",
+            "cache/p_aux",
+            "cache/t_aux",
+            "cache/sc-02-data-tree-r-last.dat"
+        ]
+    );
+
+    let import_cache_dir = tempdir()?;
+    let import_replica_file = NamedTempFile::new()?;
+    let imported = import_sector_bundle::<_, _, _, SectorShape2KiB>(
+        &config,
+        &bundle[..],
+        import_cache_dir.path(),
+        import_replica_file.path(),
+    )?;
+    assert_eq!(imported, exported);
+    let report = diagnose_sector_cache::<_, _, SectorShape2KiB>(
+        &config,
+        import_cache_dir.path(),
+        import_replica_file.path(),
+    )?;
+    assert_eq!(
+        report.possible_operations,
+        vec![SectorOperation::PoSt, SectorOperation::EncodeInto]
+    );
+
+    // A bundle for a different sector configuration is rejected.
+    let other_config = porep_config(sector_size, ARBITRARY_POREP_ID_V1_2_0, ApiVersion::V1_2_0);
+    assert!(import_sector_bundle::<_, _, _, SectorShape2KiB>(
+        &other_config,
+        &bundle[..],
+        tempdir()?.path(),
+        NamedTempFile::new()?.path(),
+    )
+    .is_err());
+
+    // Corrupted data is detected, the replica is the first file in the bundle. The existing files
+    // at the destinations are left as they are.
+    bundle[512 + 100] ^= 1;
+    let corrupted_cache_dir = tempdir()?;
+    let existing_p_aux = corrupted_cache_dir.path().join(CacheKey::PAux.to_string());
+    std::fs::write(&existing_p_aux, b"existing p_aux")?;
+    let mut existing_replica = NamedTempFile::new()?;
+    existing_replica.write_all(b"existing replica")?;
+    assert!(import_sector_bundle::<_, _, _, SectorShape2KiB>(
+        &config,
+        &bundle[..],
+        corrupted_cache_dir.path(),
+        existing_replica.path(),
+    )
+    .is_err());
+    assert_eq!(std::fs::read(&existing_p_aux)?, b"existing p_aux");
+    assert_eq!(std::fs::read(existing_replica.path())?, b"existing replica");
+    assert_eq!(read_dir(corrupted_cache_dir.path())?.count(), 1);
+
+    // The labels are gone after cleaning up, they can't be exported for commit anymore.
+    clear_cache::<SectorShape2KiB>(cache_dir.path())?;
+    assert!(export_sector_bundle::<_, _, _, SectorShape2KiB>(
+        &config,
+        cache_dir.path(),
+        sealed_sector_file.path(),
+        SectorOperation::CommitPhase1,
+        io::sink(),
+    )
+    .is_err());
+
+    Ok(())
+}
+
 #[test]
 #[ignore]
 fn test_winning_post_2kib_base_8() -> Result<()> {
@@ -1407,6 +3021,91 @@ fn test_winning_post_empty_sector_challenge() -> Result<()> {
     Ok(())
 }
 
+#[test]
+#[ignore]
+fn test_winning_post_prefetched_2kib() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let api_version = ApiVersion::V1_1_0;
+    let porep_config =
+        PoRepConfig::new_groth16(sector_size, ARBITRARY_POREP_ID_V1_1_0, api_version);
+    let (sector_id, replica, comm_r, cache_dir) =
+        create_seal::<_, SectorShape2KiB>(&porep_config, &mut rng, prover_id, true)?;
+
+    let random_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: WINNING_POST_SECTOR_COUNT,
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        typ: PoStType::Winning,
+        priority: false,
+        api_version,
+    };
+    let pub_replicas = vec![(sector_id, PublicReplicaInfo::new(comm_r)?)];
+    let priv_replicas = vec![(
+        sector_id,
+        PrivateReplicaInfo::new(replica.path().into(), comm_r, cache_dir.path().into())?,
+    )];
+    let challenges = generate_fallback_sector_challenges::<SectorShape2KiB>(
+        &config,
+        &randomness,
+        &[sector_id],
+        prover_id,
+    )?;
+
+    // Prefetching is opt-in, it must not change the vanilla proofs.
+    let vanilla_proof = generate_single_vanilla_proof::<SectorShape2KiB>(
+        &config,
+        sector_id,
+        &priv_replicas[0].1,
+        &challenges[&sector_id],
+    )?;
+    let prefetching = ProofsContext::new(Settings {
+        winning_post_max_outstanding_reads: 8,
+        ..(**SETTINGS).clone()
+    });
+    let (prefetched_vanilla_proof, proof) = prefetching.scope(|| -> Result<_> {
+        let prefetched_vanilla_proof = generate_single_vanilla_proof::<SectorShape2KiB>(
+            &config,
+            sector_id,
+            &priv_replicas[0].1,
+            &challenges[&sector_id],
+        )?;
+        let proof = generate_winning_post::<SectorShape2KiB>(
+            &config,
+            &randomness,
+            &priv_replicas,
+            prover_id,
+        )?;
+        Ok((prefetched_vanilla_proof, proof))
+    })?;
+    assert_eq!(
+        prefetched_vanilla_proof.to_bytes()?,
+        vanilla_proof.to_bytes()?
+    );
+
+    let valid = verify_winning_post::<SectorShape2KiB>(
+        &config,
+        &randomness,
+        &pub_replicas,
+        prover_id,
+        &proof,
+    )?;
+    assert!(valid, "prefetched proof did not verify");
+
+    replica.close()?;
+
+    Ok(())
+}
+
 fn winning_post<Tree: 'static + MerkleTreeTrait>(
     sector_size: u64,
     fake: bool,
@@ -1843,6 +3542,346 @@ fn partition_window_post<Tree: 'static + MerkleTreeTrait>(
     Ok(())
 }
 
+#[test]
+#[ignore]
+fn test_verify_vanilla_post_sector_proof_2kib() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let api_version = ApiVersion::V1_1_0;
+    let porep_config =
+        PoRepConfig::new_groth16(sector_size, ARBITRARY_POREP_ID_V1_1_0, api_version);
+    let (sector_id, replica, comm_r, cache_dir) =
+        create_seal::<_, SectorShape2KiB>(&porep_config, &mut rng, prover_id, true)?;
+
+    let random_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: 1,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let sectors = [sector_id];
+    let challenges = generate_fallback_sector_challenges::<SectorShape2KiB>(
+        &config,
+        &randomness,
+        &sectors,
+        prover_id,
+    )?;
+    let replica_info =
+        PrivateReplicaInfo::new(replica.path().into(), comm_r, cache_dir.path().into())?;
+    let vanilla_proof = generate_single_vanilla_proof::<SectorShape2KiB>(
+        &config,
+        sector_id,
+        &replica_info,
+        &challenges[&sector_id],
+    )?;
+
+    // Vanilla proofs can be sent to another machine and checked before the SNARK is created.
+    let bytes = vanilla_proof.to_bytes()?;
+    let verify = |comm_r: &Commitment, bytes: &[u8]| {
+        let proof = FallbackPoStSectorProof::<SectorShape2KiB>::from_bytes(bytes)?;
+        verify_vanilla_post_sector_proof::<SectorShape2KiB>(
+            &config,
+            &randomness,
+            &sectors,
+            prover_id,
+            comm_r,
+            &proof,
+        )
+    };
+    assert!(matches!(verify(&comm_r, &bytes), Ok(true)));
+
+    let mut wrong_comm_r = comm_r;
+    wrong_comm_r[0] ^= 1;
+    assert!(matches!(verify(&wrong_comm_r, &bytes), Ok(false)));
+
+    // Corrupt the leaf of the first inclusion proof, the proof is still well encoded.
+    let mut leaf = [0u8; 32];
+    let mut replica_file = File::open(replica.path())?;
+    replica_file.seek(SeekFrom::Start(
+        challenges[&sector_id][0] * NODE_SIZE as u64,
+    ))?;
+    replica_file.read_exact(&mut leaf)?;
+    let leaf_offset = bytes
+        .windows(leaf.len())
+        .position(|window| window == leaf)
+        .expect("leaf not found in the proof");
+    let mut corrupted = bytes;
+    corrupted[leaf_offset] ^= 1;
+    assert!(matches!(verify(&comm_r, &corrupted), Ok(false)));
+
+    replica.close()?;
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_window_post_skipping_faults_2kib_base_8() -> Result<()> {
+    // With API version 1.0.0, skipping a sector changes the challenges of the sectors after it.
+    window_post_skipping_faults::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, ApiVersion::V1_0_0)?;
+    window_post_skipping_faults::<SectorShape2KiB>(SECTOR_SIZE_2_KIB, ApiVersion::V1_1_0)
+}
+
+fn window_post_skipping_faults<Tree: 'static + MerkleTreeTrait>(
+    sector_size: u64,
+    api_version: ApiVersion,
+) -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let prover_fr: <Tree::Hasher as Hasher>::Domain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let porep_id = match api_version {
+        ApiVersion::V1_0_0 => ARBITRARY_POREP_ID_V1_0_0,
+        ApiVersion::V1_1_0 => ARBITRARY_POREP_ID_V1_1_0,
+        ApiVersion::V1_2_0 => ARBITRARY_POREP_ID_V1_2_0,
+    };
+
+    let mut sectors = Vec::new();
+    let mut pub_replicas = BTreeMap::new();
+    let mut priv_replicas = BTreeMap::new();
+    for _ in 0..3 {
+        let (sector_id, replica, comm_r, cache_dir) =
+            create_fake_seal::<_, Tree>(&mut rng, sector_size, &porep_id, api_version)?;
+        priv_replicas.insert(
+            sector_id,
+            PrivateReplicaInfo::<Tree>::new(
+                replica.path().into(),
+                comm_r,
+                cache_dir.path().into(),
+            )?,
+        );
+        pub_replicas.insert(sector_id, PublicReplicaInfo::new(comm_r)?);
+        sectors.push((sector_id, replica, comm_r, cache_dir));
+    }
+
+    let random_fr: <Tree::Hasher as Hasher>::Domain = Fr::random(&mut rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+
+    let config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: *WINDOW_POST_SECTOR_COUNT
+            .read()
+            .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+            .get(&sector_size)
+            .expect("unknown sector size"),
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+
+    // The replica of the first sector is truncated, the others are moved to its position.
+    let bad_replica = NamedTempFile::new()?;
+    bad_replica.as_file().set_len(1)?;
+    let faulty_replica = |(_, comm_r, cache_dir): (&SectorId, &Commitment, &TempDir)| {
+        PrivateReplicaInfo::<Tree>::new(bad_replica.path().into(), *comm_r, cache_dir.path().into())
+    };
+    let (faulty_sector_id, _, comm_r, cache_dir) = &sectors[0];
+    let mut mixed_replicas = priv_replicas.clone();
+    mixed_replicas.insert(
+        *faulty_sector_id,
+        faulty_replica((faulty_sector_id, comm_r, cache_dir))?,
+    );
+
+    let output = generate_window_post_skipping_faults::<Tree>(
+        &config,
+        &randomness,
+        &mixed_replicas,
+        prover_id,
+    )?;
+    assert_eq!(output.skipped, vec![*faulty_sector_id]);
+    let mut remaining_pub_replicas = pub_replicas.clone();
+    remaining_pub_replicas.remove(faulty_sector_id);
+    let valid = verify_window_post::<Tree>(
+        &config,
+        &randomness,
+        &remaining_pub_replicas,
+        prover_id,
+        &output.proof,
+    )?;
+    assert!(valid, "proof without the faulty sector did not verify");
+
+    // There is nothing left to prove if all sectors are faulty.
+    let faulty_replicas = sectors
+        .iter()
+        .map(|(sector_id, _, comm_r, cache_dir)| {
+            Ok((*sector_id, faulty_replica((sector_id, comm_r, cache_dir))?))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+    match generate_window_post_skipping_faults::<Tree>(
+        &config,
+        &randomness,
+        &faulty_replicas,
+        prover_id,
+    ) {
+        Err(ProofsError::FaultySectors { sectors, .. }) => {
+            assert_eq!(sectors, faulty_replicas.keys().copied().collect::<Vec<_>>())
+        }
+        other => panic!("expected faulty sectors, got {:?}", other.map(|_| ())),
+    }
+
+    // The trees of the second sector are intact, but its p_aux doesn't belong to its comm_r.
+    let (bad_aux_sector_id, replica, comm_r, cache_dir) = &sectors[1];
+    let p_aux_path = cache_dir.path().join(CacheKey::PAux.to_string());
+    let mut p_aux: PersistentAux<<Tree::Hasher as Hasher>::Domain> =
+        deserialize(&std::fs::read(&p_aux_path)?)?;
+    p_aux.comm_c = Default::default();
+    std::fs::write(&p_aux_path, serialize(&p_aux)?)?;
+    let mut bad_aux_replicas = priv_replicas.clone();
+    bad_aux_replicas.insert(
+        *bad_aux_sector_id,
+        PrivateReplicaInfo::<Tree>::new(replica.path().into(), *comm_r, cache_dir.path().into())?,
+    );
+
+    let output = generate_window_post_skipping_faults::<Tree>(
+        &config,
+        &randomness,
+        &bad_aux_replicas,
+        prover_id,
+    )?;
+    assert_eq!(output.skipped, vec![*bad_aux_sector_id]);
+    let mut remaining_pub_replicas = pub_replicas.clone();
+    remaining_pub_replicas.remove(bad_aux_sector_id);
+    let valid = verify_window_post::<Tree>(
+        &config,
+        &randomness,
+        &remaining_pub_replicas,
+        prover_id,
+        &output.proof,
+    )?;
+    assert!(valid, "proof without the bad p_aux sector did not verify");
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_verify_post_batch_2kib_base_8() -> Result<()> {
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+
+    let prover_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut prover_id = [0u8; 32];
+    prover_id.copy_from_slice(AsRef::<[u8]>::as_ref(&prover_fr));
+
+    let sector_size = SECTOR_SIZE_2_KIB;
+    let api_version = ApiVersion::V1_1_0;
+    let porep_id = ARBITRARY_POREP_ID_V1_1_0;
+    let window_sector_count = *WINDOW_POST_SECTOR_COUNT
+        .read()
+        .expect("WINDOW_POST_SECTOR_COUNT poisoned")
+        .get(&sector_size)
+        .expect("unknown sector size");
+
+    let mut sectors = Vec::new();
+    let mut pub_replicas = BTreeMap::new();
+    let mut priv_replicas = BTreeMap::new();
+    for _ in 0..window_sector_count {
+        let (sector_id, replica, comm_r, cache_dir) =
+            create_fake_seal::<_, SectorShape2KiB>(&mut rng, sector_size, &porep_id, api_version)?;
+        priv_replicas.insert(
+            sector_id,
+            PrivateReplicaInfo::new(replica.path().into(), comm_r, cache_dir.path().into())?,
+        );
+        pub_replicas.insert(sector_id, PublicReplicaInfo::new(comm_r)?);
+        sectors.push((replica, cache_dir));
+    }
+
+    let random_fr: DefaultTreeDomain = Fr::random(&mut rng).into();
+    let mut randomness = [0u8; 32];
+    randomness.copy_from_slice(AsRef::<[u8]>::as_ref(&random_fr));
+    let mut wrong_randomness = randomness;
+    wrong_randomness[0] ^= 1;
+
+    let window_config = PoStConfig {
+        sector_size: sector_size.into(),
+        sector_count: window_sector_count,
+        challenge_count: WINDOW_POST_CHALLENGE_COUNT,
+        typ: PoStType::Window,
+        priority: false,
+        api_version,
+    };
+    let proof = generate_window_post::<SectorShape2KiB>(
+        &window_config,
+        &randomness,
+        &priv_replicas,
+        prover_id,
+    )?;
+    let item = WindowPoStBatchItem {
+        randomness: &randomness,
+        replicas: &pub_replicas,
+        prover_id,
+        proof: &proof,
+    };
+    let valid = verify_window_post_batch::<SectorShape2KiB>(
+        &window_config,
+        &[
+            item,
+            WindowPoStBatchItem {
+                randomness: &wrong_randomness,
+                ..item
+            },
+            WindowPoStBatchItem {
+                proof: &proof[1..],
+                ..item
+            },
+        ],
+    )?;
+    assert_eq!(valid, vec![true, false, false]);
+
+    let winning_config = PoStConfig {
+        sector_count: WINNING_POST_SECTOR_COUNT,
+        challenge_count: WINNING_POST_CHALLENGE_COUNT,
+        typ: PoStType::Winning,
+        ..window_config
+    };
+    let (sector_id, replica) = priv_replicas
+        .into_iter()
+        .next()
+        .expect("no sectors were sealed");
+    let winning_replicas = vec![(sector_id, replica)];
+    let winning_pub_replicas = vec![(sector_id, pub_replicas[&sector_id].clone())];
+    let proof = generate_winning_post::<SectorShape2KiB>(
+        &winning_config,
+        &randomness,
+        &winning_replicas,
+        prover_id,
+    )?;
+    let item = WinningPoStBatchItem {
+        randomness: &randomness,
+        replicas: &winning_pub_replicas,
+        prover_id,
+        proof: &proof,
+    };
+    let valid = verify_winning_post_batch::<SectorShape2KiB>(
+        &winning_config,
+        &[
+            item,
+            WinningPoStBatchItem {
+                randomness: &wrong_randomness,
+                ..item
+            },
+            item,
+        ],
+    )?;
+    assert_eq!(valid, vec![true, false, true]);
+
+    Ok(())
+}
+
 /// Make all files recursively read-only/writeable, starting at the given directory/file.
 fn set_readonly_flag(path: &Path, readonly: bool) {
     for entry in walkdir::WalkDir::new(path) {
@@ -1981,7 +4020,6 @@ fn window_post<Tree: 'static + MerkleTreeTrait>(
         let proof =
             generate_window_post::<Tree>(&config, &randomness, &priv_faulty_replicas, prover_id);
 
-        use storage_proofs_core::error::Error as FaultySectorError;
         match proof {
             Ok(proof) => {
                 let valid = verify_window_post::<Tree>(
@@ -1993,14 +4031,11 @@ fn window_post<Tree: 'static + MerkleTreeTrait>(
                 )?;
                 assert!(!valid, "proof made with faulty sectors verified");
             }
-            Err(e) => match e.downcast::<FaultySectorError>() {
-                Err(_) => panic!("failed to downcast to Error"),
-                Ok(FaultySectorError::FaultySectors(sector_ids)) => {
-                    info!("faulty_sectors detected properly: {:?}", sector_ids);
-                    faulty_sectors.extend(sector_ids);
-                }
-                Ok(_) => panic!("PoSt failed to return FaultySectors error."),
-            },
+            Err(filecoin_proofs::Error::FaultySectors { sectors, .. }) => {
+                info!("faulty_sectors detected properly: {:?}", sectors);
+                faulty_sectors.extend(sectors);
+            }
+            Err(e) => panic!("PoSt failed to return FaultySectors error: {:?}", e),
         };
 
         // This assertion is for the case of a total failure, not a
@@ -2689,6 +4724,11 @@ fn create_seal_for_upgrade<R: Rng, Tree: 'static + MerkleTreeTrait<Hasher = Tree
     )?;
     ensure!(proofs_are_valid, "Partition proofs failed to verify");
 
+    let partition_proofs = partition_proofs
+        .iter()
+        .map(|proof| PartitionProof::<Tree>::from_bytes(&proof.to_bytes()?))
+        .collect::<Result<Vec<_>, _>>()?;
+
     let proof = generate_empty_sector_update_proof_with_vanilla::<Tree>(
         porep_config,
         partition_proofs,
@@ -4,12 +4,14 @@ use std::iter::Iterator;
 use anyhow::Result;
 use blstrs::Scalar as Fr;
 use filecoin_proofs::{
-    add_piece, commitment_from_fr,
+    add_piece, commitment_from_fr, generate_piece_commitment, generate_piece_commitment_parallel,
+    generate_piece_inclusion_proofs_from_unsealed,
     pieces::{
-        compute_comm_d, get_piece_alignment, get_piece_start_byte, piece_hash, verify_pieces,
-        zero_padding, EmptySource, PieceAlignment,
+        compute_comm_d, get_piece_alignment, get_piece_offsets, get_piece_start_byte, piece_hash,
+        plan_piece_layout, verify_pieces, zero_padding, EmptySource, PieceAlignment,
     },
-    Commitment, DataTree, DefaultPieceHasher, PaddedBytesAmount, PieceInfo, SectorSize,
+    verify_piece_inclusion_proof, Commitment, DataTree, DefaultPieceHasher, LayoutPieceKind,
+    PackingStrategy, PaddedBytesAmount, PieceCommitmentConfig, PieceInfo, SectorSize,
     UnpaddedByteIndex, UnpaddedBytesAmount, DRG_DEGREE, EXP_DEGREE, TEST_SEED,
 };
 use rand::{Rng, RngCore, SeedableRng};
@@ -29,6 +31,25 @@ fn test_empty_source() {
     assert_eq!(target, vec![0u8; 12]);
 }
 
+#[test]
+fn test_generate_piece_commitment_parallel() -> Result<()> {
+    let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+    let piece_size = UnpaddedBytesAmount(127 * 1024);
+    let mut piece_bytes = vec![0u8; u64::from(piece_size) as usize];
+    rng.fill_bytes(&mut piece_bytes);
+
+    let expected = generate_piece_commitment(Cursor::new(&piece_bytes), piece_size)?;
+    let config = PieceCommitmentConfig {
+        num_threads: 4,
+        max_memory: 16 * 1024,
+    };
+    let piece_info =
+        generate_piece_commitment_parallel(Cursor::new(&piece_bytes), piece_size, &config)?;
+    assert_eq!(piece_info, expected);
+
+    Ok(())
+}
+
 #[test]
 fn test_compute_comm_d_empty() {
     let comm_d =
@@ -366,6 +387,227 @@ fn test_verify_random_pieces() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_piece_inclusion_proofs() -> Result<()> {
+    let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+    let sector_size = SectorSize(2048);
+    let piece_sizes = [
+        UnpaddedBytesAmount(127 * 2),
+        UnpaddedBytesAmount(127),
+        UnpaddedBytesAmount(127 * 4),
+        UnpaddedBytesAmount(127 * 8),
+    ];
+
+    let mut staged_sector = Vec::with_capacity(u64::from(sector_size) as usize);
+    let mut piece_infos = Vec::with_capacity(piece_sizes.len());
+    for (i, piece_size) in piece_sizes.iter().enumerate() {
+        let mut piece_bytes = vec![0u8; u64::from(*piece_size) as usize];
+        rng.fill_bytes(&mut piece_bytes);
+        let (piece_info, _) = add_piece(
+            Cursor::new(&piece_bytes),
+            &mut staged_sector,
+            *piece_size,
+            &piece_sizes[..i],
+        )?;
+        piece_infos.push(piece_info);
+    }
+    assert_eq!(staged_sector.len(), u64::from(sector_size) as usize);
+
+    let comm_d = compute_comm_d(sector_size, &piece_infos)?;
+    let offsets = get_piece_offsets(sector_size, &piece_infos)?;
+    assert_eq!(
+        offsets,
+        vec![
+            UnpaddedByteIndex(0),
+            UnpaddedByteIndex(127 * 2),
+            UnpaddedByteIndex(127 * 4),
+            UnpaddedByteIndex(127 * 8),
+        ]
+    );
+
+    let proofs = generate_piece_inclusion_proofs_from_unsealed(
+        sector_size,
+        Cursor::new(&staged_sector),
+        &piece_infos,
+    )?;
+    assert_eq!(proofs.len(), piece_infos.len());
+
+    for ((piece_info, offset), proof) in piece_infos.iter().zip(&offsets).zip(&proofs) {
+        assert!(verify_piece_inclusion_proof(
+            sector_size,
+            &comm_d,
+            &piece_info.commitment,
+            piece_info.size,
+            *offset,
+            proof,
+        )?);
+
+        // Wrong offsets don't verify.
+        for wrong_offset in [
+            UnpaddedByteIndex(u64::from(*offset) + u64::from(piece_info.size)),
+            UnpaddedByteIndex(u64::from(*offset) + 1),
+            UnpaddedByteIndex(u64::from(sector_size)),
+        ] {
+            assert!(!verify_piece_inclusion_proof(
+                sector_size,
+                &comm_d,
+                &piece_info.commitment,
+                piece_info.size,
+                wrong_offset,
+                proof,
+            )?);
+        }
+
+        // Tampered proofs don't verify.
+        let mut tampered = proof.clone();
+        tampered.path[0][0] ^= 1;
+        assert!(!verify_piece_inclusion_proof(
+            sector_size,
+            &comm_d,
+            &piece_info.commitment,
+            piece_info.size,
+            *offset,
+            &tampered,
+        )?);
+
+        let mut wrong_comm_p = piece_info.commitment;
+        wrong_comm_p[0] ^= 1;
+        assert!(!verify_piece_inclusion_proof(
+            sector_size,
+            &comm_d,
+            &wrong_comm_p,
+            piece_info.size,
+            *offset,
+            proof,
+        )?);
+    }
+
+    // A proof for one piece doesn't verify for a piece with a different size.
+    assert!(!verify_piece_inclusion_proof(
+        sector_size,
+        &comm_d,
+        &piece_infos[0].commitment,
+        UnpaddedBytesAmount(127),
+        offsets[0],
+        &proofs[0],
+    )?);
+
+    // The unsealed data has to match the pieces.
+    staged_sector[0] ^= 1;
+    assert!(generate_piece_inclusion_proofs_from_unsealed(
+        sector_size,
+        Cursor::new(&staged_sector),
+        &piece_infos,
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_plan_piece_layout() -> Result<()> {
+    let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+    let sector_size = SectorSize(2048);
+    let candidate_sizes = [127 * 2, 127 * 8, 127 * 8];
+
+    let mut piece_bytes = Vec::new();
+    let mut candidates = Vec::new();
+    for size in candidate_sizes {
+        let mut bytes = vec![0u8; size];
+        rng.fill_bytes(&mut bytes);
+        candidates.push(generate_piece_commitment(
+            Cursor::new(&bytes),
+            UnpaddedBytesAmount(size as u64),
+        )?);
+        piece_bytes.push(bytes);
+    }
+
+    let layout = plan_piece_layout(sector_size, &[], &candidates, PackingStrategy::FirstFit)?;
+    assert_eq!(layout.excluded, vec![2]);
+    assert_eq!(
+        layout
+            .pieces
+            .iter()
+            .map(|piece| (piece.kind, u64::from(piece.offset)))
+            .collect::<Vec<_>>(),
+        vec![
+            (LayoutPieceKind::Candidate(1), 0),
+            (LayoutPieceKind::Candidate(0), 127 * 8),
+            (LayoutPieceKind::Filler, 127 * 10),
+            (LayoutPieceKind::Filler, 127 * 12),
+        ]
+    );
+
+    let layout = plan_piece_layout(sector_size, &[], &candidates, PackingStrategy::BestFit)?;
+    assert_eq!(layout.excluded, vec![0]);
+    assert_eq!(layout.pieces.len(), 2);
+
+    // Required pieces take precedence over the candidates.
+    let layout = plan_piece_layout(
+        sector_size,
+        &candidates[..1],
+        &candidates[1..],
+        PackingStrategy::BestFit,
+    )?;
+    assert_eq!(layout.excluded, vec![1]);
+    assert_eq!(
+        layout.pieces[1].kind,
+        LayoutPieceKind::Required(0),
+        "pieces are ordered by size"
+    );
+
+    // Write the pieces in the planned order and check that CommD matches the data.
+    let mut staged_sector = Vec::with_capacity(u64::from(sector_size) as usize);
+    let mut written = Vec::new();
+    for piece in &layout.pieces {
+        let bytes = match piece.kind {
+            LayoutPieceKind::Required(i) => piece_bytes[i].clone(),
+            LayoutPieceKind::Candidate(i) => piece_bytes[i + 1].clone(),
+            LayoutPieceKind::Filler => vec![0; u64::from(piece.piece_info.size) as usize],
+        };
+        assert_eq!(
+            get_piece_start_byte(&written, piece.piece_info.size),
+            piece.offset
+        );
+        let (piece_info, _) = add_piece(
+            Cursor::new(&bytes),
+            &mut staged_sector,
+            piece.piece_info.size,
+            &written,
+        )?;
+        assert_eq!(piece_info, piece.piece_info);
+        written.push(piece.piece_info.size);
+    }
+    assert_eq!(staged_sector.len(), u64::from(sector_size) as usize);
+
+    let data_tree = create_base_merkle_tree::<DataTree>(
+        None,
+        u64::from(sector_size) as usize / NODE_SIZE,
+        &staged_sector,
+    )?;
+    let comm_d_root: Fr = data_tree.root().into();
+    assert_eq!(commitment_from_fr(comm_d_root), layout.comm_d);
+    assert_eq!(
+        layout.comm_d,
+        compute_comm_d(sector_size, &layout.piece_infos())?
+    );
+
+    // Required pieces that don't fit are an error.
+    assert!(plan_piece_layout(
+        sector_size,
+        &[
+            candidates[1].clone(),
+            candidates[2].clone(),
+            candidates[0].clone()
+        ],
+        &[],
+        PackingStrategy::FirstFit,
+    )
+    .is_err());
+
+    Ok(())
+}
+
 fn build_sector(
     piece_sizes: &[UnpaddedBytesAmount],
     sector_size: SectorSize,
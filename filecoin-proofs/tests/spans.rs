@@ -0,0 +1,129 @@
+#![cfg(feature = "tracing")]
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use filecoin_proofs::{
+    decode_from, generate_partition_proofs, PoRepConfig, SectorShape2KiB, SectorUpdateConfig,
+    SECTOR_SIZE_2_KIB,
+};
+use storage_proofs_core::api_version::ApiVersion;
+use tempfile::tempdir;
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+/// A span that was created while the [`SpanCapture`] was the default subscriber.
+#[derive(Debug)]
+struct CapturedSpan {
+    name: &'static str,
+    fields: Vec<(&'static str, String)>,
+}
+
+struct FieldVisitor<'a>(&'a mut Vec<(&'static str, String)>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.push((field.name(), format!("{:?}", value)));
+    }
+}
+
+/// Records the name and the fields of every new span.
+#[derive(Clone, Default)]
+struct SpanCapture {
+    spans: Arc<Mutex<Vec<CapturedSpan>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl SpanCapture {
+    fn fields(&self, name: &str) -> Vec<(&'static str, String)> {
+        let spans = self.spans.lock().expect("spans lock poisoned");
+        spans
+            .iter()
+            .find(|span| span.name == name)
+            .unwrap_or_else(|| panic!("no `{}` span in {:?}", name, spans))
+            .fields
+            .clone()
+    }
+}
+
+impl Subscriber for SpanCapture {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+        let mut fields = Vec::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        self.spans
+            .lock()
+            .expect("spans lock poisoned")
+            .push(CapturedSpan {
+                name: attrs.metadata().name(),
+                fields,
+            });
+        span::Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+#[test]
+fn test_update_spans_identify_replica() {
+    let config = SectorUpdateConfig::from_porep_config(&PoRepConfig::new_groth16(
+        SECTOR_SIZE_2_KIB,
+        [0; 32],
+        ApiVersion::V1_2_0,
+    ));
+    let dir = tempdir().expect("failed to create temp dir");
+    let replica_path = dir.path().join("replica");
+    let missing = dir.path().join("missing");
+    let comm_r_new = [0xab; 32];
+
+    // The files don't exist, the calls fail after their span was created.
+    let capture = SpanCapture::default();
+    tracing::subscriber::with_default(capture.clone(), || {
+        decode_from::<SectorShape2KiB>(
+            config,
+            &missing,
+            &replica_path,
+            &missing,
+            &missing,
+            [0; 32],
+        )
+        .expect_err("decode_from should fail without its files");
+        let proofs = generate_partition_proofs::<SectorShape2KiB>(
+            config,
+            [0; 32],
+            comm_r_new,
+            [0; 32],
+            &missing,
+            &missing,
+            &replica_path,
+            &missing,
+        );
+        assert!(
+            proofs.is_err(),
+            "generate_partition_proofs should fail without its files"
+        );
+    });
+
+    assert_eq!(
+        capture.fields("decode_from"),
+        vec![("replica", replica_path.display().to_string())]
+    );
+    assert_eq!(
+        capture.fields("generate_partition_proofs"),
+        vec![("comm_r_new", "abababab".to_string())]
+    );
+}
@@ -1,8 +1,10 @@
+use std::io::{Cursor, Read};
+
 use blstrs::Scalar as Fr;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use ff::Field;
-use fr32::{bytes_into_fr, fr_into_bytes};
-use rand::thread_rng;
+use fr32::{bytes_into_fr, fr_into_bytes, write_unpadded, Fr32Reader};
+use rand::{thread_rng, RngCore};
 
 fn fr_benchmark(c: &mut Criterion) {
     c.bench_function("fr-to-bytes-32", move |b| {
@@ -21,5 +23,34 @@ fn fr_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, fr_benchmark);
+fn padding_benchmark(c: &mut Criterion) {
+    let mut data = vec![0u8; 127 * 1024];
+    thread_rng().fill_bytes(&mut data);
+    let mut padded = Vec::new();
+    Fr32Reader::new(Cursor::new(&data))
+        .read_to_end(&mut padded)
+        .expect("in-memory read failed");
+
+    c.bench_function("fr32-reader-127KiB", |b| {
+        let mut target = Vec::with_capacity(padded.len());
+        b.iter(|| {
+            target.clear();
+            Fr32Reader::new(Cursor::new(&data))
+                .read_to_end(&mut target)
+                .expect("in-memory read failed");
+            black_box(&target);
+        })
+    });
+
+    c.bench_function("write-unpadded-128KiB", |b| {
+        let mut target = Vec::with_capacity(data.len());
+        b.iter(|| {
+            target.clear();
+            write_unpadded(&padded, &mut target, 0, data.len()).expect("in-memory write failed");
+            black_box(&target);
+        })
+    });
+}
+
+criterion_group!(benches, fr_benchmark, padding_benchmark);
 criterion_main!(benches);
@@ -0,0 +1,364 @@
+use std::convert::TryInto;
+
+use crate::reader::{MASK_SKIP_HIGH_2, NUM_BYTES_OUT_BLOCK};
+
+/// A block of 127 unpadded bytes or four `Fr32`s. Unpadded blocks are stored with a trailing zero
+/// byte, so that both can be processed as 128-bit or 256-bit words.
+pub(crate) type Block = [u8; NUM_BYTES_OUT_BLOCK];
+
+/// Pads the first 127 bytes of `input` into four `Fr32`s.
+#[inline]
+pub(crate) fn pad_block(input: &Block, output: &mut Block) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safety: AVX2 is supported by the CPU.
+            unsafe { avx2::pad_block(input, output) };
+            return;
+        }
+    }
+    scalar::pad_block(input, output)
+}
+
+/// Unpads four `Fr32`s into 127 bytes, the last byte of `output` is always zero.
+#[inline]
+pub(crate) fn unpad_block(input: &Block, output: &mut Block) {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // Safety: AVX2 is supported by the CPU.
+            unsafe { avx2::unpad_block(input, output) };
+            return;
+        }
+    }
+    scalar::unpad_block(input, output)
+}
+
+mod scalar {
+    use super::*;
+
+    macro_rules! process_fr {
+        (
+            $in_buffer:expr,
+            $out0:expr,
+            $out1:expr,
+            $bit_offset:expr
+        ) => {{
+            $out0 = $in_buffer[0] >> 128 - $bit_offset;
+            $out0 |= $in_buffer[1] << $bit_offset;
+            $out1 = $in_buffer[1] >> 128 - $bit_offset;
+            $out1 |= $in_buffer[2] << $bit_offset;
+            $out1 &= MASK_SKIP_HIGH_2; // zero high 2 bits
+        }};
+    }
+
+    fn to_words(bytes: &Block) -> [u128; 8] {
+        let mut words = [0u128; 8];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(16)) {
+            *word = u128::from_le_bytes(chunk.try_into().expect("chunks are 16 bytes"));
+        }
+        words
+    }
+
+    fn from_words(words: [u128; 8], bytes: &mut Block) {
+        for (chunk, word) in bytes.chunks_exact_mut(16).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    pub(super) fn pad_block(input: &Block, output: &mut Block) {
+        let in_buffer = to_words(input);
+        let mut out = [0u128; 8];
+
+        // 0..254
+        {
+            out[0] = in_buffer[0];
+            out[1] = in_buffer[1] & MASK_SKIP_HIGH_2;
+        }
+        // 254..508
+        process_fr!(&in_buffer[1..], out[2], out[3], 2);
+        // 508..762
+        process_fr!(&in_buffer[3..], out[4], out[5], 4);
+        // 762..1016
+        process_fr!(&in_buffer[5..], out[6], out[7], 6);
+
+        from_words(out, output);
+    }
+
+    pub(super) fn unpad_block(input: &Block, output: &mut Block) {
+        let mut p = to_words(input);
+        p[1] &= MASK_SKIP_HIGH_2;
+        p[3] &= MASK_SKIP_HIGH_2;
+        p[5] &= MASK_SKIP_HIGH_2;
+        p[7] &= MASK_SKIP_HIGH_2;
+
+        let out = [
+            // 0..254
+            p[0],
+            p[1] | p[2] << 126,
+            // 254..508
+            p[2] >> 2 | p[3] << 126,
+            p[3] >> 2 | p[4] << 124,
+            // 508..762
+            p[4] >> 4 | p[5] << 124,
+            p[5] >> 4 | p[6] << 122,
+            // 762..1016
+            p[6] >> 6 | p[7] << 122,
+            p[7] >> 6,
+        ];
+
+        from_words(out, output);
+    }
+}
+
+/// The same transforms on 256-bit words. Each `Fr32` is one word, the `Fr32` at index `i` is
+/// shifted by `2 * i` bits against the unpadded data.
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::*;
+
+    use std::arch::x86_64::*;
+
+    /// Clears the two highest bits of an `Fr32`.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn fr_mask() -> __m256i {
+        _mm256_set_epi64x(0x3fff_ffff_ffff_ffff, -1, -1, -1)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn pad_block(input: &Block, output: &mut Block) {
+        let src = input.as_ptr();
+        let dst = output.as_mut_ptr() as *mut __m256i;
+        let mask = fr_mask();
+
+        // 0..254
+        let fr = _mm256_loadu_si256(src as *const __m256i);
+        _mm256_storeu_si256(dst, _mm256_and_si256(fr, mask));
+
+        // The `Fr32` at index `i` starts at bit `64 * 4 * i - 2 * i`, so each of its 64-bit
+        // lanes combines the high bits of the previous lane with the low bits of the current one.
+        for i in 1..4 {
+            let current = _mm256_loadu_si256(src.add(32 * i) as *const __m256i);
+            let previous = _mm256_loadu_si256(src.add(32 * i - 8) as *const __m256i);
+            let fr = _mm256_or_si256(
+                _mm256_sllv_epi64(current, _mm256_set1_epi64x(2 * i as i64)),
+                _mm256_srlv_epi64(previous, _mm256_set1_epi64x(64 - 2 * i as i64)),
+            );
+            _mm256_storeu_si256(dst.add(i), _mm256_and_si256(fr, mask));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn unpad_block(input: &Block, output: &mut Block) {
+        let src = input.as_ptr();
+        let dst = output.as_mut_ptr() as *mut __m256i;
+        let mask = fr_mask();
+        // The lanes following the lanes of an `Fr32`, the third one is its last lane.
+        let next_mask = _mm256_set_epi64x(-1, 0x3fff_ffff_ffff_ffff, -1, -1);
+
+        for i in 0..4 {
+            let fr = _mm256_and_si256(_mm256_loadu_si256(src.add(32 * i) as *const __m256i), mask);
+            // The last lane is the first lane of the next `Fr32`, which doesn't exist for the last
+            // one. It's not loaded, as it would be out of bounds.
+            let next_ptr = src.add(32 * i + 8) as *const i64;
+            let next = if i < 3 {
+                _mm256_loadu_si256(next_ptr as *const __m256i)
+            } else {
+                _mm256_maskload_epi64(next_ptr, _mm256_set_epi64x(0, -1, -1, -1))
+            };
+            let next = _mm256_and_si256(next, next_mask);
+
+            // The last lane of an `Fr32` only has 62 bits of data.
+            let shift = 2 * i as i64;
+            let data = _mm256_or_si256(
+                _mm256_srlv_epi64(fr, _mm256_set1_epi64x(shift)),
+                _mm256_sllv_epi64(
+                    next,
+                    _mm256_set_epi64x(62 - shift, 64 - shift, 64 - shift, 64 - shift),
+                ),
+            );
+            _mm256_storeu_si256(dst.add(i), data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::{collection::vec, num::u8, proptest};
+
+    use crate::{reader::NUM_BYTES_IN_BLOCK, write_unpadded};
+
+    /// The implementations of the block transforms that are available on this CPU.
+    fn pad_impls() -> Vec<(&'static str, fn(&Block, &mut Block))> {
+        let mut impls: Vec<(&'static str, fn(&Block, &mut Block))> =
+            vec![("dispatch", pad_block), ("scalar", scalar::pad_block)];
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // Safety: AVX2 is supported by the CPU.
+            impls.push(("avx2", |input, output| unsafe {
+                avx2::pad_block(input, output)
+            }));
+        }
+        impls
+    }
+
+    fn unpad_impls() -> Vec<(&'static str, fn(&Block, &mut Block))> {
+        let mut impls: Vec<(&'static str, fn(&Block, &mut Block))> =
+            vec![("dispatch", unpad_block), ("scalar", scalar::unpad_block)];
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            // Safety: AVX2 is supported by the CPU.
+            impls.push(("avx2", |input, output| unsafe {
+                avx2::unpad_block(input, output)
+            }));
+        }
+        impls
+    }
+
+    fn block(bytes: &[u8]) -> Block {
+        let mut block = [0; NUM_BYTES_OUT_BLOCK];
+        block[..bytes.len()].copy_from_slice(bytes);
+        block
+    }
+
+    /// Unpads a block with the bit by bit path of `write_unpadded`, which is the original
+    /// implementation. Unaligned offsets don't take the block transform.
+    fn baseline_unpad(padded: &[u8]) -> Vec<u8> {
+        let mut unpadded = Vec::new();
+        write_unpadded(padded, &mut unpadded, 0, 1).expect("in-memory write failed");
+        write_unpadded(padded, &mut unpadded, 1, 126).expect("in-memory write failed");
+        unpadded
+    }
+
+    /// The input of the test vectors.
+    fn input(len: usize) -> Vec<u8> {
+        (0..len as u32)
+            .map(|i| (i.wrapping_mul(0x9e37_79b1) >> 24) as u8)
+            .collect()
+    }
+
+    /// `input(254)` padded by the `Fr32Reader` of the original scalar implementation.
+    const BASELINE_PADDED: [u8; 256] = [
+        0x00, 0x9e, 0x3c, 0xda, 0x78, 0x17, 0xb5, 0x53, 0xf1, 0x8f, 0x2e, 0xcc, 0x6a, 0x08, 0xa7,
+        0x45, 0xe3, 0x81, 0x1f, 0xbe, 0x5c, 0xfa, 0x98, 0x36, 0xd5, 0x73, 0x11, 0xaf, 0x4e, 0xec,
+        0x8a, 0x28, 0x18, 0x97, 0x0d, 0x84, 0xfe, 0x78, 0xf3, 0x69, 0xe0, 0x5a, 0xd5, 0x4f, 0xc6,
+        0x3c, 0xb7, 0x31, 0xa8, 0x22, 0x99, 0x17, 0x8e, 0x04, 0x7f, 0xf5, 0x73, 0xea, 0x60, 0xdb,
+        0x55, 0xcc, 0x46, 0x3d, 0xde, 0xc8, 0xa2, 0x8c, 0x66, 0x40, 0x3a, 0x14, 0xfe, 0xd7, 0xc1,
+        0xab, 0x85, 0x6f, 0x49, 0x33, 0x1d, 0xf7, 0xd0, 0xba, 0xa4, 0x8e, 0x68, 0x42, 0x3c, 0x16,
+        0xf0, 0xd9, 0xb3, 0xad, 0x87, 0x21, 0x2d, 0xd5, 0x7c, 0xe4, 0x4b, 0xf3, 0x9a, 0x02, 0xaa,
+        0x11, 0xb9, 0x60, 0xc8, 0x6f, 0xd7, 0xbe, 0x26, 0x8e, 0x35, 0x9d, 0x44, 0xec, 0x53, 0xfb,
+        0xa2, 0x0a, 0xb2, 0x19, 0x81, 0x68, 0xd0, 0x37, 0x7d, 0x1b, 0xb9, 0x58, 0xf6, 0x94, 0x32,
+        0xd1, 0x6f, 0x0d, 0xab, 0x49, 0xe8, 0x86, 0x24, 0xc2, 0x60, 0xff, 0x9d, 0x3b, 0xd9, 0x78,
+        0x16, 0xb4, 0x52, 0xf0, 0x8f, 0x2d, 0xcb, 0x69, 0x08, 0x26, 0x12, 0x89, 0x03, 0x7e, 0xf4,
+        0x6e, 0xe5, 0x5f, 0xda, 0x50, 0xcb, 0x41, 0xbc, 0x36, 0xad, 0x27, 0x9e, 0x18, 0x93, 0x09,
+        0x80, 0xfe, 0x74, 0xef, 0x65, 0xdc, 0x5a, 0xd1, 0x4b, 0xc2, 0x38, 0x37, 0xb6, 0x90, 0x7a,
+        0x64, 0x4e, 0x28, 0x02, 0xec, 0xd5, 0xbf, 0x99, 0x73, 0x5d, 0x47, 0x21, 0x0b, 0xe5, 0xde,
+        0xb8, 0x92, 0x7c, 0x56, 0x40, 0x2a, 0x04, 0xee, 0xd7, 0xb1, 0x9b, 0x75, 0x5f, 0x09, 0x8d,
+        0x34, 0x9c, 0x03, 0xeb, 0x52, 0xfa, 0x61, 0x09, 0xb1, 0x18, 0x80, 0x27, 0xcf, 0x76, 0xde,
+        0x45, 0x2d, 0x95, 0x3c, 0xa4, 0x0b, 0xf3, 0x5a, 0xc2, 0x69, 0xd1, 0xb8, 0x20, 0x88, 0x2f,
+        0x17,
+    ];
+
+    /// `input(256)` unpadded by `write_unpadded` of the original bit by bit implementation.
+    const BASELINE_UNPADDED: [u8; 254] = [
+        0x00, 0x9e, 0x3c, 0xda, 0x78, 0x17, 0xb5, 0x53, 0xf1, 0x8f, 0x2e, 0xcc, 0x6a, 0x08, 0xa7,
+        0x45, 0xe3, 0x81, 0x1f, 0xbe, 0x5c, 0xfa, 0x98, 0x36, 0xd5, 0x73, 0x11, 0xaf, 0x4e, 0xec,
+        0x8a, 0xa8, 0x71, 0xd9, 0x40, 0xe8, 0x8f, 0x37, 0x9f, 0x06, 0xae, 0x55, 0xfd, 0x64, 0xcc,
+        0x73, 0x1b, 0x83, 0x2a, 0x92, 0x79, 0xe1, 0x48, 0xf0, 0x57, 0x3f, 0xa7, 0x0e, 0xb6, 0x5d,
+        0xc5, 0x6c, 0xd4, 0xdb, 0xc8, 0xa2, 0x8c, 0x66, 0x40, 0x3a, 0x14, 0xfe, 0xd7, 0xc1, 0xab,
+        0x85, 0x6f, 0x49, 0x33, 0x1d, 0xf7, 0xd0, 0xba, 0xa4, 0x8e, 0x68, 0x42, 0x3c, 0x16, 0xf0,
+        0xd9, 0xb3, 0xad, 0x87, 0x61, 0x53, 0xcd, 0x47, 0xbe, 0x34, 0xaf, 0x29, 0xa0, 0x1a, 0x91,
+        0x0b, 0x86, 0xfc, 0x76, 0xed, 0x6b, 0xe2, 0x58, 0xd3, 0x49, 0xc4, 0x3e, 0xb5, 0x2f, 0xaa,
+        0x20, 0x9b, 0x11, 0x88, 0x06, 0x7d, 0xf7, 0x1b, 0xb9, 0x58, 0xf6, 0x94, 0x32, 0xd1, 0x6f,
+        0x0d, 0xab, 0x49, 0xe8, 0x86, 0x24, 0xc2, 0x60, 0xff, 0x9d, 0x3b, 0xd9, 0x78, 0x16, 0xb4,
+        0x52, 0xf0, 0x8f, 0x2d, 0xcb, 0x69, 0x08, 0xa6, 0x84, 0x38, 0xe0, 0x47, 0xef, 0x56, 0xfe,
+        0xa5, 0x0d, 0xb5, 0x1c, 0xc4, 0x6b, 0xd3, 0x7a, 0xe2, 0x89, 0x31, 0x99, 0x00, 0xe8, 0x4f,
+        0xf7, 0x5e, 0xc6, 0xad, 0x15, 0xbd, 0x24, 0x8c, 0x73, 0xdb, 0x92, 0x7a, 0x64, 0x4e, 0x28,
+        0x02, 0xec, 0xd5, 0xbf, 0x99, 0x73, 0x5d, 0x47, 0x21, 0x0b, 0xe5, 0xde, 0xb8, 0x92, 0x7c,
+        0x56, 0x40, 0x2a, 0x04, 0xee, 0xd7, 0xb1, 0x9b, 0x75, 0x5f, 0x49, 0x23, 0xc1, 0x39, 0xb0,
+        0x2e, 0xa5, 0x1f, 0x96, 0x10, 0x8b, 0x01, 0x78, 0xf2, 0x6c, 0xe7, 0x5d, 0xd4, 0x52, 0xc9,
+        0x43, 0xba, 0x30, 0xaf, 0x25, 0x9c, 0x16, 0x8d, 0x0b, 0x82, 0xf8, 0x72, 0xed, 0x67,
+    ];
+
+    #[test]
+    fn pad_block_matches_baseline_vector() {
+        let input = input(254);
+        for (name, pad) in pad_impls() {
+            for (i, data) in input.chunks(NUM_BYTES_IN_BLOCK).enumerate() {
+                let mut padded = [0; NUM_BYTES_OUT_BLOCK];
+                pad(&block(data), &mut padded);
+                let expected = &BASELINE_PADDED[i * NUM_BYTES_OUT_BLOCK..][..NUM_BYTES_OUT_BLOCK];
+                assert_eq!(&padded[..], expected, "{} block {}", name, i);
+            }
+        }
+    }
+
+    #[test]
+    fn unpad_block_matches_baseline_vector() {
+        let input = input(256);
+        for (name, unpad) in unpad_impls() {
+            for (i, data) in input.chunks(NUM_BYTES_OUT_BLOCK).enumerate() {
+                let mut unpadded = [0; NUM_BYTES_OUT_BLOCK];
+                unpad(&block(data), &mut unpadded);
+                let expected = &BASELINE_UNPADDED[i * NUM_BYTES_IN_BLOCK..][..NUM_BYTES_IN_BLOCK];
+                assert_eq!(
+                    &unpadded[..NUM_BYTES_IN_BLOCK],
+                    expected,
+                    "{} block {}",
+                    name,
+                    i
+                );
+                assert_eq!(unpadded[NUM_BYTES_IN_BLOCK], 0, "{} block {}", name, i);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn pad_block_matches_baseline(data in vec(u8::ANY, 128)) {
+            // Padding is the inverse of the original unpadding that leaves the two highest bits of
+            // each `Fr32` zero, the last input byte is ignored.
+            for (name, pad) in pad_impls() {
+                let mut padded = [0; NUM_BYTES_OUT_BLOCK];
+                pad(&block(&data), &mut padded);
+                assert_eq!(&baseline_unpad(&padded)[..], &data[..127], "{}", name);
+                for fr in padded.chunks(32) {
+                    assert_eq!(fr[31] & 0b1100_0000, 0, "{}", name);
+                }
+            }
+        }
+
+        #[test]
+        fn pad_block_ignores_last_byte(data in vec(u8::ANY, 128)) {
+            let mut expected = [0; NUM_BYTES_OUT_BLOCK];
+            pad_block(&block(&data[..127]), &mut expected);
+            let mut padded = [0; NUM_BYTES_OUT_BLOCK];
+            pad_block(&block(&data), &mut padded);
+            assert_eq!(padded, expected);
+        }
+
+        #[test]
+        fn unpad_block_matches_baseline(data in vec(u8::ANY, 128)) {
+            let expected = baseline_unpad(&data);
+            for (name, unpad) in unpad_impls() {
+                let mut unpadded = [0; NUM_BYTES_OUT_BLOCK];
+                unpad(&block(&data), &mut unpadded);
+                assert_eq!(&unpadded[..127], &expected[..], "{}", name);
+                assert_eq!(unpadded[127], 0, "{}", name);
+            }
+        }
+
+        #[test]
+        fn pad_unpad_roundtrip(data in vec(u8::ANY, 127)) {
+            let mut padded = [0; NUM_BYTES_OUT_BLOCK];
+            pad_block(&block(&data), &mut padded);
+            let mut unpadded = [0; NUM_BYTES_OUT_BLOCK];
+            unpad_block(&padded, &mut unpadded);
+            assert_eq!(&unpadded[..127], &data[..]);
+            assert_eq!(unpadded[127], 0);
+        }
+    }
+}
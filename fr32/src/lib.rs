@@ -1,7 +1,10 @@
+mod block;
 mod convert;
 mod padding;
 mod reader;
+mod unpadding;
 
 pub use convert::*;
 pub use padding::*;
 pub use reader::*;
+pub use unpadding::*;
@@ -1,6 +1,12 @@
 use std::cmp::{min, Ordering};
+use std::convert::TryInto;
 use std::io::{self, Error, ErrorKind, Write};
 
+use crate::{
+    block::{unpad_block, Block},
+    reader::{NUM_BYTES_IN_BLOCK, NUM_BYTES_OUT_BLOCK},
+};
+
 /** PaddingMap represents a mapping between data and its padded equivalent.
 
 The padding process takes a *byte-aligned stream* of unpadded *raw* data
@@ -600,7 +606,36 @@ where
     let mut offset = offset;
     let mut len = len;
 
+    // Whole blocks from a block boundary on are unpadded with the block transform.
+    if offset % NUM_BYTES_IN_BLOCK == 0 {
+        let start = offset / NUM_BYTES_IN_BLOCK * NUM_BYTES_OUT_BLOCK;
+        let num_blocks = min(
+            len / NUM_BYTES_IN_BLOCK,
+            (source.len() - start) / NUM_BYTES_OUT_BLOCK,
+        );
+        let end = start + num_blocks * NUM_BYTES_OUT_BLOCK;
+
+        let mut raw_data = Vec::with_capacity(min(num_blocks, n) * NUM_BYTES_IN_BLOCK);
+        let mut unpadded = [0; NUM_BYTES_OUT_BLOCK];
+        for chunk in source[start..end].chunks(chunk_size) {
+            for block in chunk.chunks_exact(NUM_BYTES_OUT_BLOCK) {
+                let block: &Block = block.try_into().expect("blocks are 128 bytes");
+                unpad_block(block, &mut unpadded);
+                raw_data.extend_from_slice(&unpadded[..NUM_BYTES_IN_BLOCK]);
+            }
+            target.write_all(&raw_data)?;
+            raw_data.clear();
+        }
+
+        written += num_blocks * NUM_BYTES_IN_BLOCK;
+        offset += num_blocks * NUM_BYTES_IN_BLOCK;
+        len -= num_blocks * NUM_BYTES_IN_BLOCK;
+    }
+
     for chunk in source.chunks(chunk_size) {
+        if len == 0 {
+            break;
+        }
         let write_len = min(len, chunk.len());
 
         written += write_unpadded_aux(&FR32_PADDING_MAP, source, target, offset, write_len)?;
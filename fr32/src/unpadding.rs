@@ -0,0 +1,365 @@
+use std::cmp::min;
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+use crate::{
+    block::{unpad_block, Block},
+    reader::{IN_BITS_FR, NUM_BYTES_IN_BLOCK, NUM_BYTES_OUT_BLOCK, OUT_BITS_FR},
+};
+
+/// The amount of unpadded bytes that are collected before they are written to the target.
+const WRITE_BUFFER_SIZE: usize = 1 << 20;
+
+/// Returns the number of whole unpadded bytes within the first `padded_len` bytes of a block.
+fn unpadded_block_len(padded_len: usize) -> usize {
+    let fr_bytes = OUT_BITS_FR / 8;
+    let bits = padded_len / fr_bytes * IN_BITS_FR + min(padded_len % fr_bytes * 8, IN_BITS_FR);
+    bits / 8
+}
+
+/// An `io::Write` that converts `Fr32` padded input into unpadded output.
+///
+/// The input must start at an `Fr32` boundary, but may be split into writes of any size. Bytes
+/// are only written to the target once a block of four `Fr32`s is complete, call
+/// [`Fr32Writer::finish`] to write the unpadded bytes of an incomplete last block. Trailing bits
+/// that don't form a whole byte are dropped.
+pub struct Fr32Writer<W: Write> {
+    /// The target of the unpadded bytes.
+    target: W,
+    /// The currently written, incomplete block.
+    block: Block,
+    /// The number of bytes in `block`.
+    block_len: usize,
+    /// The range of the unpadded stream that is written to the target.
+    range: Range<u64>,
+    /// The current position within the unpadded stream.
+    position: u64,
+    /// Unpadded bytes that are not yet written to the target.
+    out_buffer: Vec<u8>,
+}
+
+impl<W: Write> Fr32Writer<W> {
+    pub fn new(target: W) -> Self {
+        Self::with_range(target, 0, u64::MAX)
+    }
+
+    /// Creates a writer that only writes `len` unpadded bytes, starting at the unpadded byte
+    /// `offset`, to the target. All other bytes are dropped. This is the streaming equivalent of
+    /// [`crate::write_unpadded`].
+    pub fn with_range(target: W, offset: u64, len: u64) -> Self {
+        Fr32Writer {
+            target,
+            block: [0; NUM_BYTES_OUT_BLOCK],
+            block_len: 0,
+            range: offset..offset.saturating_add(len),
+            position: 0,
+            out_buffer: Vec::new(),
+        }
+    }
+
+    /// Adds unpadded bytes to the output buffer, as far as they are within the range.
+    fn push_unpadded(&mut self, unpadded: &[u8]) {
+        let start = self.position;
+        let end = start + unpadded.len() as u64;
+        self.position = end;
+
+        let from = self.range.start.clamp(start, end);
+        let to = self.range.end.clamp(start, end);
+        self.out_buffer
+            .extend_from_slice(&unpadded[(from - start) as usize..(to - start) as usize]);
+    }
+
+    fn write_out_buffer(&mut self) -> io::Result<()> {
+        self.target.write_all(&self.out_buffer)?;
+        self.out_buffer.clear();
+        Ok(())
+    }
+
+    /// Writes the unpadded bytes of the incomplete last block and returns the target.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.block_len > 0 {
+            self.block[self.block_len..].fill(0);
+            let mut unpadded = [0; NUM_BYTES_OUT_BLOCK];
+            unpad_block(&self.block, &mut unpadded);
+            self.push_unpadded(&unpadded[..unpadded_block_len(self.block_len)]);
+            self.block_len = 0;
+        }
+        self.write_out_buffer()?;
+        self.target.flush()?;
+
+        Ok(self.target)
+    }
+}
+
+impl<W: Write> Write for Fr32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut unpadded = [0; NUM_BYTES_OUT_BLOCK];
+        let mut input = buf;
+
+        // Complete the block of the previous write first.
+        if self.block_len > 0 {
+            let len = min(NUM_BYTES_OUT_BLOCK - self.block_len, input.len());
+            self.block[self.block_len..self.block_len + len].copy_from_slice(&input[..len]);
+            self.block_len += len;
+            input = &input[len..];
+
+            if self.block_len == NUM_BYTES_OUT_BLOCK {
+                unpad_block(&self.block, &mut unpadded);
+                self.push_unpadded(&unpadded[..NUM_BYTES_IN_BLOCK]);
+                self.block_len = 0;
+            }
+        }
+
+        let mut blocks = input.chunks_exact(NUM_BYTES_OUT_BLOCK);
+        for block in &mut blocks {
+            let block: &Block = block.try_into().expect("blocks are 128 bytes");
+            unpad_block(block, &mut unpadded);
+            self.push_unpadded(&unpadded[..NUM_BYTES_IN_BLOCK]);
+            if self.out_buffer.len() >= WRITE_BUFFER_SIZE {
+                self.write_out_buffer()?;
+            }
+        }
+
+        let remainder = blocks.remainder();
+        self.block[self.block_len..self.block_len + remainder.len()].copy_from_slice(remainder);
+        self.block_len += remainder.len();
+
+        self.write_out_buffer()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_out_buffer()?;
+        self.target.flush()
+    }
+}
+
+/// An `io::Reader` that converts `Fr32` padded input into unpadded output.
+///
+/// The source must start at an `Fr32` boundary. Trailing bits that don't form a whole byte are
+/// dropped.
+pub struct Fr32UnpadReader<R> {
+    /// The source being unpadded.
+    source: R,
+    /// Currently read block.
+    in_buffer: Block,
+    /// Currently unpadded block.
+    out_buffer: Block,
+    /// The current offset into the `out_buffer`.
+    out_offset: usize,
+    /// The number of valid bytes in the `out_buffer`.
+    out_len: usize,
+    /// Are we done reading?
+    done: bool,
+}
+
+impl<R: Read> Fr32UnpadReader<R> {
+    pub fn new(source: R) -> Self {
+        Fr32UnpadReader {
+            source,
+            in_buffer: [0; NUM_BYTES_OUT_BLOCK],
+            out_buffer: [0; NUM_BYTES_OUT_BLOCK],
+            out_offset: 0,
+            out_len: 0,
+            done: false,
+        }
+    }
+
+    fn fill_in_buffer(&mut self) -> io::Result<usize> {
+        let mut bytes_read = 0;
+        let mut buf = &mut self.in_buffer[..];
+
+        while !buf.is_empty() {
+            match self.source.read(buf) {
+                Ok(0) => {
+                    break;
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    bytes_read += n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // Clear unfilled memory.
+        self.in_buffer[bytes_read..].fill(0);
+
+        Ok(bytes_read)
+    }
+}
+
+impl<R: Read> Read for Fr32UnpadReader<R> {
+    fn read(&mut self, target: &mut [u8]) -> io::Result<usize> {
+        let mut bytes_read = 0;
+
+        while bytes_read < target.len() {
+            // Load and unpad the next block, once the current one is used up.
+            if self.out_offset == self.out_len {
+                if self.done {
+                    break;
+                }
+
+                let padded_len = self.fill_in_buffer()?;
+                // A short read means that the source is exhausted.
+                self.done = padded_len < NUM_BYTES_OUT_BLOCK;
+                unpad_block(&self.in_buffer, &mut self.out_buffer);
+                self.out_offset = 0;
+                self.out_len = unpadded_block_len(padded_len);
+                continue;
+            }
+
+            let len = min(self.out_len - self.out_offset, target.len() - bytes_read);
+            target[bytes_read..bytes_read + len]
+                .copy_from_slice(&self.out_buffer[self.out_offset..self.out_offset + len]);
+            bytes_read += len;
+            self.out_offset += len;
+        }
+
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use pretty_assertions::assert_eq;
+    use rand::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    use crate::{write_unpadded, Fr32Reader};
+
+    const TEST_SEED: [u8; 16] = [
+        0x59, 0x62, 0xbe, 0x5d, 0x76, 0x3d, 0x31, 0x8d, 0x17, 0xdb, 0x37, 0x32, 0x54, 0x06, 0xbc,
+        0xe5,
+    ];
+
+    fn padded(data: &[u8]) -> Vec<u8> {
+        let mut padded = Vec::new();
+        Fr32Reader::new(Cursor::new(data))
+            .read_to_end(&mut padded)
+            .expect("in-memory read failed");
+        padded
+    }
+
+    /// Writes `padded` in chunks of the given sizes, repeating them until all is written.
+    fn write_in_chunks(
+        writer: &mut Fr32Writer<&mut Vec<u8>>,
+        padded: &[u8],
+        chunk_sizes: &[usize],
+    ) {
+        let mut padded = padded;
+        for chunk_size in chunk_sizes.iter().cycle() {
+            if padded.is_empty() {
+                break;
+            }
+            let len = min(*chunk_size, padded.len());
+            writer
+                .write_all(&padded[..len])
+                .expect("in-memory write failed");
+            padded = &padded[len..];
+        }
+    }
+
+    #[test]
+    fn test_writer_roundtrip() {
+        let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+
+        for len in [1, 30, 31, 32, 33, 126, 127, 128, 254, 1000, 127 * 64] {
+            let mut data = vec![0u8; len];
+            rng.fill_bytes(&mut data);
+            let padded = padded(&data);
+
+            for chunk_sizes in [&[1][..], &[7, 31, 33], &[127, 129, 300], &[4096]] {
+                let mut unpadded = Vec::new();
+                let mut writer = Fr32Writer::with_range(&mut unpadded, 0, len as u64);
+                write_in_chunks(&mut writer, &padded, chunk_sizes);
+                writer.finish().expect("in-memory write failed");
+
+                assert_eq!(unpadded, data, "len {} chunks {:?}", len, chunk_sizes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_writer_whole_blocks() {
+        let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+        let mut data = vec![0u8; 127 * 8];
+        rng.fill_bytes(&mut data);
+
+        let mut unpadded = Vec::new();
+        let mut writer = Fr32Writer::new(&mut unpadded);
+        writer
+            .write_all(&padded(&data))
+            .expect("in-memory write failed");
+        // Whole blocks are written without calling `finish`.
+        drop(writer);
+
+        assert_eq!(unpadded, data);
+    }
+
+    #[test]
+    fn test_writer_range() {
+        let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+        let mut data = vec![0u8; 1000];
+        rng.fill_bytes(&mut data);
+        let padded = padded(&data);
+
+        for (offset, len) in [(0, 1000), (1, 1), (3, 4), (31, 64), (127, 128), (500, 499)] {
+            let mut expected = Vec::new();
+            write_unpadded(&padded, &mut expected, offset, len).expect("in-memory write failed");
+
+            let mut unpadded = Vec::new();
+            let mut writer = Fr32Writer::with_range(&mut unpadded, offset as u64, len as u64);
+            write_in_chunks(&mut writer, &padded, &[13, 100]);
+            writer.finish().expect("in-memory write failed");
+
+            assert_eq!(unpadded, expected, "offset {} len {}", offset, len);
+            assert_eq!(unpadded, &data[offset..offset + len]);
+        }
+    }
+
+    #[test]
+    fn test_unpad_reader_roundtrip() {
+        let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+
+        for len in [1, 30, 31, 32, 33, 126, 127, 128, 254, 1000, 127 * 64] {
+            let mut data = vec![0u8; len];
+            rng.fill_bytes(&mut data);
+            let padded = padded(&data);
+
+            for buf_size in [1, 7, 127, 4096] {
+                let mut reader = Fr32UnpadReader::new(Cursor::new(&padded)).take(len as u64);
+                let mut unpadded = Vec::new();
+                let mut buf = vec![0u8; buf_size];
+                loop {
+                    let n = reader.read(&mut buf).expect("in-memory read failed");
+                    if n == 0 {
+                        break;
+                    }
+                    unpadded.extend_from_slice(&buf[..n]);
+                }
+
+                assert_eq!(unpadded, data, "len {} buf_size {}", len, buf_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpadded_length() {
+        // Without a limit all whole bytes are returned, the extra ones are zero.
+        let data = vec![255u8; 30];
+        let mut unpadded = Vec::new();
+        Fr32UnpadReader::new(Cursor::new(padded(&data)))
+            .read_to_end(&mut unpadded)
+            .expect("in-memory read failed");
+        assert_eq!(unpadded.len(), 31);
+        assert_eq!(&unpadded[..30], &data[..]);
+        assert_eq!(unpadded[30], 0);
+    }
+}
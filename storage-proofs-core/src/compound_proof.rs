@@ -24,14 +24,16 @@ use crate::{
     multi_proof::MultiProof,
     parameter_cache::{Bls12GrothParams, CacheableParameters, ParameterSetMetadata},
     partitions::partition_count,
+    progress::{Progress, SealContext},
     proof::ProofScheme,
+    settings::ProofsContext,
 };
 
 /// The maximum number of Groth16 proofs that will be processed in parallel. This limit is set as
 /// synthesis takes a lot of memory. The current value is based on the number of proofs that are
 /// run in parallel in the interactive PoRep (the number of partitions). This way there's just a
 /// single batch for the interactive PoRep, but the non-interactive PoRep is split into batches.
-const MAX_GROTH16_BATCH_SIZE: usize = 10;
+pub const MAX_GROTH16_BATCH_SIZE: usize = 10;
 
 #[derive(Clone)]
 pub struct SetupParams<'a, S: ProofScheme<'a>> {
@@ -243,17 +245,41 @@ where
         pub_params: &S::PublicParams,
         groth_params: &Bls12GrothParams,
         priority: bool,
+    ) -> Result<Vec<groth16::Proof<Bls12>>> {
+        Self::circuit_proofs_with_context(
+            pub_in,
+            vanilla_proofs,
+            pub_params,
+            groth_params,
+            priority,
+            &SealContext::default(),
+        )
+    }
+
+    /// Like `circuit_proofs`, but reports the proven partitions to `ctx` and stops with
+    /// `Error::Cancelled` between batches of partitions if the cancellation was requested.
+    fn circuit_proofs_with_context(
+        pub_in: &S::PublicInputs,
+        vanilla_proofs: Vec<S::Proof>,
+        pub_params: &S::PublicParams,
+        groth_params: &Bls12GrothParams,
+        priority: bool,
+        ctx: &SealContext,
     ) -> Result<Vec<groth16::Proof<Bls12>>> {
         let mut rng = OsRng;
         ensure!(
             !vanilla_proofs.is_empty(),
             "cannot create a circuit proof over missing vanilla proofs"
         );
+        ctx.check_cancelled()?;
 
+        // The circuits read the settings when they're created, not when bellperson synthesizes them.
+        let context = ProofsContext::current();
         let mut circuits = vanilla_proofs
             .into_par_iter()
             .enumerate()
             .map(|(k, vanilla_proof)| {
+                let _context = context.enter();
                 Self::circuit(
                     pub_in,
                     C::ComponentPrivateInputs::default(),
@@ -272,14 +298,20 @@ where
             create_random_proof_batch
         };
 
-        let mut groth_proofs = Vec::with_capacity(circuits.len());
+        let partitions = circuits.len();
+        let mut groth_proofs = Vec::with_capacity(partitions);
         // Bellperson expects a vector of proofs, hence drain it from the list of proofs, so that
         // we don't need to keep an extra copy around.
         while !circuits.is_empty() {
+            ctx.check_cancelled()?;
             let size = cmp::min(MAX_GROTH16_BATCH_SIZE, circuits.len());
             let batch = circuits.drain(0..size).collect();
             let proofs = create_random_proof_batch_fun(batch, groth_params, &mut rng)?;
             groth_proofs.extend_from_slice(&proofs);
+            ctx.report(Progress::PartitionsProven {
+                proven: groth_proofs.len(),
+                partitions,
+            });
         }
 
         groth_proofs
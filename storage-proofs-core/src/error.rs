@@ -41,6 +41,8 @@ pub enum Error {
     FaultySectors(Vec<SectorId>),
     #[error("Invalid parameters file: {}", _0)]
     InvalidParameters(String),
+    #[error("operation was cancelled")]
+    Cancelled,
 }
 
 impl From<Box<dyn Any + Send>> for Error {
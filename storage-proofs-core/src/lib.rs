@@ -21,14 +21,18 @@ pub mod error;
 pub mod gadgets;
 pub mod measurements;
 pub mod merkle;
+pub mod metrics;
 pub mod multi_proof;
 pub mod parameter_cache;
 pub mod partitions;
 pub mod pieces;
 pub mod por;
+pub mod progress;
 pub mod proof;
 pub mod sector;
 pub mod settings;
+pub mod spans;
+pub mod storage;
 pub mod test_helper;
 pub mod util;
 
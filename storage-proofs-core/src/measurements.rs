@@ -9,6 +9,8 @@ use std::time::Duration;
 use lazy_static::lazy_static;
 use serde::Serialize;
 
+pub use crate::metrics::OpDetails;
+
 #[cfg(feature = "measurements")]
 lazy_static! {
     pub static ref OP_MEASUREMENTS: (
@@ -29,8 +31,11 @@ pub struct OpMeasurement {
     pub wall_time: Duration,
 }
 
-#[derive(Debug, Serialize)]
+/// An operation whose time is measured. Operations are added as more of the API is measured, so
+/// matches on it need a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 #[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
 pub enum Operation {
     AddPiece,
     GeneratePieceCommitment,
@@ -44,12 +49,71 @@ pub enum Operation {
     PostFinalizeTicket,
     PostReadChallengedRange,
     PostPartialTicketHash,
+    SealPreCommitPhase1,
+    SealPreCommitPhase2,
+    SealCommitPhase1,
+    SealCommitPhase2,
+    GenerateWinningPost,
+    GenerateWindowPost,
+    EncodeInto,
+    GenerateEmptySectorUpdateProof,
 }
 
-#[cfg(feature = "measurements")]
+impl Operation {
+    /// Returns the name of the operation in snake case, as it's used in metric labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::AddPiece => "add_piece",
+            Operation::GeneratePieceCommitment => "generate_piece_commitment",
+            Operation::GenerateTreeC => "generate_tree_c",
+            Operation::GenerateTreeRLast => "generate_tree_r_last",
+            Operation::CommD => "comm_d",
+            Operation::EncodeWindowTimeAll => "encode_window_time_all",
+            Operation::WindowCommLeavesTime => "window_comm_leaves_time",
+            Operation::PorepCommitTime => "porep_commit_time",
+            Operation::PostInclusionProofs => "post_inclusion_proofs",
+            Operation::PostFinalizeTicket => "post_finalize_ticket",
+            Operation::PostReadChallengedRange => "post_read_challenged_range",
+            Operation::PostPartialTicketHash => "post_partial_ticket_hash",
+            Operation::SealPreCommitPhase1 => "seal_pre_commit_phase1",
+            Operation::SealPreCommitPhase2 => "seal_pre_commit_phase2",
+            Operation::SealCommitPhase1 => "seal_commit_phase1",
+            Operation::SealCommitPhase2 => "seal_commit_phase2",
+            Operation::GenerateWinningPost => "generate_winning_post",
+            Operation::GenerateWindowPost => "generate_window_post",
+            Operation::EncodeInto => "encode_into",
+            Operation::GenerateEmptySectorUpdateProof => "generate_empty_sector_update_proof",
+        }
+    }
+}
+
+#[cfg(any(feature = "measurements", feature = "metrics"))]
 pub fn measure_op<T, F>(op: Operation, f: F) -> T
 where
     F: FnOnce() -> T,
+{
+    measure(op, OpDetails::default(), f, |_| true)
+}
+
+/// Like [`measure_op`] for an operation that may fail, with the sector size and the number of
+/// bytes that are recorded in the [`crate::metrics`]. An `Err` is recorded as a failure.
+#[cfg(any(feature = "measurements", feature = "metrics"))]
+pub fn measure_op_with<T, E, F>(
+    op: Operation,
+    details: OpDetails,
+    f: F,
+) -> std::result::Result<T, E>
+where
+    F: FnOnce() -> std::result::Result<T, E>,
+{
+    measure(op, details, f, std::result::Result::is_ok)
+}
+
+#[cfg(any(feature = "measurements", feature = "metrics"))]
+fn measure<T, F, S>(op: Operation, details: OpDetails, f: F, success: S) -> T
+where
+    F: FnOnce() -> T,
+    S: FnOnce(&T) -> bool,
 {
     use std::time::Instant;
 
@@ -70,28 +134,53 @@ where
         .stop()
         .unwrap();
 
-    let opt_tx = OP_MEASUREMENTS
-        .0
-        .lock()
-        .expect("acquire lock on tx side of perf channel");
-
-    if let Some(tx) = opt_tx.as_ref() {
-        tx.clone()
-            .send(OpMeasurement {
-                op,
-                cpu_time: cpu_time_start.elapsed(),
-                wall_time: wall_start_time.elapsed(),
-            })
-            .expect("failed to send to perf channel");
+    let cpu_time = cpu_time_start.elapsed();
+    let wall_time = wall_start_time.elapsed();
+
+    #[cfg(feature = "measurements")]
+    {
+        let opt_tx = OP_MEASUREMENTS
+            .0
+            .lock()
+            .expect("acquire lock on tx side of perf channel");
+
+        if let Some(tx) = opt_tx.as_ref() {
+            tx.clone()
+                .send(OpMeasurement {
+                    op,
+                    cpu_time,
+                    wall_time,
+                })
+                .expect("failed to send to perf channel");
+        }
     }
 
+    #[cfg(feature = "metrics")]
+    crate::metrics::record(&crate::metrics::OpSample {
+        op,
+        details,
+        cpu_time,
+        wall_time,
+        success: success(&x),
+    });
+    #[cfg(not(feature = "metrics"))]
+    let _ = (details, success);
+
     x
 }
 
-#[cfg(not(feature = "measurements"))]
+#[cfg(not(any(feature = "measurements", feature = "metrics")))]
 pub fn measure_op<T, F>(_: Operation, f: F) -> T
 where
     F: FnOnce() -> T,
 {
     f()
 }
+
+#[cfg(not(any(feature = "measurements", feature = "metrics")))]
+pub fn measure_op_with<T, E, F>(_: Operation, _: OpDetails, f: F) -> std::result::Result<T, E>
+where
+    F: FnOnce() -> std::result::Result<T, E>,
+{
+    f()
+}
@@ -0,0 +1,427 @@
+//! Aggregated metrics of the proof operations, rendered in the Prometheus text format.
+//!
+//! With the `metrics` feature, every [`Operation`] that is timed through
+//! [`crate::measurements::measure_op`] or an [`OpTimer`] is recorded in the [`global`] registry
+//! and forwarded to the sink that was installed with [`set_sink`]. Without the feature, nothing
+//! is recorded and the registry stays empty.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::measurements::Operation;
+
+/// Upper bounds of the latency histogram buckets, in seconds. They span from the fast PoSt
+/// operations up to PC1 of the largest sectors.
+pub const LATENCY_BUCKETS: [f64; 13] = [
+    0.01, 0.1, 1.0, 10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0,
+];
+
+const METRIC_PREFIX: &str = "filecoin_proofs";
+
+lazy_static! {
+    static ref GLOBAL: Metrics = Metrics::new();
+    static ref SINK: RwLock<Option<Arc<dyn MetricsSink>>> = RwLock::new(None);
+}
+
+/// Additional labels and counters of a single operation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OpDetails {
+    /// The sector size in bytes, it's used as the `sector_size` label.
+    pub sector_size: Option<u64>,
+    /// The number of bytes that were processed by the operation.
+    pub bytes: Option<u64>,
+}
+
+impl OpDetails {
+    pub fn sector_size(sector_size: u64) -> Self {
+        OpDetails {
+            sector_size: Some(sector_size),
+            bytes: None,
+        }
+    }
+
+    pub fn with_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+}
+
+/// A single finished operation.
+#[derive(Debug, Clone)]
+pub struct OpSample {
+    pub op: Operation,
+    pub details: OpDetails,
+    pub cpu_time: Duration,
+    pub wall_time: Duration,
+    /// Whether the operation returned successfully.
+    pub success: bool,
+}
+
+/// Receives every recorded [`OpSample`], e.g. to forward it to another metrics system.
+pub trait MetricsSink: Send + Sync {
+    fn record(&self, sample: &OpSample);
+}
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    /// Non-cumulative counts per bucket of [`LATENCY_BUCKETS`], the last one is `+Inf`.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        let index = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[index] += 1;
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct OpStats {
+    count: u64,
+    failures: u64,
+    bytes: u64,
+    wall_time: Histogram,
+    cpu_time: Histogram,
+}
+
+/// A registry of per operation and sector size counters and latency histograms.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    stats: Mutex<BTreeMap<(Operation, Option<u64>), OpStats>>,
+}
+
+impl MetricsSink for Metrics {
+    fn record(&self, sample: &OpSample) {
+        let mut stats = self.stats.lock().expect("metrics lock poisoned");
+        let stats = stats
+            .entry((sample.op, sample.details.sector_size))
+            .or_default();
+        stats.count += 1;
+        if sample.success {
+            stats.bytes += sample.details.bytes.unwrap_or(0);
+        } else {
+            stats.failures += 1;
+        }
+        stats.wall_time.observe(sample.wall_time);
+        stats.cpu_time.observe(sample.cpu_time);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes all recorded samples.
+    pub fn reset(&self) {
+        self.stats.lock().expect("metrics lock poisoned").clear();
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let stats = self.stats.lock().expect("metrics lock poisoned");
+        let mut out = String::new();
+
+        let counters: [(&str, &str, fn(&OpStats) -> u64); 3] = [
+            (
+                "operations_total",
+                "Number of finished operations.",
+                |stats| stats.count,
+            ),
+            (
+                "operation_failures_total",
+                "Number of operations that returned an error.",
+                |stats| stats.failures,
+            ),
+            (
+                "operation_bytes_total",
+                "Number of bytes processed by the successful operations.",
+                |stats| stats.bytes,
+            ),
+        ];
+        for (name, help, value) in counters.iter() {
+            write_header(&mut out, name, help, "counter");
+            for ((op, sector_size), stats) in stats.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}_{}{{{}}} {}",
+                    METRIC_PREFIX,
+                    name,
+                    labels(*op, *sector_size),
+                    value(stats)
+                );
+            }
+        }
+
+        let histograms: [(&str, &str, fn(&OpStats) -> &Histogram); 2] = [
+            (
+                "operation_wall_seconds",
+                "Wall clock time of the operations.",
+                |stats| &stats.wall_time,
+            ),
+            (
+                "operation_cpu_seconds",
+                "CPU time of the process during the operations.",
+                |stats| &stats.cpu_time,
+            ),
+        ];
+        for (name, help, histogram) in histograms.iter() {
+            write_header(&mut out, name, help, "histogram");
+            for ((op, sector_size), stats) in stats.iter() {
+                let labels = labels(*op, *sector_size);
+                let histogram = histogram(stats);
+                let mut cumulative = 0;
+                for (i, count) in histogram.buckets.iter().enumerate() {
+                    cumulative += count;
+                    let le = LATENCY_BUCKETS
+                        .get(i)
+                        .map(|bound| bound.to_string())
+                        .unwrap_or_else(|| "+Inf".to_string());
+                    let _ = writeln!(
+                        out,
+                        "{}_{}_bucket{{{},le=\"{}\"}} {}",
+                        METRIC_PREFIX, name, labels, le, cumulative
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_{}_sum{{{}}} {}",
+                    METRIC_PREFIX, name, labels, histogram.sum
+                );
+                let _ = writeln!(
+                    out,
+                    "{}_{}_count{{{}}} {}",
+                    METRIC_PREFIX, name, labels, histogram.count
+                );
+            }
+        }
+
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", METRIC_PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", METRIC_PREFIX, name, kind);
+}
+
+fn labels(op: Operation, sector_size: Option<u64>) -> String {
+    match sector_size {
+        Some(sector_size) => format!("op=\"{}\",sector_size=\"{}\"", op.name(), sector_size),
+        None => format!("op=\"{}\"", op.name()),
+    }
+}
+
+/// The process wide registry that all operations are recorded in.
+pub fn global() -> &'static Metrics {
+    &GLOBAL
+}
+
+/// Installs a sink that receives every recorded sample in addition to the [`global`] registry,
+/// `None` removes it.
+pub fn set_sink(sink: Option<Arc<dyn MetricsSink>>) {
+    *SINK.write().expect("metrics sink lock poisoned") = sink;
+}
+
+/// Renders the [`global`] registry in the Prometheus text exposition format.
+pub fn render_prometheus() -> String {
+    global().render_prometheus()
+}
+
+/// Records a sample in the [`global`] registry and the installed sink.
+pub fn record(sample: &OpSample) {
+    global().record(sample);
+    if let Some(sink) = SINK.read().expect("metrics sink lock poisoned").as_ref() {
+        sink.record(sample);
+    }
+}
+
+/// Times an operation that may fail. It's recorded as successful on [`OpTimer::finish`] and as
+/// failed when the timer is dropped without finishing, e.g. when an error is returned early.
+#[must_use]
+pub struct OpTimer {
+    #[cfg(feature = "metrics")]
+    inner: Option<TimerState>,
+}
+
+#[cfg(feature = "metrics")]
+struct TimerState {
+    op: Operation,
+    details: OpDetails,
+    cpu_time_start: cpu_time::ProcessTime,
+    wall_time_start: std::time::Instant,
+}
+
+impl OpTimer {
+    #[cfg(feature = "metrics")]
+    pub fn start(op: Operation, details: OpDetails) -> Self {
+        OpTimer {
+            inner: Some(TimerState {
+                op,
+                details,
+                cpu_time_start: cpu_time::ProcessTime::now(),
+                wall_time_start: std::time::Instant::now(),
+            }),
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    pub fn start(_op: Operation, _details: OpDetails) -> Self {
+        OpTimer {}
+    }
+
+    /// Records the operation as successful.
+    pub fn finish(mut self) {
+        self.record(true);
+    }
+
+    #[cfg(feature = "metrics")]
+    fn record(&mut self, success: bool) {
+        if let Some(state) = self.inner.take() {
+            record(&OpSample {
+                op: state.op,
+                details: state.details,
+                cpu_time: state.cpu_time_start.elapsed(),
+                wall_time: state.wall_time_start.elapsed(),
+                success,
+            });
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn record(&mut self, _success: bool) {}
+}
+
+impl Drop for OpTimer {
+    fn drop(&mut self) {
+        self.record(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = Metrics::new();
+        let sample = |op, sector_size, secs, success| OpSample {
+            op,
+            details: OpDetails {
+                sector_size,
+                bytes: Some(2048),
+            },
+            cpu_time: Duration::from_secs(secs * 2),
+            wall_time: Duration::from_secs(secs),
+            success,
+        };
+        metrics.record(&sample(Operation::SealPreCommitPhase1, Some(2048), 5, true));
+        metrics.record(&sample(
+            Operation::SealPreCommitPhase1,
+            Some(2048),
+            50,
+            false,
+        ));
+        metrics.record(&sample(Operation::AddPiece, None, 0, true));
+
+        let out = metrics.render_prometheus();
+        let lines: Vec<&str> = out.lines().collect();
+        let pc1 = "op=\"seal_pre_commit_phase1\",sector_size=\"2048\"";
+
+        for line in &[
+            "# TYPE filecoin_proofs_operations_total counter".to_string(),
+            format!("filecoin_proofs_operations_total{{{}}} 2", pc1),
+            format!("filecoin_proofs_operation_failures_total{{{}}} 1", pc1),
+            // Only the bytes of the successful operation are counted.
+            format!("filecoin_proofs_operation_bytes_total{{{}}} 2048", pc1),
+            "filecoin_proofs_operations_total{op=\"add_piece\"} 1".to_string(),
+            "# TYPE filecoin_proofs_operation_wall_seconds histogram".to_string(),
+            format!(
+                "filecoin_proofs_operation_wall_seconds_bucket{{{},le=\"1\"}} 0",
+                pc1
+            ),
+            format!(
+                "filecoin_proofs_operation_wall_seconds_bucket{{{},le=\"10\"}} 1",
+                pc1
+            ),
+            format!(
+                "filecoin_proofs_operation_wall_seconds_bucket{{{},le=\"60\"}} 2",
+                pc1
+            ),
+            format!(
+                "filecoin_proofs_operation_wall_seconds_bucket{{{},le=\"+Inf\"}} 2",
+                pc1
+            ),
+            format!("filecoin_proofs_operation_wall_seconds_sum{{{}}} 55", pc1),
+            format!("filecoin_proofs_operation_cpu_seconds_sum{{{}}} 110", pc1),
+            format!("filecoin_proofs_operation_cpu_seconds_count{{{}}} 2", pc1),
+        ] {
+            assert!(
+                lines.contains(&line.as_str()),
+                "missing {:?} in\n{}",
+                line,
+                out
+            );
+        }
+
+        metrics.reset();
+        assert!(!metrics.render_prometheus().contains("add_piece"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_op_timer_sink() {
+        use crate::measurements::measure_op_with;
+
+        #[derive(Default)]
+        struct Collect(Mutex<Vec<(Operation, bool)>>);
+
+        impl MetricsSink for Collect {
+            fn record(&self, sample: &OpSample) {
+                // Other tests may record operations concurrently.
+                if sample.op == Operation::EncodeInto {
+                    self.0
+                        .lock()
+                        .expect("lock poisoned")
+                        .push((sample.op, sample.success));
+                }
+            }
+        }
+
+        let sink = Arc::new(Collect::default());
+        set_sink(Some(sink.clone()));
+        OpTimer::start(Operation::EncodeInto, OpDetails::sector_size(1)).finish();
+        drop(OpTimer::start(
+            Operation::EncodeInto,
+            OpDetails::sector_size(1),
+        ));
+        let ok: Result<(), ()> =
+            measure_op_with(Operation::EncodeInto, OpDetails::sector_size(1), || Ok(()));
+        let err: Result<(), ()> =
+            measure_op_with(Operation::EncodeInto, OpDetails::sector_size(1), || Err(()));
+        assert!(ok.is_ok() && err.is_err());
+        set_sink(None);
+
+        assert_eq!(
+            *sink.0.lock().expect("lock poisoned"),
+            vec![
+                (Operation::EncodeInto, true),
+                (Operation::EncodeInto, false),
+                (Operation::EncodeInto, true),
+                (Operation::EncodeInto, false),
+            ]
+        );
+    }
+}
@@ -20,7 +20,7 @@ use sha2::{Digest, Sha256};
 
 use crate::{
     error::{Error, Result},
-    settings::SETTINGS,
+    settings,
 };
 
 /// Bump this when circuits change to invalidate the cache.
@@ -160,7 +160,7 @@ impl Drop for LockedFile {
 }
 
 pub fn parameter_cache_dir_name() -> String {
-    SETTINGS.parameter_cache.clone()
+    settings::current().parameter_cache.clone()
 }
 
 pub fn parameter_cache_dir() -> PathBuf {
@@ -459,7 +459,7 @@ pub fn verify_production_entry(
 pub fn read_cached_params(cache_entry_path: &Path) -> Result<Bls12GrothParams> {
     info!("checking cache_path: {:?} for parameters", cache_entry_path);
 
-    let verify_production_params = SETTINGS.verify_production_params;
+    let verify_production_params = settings::current().verify_production_params;
     info!(
         "Verify production parameters is {}",
         verify_production_params
@@ -515,7 +515,7 @@ fn read_cached_verifying_key(cache_entry_path: &Path) -> Result<groth16::Verifyi
         cache_entry_path
     );
 
-    let verify_production_params = SETTINGS.verify_production_params;
+    let verify_production_params = settings::current().verify_production_params;
     info!(
         "Verify production parameters is {}",
         verify_production_params
@@ -549,7 +549,7 @@ fn read_cached_verifying_key(cache_entry_path: &Path) -> Result<groth16::Verifyi
 fn read_cached_srs_key(cache_entry_path: &Path) -> Result<groth16::aggregate::GenericSRS<Bls12>> {
     info!("checking cache_path: {:?} for srs", cache_entry_path);
 
-    let verify_production_params = SETTINGS.verify_production_params;
+    let verify_production_params = settings::current().verify_production_params;
     info!(
         "Verify production parameters is {}",
         verify_production_params
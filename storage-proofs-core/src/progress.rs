@@ -0,0 +1,209 @@
+//! Progress reporting and cooperative cancellation of long running operations.
+//!
+//! Sealing a sector takes hours. A [`SealContext`] is handed to the sealing phases, it's used to
+//! report how far an operation got and to ask it to stop early. Cancellation is cooperative, the
+//! token is only checked at points where stopping leaves the cache directory in a consistent
+//! state. A cancelled operation fails with [`Error::Cancelled`].
+
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+use crate::settings::{ProofsContext, ProofsContextGuard};
+
+/// The trees that are built while sealing or encoding a sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TreeKind {
+    TreeD,
+    TreeC,
+    TreeRLast,
+}
+
+/// A progress report of a sealing phase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// The labels of `layer` (starting at 1) out of `layers` are on disk. It's also reported for
+    /// layers that were already generated by a previous run.
+    LayerGenerated { layer: usize, layers: usize },
+    /// A tree was built and persisted in the cache directory.
+    TreeBuilt { tree: TreeKind },
+    /// The SNARKs of `proven` out of `partitions` partitions are generated.
+    PartitionsProven { proven: usize, partitions: usize },
+}
+
+/// A token to request the cancellation of an operation, it may be cloned and shared between
+/// threads.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the cancellation. The operation stops at the next safe point.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+type ProgressCallback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Progress callback, cancellation token and settings of a sealing operation.
+///
+/// The default context neither reports progress nor can it be cancelled, it uses the settings of
+/// the context that is active on the calling thread.
+#[derive(Clone, Default)]
+pub struct SealContext {
+    on_progress: Option<ProgressCallback>,
+    cancellation: Option<CancellationToken>,
+    proofs_context: Option<ProofsContext>,
+}
+
+impl SealContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the callback that receives the progress reports. It's called from the thread doing the
+    /// work, hence it should return quickly.
+    pub fn with_progress<F>(mut self, on_progress: F) -> Self
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Runs the operation with the settings of `proofs_context`.
+    pub fn with_proofs_context(mut self, proofs_context: ProofsContext) -> Self {
+        self.proofs_context = Some(proofs_context);
+        self
+    }
+
+    /// Enters the proofs context of the operation, if one was set.
+    pub fn enter_proofs_context(&self) -> Option<ProofsContextGuard> {
+        self.proofs_context.as_ref().map(ProofsContext::enter)
+    }
+
+    pub fn report(&self, progress: Progress) {
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(progress);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .map(CancellationToken::is_cancelled)
+            .unwrap_or(false)
+    }
+
+    /// Returns an [`Error::Cancelled`] if the cancellation was requested.
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled.into());
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SealContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SealContext")
+            .field("on_progress", &self.on_progress.is_some())
+            .field("cancellation", &self.cancellation)
+            .field("proofs_context", &self.proofs_context)
+            .finish()
+    }
+}
+
+/// Returns true if the error, or any error in its chain, is an [`Error::Cancelled`].
+pub fn is_cancelled_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|cause| matches!(cause.downcast_ref::<Error>(), Some(Error::Cancelled)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use anyhow::Context;
+
+    #[test]
+    fn test_seal_context() {
+        let ctx = SealContext::default();
+        ctx.report(Progress::TreeBuilt {
+            tree: TreeKind::TreeC,
+        });
+        assert!(ctx.check_cancelled().is_ok());
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let token = CancellationToken::new();
+        let ctx = {
+            let reports = reports.clone();
+            SealContext::new()
+                .with_progress(move |progress| reports.lock().expect("poisoned").push(progress))
+                .with_cancellation(token.clone())
+        };
+
+        ctx.report(Progress::LayerGenerated {
+            layer: 1,
+            layers: 2,
+        });
+        assert!(ctx.check_cancelled().is_ok());
+
+        token.cancel();
+        let err = ctx
+            .check_cancelled()
+            .context("while labeling")
+            .expect_err("operation wasn't cancelled");
+        assert!(is_cancelled_error(&err));
+        assert!(!is_cancelled_error(&anyhow::anyhow!("some other error")));
+        assert_eq!(
+            *reports.lock().expect("poisoned"),
+            vec![Progress::LayerGenerated {
+                layer: 1,
+                layers: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_seal_context_proofs_context() {
+        use crate::settings::{self, Settings};
+
+        assert!(SealContext::default().enter_proofs_context().is_none());
+
+        let rows_to_discard = settings::SETTINGS.rows_to_discard + 1;
+        let ctx = SealContext::new().with_proofs_context(ProofsContext::new(Settings {
+            rows_to_discard,
+            ..Default::default()
+        }));
+        {
+            let _guard = ctx.enter_proofs_context();
+            assert_eq!(settings::current().rows_to_discard, rows_to_discard);
+        }
+        assert_eq!(
+            settings::current().rows_to_discard,
+            settings::SETTINGS.rows_to_discard
+        );
+    }
+}
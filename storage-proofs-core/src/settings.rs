@@ -1,5 +1,9 @@
 use std::any::TypeId;
+use std::cell::RefCell;
 use std::env;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::Arc;
 
 use config::{Config, ConfigError, Environment, File};
 use filecoin_hashers::poseidon::PoseidonHasher;
@@ -9,14 +13,21 @@ use serde::{Deserialize, Serialize};
 use crate::merkle::MerkleTreeTrait;
 
 lazy_static! {
-    pub static ref SETTINGS: Settings = Settings::new().expect("invalid configuration");
+    /// The settings of the default context, read from the config file and the environment.
+    pub static ref SETTINGS: Arc<Settings> =
+        Arc::new(Settings::new().expect("invalid configuration"));
+}
+
+thread_local! {
+    /// The context that was entered on this thread, if any.
+    static CURRENT_CONTEXT: RefCell<Option<ProofsContext>> = RefCell::new(None);
 }
 
 const SETTINGS_PATH: &str = "./rust-fil-proofs.config.toml";
 const PREFIX: &str = "FIL_PROOFS";
 pub const DEFAULT_ROWS_TO_DISCARD: u32 = 2;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(default)]
 pub struct Settings {
     pub verify_cache: bool,
@@ -28,7 +39,9 @@ pub struct Settings {
     pub max_gpu_tree_batch_size: u32,
     pub rows_to_discard: u32,
     pub sdr_parents_cache_size: u32,
+    pub sdr_checkpoint_interval: u64,
     pub window_post_synthesis_num_cpus: u32,
+    pub winning_post_max_outstanding_reads: usize,
     pub parameter_cache: String,
     pub parent_cache: String,
     pub use_multicore_sdr: bool,
@@ -49,7 +62,9 @@ impl Default for Settings {
             max_gpu_tree_batch_size: 700_000,
             rows_to_discard: DEFAULT_ROWS_TO_DISCARD,
             sdr_parents_cache_size: 2_048,
+            sdr_checkpoint_interval: 1 << 24,
             window_post_synthesis_num_cpus: num_cpus::get() as u32,
+            winning_post_max_outstanding_reads: 0,
             // `parameter_cache` does not use the cache() mechanism because it is now used
             // for durable, canonical Groth parameters and verifying keys.
             // The name is retained for backwards compatibility.
@@ -95,10 +110,16 @@ fn set_gpu_framework() {
 
 impl Settings {
     fn new() -> Result<Settings, ConfigError> {
+        Self::from_file(SETTINGS_PATH)
+    }
+
+    /// Reads the settings from a config file, which may not exist, and the environment. Values
+    /// that are set in the environment take precedence.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Settings, ConfigError> {
         set_gpu_framework();
 
         Config::builder()
-            .add_source(File::with_name(SETTINGS_PATH).required(false))
+            .add_source(File::from(path.as_ref()).required(false))
             .add_source(Environment::with_prefix(PREFIX))
             .build()?
             .try_deserialize()
@@ -116,3 +137,156 @@ impl Settings {
         self.use_gpu_tree_builder && TypeId::of::<Tree::Hasher>() == TypeId::of::<PoseidonHasher>()
     }
 }
+
+/// Returns the settings of the context that is active on the current thread.
+///
+/// Those are the settings of the entered [`ProofsContext`] or, if none was entered, [`SETTINGS`].
+pub fn current() -> Arc<Settings> {
+    CURRENT_CONTEXT.with(|current| match &*current.borrow() {
+        Some(context) => Arc::clone(&context.settings),
+        None => Arc::clone(&SETTINGS),
+    })
+}
+
+/// A set of settings that the proofs operations use instead of the global [`SETTINGS`].
+///
+/// A context is entered on the thread that calls into the API. Threads don't inherit it, the
+/// proofs enter the [`ProofsContext::current`] context of the calling thread in the closures that
+/// they run on the rayon thread pool, the same way as they propagate the `tracing` spans.
+#[derive(Debug, Clone)]
+pub struct ProofsContext {
+    settings: Arc<Settings>,
+}
+
+impl Default for ProofsContext {
+    /// Returns the context of the global [`SETTINGS`].
+    fn default() -> Self {
+        ProofsContext {
+            settings: Arc::clone(&SETTINGS),
+        }
+    }
+}
+
+impl ProofsContext {
+    pub fn new(settings: Settings) -> Self {
+        ProofsContext {
+            settings: Arc::new(settings),
+        }
+    }
+
+    /// Returns the context that is active on the current thread.
+    pub fn current() -> Self {
+        ProofsContext {
+            settings: current(),
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Makes this the active context of the current thread, until the returned guard is dropped.
+    pub fn enter(&self) -> ProofsContextGuard {
+        let previous = CURRENT_CONTEXT.with(|current| current.replace(Some(self.clone())));
+        ProofsContextGuard {
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Runs `f` with this as the active context of the current thread.
+    pub fn scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let _guard = self.enter();
+        f()
+    }
+}
+
+/// Restores the previously active context when it's dropped.
+#[must_use = "the context is left when the guard is dropped"]
+pub struct ProofsContextGuard {
+    previous: Option<ProofsContext>,
+    // The context is bound to the thread it was entered on.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ProofsContextGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_CONTEXT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_proofs_context() {
+        let default_rows_to_discard = SETTINGS.rows_to_discard;
+        assert_eq!(current().rows_to_discard, default_rows_to_discard);
+
+        let outer = ProofsContext::new(Settings {
+            rows_to_discard: default_rows_to_discard + 1,
+            ..Default::default()
+        });
+        let inner = ProofsContext::new(Settings {
+            rows_to_discard: default_rows_to_discard + 2,
+            parameter_cache: "/tmp/inner-parameters/".to_string(),
+            ..Default::default()
+        });
+
+        outer.scope(|| {
+            assert_eq!(current().rows_to_discard, default_rows_to_discard + 1);
+            inner.scope(|| {
+                assert_eq!(current().rows_to_discard, default_rows_to_discard + 2);
+                assert_eq!(
+                    ProofsContext::current().settings().parameter_cache,
+                    "/tmp/inner-parameters/"
+                );
+                // Other threads aren't affected.
+                thread::spawn(move || {
+                    assert_eq!(current().rows_to_discard, default_rows_to_discard);
+                })
+                .join()
+                .expect("thread panicked");
+            });
+            assert_eq!(current().rows_to_discard, default_rows_to_discard + 1);
+        });
+        assert_eq!(current().rows_to_discard, default_rows_to_discard);
+    }
+
+    #[test]
+    fn test_proofs_context_in_rayon_workers() {
+        let rows_to_discard = SETTINGS.rows_to_discard + 1;
+        let context = ProofsContext::new(Settings {
+            rows_to_discard,
+            ..Default::default()
+        });
+
+        let in_workers: Vec<_> = context.scope(|| {
+            let context = ProofsContext::current();
+            (0..64)
+                .into_par_iter()
+                .map(|_| {
+                    let _context = context.enter();
+                    (rayon::current_thread_index(), current().rows_to_discard)
+                })
+                .collect()
+        });
+        assert!(in_workers.iter().all(|(worker, _)| worker.is_some()));
+        assert!(in_workers
+            .iter()
+            .all(|(_, worker_rows)| *worker_rows == rows_to_discard));
+
+        // The workers are back to the global settings afterwards.
+        let after: Vec<_> = (0..64)
+            .into_par_iter()
+            .map(|_| current().rows_to_discard)
+            .collect();
+        assert!(after.iter().all(|rows| *rows == SETTINGS.rows_to_discard));
+    }
+}
@@ -0,0 +1,126 @@
+//! Structured `tracing` spans of the proving pipeline.
+//!
+//! With the `tracing` feature, [`proofs_span!`](crate::proofs_span) creates and enters an info
+//! level span with the given fields, so that subscribers can attribute nested work to a sector,
+//! phase, layer or partition. Without the feature it expands to a no-op guard and the field
+//! values are never formatted. The `log` output is the same in both cases.
+
+use std::fmt;
+
+#[cfg(feature = "tracing")]
+#[doc(hidden)]
+pub use tracing as __tracing;
+
+/// Creates an info level span and enters it until the returned [`SpanGuard`] is dropped.
+///
+/// The arguments are the ones of `tracing::info_span!`, e.g.
+/// `proofs_span!("label_layer", layer = layer)`.
+#[cfg(feature = "tracing")]
+#[macro_export]
+macro_rules! proofs_span {
+    ($($args:tt)+) => {
+        $crate::spans::SpanGuard::enter($crate::spans::__tracing::info_span!($($args)+))
+    };
+}
+
+/// Creates an info level span and enters it until the returned [`SpanGuard`] is dropped.
+///
+/// The arguments are the ones of `tracing::info_span!`, e.g.
+/// `proofs_span!("label_layer", layer = layer)`.
+#[cfg(not(feature = "tracing"))]
+#[macro_export]
+macro_rules! proofs_span {
+    ($name:literal $(, $($key:ident).+ $(= $(%)? $(?)? $value:expr)?)* $(,)?) => {{
+        // Borrow the values, so that variables that are only used as fields aren't unused.
+        $($(let _ = &$value;)?)*
+        $crate::spans::SpanGuard::disabled()
+    }};
+}
+
+/// Keeps a span entered on the current thread until it's dropped.
+#[must_use]
+pub struct SpanGuard {
+    #[cfg(feature = "tracing")]
+    _entered: tracing::span::EnteredSpan,
+}
+
+impl SpanGuard {
+    #[cfg(feature = "tracing")]
+    #[doc(hidden)]
+    pub fn enter(span: tracing::Span) -> Self {
+        SpanGuard {
+            _entered: span.entered(),
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn disabled() -> Self {
+        SpanGuard {
+            #[cfg(feature = "tracing")]
+            _entered: tracing::Span::none().entered(),
+        }
+    }
+}
+
+/// A handle to the span that is entered on the current thread.
+///
+/// Spans aren't propagated to the threads of a thread pool. Entering the handle of the calling
+/// thread in the worker makes the spans created there children of it.
+#[derive(Clone, Debug)]
+pub struct ParentSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl ParentSpan {
+    pub fn enter(&self) -> SpanGuard {
+        #[cfg(feature = "tracing")]
+        return SpanGuard::enter(self.span.clone());
+        #[cfg(not(feature = "tracing"))]
+        SpanGuard::disabled()
+    }
+}
+
+/// Returns a handle to the span that is entered on the current thread.
+pub fn current() -> ParentSpan {
+    ParentSpan {
+        #[cfg(feature = "tracing")]
+        span: tracing::Span::current(),
+    }
+}
+
+/// Displays the first bytes of an id in hex, it's used to tell replicas apart in span fields.
+pub struct ShortId<'a>(pub &'a [u8]);
+
+impl fmt::Display for ShortId<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0.iter().take(4) {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proofs_span() {
+        assert_eq!(
+            ShortId(&[0xab, 0x01, 0x02, 0x03, 0x04]).to_string(),
+            "ab010203"
+        );
+        assert_eq!(ShortId(&[0x0f]).to_string(), "0f");
+
+        let layer = 3;
+        let _span = crate::proofs_span!("label_layer", layer = layer, replica_id = %ShortId(&[1]));
+        let parent = current();
+        std::thread::spawn(move || {
+            let _parent = parent.enter();
+            let _span = crate::proofs_span!("prove_partition", partition = 0);
+        })
+        .join()
+        .expect("thread panicked");
+    }
+}
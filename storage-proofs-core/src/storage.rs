@@ -0,0 +1,307 @@
+//! Pluggable storage of sector cache directories and replicas.
+//!
+//! The proving code works on local paths, as the trees are memory mapped or read through
+//! `std::fs::File`s. A [`SectorStorage`] is used to reach sectors that are kept elsewhere, e.g. on
+//! a network block device or in an object store: only the files, or the parts of them, that an
+//! operation needs are read through it. PoSt reads just the challenged nodes, the commit phase 1
+//! stages the files it needs locally.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+/// A file that is opened for random reads.
+pub trait ReadAt: Send + Sync {
+    /// Fills `buf` with the bytes at `offset`, it fails if the file ends before.
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+/// The storage of sector cache directories and replicas.
+///
+/// Paths are the keys of the files, they don't need to exist locally.
+pub trait SectorStorage: Send + Sync + fmt::Debug {
+    /// Opens a file for random reads.
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadAt>>;
+
+    /// Creates or truncates a file for sequential writes. The file is complete once the writer
+    /// is flushed and dropped.
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>>;
+
+    /// Returns the files directly within `dir`, sorted.
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Returns the size of a file in bytes.
+    fn size(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// Reads a whole file from `storage`.
+pub fn read_all(storage: &dyn SectorStorage, path: &Path) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; storage.size(path)? as usize];
+    storage.open_read(path)?.read_exact_at(0, &mut data)?;
+    Ok(data)
+}
+
+/// Copies a whole file from `storage` to the local `dest` in chunks.
+pub fn copy_to_local(storage: &dyn SectorStorage, path: &Path, dest: &Path) -> io::Result<u64> {
+    const CHUNK_SIZE: u64 = 1 << 24;
+
+    let size = storage.size(path)?;
+    let reader = storage.open_read(path)?;
+    let mut file = File::create(dest)?;
+    let mut buf = vec![0; CHUNK_SIZE.min(size) as usize];
+    let mut offset = 0;
+    while offset < size {
+        let len = CHUNK_SIZE.min(size - offset) as usize;
+        reader.read_exact_at(offset, &mut buf[..len])?;
+        file.write_all(&buf[..len])?;
+        offset += len as u64;
+    }
+    file.sync_all()?;
+
+    Ok(size)
+}
+
+impl ReadAt for File {
+    #[cfg(unix)]
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset)
+    }
+
+    #[cfg(windows)]
+    fn read_exact_at(&self, mut offset: u64, mut buf: &mut [u8]) -> io::Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    buf = &mut buf[read..];
+                    offset += read as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The local filesystem, paths are used as they are.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalStorage;
+
+impl SectorStorage for LocalStorage {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadAt>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+}
+
+type MemoryFiles = Arc<RwLock<BTreeMap<PathBuf, Arc<Vec<u8>>>>>;
+
+/// Keeps all files in memory, it's meant for tests.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStorage {
+    files: MemoryFiles,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        )
+    }
+
+    fn get(&self, path: &Path) -> io::Result<Arc<Vec<u8>>> {
+        self.files
+            .read()
+            .expect("memory storage poisoned")
+            .get(path)
+            .cloned()
+            .ok_or_else(|| Self::not_found(path))
+    }
+}
+
+struct MemoryFile(Arc<Vec<u8>>);
+
+impl ReadAt for MemoryFile {
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let start = offset as usize;
+        let end = start + buf.len();
+        if end > self.0.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.copy_from_slice(&self.0[start..end]);
+        Ok(())
+    }
+}
+
+/// Stores the written data when it's flushed or dropped.
+struct MemoryWriter {
+    path: PathBuf,
+    data: Vec<u8>,
+    files: MemoryFiles,
+}
+
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.files
+            .write()
+            .expect("memory storage poisoned")
+            .insert(self.path.clone(), Arc::new(self.data.clone()));
+        Ok(())
+    }
+}
+
+impl Drop for MemoryWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl SectorStorage for MemoryStorage {
+    fn open_read(&self, path: &Path) -> io::Result<Box<dyn ReadAt>> {
+        Ok(Box::new(MemoryFile(self.get(path)?)))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write + Send>> {
+        let mut writer = MemoryWriter {
+            path: path.to_path_buf(),
+            data: Vec::new(),
+            files: self.files.clone(),
+        };
+        writer.flush()?;
+        Ok(Box::new(writer))
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        Ok(self
+            .files
+            .read()
+            .expect("memory storage poisoned")
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.files
+            .write()
+            .expect("memory storage poisoned")
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| Self::not_found(path))
+    }
+
+    fn size(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.get(path)?.len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_storage(storage: &dyn SectorStorage, dir: &Path) {
+        let path = dir.join("data");
+        let mut writer = storage.create(&path).expect("create failed");
+        writer.write_all(&[1, 2, 3, 4, 5]).expect("write failed");
+        writer.flush().expect("flush failed");
+        drop(writer);
+        storage
+            .create(&dir.join("other"))
+            .expect("create failed")
+            .write_all(&[6])
+            .expect("write failed");
+
+        assert_eq!(storage.size(&path).expect("size failed"), 5);
+        assert_eq!(
+            storage.list(dir).expect("list failed"),
+            vec![dir.join("data"), dir.join("other")]
+        );
+
+        let reader = storage.open_read(&path).expect("open failed");
+        let mut buf = [0u8; 2];
+        reader.read_exact_at(2, &mut buf).expect("read failed");
+        assert_eq!(buf, [3, 4]);
+        assert!(reader.read_exact_at(4, &mut buf).is_err());
+        assert_eq!(
+            read_all(storage, &path).expect("read all failed"),
+            [1, 2, 3, 4, 5]
+        );
+
+        storage.remove(&path).expect("remove failed");
+        assert!(storage.size(&path).is_err());
+        assert_eq!(
+            storage.list(dir).expect("list failed"),
+            vec![dir.join("other")]
+        );
+    }
+
+    #[test]
+    fn test_local_storage() {
+        let dir = tempfile::tempdir().expect("tempdir failed");
+        check_storage(&LocalStorage, dir.path());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let storage = MemoryStorage::new();
+        check_storage(&storage, Path::new("/sectors/s-t01000-1"));
+
+        let dest = tempfile::NamedTempFile::new().expect("tempfile failed");
+        let path = Path::new("/sectors/replica");
+        storage
+            .create(path)
+            .expect("create failed")
+            .write_all(&[7; 100])
+            .expect("write failed");
+        assert_eq!(
+            copy_to_local(&storage, path, dest.path()).expect("copy failed"),
+            100
+        );
+        assert_eq!(fs::read(dest.path()).expect("read failed"), vec![7; 100]);
+    }
+}
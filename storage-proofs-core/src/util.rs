@@ -169,7 +169,7 @@ pub fn default_rows_to_discard(leafs: usize, arity: usize) -> usize {
     #[cfg(feature = "fixed-rows-to-discard")]
     let rows_to_discard = settings::DEFAULT_ROWS_TO_DISCARD as usize;
     #[cfg(not(feature = "fixed-rows-to-discard"))]
-    let rows_to_discard = settings::SETTINGS.rows_to_discard as usize;
+    let rows_to_discard = settings::current().rows_to_discard as usize;
 
     // Discard at most 'constant value' rows (coded below,
     // differing by arity) while respecting the max number that
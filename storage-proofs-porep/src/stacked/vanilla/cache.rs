@@ -17,7 +17,7 @@ use storage_proofs_core::{
     drgraph::{Graph, BASE_DEGREE},
     error::Result,
     parameter_cache::{with_exclusive_lock, LockedFile, ParameterSetMetadata, VERSION},
-    settings::SETTINGS,
+    settings,
     util::NODE_SIZE,
 };
 
@@ -216,14 +216,14 @@ impl ParentCache {
 
                     (
                         None,
-                        SETTINGS.verify_cache,
+                        settings::current().verify_cache,
                         false, // not production since not in manifest
                         "".to_string(),
                     )
                 }
                 Some(pcd) => (
                     Some(pcd),
-                    SETTINGS.verify_cache,
+                    settings::current().verify_cache,
                     true, // is_production since it exists in the manifest
                     pcd.digest.clone(),
                 ),
@@ -238,19 +238,8 @@ impl ParentCache {
         if verify_cache {
             // Always check all of the data for integrity checks, even
             // if we're only opening a portion of it.
-            let mut hasher = Sha256::new();
             info!("[open] parent cache: calculating consistency digest");
-            let file = File::open(path)?;
-            let data = unsafe {
-                MmapOptions::new()
-                    .map(&file)
-                    .with_context(|| format!("could not mmap path={}", path.display()))?
-            };
-            hasher.update(&data);
-            drop(data);
-
-            let hash = hasher.finalize();
-            digest_hex = hash.iter().map(|x| format!("{:01$x}", x, 2)).collect();
+            digest_hex = file_digest(path)?;
 
             info!(
                 "[open] parent cache: calculated consistency digest: {:?}",
@@ -394,9 +383,10 @@ impl ParentCache {
             self.cache.len,
         );
 
-        // Shift cache by its current size.
+        // Shift cache to the window containing `node`. When reading sequentially, this is a shift
+        // by its current size, but labeling might also be resumed in the middle of a layer.
         let new_offset =
-            (self.num_cache_entries - self.cache.len).min(self.cache.offset + self.cache.len);
+            (self.num_cache_entries - self.cache.len).min((node / self.cache.len) * self.cache.len);
         self.cache.shift(new_offset)?;
 
         Ok(self.cache.read(node))
@@ -408,8 +398,44 @@ impl ParentCache {
     }
 }
 
+/// Hashes the whole parent cache file at `path`.
+fn file_digest(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let file = File::open(path)?;
+    let data = unsafe {
+        MmapOptions::new()
+            .map(&file)
+            .with_context(|| format!("could not mmap path={}", path.display()))?
+    };
+    hasher.update(&data);
+    drop(data);
+
+    let hash = hasher.finalize();
+    Ok(hash.iter().map(|x| format!("{:01$x}", x, 2)).collect())
+}
+
+/// Hashes the parent cache at `path` and compares it with the digest in the manifest.
+///
+/// Returns `None` if the parent cache isn't part of the manifest, as it's the case for the
+/// caches of test sectors.
+pub fn verify_parent_cache_digest(path: &Path) -> Result<Option<bool>> {
+    match get_parent_cache_data(path) {
+        None => Ok(None),
+        Some(pcd) => Ok(Some(file_digest(path)? == pcd.digest)),
+    }
+}
+
+/// Returns the path of the parent cache of `graph` that contains all nodes.
+pub(crate) fn parent_cache_path<H, G>(graph: &StackedGraph<H, G>) -> PathBuf
+where
+    H: Hasher,
+    G: Graph<H> + ParameterSetMetadata + Send + Sync,
+{
+    cache_path(graph.size() as u32, graph)
+}
+
 fn parent_cache_dir_name() -> String {
-    SETTINGS.parent_cache.clone()
+    settings::current().parent_cache.clone()
 }
 
 fn parent_cache_id(path: &Path) -> String {
@@ -549,6 +575,44 @@ mod tests {
         test_read_partial_range(api_version, porep_id);
     }
 
+    #[test]
+    fn test_verify_parent_cache_digest() {
+        fil_logger::maybe_init();
+        // The porep id of the 2KiB V1_1 production proofs, its parent cache is in the manifest.
+        let mut porep_id = [0u8; 32];
+        porep_id[0] = 5;
+        let nodes = 64u32;
+        let graph = StackedBucketGraph::<PoseidonHasher>::new_stacked(
+            nodes as usize,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            porep_id,
+            ApiVersion::V1_1_0,
+        )
+        .expect("new_stacked failure");
+        let path = graph.parent_cache_path();
+        graph.parent_cache().expect("parent cache failure");
+        assert_eq!(
+            verify_parent_cache_digest(&path).expect("verify failure"),
+            Some(true)
+        );
+
+        let graph = StackedBucketGraph::<PoseidonHasher>::new_stacked(
+            nodes as usize,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            [1u8; 32],
+            ApiVersion::V1_1_0,
+        )
+        .expect("new_stacked failure");
+        let path = graph.parent_cache_path();
+        graph.parent_cache().expect("parent cache failure");
+        assert_eq!(
+            verify_parent_cache_digest(&path).expect("verify failure"),
+            None
+        );
+    }
+
     #[test]
     fn test_read_partial_range_v1_0() {
         let porep_id = [0u8; 32];
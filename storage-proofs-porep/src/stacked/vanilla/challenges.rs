@@ -290,6 +290,11 @@ pub(crate) mod synthetic {
         }
     }
 
+    /// Returns the number of synthetic challenges that are generated for a sector.
+    pub(crate) fn default_num_synth_challenges(sector_nodes: usize) -> usize {
+        min(sector_nodes, DEFAULT_SYNTH_CHALLENGE_COUNT)
+    }
+
     impl SynthChallengeGenerator {
         pub fn new(
             sector_nodes: usize,
@@ -315,7 +320,7 @@ pub(crate) mod synthetic {
         }
 
         pub fn default(sector_nodes: usize, replica_id: &Fr, comm_r: &Fr) -> Self {
-            let num_synth_challenges = min(sector_nodes, DEFAULT_SYNTH_CHALLENGE_COUNT);
+            let num_synth_challenges = default_num_synth_challenges(sector_nodes);
             Self::new(sector_nodes, replica_id, comm_r, num_synth_challenges)
         }
 
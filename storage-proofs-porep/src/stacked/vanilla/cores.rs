@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::sync::{Mutex, MutexGuard};
 
@@ -5,18 +6,14 @@ use anyhow::{format_err, Result};
 use hwloc::{Bitmap, CpuBindFlags, ObjectType, Topology, TopologyObject};
 use lazy_static::lazy_static;
 use log::{debug, warn};
-use storage_proofs_core::settings::SETTINGS;
 
 type CoreUnit = Vec<CoreIndex>;
+type CoreGroups = Option<Vec<Mutex<CoreUnit>>>;
 lazy_static! {
     pub static ref TOPOLOGY: Mutex<Topology> =
         Mutex::new(Topology::new().expect("failed to initialize and load cpu topology"));
-    pub static ref CORE_GROUPS: Option<Vec<Mutex<CoreUnit>>> = {
-        let num_producers = &SETTINGS.multicore_sdr_producers;
-        let cores_per_unit = num_producers + 1;
-
-        core_units(cores_per_unit)
-    };
+    /// The core units by the number of producers they were created for, see [`core_groups`].
+    static ref CORE_GROUPS: Mutex<HashMap<usize, &'static CoreGroups>> = Default::default();
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -24,8 +21,23 @@ lazy_static! {
 /// should only ever be created with a value known to be less than the number of visible cores.
 pub struct CoreIndex(usize);
 
-pub fn checkout_core_group() -> Option<MutexGuard<'static, CoreUnit>> {
-    match &*CORE_GROUPS {
+/// Returns the core units for multicore SDR runs with `num_producers` producer threads, each unit
+/// has a core for the main thread and one for each producer.
+///
+/// The number of producers is taken from the `ProofsContext` of each run, hence there is a set of
+/// units per number of producers. A set is created on first use and lives as long as the process.
+/// The sets are independent of each other, runs with different numbers of producers may be bound
+/// to the same cores.
+fn core_groups(num_producers: usize) -> &'static CoreGroups {
+    let mut groups = CORE_GROUPS.lock().expect("CORE_GROUPS poisoned");
+    groups
+        .entry(num_producers)
+        .or_insert_with(|| Box::leak(Box::new(core_units(num_producers + 1))))
+}
+
+/// Checks out a unit of cores for a multicore SDR run with `num_producers` producer threads.
+pub fn checkout_core_group(num_producers: usize) -> Option<MutexGuard<'static, CoreUnit>> {
+    match core_groups(num_producers) {
         Some(units) => {
             for (i, unit) in units.iter().enumerate() {
                 match unit.try_lock() {
@@ -223,7 +235,7 @@ fn get_shared_cache_count(topo: &Topology, depth: u32, core_count: usize) -> usi
     1
 }
 
-fn core_units(cores_per_unit: usize) -> Option<Vec<Mutex<CoreUnit>>> {
+fn core_units(cores_per_unit: usize) -> CoreGroups {
     let topo = TOPOLOGY.lock().expect("poisoned lock");
 
     // At which depths the cores within one package are. If you think of the "depths" as a
@@ -277,6 +289,19 @@ mod tests {
         core_units(2);
     }
 
+    #[test]
+    fn test_core_groups_per_num_producers() {
+        fil_logger::maybe_init();
+        let groups = core_groups(1);
+        assert!(std::ptr::eq(groups, core_groups(1)));
+        for (num_producers, groups) in [(1, groups), (3, core_groups(3))] {
+            for unit in groups.iter().flatten() {
+                let unit = unit.lock().expect("core unit poisoned");
+                assert!(unit.len() <= num_producers + 1);
+            }
+        }
+    }
+
     #[test]
     #[cfg(feature = "isolated-testing")]
     // This test should not be run while other tests are running, as
@@ -284,9 +309,9 @@ mod tests {
     // failure.
     fn test_checkout_cores() {
         fil_logger::maybe_init();
-        let checkout1 = checkout_core_group();
+        let checkout1 = checkout_core_group(1);
         dbg!(&checkout1);
-        let checkout2 = checkout_core_group();
+        let checkout2 = checkout_core_group(1);
         dbg!(&checkout2);
 
         // This test might fail if run on a machine with fewer than four cores.
@@ -0,0 +1,411 @@
+//! Checkpoints of partially generated layers.
+//!
+//! Generating the labels of a single layer of a large sector takes hours. In order to not lose
+//! that work if the process dies in the middle of a layer, the labels generated so far are
+//! periodically appended to a checkpoint file next to the layer files in the cache directory. When
+//! the labeling is started again, it continues from the last valid checkpoint.
+//!
+//! The checkpoint file starts with a fixed size header, followed by the labels of the nodes in
+//! ascending order, starting with node 0. The header is only updated once the labels it refers to
+//! are synced to disk, hence a checkpoint is always consistent, even if the process is killed
+//! while writing it. A checkpoint is bound to the PoRep ID, the replica ID and the layer it was
+//! created for, a checkpoint that doesn't match is discarded.
+
+use std::fs::{remove_file, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{ensure, Context, Result};
+use log::{info, warn};
+use merkletree::store::StoreConfig;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use storage_proofs_core::{cache_key::CacheKey, util::NODE_SIZE, PoRepID};
+
+/// Version of the on-disk format, it's increased on incompatible changes.
+const CHECKPOINT_VERSION: u32 = 1;
+
+/// Size of the header in bytes. The labels start right after it.
+const HEADER_SIZE: u64 = 4096;
+
+/// Length of the checksum that is appended to the serialized header.
+const CHECKSUM_SIZE: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CheckpointHeader {
+    version: u32,
+    porep_id: PoRepID,
+    replica_id: Vec<u8>,
+    layer: u32,
+    /// The layer the expander parents are read from, it's `None` for the first layer.
+    exp_layer: Option<String>,
+    /// The total number of nodes of the layer.
+    num_nodes: u64,
+    /// The number of nodes, starting at node 0, whose labels are stored in the checkpoint.
+    nodes: u64,
+}
+
+impl CheckpointHeader {
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = bincode::serialize(self)?;
+        let checksum = Sha256::digest(&bytes);
+        bytes.extend_from_slice(&checksum);
+        ensure!(
+            bytes.len() + 8 <= HEADER_SIZE as usize,
+            "checkpoint header too large"
+        );
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        header.extend_from_slice(&bytes);
+        header.resize(HEADER_SIZE as usize, 0);
+        Ok(header)
+    }
+
+    fn from_bytes(header: &[u8]) -> Result<Self> {
+        ensure!(header.len() >= 8, "checkpoint header too short");
+        let mut len_bytes = [0u8; 8];
+        len_bytes.copy_from_slice(&header[..8]);
+        let len = u64::from_le_bytes(len_bytes) as usize;
+        ensure!(
+            len > CHECKSUM_SIZE && 8 + len <= header.len(),
+            "invalid checkpoint header length"
+        );
+
+        let (bytes, checksum) = header[8..8 + len].split_at(len - CHECKSUM_SIZE);
+        ensure!(
+            Sha256::digest(bytes).as_slice() == checksum,
+            "checkpoint header checksum mismatch"
+        );
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Returns the path of the checkpoint file for the layer with the given `StoreConfig`.
+///
+/// The name is derived from the layer's id, so that checkpoints are removed together with the
+/// layers when the cache is cleared.
+pub fn checkpoint_path(config: &StoreConfig) -> PathBuf {
+    StoreConfig::data_path(&config.path, &format!("{}-checkpoint", config.id))
+}
+
+/// Removes the checkpoint of the layer with the given `StoreConfig`, if there is one.
+pub fn remove_checkpoint(config: &StoreConfig) {
+    let path = checkpoint_path(config);
+    if path.exists() {
+        if let Err(err) = remove_file(&path) {
+            warn!("failed to delete checkpoint {:?}: {}", path, err);
+        }
+    }
+}
+
+/// A checkpoint of the layer that is currently generated.
+#[derive(Debug)]
+pub struct LayerCheckpoint {
+    path: PathBuf,
+    header: CheckpointHeader,
+    /// Number of nodes after which a new checkpoint is persisted, `0` disables checkpoints.
+    interval: u64,
+    file: Option<File>,
+}
+
+impl LayerCheckpoint {
+    pub fn new(
+        config: &StoreConfig,
+        porep_id: PoRepID,
+        replica_id: &[u8],
+        layer: usize,
+        num_nodes: usize,
+        interval: u64,
+    ) -> Self {
+        let exp_layer = if layer > 1 {
+            Some(CacheKey::label_layer(layer - 1))
+        } else {
+            None
+        };
+
+        Self {
+            path: checkpoint_path(config),
+            header: CheckpointHeader {
+                version: CHECKPOINT_VERSION,
+                porep_id,
+                replica_id: replica_id.to_vec(),
+                layer: layer as u32,
+                exp_layer,
+                num_nodes: num_nodes as u64,
+                nodes: 0,
+            },
+            interval,
+            file: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The number of nodes whose labels are covered by this checkpoint.
+    pub fn nodes(&self) -> u64 {
+        self.header.nodes
+    }
+
+    /// Restores the labels from a previously persisted checkpoint into `labels`.
+    ///
+    /// Returns the number of nodes that were restored, which is the node the labeling should
+    /// continue with. If there is no checkpoint, or it doesn't belong to this layer of this
+    /// replica, it is discarded and `0` is returned.
+    pub fn restore(&mut self, labels: &mut [u8]) -> Result<u64> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to open {:?}", self.path));
+            }
+        };
+
+        let stored = match self.read_header(&mut file) {
+            Ok(stored) => stored,
+            Err(err) => {
+                warn!("discarding invalid checkpoint {:?}: {:#}", self.path, err);
+                drop(file);
+                self.discard();
+                return Ok(0);
+            }
+        };
+
+        let nodes = stored.nodes;
+        let len = nodes as usize * NODE_SIZE;
+        ensure!(
+            labels.len() >= len,
+            "labels buffer too small for checkpoint"
+        );
+        file.seek(SeekFrom::Start(HEADER_SIZE))?;
+        file.read_exact(&mut labels[..len])
+            .with_context(|| format!("failed to read checkpoint {:?}", self.path))?;
+
+        info!(
+            "resuming layer {} from checkpoint at node {}",
+            self.header.layer, nodes
+        );
+        self.header.nodes = nodes;
+        self.file = Some(file);
+
+        Ok(nodes)
+    }
+
+    /// Reads the header of a checkpoint and makes sure it belongs to this layer.
+    fn read_header(&self, file: &mut File) -> Result<CheckpointHeader> {
+        let mut header_bytes = vec![0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header_bytes)
+            .context("failed to read checkpoint header")?;
+        let stored = CheckpointHeader::from_bytes(&header_bytes)?;
+
+        ensure!(
+            stored.version == self.header.version,
+            "unsupported checkpoint version {}",
+            stored.version
+        );
+        ensure!(
+            stored.porep_id == self.header.porep_id,
+            "checkpoint was created for a different porep_id"
+        );
+        ensure!(
+            stored.replica_id == self.header.replica_id,
+            "checkpoint was created for a different replica_id"
+        );
+        ensure!(
+            stored.layer == self.header.layer && stored.exp_layer == self.header.exp_layer,
+            "checkpoint was created for a different layer"
+        );
+        ensure!(
+            stored.num_nodes == self.header.num_nodes && stored.nodes < stored.num_nodes,
+            "checkpoint was created for a different sector size"
+        );
+
+        let data_len = file.metadata()?.len().saturating_sub(HEADER_SIZE);
+        ensure!(
+            data_len >= stored.nodes * NODE_SIZE as u64,
+            "checkpoint is truncated"
+        );
+
+        Ok(stored)
+    }
+
+    /// Persists a new checkpoint, if at least `interval` nodes were generated since the last one.
+    ///
+    /// `labels` must contain the final labels of at least the first `nodes` nodes. Failing to
+    /// write a checkpoint is not fatal, the labeling continues without checkpoints.
+    pub fn maybe_persist(&mut self, labels: &[u8], nodes: u64) {
+        if self.interval == 0
+            || nodes < self.header.nodes + self.interval
+            || nodes >= self.header.num_nodes
+        {
+            return;
+        }
+
+        if let Err(err) = self.persist(labels, nodes) {
+            warn!(
+                "failed to persist checkpoint {:?}, disabling checkpoints for layer {}: {:#}",
+                self.path, self.header.layer, err
+            );
+            self.interval = 0;
+        }
+    }
+
+    /// Persists a checkpoint of the first `nodes` nodes regardless of the interval. It's used when
+    /// the labeling is interrupted, so that it can continue from there later on.
+    pub fn flush(&mut self, labels: &[u8], nodes: u64) {
+        if self.interval == 0 || nodes <= self.header.nodes || nodes >= self.header.num_nodes {
+            return;
+        }
+
+        if let Err(err) = self.persist(labels, nodes) {
+            warn!("failed to persist checkpoint {:?}: {:#}", self.path, err);
+        }
+    }
+
+    fn persist(&mut self, labels: &[u8], nodes: u64) -> Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&self.path)
+                .with_context(|| format!("failed to create {:?}", self.path))?;
+            self.header.nodes = 0;
+            self.file = Some(file);
+        }
+        let file = self.file.as_mut().expect("checkpoint file is open");
+
+        // Append the labels generated since the last checkpoint and make sure they are on disk
+        // before the header refers to them.
+        let start = self.header.nodes as usize * NODE_SIZE;
+        let end = nodes as usize * NODE_SIZE;
+        file.seek(SeekFrom::Start(HEADER_SIZE + start as u64))?;
+        file.write_all(&labels[start..end])?;
+        file.sync_data()?;
+
+        let mut header = self.header.clone();
+        header.nodes = nodes;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header.to_bytes()?)?;
+        file.sync_data()?;
+
+        self.header = header;
+
+        Ok(())
+    }
+
+    /// Removes the checkpoint, it's called once the layer was fully written.
+    pub fn discard(&mut self) {
+        self.file = None;
+        self.header.nodes = 0;
+        if self.path.exists() {
+            if let Err(err) = remove_file(&self.path) {
+                warn!("failed to delete checkpoint {:?}: {}", self.path, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    const NUM_NODES: usize = 64;
+
+    fn layer_config(path: &Path, layer: usize) -> StoreConfig {
+        StoreConfig {
+            path: path.to_path_buf(),
+            id: CacheKey::label_layer(layer),
+            size: Some(NUM_NODES),
+            rows_to_discard: 0,
+        }
+    }
+
+    fn labels() -> Vec<u8> {
+        (0..NUM_NODES * NODE_SIZE).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let cache_dir = tempdir().expect("tempdir failure");
+        let config = layer_config(cache_dir.path(), 3);
+        let labels = labels();
+
+        let mut checkpoint = LayerCheckpoint::new(&config, [1; 32], &[2; 32], 3, NUM_NODES, 10);
+        // Not enough nodes for a checkpoint yet.
+        checkpoint.maybe_persist(&labels, 9);
+        assert!(!checkpoint.path().exists());
+        checkpoint.maybe_persist(&labels, 10);
+        checkpoint.maybe_persist(&labels, 25);
+        assert_eq!(checkpoint.nodes(), 25);
+        // The last node is never checkpointed, the layer is written instead.
+        checkpoint.maybe_persist(&labels, NUM_NODES as u64);
+        assert_eq!(checkpoint.nodes(), 25);
+
+        let mut restored = vec![0u8; labels.len()];
+        let mut checkpoint = LayerCheckpoint::new(&config, [1; 32], &[2; 32], 3, NUM_NODES, 10);
+        assert_eq!(
+            checkpoint.restore(&mut restored).expect("restore failed"),
+            25
+        );
+        assert_eq!(&restored[..25 * NODE_SIZE], &labels[..25 * NODE_SIZE]);
+        assert!(restored[25 * NODE_SIZE..].iter().all(|b| *b == 0));
+
+        // Appending continues after the restored nodes.
+        checkpoint.maybe_persist(&labels, 40);
+        let mut restored = vec![0u8; labels.len()];
+        let mut checkpoint = LayerCheckpoint::new(&config, [1; 32], &[2; 32], 3, NUM_NODES, 10);
+        assert_eq!(
+            checkpoint.restore(&mut restored).expect("restore failed"),
+            40
+        );
+        assert_eq!(&restored[..40 * NODE_SIZE], &labels[..40 * NODE_SIZE]);
+
+        // Flushing ignores the interval.
+        checkpoint.flush(&labels, 43);
+        assert_eq!(checkpoint.nodes(), 43);
+        checkpoint.flush(&labels, 43);
+        assert_eq!(checkpoint.nodes(), 43);
+
+        checkpoint.discard();
+        assert!(!checkpoint.path().exists());
+    }
+
+    #[test]
+    fn test_checkpoint_stale() {
+        let cache_dir = tempdir().expect("tempdir failure");
+        let config = layer_config(cache_dir.path(), 2);
+        let labels = labels();
+
+        let mut checkpoint = LayerCheckpoint::new(&config, [1; 32], &[2; 32], 2, NUM_NODES, 10);
+        checkpoint.maybe_persist(&labels, 20);
+        assert!(checkpoint.path().exists());
+
+        let mut restored = vec![0u8; labels.len()];
+        for (porep_id, replica_id) in [([9; 32], [2; 32]), ([1; 32], [9; 32])] {
+            let mut other = LayerCheckpoint::new(&config, porep_id, &replica_id, 2, NUM_NODES, 10);
+            assert_eq!(other.restore(&mut restored).expect("restore failed"), 0);
+            // A mismatching checkpoint is removed.
+            assert!(!other.path().exists());
+            checkpoint.discard();
+            checkpoint.maybe_persist(&labels, 20);
+        }
+        assert!(restored.iter().all(|b| *b == 0));
+
+        // A corrupted header is never used.
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(checkpoint.path())
+            .expect("failed to open checkpoint");
+        file.seek(SeekFrom::Start(20)).expect("seek failed");
+        file.write_all(&[0xff; 4]).expect("write failed");
+        drop(file);
+        let mut other = LayerCheckpoint::new(&config, [1; 32], &[2; 32], 2, NUM_NODES, 10);
+        assert_eq!(other.restore(&mut restored).expect("restore failed"), 0);
+    }
+}
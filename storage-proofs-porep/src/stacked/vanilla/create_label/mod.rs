@@ -12,12 +12,16 @@ use storage_proofs_core::{
 
 use crate::stacked::vanilla::{proof::LayerState, StackedBucketGraph};
 
+use self::checkpoint::remove_checkpoint;
+
+pub mod checkpoint;
 #[cfg(feature = "multicore-sdr")]
 pub mod multi;
 pub mod single;
 
 /// Prepares the necessary `StoreConfig`s with which the layers are stored.
-/// Also checks for already existing layers and marks them as such.
+/// Also checks for already existing layers and marks them as such. Checkpoints of
+/// layers that are already fully written are removed.
 pub fn prepare_layers<P, Tree: 'static + MerkleTreeTrait>(
     graph: &StackedBucketGraph<Tree::Hasher>,
     cache_path: P,
@@ -43,6 +47,7 @@ where
         if generated {
             // succesful load
             info!("found valid labels for layer {}", layer);
+            remove_checkpoint(&label_config);
         }
 
         states.push(LayerState {
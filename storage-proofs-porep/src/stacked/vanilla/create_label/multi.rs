@@ -3,7 +3,7 @@ use std::marker::PhantomData;
 use std::mem::{self, size_of};
 use std::path::Path;
 use std::sync::{
-    atomic::{AtomicU64, Ordering::SeqCst},
+    atomic::{AtomicBool, AtomicU64, Ordering::SeqCst},
     Arc, MutexGuard,
 };
 use std::thread;
@@ -23,14 +23,15 @@ use storage_proofs_core::{
     cache_key::CacheKey,
     drgraph::{Graph, BASE_DEGREE},
     merkle::MerkleTreeTrait,
-    settings::SETTINGS,
+    progress::{Progress, SealContext},
+    proofs_span, settings,
     util::NODE_SIZE,
 };
 
 use crate::stacked::vanilla::{
     cache::ParentCache,
     cores::{bind_core, checkout_core_group, CoreIndex},
-    create_label::{prepare_layers, read_layer, write_layer},
+    create_label::{checkpoint::LayerCheckpoint, prepare_layers, read_layer, write_layer},
     graph::{StackedBucketGraph, DEGREE, EXP_DEGREE},
     memory_handling::{setup_create_label_memory, CacheReader},
     params::{Labels, LabelsCache},
@@ -138,6 +139,8 @@ fn fill_buffer(
 // - base_parent_missing - Bit mask of any base parent nodes that could not
 //                         be filled in. This is an array of size lookahead.
 // - is_layer0    - Indicates first (no expander parents) or subsequent layer
+// - aborted      - Set by the hashing thread when it stops early, the producers
+//                  then stop as well
 #[allow(clippy::too_many_arguments)]
 fn create_label_runner(
     parents_cache: &CacheReader<u32>,
@@ -150,13 +153,14 @@ fn create_label_runner(
     lookahead: u64,
     ring_buf: &RingBuf,
     base_parent_missing: &UnsafeSlice<'_, BitMask>,
+    aborted: &AtomicBool,
 ) {
     info!("created label runner");
     // Label data bytes per node
     loop {
         // Get next work items
         let work = cur_awaiting.fetch_add(stride, SeqCst);
-        if work >= num_nodes {
+        if work >= num_nodes || aborted.load(SeqCst) {
             break;
         }
         let count = if work + stride > num_nodes {
@@ -173,6 +177,9 @@ fn create_label_runner(
 
             // Don't overrun the buffer
             while cur_node > (parents_cache.get_consumer() + lookahead - 1) {
+                if aborted.load(SeqCst) {
+                    return;
+                }
                 thread::sleep(Duration::from_micros(10));
             }
 
@@ -193,6 +200,9 @@ fn create_label_runner(
 
         // Wait for the previous node to finish
         while work > (cur_producer.load(SeqCst) + 1) {
+            if aborted.load(SeqCst) {
+                return;
+            }
             thread::sleep(Duration::from_micros(10));
         }
 
@@ -201,19 +211,30 @@ fn create_label_runner(
     }
 }
 
+// - start_node - The first node to label. All nodes before it must already be
+//                present in `layer_labels`, e.g. restored from a checkpoint.
+// - ctx        - If the cancellation is requested, the labels generated so far
+//                are checkpointed and `Error::Cancelled` is returned.
+#[allow(clippy::too_many_arguments)]
 fn create_layer_labels(
     parents_cache: &CacheReader<u32>,
     replica_id: &[u8],
     layer_labels: &mut MmapMut,
     exp_labels: Option<&mut MmapMut>,
     num_nodes: u64,
+    start_node: u64,
     cur_layer: u32,
     core_group: Arc<Option<MutexGuard<'_, Vec<CoreIndex>>>>,
-) {
-    info!("Creating labels for layer {}", cur_layer);
+    mut checkpoint: Option<&mut LayerCheckpoint>,
+    ctx: &SealContext,
+) -> Result<()> {
+    info!(
+        "Creating labels for layer {} starting at node {}",
+        cur_layer, start_node
+    );
     // num_producers is the number of producer threads
     let (lookahead, num_producers, producer_stride) = {
-        let settings = &SETTINGS;
+        let settings = settings::current();
         let lookahead = settings.multicore_sdr_lookahead;
         let num_producers = settings.multicore_sdr_producers;
         // NOTE: Stride must not exceed the number of nodes in parents_cache's window. If it does, the process will deadlock
@@ -235,10 +256,14 @@ fn create_layer_labels(
         prepare_block(replica_id, cur_layer, buf);
     }
 
+    // Node 0 has no parents and is calculated separately
+    let first_node = start_node.max(1);
     // Highest node that is ready from the producer
-    let cur_producer = AtomicU64::new(0);
+    let cur_producer = AtomicU64::new(first_node - 1);
     // Next node to be filled
-    let cur_awaiting = AtomicU64::new(1);
+    let cur_awaiting = AtomicU64::new(first_node);
+    // Set when the labeling is cancelled
+    let aborted = AtomicBool::new(false);
 
     // These UnsafeSlices are managed through the 2 Atomics above and the `CacheReader`, to
     // minimize any locking overhead.
@@ -262,6 +287,7 @@ fn create_layer_labels(
             let cur_awaiting = &cur_awaiting;
             let ring_buf = &ring_buf;
             let base_parent_missing = &base_parent_missing;
+            let aborted = &aborted;
 
             let core_index = if let Some(cg) = &*core_group {
                 cg.get(i + 1)
@@ -286,43 +312,56 @@ fn create_layer_labels(
                     lookahead as u64,
                     ring_buf,
                     base_parent_missing,
+                    aborted,
                 )
             }));
         }
 
-        let mut cur_node_ptr = unsafe { layer_labels.as_mut_slice() };
-        let mut cur_parent_ptr = unsafe { parents_cache.consumer_slice_at(DEGREE) };
-        let mut cur_parent_ptr_offset = DEGREE;
+        // Points to the node before the first one that is calculated in the main loop.
+        let mut cur_node_ptr =
+            unsafe { &mut layer_labels.as_mut_slice()[(first_node as usize - 1) * NODE_WORDS..] };
+        let mut cur_parent_ptr_offset = first_node as usize * DEGREE;
+        let mut cur_parent_ptr = unsafe { parents_cache.consumer_slice_at(cur_parent_ptr_offset) };
 
-        // Calculate node 0 (special case with no parents)
-        // Which is replica_id || cur_layer || 0
-        // TODO - Hash and save intermediate result: replica_id || cur_layer
-        let mut buf = [0u8; (NODE_SIZE * DEGREE) + 64];
-        prepare_block(replica_id, cur_layer, &mut buf);
+        if start_node == 0 {
+            // Calculate node 0 (special case with no parents)
+            // Which is replica_id || cur_layer || 0
+            // TODO - Hash and save intermediate result: replica_id || cur_layer
+            let mut buf = [0u8; (NODE_SIZE * DEGREE) + 64];
+            prepare_block(replica_id, cur_layer, &mut buf);
 
-        cur_node_ptr[..8].copy_from_slice(&SHA256_INITIAL_DIGEST);
-        compress256!(cur_node_ptr, buf, 2);
+            cur_node_ptr[..8].copy_from_slice(&SHA256_INITIAL_DIGEST);
+            compress256!(cur_node_ptr, buf, 2);
 
-        // Fix endianess
-        cur_node_ptr[..8].iter_mut().for_each(|x| *x = x.to_be());
+            // Fix endianess
+            cur_node_ptr[..8].iter_mut().for_each(|x| *x = x.to_be());
 
-        cur_node_ptr[7] &= 0x3FFF_FFFF; // Strip last two bits to ensure in Fr
+            cur_node_ptr[7] &= 0x3FFF_FFFF; // Strip last two bits to ensure in Fr
+        }
 
         // Keep track of which node slot in the ring_buffer to use
-        let mut cur_slot = 0;
+        let mut cur_slot = (first_node as usize - 1) % lookahead;
         let mut count_not_ready = 0;
 
-        // Calculate nodes 1 to n
+        // Calculate nodes `first_node` to n
 
-        // Skip first node.
-        parents_cache.store_consumer(1);
-        let mut i = 1;
+        // Skip the nodes that are already calculated.
+        parents_cache.store_consumer(first_node);
+        let mut i = first_node;
         while i < num_nodes {
+            if ctx.is_cancelled() {
+                aborted.store(true, SeqCst);
+                break;
+            }
+
             // Ensure next buffer is ready
             let mut counted = false;
             let mut producer_val = cur_producer.load(SeqCst);
 
             while producer_val < i {
+                if ctx.is_cancelled() {
+                    break;
+                }
                 if !counted {
                     counted = true;
                     count_not_ready += 1;
@@ -332,7 +371,7 @@ fn create_layer_labels(
             }
 
             // Process as many nodes as are ready
-            let ready_count = producer_val - i + 1;
+            let ready_count = (producer_val + 1).saturating_sub(i);
             for _count in 0..ready_count {
                 // If we have used up the last cache window's parent data, get some more.
                 if cur_parent_ptr.is_empty() {
@@ -424,6 +463,11 @@ fn create_layer_labels(
                 }
                 i += 1;
                 cur_slot = (cur_slot + 1) % lookahead;
+
+                // All nodes before `i` are final, they can safely be checkpointed.
+                if let Some(checkpoint) = checkpoint.as_mut() {
+                    checkpoint.maybe_persist(unsafe { layer_labels.as_slice() }.as_byte_slice(), i);
+                }
             }
         }
 
@@ -432,8 +476,21 @@ fn create_layer_labels(
         for runner in runners {
             runner.join().expect("join failed");
         }
+
+        if aborted.load(SeqCst) {
+            // All nodes before `i` are final, keep them for the next run.
+            if let Some(checkpoint) = checkpoint.as_mut() {
+                checkpoint.flush(unsafe { layer_labels.as_slice() }.as_byte_slice(), i);
+            }
+        }
     })
     .expect("crossbeam scope failure");
+
+    if aborted.into_inner() {
+        ctx.check_cancelled()?;
+    }
+
+    Ok(())
 }
 
 #[allow(clippy::type_complexity)]
@@ -447,6 +504,7 @@ pub fn create_labels_for_encoding<
     layers: usize,
     replica_id: T,
     cache_path: P,
+    ctx: &SealContext,
 ) -> Result<(Labels<Tree>, Vec<LayerState>)> {
     info!("create labels");
 
@@ -454,11 +512,15 @@ pub fn create_labels_for_encoding<
 
     let sector_size = graph.size() * NODE_SIZE;
     let node_count = graph.size() as u64;
-    let cache_window_nodes = SETTINGS.sdr_parents_cache_size as usize;
+    let cache_window_nodes = settings::current().sdr_parents_cache_size as usize;
 
     let default_cache_size = DEGREE * 4 * cache_window_nodes;
 
-    let core_group = Arc::new(checkout_core_group());
+    // The core group must have a core for each producer of `create_layer_labels`, both read the
+    // number of producers from the same context.
+    let core_group = Arc::new(checkout_core_group(
+        settings::current().multicore_sdr_producers,
+    ));
 
     // When `_cleanup_handle` is dropped, the previous binding of thread will be restored.
     let _cleanup_handle = (*core_group).as_ref().map(|group| {
@@ -478,20 +540,35 @@ pub fn create_labels_for_encoding<
 
     for (layer, layer_state) in (1..=layers).zip(layer_states.iter()) {
         info!("Layer {}", layer);
+        let _span = proofs_span!("label_layer", layer = layer);
 
         if layer_state.generated {
             info!("skipping layer {}, already generated", layer);
 
             // load the already generated layer into exp_labels
             read_layer(&layer_state.config, &mut exp_labels)?;
+            ctx.report(Progress::LayerGenerated { layer, layers });
             continue;
         }
 
+        let mut checkpoint = LayerCheckpoint::new(
+            &layer_state.config,
+            graph.porep_id(),
+            replica_id.as_ref(),
+            layer,
+            graph.size(),
+            settings::current().sdr_checkpoint_interval,
+        );
+        let start_node = checkpoint.restore(&mut layer_labels)?;
+
         // Cache reset happens in two parts.
         // The second part (the finish) happens before each layer but the first.
         if layers != 1 {
             parents_cache.finish_reset()?;
         }
+        if start_node != 0 {
+            parents_cache.seek(start_node)?;
+        }
 
         create_layer_labels(
             &parents_cache,
@@ -503,9 +580,12 @@ pub fn create_labels_for_encoding<
                 Some(&mut exp_labels)
             },
             node_count,
+            start_node,
             layer as u32,
             core_group.clone(),
-        );
+            Some(&mut checkpoint),
+            ctx,
+        )?;
 
         // Cache reset happens in two parts.
         // The first part (the start) happens after each layer but the last.
@@ -519,12 +599,14 @@ pub fn create_labels_for_encoding<
 
             info!("  storing labels on disk");
             write_layer(&exp_labels, layer_config).context("failed to store labels")?;
+            checkpoint.discard();
 
             info!(
                 "  generated layer {} store with id {}",
                 layer, layer_config.id
             );
         }
+        ctx.report(Progress::LayerGenerated { layer, layers });
     }
 
     Ok((
@@ -552,11 +634,15 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
 
     let sector_size = graph.size() * NODE_SIZE;
     let node_count = graph.size() as u64;
-    let cache_window_nodes = (&SETTINGS.sdr_parents_cache_size / 2) as usize;
+    let cache_window_nodes = (settings::current().sdr_parents_cache_size / 2) as usize;
 
     let default_cache_size = DEGREE * 4 * cache_window_nodes;
 
-    let core_group = Arc::new(checkout_core_group());
+    // The core group must have a core for each producer of `create_layer_labels`, both read the
+    // number of producers from the same context.
+    let core_group = Arc::new(checkout_core_group(
+        settings::current().multicore_sdr_producers,
+    ));
 
     // When `_cleanup_handle` is dropped, the previous binding of thread will be restored.
     let _cleanup_handle = (*core_group).as_ref().map(|group| {
@@ -576,6 +662,7 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
 
     for layer in 1..=layers {
         info!("Layer {}", layer);
+        let _span = proofs_span!("label_layer", layer = layer);
 
         // Cache reset happens in two parts.
         // The second part (the finish) happens before each layer but the first.
@@ -593,9 +680,12 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
                 Some(&mut exp_labels)
             },
             node_count,
+            0,
             layer as u32,
             core_group.clone(),
-        );
+            None,
+            &SealContext::default(),
+        )?;
 
         // Cache reset happens in two parts.
         // The first part (the start) happens after each layer but the last.
@@ -641,13 +731,21 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
 mod tests {
     use super::*;
 
+    use std::sync::Mutex;
+
     use blstrs::Scalar as Fr;
     use ff::PrimeField;
     use filecoin_hashers::poseidon::PoseidonHasher;
     use generic_array::typenum::{U0, U2, U8};
-    use storage_proofs_core::{api_version::ApiVersion, merkle::LCTree};
+    use storage_proofs_core::{
+        api_version::ApiVersion,
+        merkle::LCTree,
+        progress::{is_cancelled_error, CancellationToken},
+    };
     use tempfile::tempdir;
 
+    use crate::stacked::vanilla::create_label::checkpoint::checkpoint_path;
+
     #[test]
     fn test_create_labels() {
         let layers = 11;
@@ -719,6 +817,205 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_create_labels_resume_from_checkpoint() {
+        type Tree = LCTree<PoseidonHasher, U8, U0, U2>;
+
+        let layers = 3;
+        // Several windows of the parents cache, so that resuming needs to seek.
+        let nodes = 4 * settings::current().sdr_parents_cache_size as usize;
+        let replica_id = [9u8; 32];
+        let porep_id = [123; 32];
+
+        let graph = StackedBucketGraph::<PoseidonHasher>::new(
+            None,
+            nodes,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            porep_id,
+            ApiVersion::V1_2_0,
+        )
+        .expect("stacked bucket graph new failed");
+        let cache = graph.parent_cache().expect("parent_cache failed");
+
+        let cache_dir = tempdir().expect("tempdir failure");
+        let read_layer = |config: &StoreConfig| {
+            std::fs::read(StoreConfig::data_path(&config.path, &config.id))
+                .expect("failed to read layer")
+        };
+
+        let (_, layer_states) = create_labels_for_encoding::<Tree, _, _>(
+            &graph,
+            &cache,
+            layers,
+            replica_id,
+            cache_dir.path(),
+            &SealContext::default(),
+        )
+        .expect("create_labels_for_encoding failed");
+        let expected: Vec<Vec<u8>> = layer_states.iter().map(|s| read_layer(&s.config)).collect();
+
+        let window_nodes = settings::current().sdr_parents_cache_size as usize;
+        for resume_node in [1, 1500, 2 * window_nodes + 17, nodes - 1] {
+            for state in &layer_states[1..] {
+                std::fs::remove_file(StoreConfig::data_path(&state.config.path, &state.config.id))
+                    .expect("failed to delete layer");
+            }
+            let mut checkpoint = LayerCheckpoint::new(
+                &layer_states[1].config,
+                porep_id,
+                &replica_id,
+                2,
+                nodes,
+                resume_node as u64,
+            );
+            checkpoint.maybe_persist(&expected[1], resume_node as u64);
+            assert_eq!(checkpoint.nodes(), resume_node as u64);
+
+            let (_, resumed_states) = create_labels_for_encoding::<Tree, _, _>(
+                &graph,
+                &cache,
+                layers,
+                replica_id,
+                cache_dir.path(),
+                &SealContext::default(),
+            )
+            .expect("create_labels_for_encoding failed");
+            for (state, expected) in resumed_states.iter().zip(expected.iter()) {
+                assert_eq!(
+                    &read_layer(&state.config),
+                    expected,
+                    "mismatch when resuming at node {}",
+                    resume_node
+                );
+            }
+            assert!(!checkpoint.path().exists());
+        }
+
+        // The labels of the checkpoint are taken as they are, which shows that the labeling
+        // really resumed from the checkpoint.
+        for state in &layer_states[1..] {
+            std::fs::remove_file(StoreConfig::data_path(&state.config.path, &state.config.id))
+                .expect("failed to delete layer");
+        }
+        let mut tampered = expected[1].clone();
+        tampered[..NODE_SIZE].copy_from_slice(&[7; NODE_SIZE]);
+        let mut checkpoint = LayerCheckpoint::new(
+            &layer_states[1].config,
+            porep_id,
+            &replica_id,
+            2,
+            nodes,
+            1500,
+        );
+        checkpoint.maybe_persist(&tampered, 1500);
+        let (_, resumed_states) = create_labels_for_encoding::<Tree, _, _>(
+            &graph,
+            &cache,
+            layers,
+            replica_id,
+            cache_dir.path(),
+            &SealContext::default(),
+        )
+        .expect("create_labels_for_encoding failed");
+        let resumed = read_layer(&resumed_states[1].config);
+        assert_eq!(&resumed[..NODE_SIZE], &[7; NODE_SIZE]);
+        assert_ne!(resumed, expected[1]);
+    }
+
+    #[test]
+    fn test_create_labels_cancelled() {
+        type Tree = LCTree<PoseidonHasher, U8, U0, U2>;
+
+        let layers = 3;
+        let nodes = 1 << 11;
+        let replica_id = [9u8; 32];
+        let porep_id = [123; 32];
+
+        let graph = StackedBucketGraph::<PoseidonHasher>::new(
+            None,
+            nodes,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            porep_id,
+            ApiVersion::V1_2_0,
+        )
+        .expect("stacked bucket graph new failed");
+        let cache = graph.parent_cache().expect("parent_cache failed");
+
+        let expected_dir = tempdir().expect("tempdir failure");
+        let (_, expected_states) = create_labels_for_encoding::<Tree, _, _>(
+            &graph,
+            &cache,
+            layers,
+            replica_id,
+            expected_dir.path(),
+            &SealContext::default(),
+        )
+        .expect("create_labels_for_encoding failed");
+
+        // Cancel as soon as the first layer is done.
+        let cache_dir = tempdir().expect("tempdir failure");
+        let token = CancellationToken::new();
+        let ctx = {
+            let token = token.clone();
+            SealContext::new().with_progress(move |progress| {
+                if progress == (Progress::LayerGenerated { layer: 1, layers }) {
+                    token.cancel();
+                }
+            })
+        }
+        .with_cancellation(token);
+        let err = create_labels_for_encoding::<Tree, _, _>(
+            &graph,
+            &cache,
+            layers,
+            replica_id,
+            cache_dir.path(),
+            &ctx,
+        )
+        .expect_err("labeling wasn't cancelled");
+        assert!(is_cancelled_error(&err));
+
+        let layer_states = prepare_layers::<_, Tree>(&graph, cache_dir.path(), layers);
+        assert!(layer_states[0].generated);
+        assert!(!layer_states[1].generated);
+        assert!(checkpoint_path(&layer_states[1].config).exists());
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let ctx = {
+            let reports = reports.clone();
+            SealContext::new()
+                .with_progress(move |progress| reports.lock().expect("poisoned").push(progress))
+        };
+        let (_, layer_states) = create_labels_for_encoding::<Tree, _, _>(
+            &graph,
+            &cache,
+            layers,
+            replica_id,
+            cache_dir.path(),
+            &ctx,
+        )
+        .expect("create_labels_for_encoding failed");
+        assert_eq!(
+            *reports.lock().expect("poisoned"),
+            (1..=layers)
+                .map(|layer| Progress::LayerGenerated { layer, layers })
+                .collect::<Vec<_>>()
+        );
+        for (state, expected) in layer_states.iter().zip(expected_states.iter()) {
+            assert_eq!(
+                std::fs::read(StoreConfig::data_path(&state.config.path, &state.config.id))
+                    .expect("failed to read layer"),
+                std::fs::read(StoreConfig::data_path(
+                    &expected.config.path,
+                    &expected.config.id
+                ))
+                .expect("failed to read layer"),
+            );
+        }
+    }
+
     fn test_create_labels_aux(
         sector_size: usize,
         layers: usize,
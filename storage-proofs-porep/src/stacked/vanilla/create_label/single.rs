@@ -11,12 +11,14 @@ use sha2raw::Sha256;
 use storage_proofs_core::{
     drgraph::Graph,
     merkle::MerkleTreeTrait,
+    progress::{Progress, SealContext},
+    proofs_span, settings,
     util::{data_at_node_offset, NODE_SIZE},
 };
 
 use crate::stacked::vanilla::{
     cache::ParentCache,
-    create_label::{prepare_layers, read_layer, write_layer},
+    create_label::{checkpoint::LayerCheckpoint, prepare_layers, read_layer, write_layer},
     proof::LayerState,
     Labels, LabelsCache, StackedBucketGraph,
 };
@@ -32,6 +34,7 @@ pub fn create_labels_for_encoding<
     layers: usize,
     replica_id: T,
     cache_path: P,
+    ctx: &SealContext,
 ) -> Result<(Labels<Tree>, Vec<LayerState>)> {
     info!("generate labels");
 
@@ -44,18 +47,30 @@ pub fn create_labels_for_encoding<
 
     for (layer, layer_state) in (1..=layers).zip(layer_states.iter()) {
         info!("generating layer: {}", layer);
+        let _span = proofs_span!("label_layer", layer = layer);
         if layer_state.generated {
             info!("skipping layer {}, already generated", layer);
 
             // load the already generated layer into exp_labels
             read_layer(&layer_state.config, &mut exp_labels)?;
+            ctx.report(Progress::LayerGenerated { layer, layers });
             continue;
         }
 
+        let mut checkpoint = LayerCheckpoint::new(
+            &layer_state.config,
+            graph.porep_id(),
+            replica_id.as_ref(),
+            layer,
+            graph.size(),
+            settings::current().sdr_checkpoint_interval,
+        );
+        let start = checkpoint.restore(&mut layer_labels)? as usize;
+
         parents_cache.reset()?;
 
         if layer == 1 {
-            for node in 0..graph.size() {
+            for node in start..graph.size() {
                 create_label(
                     graph,
                     Some(parents_cache),
@@ -64,9 +79,14 @@ pub fn create_labels_for_encoding<
                     layer,
                     node,
                 )?;
+                checkpoint.maybe_persist(&layer_labels, node as u64 + 1);
+                if ctx.is_cancelled() {
+                    checkpoint.flush(&layer_labels, node as u64 + 1);
+                    ctx.check_cancelled()?;
+                }
             }
         } else {
-            for node in 0..graph.size() {
+            for node in start..graph.size() {
                 create_label_exp(
                     graph,
                     Some(parents_cache),
@@ -76,6 +96,11 @@ pub fn create_labels_for_encoding<
                     layer,
                     node,
                 )?;
+                checkpoint.maybe_persist(&layer_labels, node as u64 + 1);
+                if ctx.is_cancelled() {
+                    checkpoint.flush(&layer_labels, node as u64 + 1);
+                    ctx.check_cancelled()?;
+                }
             }
         }
 
@@ -84,11 +109,13 @@ pub fn create_labels_for_encoding<
 
         info!("  storing labels on disk");
         write_layer(&layer_labels, layer_config).context("failed to store labels")?;
+        checkpoint.discard();
 
         info!(
             "  generated layer {} store with id {}",
             layer, layer_config.id
         );
+        ctx.report(Progress::LayerGenerated { layer, layers });
 
         info!("  setting exp parents");
         mem::swap(&mut layer_labels, &mut exp_labels);
@@ -123,6 +150,7 @@ pub fn create_labels_for_decoding<Tree: 'static + MerkleTreeTrait, T: AsRef<[u8]
 
     for layer in 1..=layers {
         info!("generating layer: {}", layer);
+        let _span = proofs_span!("label_layer", layer = layer);
 
         parents_cache.reset()?;
 
@@ -1,6 +1,7 @@
 use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Debug, Formatter};
 use std::marker::PhantomData;
+use std::path::PathBuf;
 
 use anyhow::ensure;
 use filecoin_hashers::Hasher;
@@ -16,12 +17,12 @@ use storage_proofs_core::{
     drgraph::{BucketGraph, Graph, BASE_DEGREE},
     error::Result,
     parameter_cache::ParameterSetMetadata,
-    settings::SETTINGS,
+    settings,
     util::NODE_SIZE,
     PoRepID,
 };
 
-use crate::stacked::vanilla::cache::ParentCache;
+use crate::stacked::vanilla::cache::{parent_cache_path, ParentCache};
 
 /// The expansion degree used for Stacked Graphs.
 pub const EXP_DEGREE: usize = 8;
@@ -39,6 +40,7 @@ where
     pub(crate) feistel_keys: [feistel::Index; 4],
     feistel_precomputed: FeistelPrecomputed,
     api_version: ApiVersion,
+    porep_id: PoRepID,
     id: String,
     _h: PhantomData<H>,
 }
@@ -126,16 +128,22 @@ where
             feistel_keys,
             feistel_precomputed: feistel::precompute((expansion_degree * nodes) as feistel::Index),
             api_version,
+            porep_id,
             _h: PhantomData,
         };
 
         Ok(res)
     }
 
+    /// Returns the PoRep ID this graph was derived from.
+    pub fn porep_id(&self) -> PoRepID {
+        self.porep_id
+    }
+
     /// Returns a reference to the parent cache.
     pub fn parent_cache(&self) -> Result<ParentCache> {
         // Number of nodes to be cached in memory
-        let default_cache_size = SETTINGS.sdr_parents_cache_size;
+        let default_cache_size = settings::current().sdr_parents_cache_size;
         let cache_entries = self.size() as u32;
         let cache_size = cache_entries.min(default_cache_size);
 
@@ -143,6 +151,12 @@ where
 
         ParentCache::new(cache_size, cache_entries, self)
     }
+
+    /// Returns the path of the parent cache, the file may not have been generated yet.
+    pub fn parent_cache_path(&self) -> PathBuf {
+        parent_cache_path(self)
+    }
+
     pub fn copy_parents_data_exp(
         &self,
         node: u32,
@@ -187,6 +187,31 @@ impl<T: FromByteSlice> CacheReader<T> {
         Ok(())
     }
 
+    /// Moves the consumer to `node`, mapping the windows that contain its parents. This is used
+    /// to resume the labeling of a layer in the middle and must only be called after a reset,
+    /// before any parents of the layer were read.
+    pub fn seek(&self, node: u64) -> Result<()> {
+        let window = (node as usize * self.degree) / self.window_element_count();
+        if window > 0 {
+            // Map the window containing the node, together with the one after it. The cursor
+            // always points to the latter one. If the node is in the last window, the one before
+            // is mapped instead.
+            let next = if (window + 1) * self.window_size < self.size {
+                window + 1
+            } else {
+                window
+            };
+            let bufs = unsafe { self.get_mut_bufs() };
+            for w in [next - 1, next] {
+                bufs[w % 2] =
+                    Self::map_buf((w * self.window_size) as u64, self.window_size, &self.file)?;
+            }
+            self.cursor.store(next);
+        }
+        self.store_consumer(node);
+        Ok(())
+    }
+
     fn map_buf(offset: u64, len: usize, file: &File) -> Result<Mmap> {
         unsafe {
             MmapOptions::new()
@@ -22,6 +22,7 @@ mod proof_scheme;
 #[cfg(feature = "multicore-sdr")]
 mod utils;
 
+pub use cache::verify_parent_cache_digest;
 pub use challenges::{
     synthetic::SYNTHETIC_POREP_VANILLA_PROOFS_EXT, synthetic::SYNTHETIC_POREP_VANILLA_PROOFS_KEY,
     ChallengeRequirements, Challenges,
@@ -29,9 +29,9 @@ use storage_proofs_core::{
 };
 
 use crate::stacked::vanilla::{
-    challenges::SynthChallenges, Challenges, Column, ColumnProof, EncodingProof, LabelingProof,
-    StackedBucketGraph, EXP_DEGREE, SYNTHETIC_POREP_VANILLA_PROOFS_EXT,
-    SYNTHETIC_POREP_VANILLA_PROOFS_KEY, TOTAL_PARENTS,
+    challenges::{synthetic, SynthChallenges},
+    Challenges, Column, ColumnProof, EncodingProof, LabelingProof, StackedBucketGraph, EXP_DEGREE,
+    SYNTHETIC_POREP_VANILLA_PROOFS_EXT, SYNTHETIC_POREP_VANILLA_PROOFS_KEY, TOTAL_PARENTS,
 };
 
 pub const BINARY_ARITY: usize = 2;
@@ -821,6 +821,26 @@ impl SynthProofs {
     }
 }
 
+/// Returns the size of the file with the synthetic vanilla proofs of a sector with `sector_nodes`
+/// nodes.
+pub fn synth_proofs_file_size<Tree: MerkleTreeTrait>(
+    sector_nodes: usize,
+    num_layers: usize,
+) -> u64 {
+    let num_synth_challenges = synthetic::default_num_synth_challenges(sector_nodes);
+    let proof_size = SynthProofs::proof_size::<Tree>(sector_nodes, num_layers);
+    (3 * NODE_SIZE + num_synth_challenges * proof_size) as u64
+}
+
+/// Returns the size of the serialized vanilla proof of a single challenge, which is the same for
+/// synthetic and interactive challenges.
+pub fn challenge_proof_size<Tree: MerkleTreeTrait>(
+    sector_nodes: usize,
+    num_layers: usize,
+) -> usize {
+    SynthProofs::proof_size::<Tree>(sector_nodes, num_layers)
+}
+
 pub type TransformedLayers<Tree, G> = (
     Tau<<<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain, <G as Hasher>::Domain>,
     PersistentAux<<<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain>,
@@ -21,7 +21,7 @@ use rayon::prelude::{
     IndexedParallelIterator, IntoParallelIterator, ParallelIterator, ParallelSliceMut,
 };
 #[cfg(any(feature = "cuda", feature = "multicore-sdr", feature = "opencl"))]
-use storage_proofs_core::settings::SETTINGS;
+use storage_proofs_core::settings;
 use storage_proofs_core::{
     cache_key::CacheKey,
     data::Data,
@@ -33,6 +33,9 @@ use storage_proofs_core::{
         split_config_and_replica, BinaryMerkleTree, DiskTree, LCTree, MerkleProofTrait,
         MerkleTreeTrait,
     },
+    progress::{Progress, SealContext, TreeKind},
+    proofs_span,
+    spans::ShortId,
     util::{default_rows_to_discard, NODE_SIZE},
 };
 use yastl::Pool;
@@ -117,6 +120,10 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         assert!(num_layers > 0);
         // Sanity checks on restored trees.
         assert!(pub_inputs.tau.is_some());
+        let _span = proofs_span!(
+            "prove_layers",
+            replica_id = %ShortId(pub_inputs.replica_id.as_ref()),
+        );
 
         match challenges {
             Challenges::Interactive(interactive_challenges) => {
@@ -129,6 +136,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                 (0..partition_count)
                     .map(|k| {
                         trace!("proving partition {}/{}", k + 1, partition_count);
+                        let _span = proofs_span!("prove_partition", partition = k);
 
                         // Derive the set of challenges we are proving over.
                         let challenge_positions = interactive_challenges.derive(
@@ -208,6 +216,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                 (0..partition_count)
                     .map(|k| {
                         trace!("proving partition {}/{}", k + 1, partition_count);
+                        let _span = proofs_span!("prove_partition", partition = k);
 
                         // Derive the set of challenges we are proving over.
                         let challenge_positions = ni_challenges.derive(
@@ -672,6 +681,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         num_layers: usize,
         replica_id: &<Tree::Hasher as Hasher>::Domain,
         cache_path: P,
+        ctx: &SealContext,
     ) -> Result<(Labels<Tree>, Vec<LayerState>)>
     where
         P: AsRef<Path>,
@@ -680,7 +690,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
 
         #[cfg(feature = "multicore-sdr")]
         {
-            if SETTINGS.use_multicore_sdr {
+            if settings::current().use_multicore_sdr {
                 info!("multi core replication");
                 create_label::multi::create_labels_for_encoding(
                     graph,
@@ -688,6 +698,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                     num_layers,
                     replica_id,
                     &cache_path,
+                    ctx,
                 )
             } else {
                 info!("single core replication");
@@ -697,6 +708,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                     num_layers,
                     replica_id,
                     &cache_path,
+                    ctx,
                 )
             }
         }
@@ -710,6 +722,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
                 num_layers,
                 replica_id,
                 &cache_path,
+                ctx,
             )
         }
     }
@@ -725,7 +738,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
 
         #[cfg(feature = "multicore-sdr")]
         {
-            if SETTINGS.use_multicore_sdr {
+            if settings::current().use_multicore_sdr {
                 info!("multi core replication");
                 create_label::multi::create_labels_for_decoding(
                     graph,
@@ -793,7 +806,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         ColumnArity: 'static + PoseidonArity,
         TreeArity: PoseidonArity,
     {
-        if SETTINGS.use_gpu_column_builder::<Tree>() {
+        if settings::current().use_gpu_column_builder::<Tree>() {
             Self::generate_tree_c_gpu::<ColumnArity, TreeArity>(
                 nodes_count,
                 tree_count,
@@ -843,6 +856,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         };
 
         info!("generating tree c using the GPU");
+        let _span = proofs_span!("generate_tree_c", gpu = true);
         // Build the tree for CommC
         measure_op(Operation::GenerateTreeC, || {
             info!("Building column hashes");
@@ -856,9 +870,9 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             // Override these values with care using environment variables:
             // FIL_PROOFS_MAX_GPU_COLUMN_BATCH_SIZE, FIL_PROOFS_MAX_GPU_TREE_BATCH_SIZE, and
             // FIL_PROOFS_COLUMN_WRITE_BATCH_SIZE respectively.
-            let max_gpu_column_batch_size = SETTINGS.max_gpu_column_batch_size as usize;
-            let max_gpu_tree_batch_size = SETTINGS.max_gpu_tree_batch_size as usize;
-            let column_write_batch_size = SETTINGS.column_write_batch_size as usize;
+            let max_gpu_column_batch_size = settings::current().max_gpu_column_batch_size as usize;
+            let max_gpu_tree_batch_size = settings::current().max_gpu_tree_batch_size as usize;
+            let column_write_batch_size = settings::current().column_write_batch_size as usize;
 
             // This channel will receive batches of columns and add them to the ColumnTreeBuilder.
             let (builder_tx, builder_rx) = channel(0);
@@ -1090,6 +1104,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         ColumnArity: PoseidonArity,
     {
         info!("generating tree c using the CPU");
+        let _span = proofs_span!("generate_tree_c", gpu = false);
         measure_op(Operation::GenerateTreeC, || {
             info!("Building column hashes");
 
@@ -1183,7 +1198,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         start: usize,
         end: usize,
     ) -> Result<TreeRElementData<Tree>> {
-        if SETTINGS.use_gpu_tree_builder::<Tree>() {
+        if settings::current().use_gpu_tree_builder::<Tree>() {
             use ff::PrimeField;
             use fr32::bytes_into_fr;
 
@@ -1291,7 +1306,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             None => Self::prepare_tree_r_data,
         };
 
-        if SETTINGS.use_gpu_tree_builder::<Tree>() {
+        if settings::current().use_gpu_tree_builder::<Tree>() {
             Self::generate_tree_r_last_gpu(
                 data,
                 nodes_count,
@@ -1371,7 +1386,8 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         )?;
 
         info!("generating tree r last using the GPU");
-        let max_gpu_tree_batch_size = SETTINGS.max_gpu_tree_batch_size as usize;
+        let _span = proofs_span!("generate_tree_r_last", gpu = true);
+        let max_gpu_tree_batch_size = settings::current().max_gpu_tree_batch_size as usize;
 
         // This channel will receive batches of leaf nodes and add them to the TreeBuilder.
         let (builder_tx, builder_rx) = channel::<(Vec<Fr>, bool)>(0);
@@ -1535,6 +1551,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         )?;
 
         info!("generating tree r last using the CPU");
+        let _span = proofs_span!("generate_tree_r_last", gpu = false);
 
         // Note that nodes_count is the count of nodes in each base tree
         let mut start = 0;
@@ -1585,6 +1602,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn transform_and_replicate_layers(
         graph: &StackedBucketGraph<Tree::Hasher>,
         num_layers: usize,
@@ -1594,6 +1612,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         cache_path: PathBuf,
         replica_path: PathBuf,
         label_configs: Labels<Tree>,
+        ctx: &SealContext,
     ) -> Result<TransformedLayers<Tree, G>> {
         trace!("transform_and_replicate_layers");
         let total_nodes_count = graph.size();
@@ -1669,6 +1688,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             None => error!("Failed to raise the fd limit"),
         };
 
+        ctx.check_cancelled()?;
         let tree_c_root = match num_layers {
             2 => {
                 let tree_c = Self::generate_tree_c::<U2, Tree::Arity>(
@@ -1691,6 +1711,9 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             _ => panic_any("Unsupported column arity"),
         };
         info!("tree_c done");
+        ctx.report(Progress::TreeBuilt {
+            tree: TreeKind::TreeC,
+        });
 
         // Build the MerkleTree over the original data (if needed).
         let tree_d = match data_tree {
@@ -1702,10 +1725,15 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             }
             None => {
                 trace!("building merkle tree for the original data");
+                ctx.check_cancelled()?;
                 data.ensure_data()?;
-                measure_op(Operation::CommD, || {
+                let tree_d = measure_op(Operation::CommD, || {
                     Self::build_binary_tree::<G>(data.as_ref(), tree_d_config.clone())
-                })?
+                })?;
+                ctx.report(Progress::TreeBuilt {
+                    tree: TreeKind::TreeD,
+                });
+                tree_d
             }
         };
         assert_eq!(
@@ -1716,6 +1744,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         drop(tree_d);
 
         // Encode original data into the last layer.
+        ctx.check_cancelled()?;
         let last_layer_labels = labels.labels_for_last_layer()?;
         data.ensure_data()?;
 
@@ -1733,6 +1762,9 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             .context("failed to generate tree_r_last")
         })?;
         info!("tree_r_last done");
+        ctx.report(Progress::TreeBuilt {
+            tree: TreeKind::TreeRLast,
+        });
 
         let tree_r_last_root = tree_r_last.root();
         drop(tree_r_last);
@@ -1768,13 +1800,36 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         replica_id: &<Tree::Hasher as Hasher>::Domain,
         cache_path: P,
     ) -> Result<(Labels<Tree>, Vec<LayerState>)>
+    where
+        P: AsRef<Path>,
+    {
+        Self::replicate_phase1_with_context(pp, replica_id, cache_path, &SealContext::default())
+    }
+
+    /// Phase1 of replication, reporting the generated layers to `ctx`.
+    ///
+    /// If it is cancelled, the labels generated so far are kept as checkpoint in the cache
+    /// directory (unless checkpoints are disabled), calling it again continues from there.
+    pub fn replicate_phase1_with_context<P>(
+        pp: &'a PublicParams<Tree>,
+        replica_id: &<Tree::Hasher as Hasher>::Domain,
+        cache_path: P,
+        ctx: &SealContext,
+    ) -> Result<(Labels<Tree>, Vec<LayerState>)>
     where
         P: AsRef<Path>,
     {
         info!("replicate_phase1");
+        let _span = proofs_span!("replicate_phase1", replica_id = %ShortId(replica_id.as_ref()));
 
         let labels_and_layer_states = measure_op(Operation::EncodeWindowTimeAll, || {
-            Self::generate_labels_for_encoding(&pp.graph, pp.num_layers, replica_id, cache_path)
+            Self::generate_labels_for_encoding(
+                &pp.graph,
+                pp.num_layers,
+                replica_id,
+                cache_path,
+                ctx,
+            )
         })?;
 
         Ok(labels_and_layer_states)
@@ -1795,8 +1850,40 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             PersistentAux<<Tree::Hasher as Hasher>::Domain>,
             TemporaryAux<Tree, G>,
         ),
+    )> {
+        Self::replicate_phase2_with_context(
+            pp,
+            label_configs,
+            data,
+            data_tree,
+            cache_path,
+            replica_path,
+            &SealContext::default(),
+        )
+    }
+
+    /// Phase2 of replication, reporting the built trees to `ctx`.
+    ///
+    /// The cancellation is checked before each tree is built. The trees are rebuilt from scratch
+    /// when it is called again, the labels of phase1 are kept.
+    #[allow(clippy::type_complexity)]
+    pub fn replicate_phase2_with_context(
+        pp: &'a PublicParams<Tree>,
+        label_configs: Labels<Tree>,
+        data: Data<'a>,
+        data_tree: Option<BinaryMerkleTree<G>>,
+        cache_path: PathBuf,
+        replica_path: PathBuf,
+        ctx: &SealContext,
+    ) -> Result<(
+        Tau<<Tree::Hasher as Hasher>::Domain, <G as Hasher>::Domain>,
+        (
+            PersistentAux<<Tree::Hasher as Hasher>::Domain>,
+            TemporaryAux<Tree, G>,
+        ),
     )> {
         info!("replicate_phase2");
+        let _span = proofs_span!("replicate_phase2");
 
         let (tau, paux, taux) = Self::transform_and_replicate_layers(
             &pp.graph,
@@ -1806,6 +1893,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             cache_path,
             replica_path,
             label_configs,
+            ctx,
         )?;
 
         Ok((tau, (paux, taux)))
@@ -1839,9 +1927,10 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             tree_count,
         )?;
 
-        if SETTINGS.use_gpu_tree_builder::<Tree>() {
+        if settings::current().use_gpu_tree_builder::<Tree>() {
             info!("generating tree r last using the GPU");
-            let max_gpu_tree_batch_size = SETTINGS.max_gpu_tree_batch_size as usize;
+            let _span = proofs_span!("generate_tree_r_last", gpu = true);
+            let max_gpu_tree_batch_size = settings::current().max_gpu_tree_batch_size as usize;
 
             let _gpu_lock = GPU_LOCK.lock().expect("failed to get gpu lock");
             let batcher = match Batcher::pick_gpu(max_gpu_tree_batch_size) {
@@ -1921,6 +2010,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
             }
         } else {
             info!("generating tree r last using the CPU");
+            let _span = proofs_span!("generate_tree_r_last", gpu = false);
             for (i, config) in configs.iter().enumerate() {
                 let encoded_data = vec![<Tree::Hasher as Hasher>::Domain::default(); nodes_count];
 
@@ -1961,6 +2051,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait, G: 'static + Hasher> StackedDrg<'a, Tr
         )?;
 
         info!("generating tree r last using the CPU");
+        let _span = proofs_span!("generate_tree_r_last", gpu = false);
         for (i, config) in configs.iter().enumerate() {
             let encoded_data = vec![<Tree::Hasher as Hasher>::Domain::default(); nodes_count];
 
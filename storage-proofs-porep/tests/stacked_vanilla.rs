@@ -23,8 +23,8 @@ use storage_proofs_core::{
     TEST_SEED,
 };
 use storage_proofs_porep::stacked::{
-    self, Challenges, PrivateInputs, PublicInputs, SetupParams, StackedBucketGraph, StackedDrg,
-    TemporaryAuxCache, EXP_DEGREE,
+    self, create_label::checkpoint::LayerCheckpoint, Challenges, PrivateInputs, PublicInputs,
+    SetupParams, StackedBucketGraph, StackedDrg, TemporaryAuxCache, EXP_DEGREE,
 };
 use tempfile::tempdir;
 
@@ -295,6 +295,91 @@ fn test_stacked_porep_resume_seal() {
     }
 }
 
+#[test]
+fn test_stacked_porep_resume_labels_from_checkpoint() {
+    type Tree = DiskTree<PoseidonHasher, U8, U8, U2>;
+
+    let mut rng = XorShiftRng::from_seed(TEST_SEED);
+    let replica_id = <PoseidonHasher as Hasher>::Domain::random(&mut rng);
+    let nodes = 64 * get_base_tree_count::<Tree>();
+
+    let sp = SetupParams {
+        nodes,
+        degree: BASE_DEGREE,
+        expansion_degree: EXP_DEGREE,
+        porep_id: [32; 32],
+        challenges: Challenges::new_interactive(5),
+        num_layers: DEFAULT_STACKED_LAYERS,
+        api_version: ApiVersion::V1_2_0,
+        api_features: vec![],
+    };
+    let pp = StackedDrg::<Tree, Blake2sHasher>::setup(&sp).expect("setup failed");
+
+    let cache_dir = tempdir().expect("tempdir failure");
+    let read_layer = |config: &StoreConfig| {
+        std::fs::read(StoreConfig::data_path(&config.path, &config.id))
+            .expect("failed to read layer")
+    };
+
+    let (_, layer_states) =
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+            .expect("label generation failed");
+    let expected: Vec<Vec<u8>> = layer_states.iter().map(|s| read_layer(&s.config)).collect();
+
+    // Simulate a crash in the middle of a layer, all layers from there on are missing, but there
+    // is a checkpoint of the one that was in progress.
+    let resume_layer = 5;
+    let resume_node = nodes / 2 + 3;
+    let resume_config = &layer_states[resume_layer - 1].config;
+    let crash = |labels: &[u8]| {
+        for state in &layer_states[resume_layer - 1..] {
+            remove_file(StoreConfig::data_path(&state.config.path, &state.config.id))
+                .expect("failed to delete layer");
+        }
+        let mut checkpoint = LayerCheckpoint::new(
+            resume_config,
+            sp.porep_id,
+            replica_id.as_ref(),
+            resume_layer,
+            nodes,
+            resume_node as u64,
+        );
+        checkpoint.maybe_persist(labels, resume_node as u64);
+        assert_eq!(checkpoint.nodes(), resume_node as u64);
+    };
+
+    // The labels of the checkpoint are taken as they are, which shows that the labeling resumed
+    // from the checkpoint instead of starting the layer from scratch.
+    let mut tampered = expected[resume_layer - 1].clone();
+    tampered[..NODE_SIZE].copy_from_slice(&[7; NODE_SIZE]);
+    crash(&tampered);
+    let (_, layer_states) =
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+            .expect("label generation failed");
+    let resumed = read_layer(&layer_states[resume_layer - 1].config);
+    assert_eq!(&resumed[..NODE_SIZE], &[7; NODE_SIZE]);
+    assert_ne!(resumed, expected[resume_layer - 1]);
+
+    // Resuming from a valid checkpoint results in the same labels.
+    crash(&expected[resume_layer - 1]);
+    let (_, layer_states) =
+        StackedDrg::<Tree, Blake2sHasher>::replicate_phase1(&pp, &replica_id, cache_dir.path())
+            .expect("label generation failed");
+    for (state, expected) in layer_states.iter().zip(expected.iter()) {
+        assert_eq!(&read_layer(&state.config), expected);
+    }
+    // The checkpoint is removed once the layer is written.
+    let checkpoint = LayerCheckpoint::new(
+        resume_config,
+        sp.porep_id,
+        replica_id.as_ref(),
+        resume_layer,
+        nodes,
+        0,
+    );
+    assert!(!checkpoint.path().exists());
+}
+
 table_tests! {
     test_prove_verify_fixed {
        test_stacked_porep_prove_verify(64);
@@ -13,7 +13,6 @@ use storage_proofs_core::{
     },
     merkle::MerkleTreeTrait,
     por,
-    settings::SETTINGS,
     util::NODE_SIZE,
 };
 
@@ -23,6 +22,10 @@ use crate::fallback::{PublicParams, PublicSector, SectorProof};
 pub struct FallbackPoStCircuit<Tree: MerkleTreeTrait> {
     pub prover_id: Option<Fr>,
     pub sectors: Vec<Sector<Tree>>,
+    /// The number of chunks of sectors that are synthesized in parallel into an extensible
+    /// constraint system. It's the `window_post_synthesis_num_cpus` setting when the circuit was
+    /// created, as the synthesis runs on the threads of bellperson.
+    pub synthesis_num_cpus: usize,
 }
 
 // We must manually implement Clone for all types generic over MerkleTreeTrait (instead of using
@@ -34,6 +37,7 @@ impl<Tree: 'static + MerkleTreeTrait> Clone for FallbackPoStCircuit<Tree> {
         FallbackPoStCircuit {
             prover_id: self.prover_id,
             sectors: self.sectors.clone(),
+            synthesis_num_cpus: self.synthesis_num_cpus,
         }
     }
 }
@@ -212,9 +216,11 @@ impl<Tree: 'static + MerkleTreeTrait> FallbackPoStCircuit<Tree> {
         self,
         cs: &mut CS,
     ) -> Result<(), SynthesisError> {
-        let FallbackPoStCircuit { sectors, .. } = self;
-
-        let num_chunks = SETTINGS.window_post_synthesis_num_cpus as usize;
+        let FallbackPoStCircuit {
+            sectors,
+            synthesis_num_cpus: num_chunks,
+            ..
+        } = self;
 
         let chunk_size = (sectors.len() / num_chunks).max(1);
         let css = sectors
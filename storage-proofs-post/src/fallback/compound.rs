@@ -13,6 +13,7 @@ use storage_proofs_core::{
     parameter_cache::{CacheableParameters, ParameterSetMetadata},
     por,
     proof::ProofScheme,
+    settings,
     util::NODE_SIZE,
 };
 
@@ -150,6 +151,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait>
         Ok(FallbackPoStCircuit {
             prover_id: Some(pub_in.prover_id.into()),
             sectors: res_sectors,
+            synthesis_num_cpus: settings::current().window_post_synthesis_num_cpus as usize,
         })
     }
 
@@ -163,6 +165,7 @@ impl<'a, Tree: 'static + MerkleTreeTrait>
         FallbackPoStCircuit {
             prover_id: None,
             sectors,
+            synthesis_num_cpus: settings::current().window_post_synthesis_num_cpus as usize,
         }
     }
 }
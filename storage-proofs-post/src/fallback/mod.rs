@@ -1,9 +1,11 @@
 mod circuit;
 mod compound;
+mod prefetch;
 mod utils;
 mod vanilla;
 
 pub use circuit::*;
 pub use compound::*;
+pub use prefetch::*;
 pub use utils::*;
 pub use vanilla::*;
@@ -0,0 +1,464 @@
+//! Prefetching of the data that the vanilla proofs of a sector read.
+//!
+//! [`vanilla_proof`](super::vanilla_proof) reads the challenged segments of the replica and the
+//! cached rows of tree-r-last one after the other through the merkle tree stores. On disks with a
+//! high latency that dominates the time of a Winning PoSt. Here all reads are planned upfront from
+//! the challenges, issued concurrently and the proofs are then built from the buffers.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use anyhow::{anyhow, ensure, Context};
+use filecoin_hashers::{Domain, Hasher};
+use generic_array::typenum::{Unsigned, U0};
+use log::trace;
+use merkletree::{
+    hash::Algorithm,
+    merkle::{get_merkle_tree_cache_size, get_merkle_tree_leafs, MerkleTree},
+    store::{StoreConfig, VecStore},
+};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use storage_proofs_core::{
+    error::Result,
+    merkle::{get_base_tree_count, MerkleProof, MerkleProofTrait, MerkleTreeTrait},
+    sector::SectorId,
+    storage::ReadAt,
+    util::{default_rows_to_discard, NODE_SIZE},
+};
+
+use super::{Proof, SectorProof};
+
+/// The file a [`PlannedRead`] reads from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReadSource {
+    Replica,
+    /// The tree-r-last file of the base tree with the given index.
+    TreeRLast(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PlannedRead {
+    pub source: ReadSource,
+    /// The offset in bytes.
+    pub offset: u64,
+    /// The length in bytes.
+    pub len: usize,
+}
+
+/// The shape of tree-r-last of a sector, it determines where the nodes of an inclusion proof are
+/// stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeRLastLayout {
+    pub base_tree_leafs: usize,
+    pub base_tree_count: usize,
+    pub arity: usize,
+    pub sub_tree_arity: usize,
+    pub top_tree_arity: usize,
+    /// The number of rows of each base tree that aren't stored in its tree-r-last file.
+    pub rows_to_discard: usize,
+}
+
+impl TreeRLastLayout {
+    pub fn new<Tree: MerkleTreeTrait>(sector_size: u64) -> Result<Self> {
+        let arity = Tree::Arity::to_usize();
+        let leafs = (sector_size / NODE_SIZE as u64) as usize;
+        let base_tree_count = get_base_tree_count::<Tree>();
+        let base_tree_leafs = leafs / base_tree_count;
+        ensure!(
+            base_tree_leafs * base_tree_count == leafs,
+            "sector size {} doesn't match the tree shape",
+            sector_size
+        );
+
+        // The same number of rows that `vanilla_proof` passes to the merkle tree, which limits it
+        // to the default of the base trees.
+        let rows_to_discard = default_rows_to_discard(leafs, arity)
+            .min(StoreConfig::default_rows_to_discard(base_tree_leafs, arity));
+
+        Ok(TreeRLastLayout {
+            base_tree_leafs,
+            base_tree_count,
+            arity,
+            sub_tree_arity: Tree::SubTreeArity::to_usize(),
+            top_tree_arity: Tree::TopTreeArity::to_usize(),
+            rows_to_discard,
+        })
+    }
+
+    /// The number of nodes in each tree-r-last file.
+    fn cache_size(&self) -> Result<usize> {
+        get_merkle_tree_cache_size(self.base_tree_leafs, self.arity, self.rows_to_discard)
+    }
+
+    /// The number of leafs of a base tree that are hashed together into a node of the first row
+    /// that is stored in the tree-r-last file.
+    fn segment_width(&self) -> Result<usize> {
+        let cached_leafs = get_merkle_tree_leafs(self.cache_size()?, self.arity)?;
+        Ok(self.base_tree_leafs / cached_leafs)
+    }
+
+    /// Returns all reads that are needed for the inclusion proofs of `challenges`, sorted and
+    /// without duplicates.
+    pub fn plan_reads(&self, challenges: &[u64]) -> Result<Vec<PlannedRead>> {
+        let cache_size = self.cache_size()?;
+        let segment_width = self.segment_width()?;
+        let cached_rows = self.cached_rows()?;
+        let sector_leafs = self.base_tree_leafs * self.base_tree_count;
+
+        // The roots of all base trees are needed for the upper layers of compound trees.
+        let mut reads: Vec<_> = (0..self.base_tree_count)
+            .map(|tree_index| PlannedRead {
+                source: ReadSource::TreeRLast(tree_index),
+                offset: node_offset(cache_size - 1),
+                len: NODE_SIZE,
+            })
+            .collect();
+
+        for challenge in challenges {
+            let leaf = *challenge as usize;
+            ensure!(
+                leaf < sector_leafs,
+                "challenge {} is out of bounds (max: {})",
+                leaf,
+                sector_leafs
+            );
+            let tree_index = leaf / self.base_tree_leafs;
+            let segment_start = (leaf % self.base_tree_leafs) / segment_width * segment_width;
+            reads.push(PlannedRead {
+                source: ReadSource::Replica,
+                offset: node_offset(tree_index * self.base_tree_leafs + segment_start),
+                len: segment_width * NODE_SIZE,
+            });
+
+            let mut node = segment_start / segment_width;
+            for row_start in &cached_rows {
+                reads.push(PlannedRead {
+                    source: ReadSource::TreeRLast(tree_index),
+                    offset: node_offset(row_start + node / self.arity * self.arity),
+                    len: self.arity * NODE_SIZE,
+                });
+                node /= self.arity;
+            }
+        }
+
+        reads.sort_unstable();
+        reads.dedup();
+        Ok(reads)
+    }
+
+    /// Returns the index of the first node of each row in a tree-r-last file, except the root.
+    fn cached_rows(&self) -> Result<Vec<usize>> {
+        let mut width = get_merkle_tree_leafs(self.cache_size()?, self.arity)?;
+        let mut row_start = 0;
+        let mut rows = Vec::new();
+        while width > 1 {
+            rows.push(row_start);
+            row_start += width;
+            width /= self.arity;
+        }
+        Ok(rows)
+    }
+}
+
+fn node_offset(index: usize) -> u64 {
+    (index * NODE_SIZE) as u64
+}
+
+/// The data of the [`PlannedRead`]s, as returned by [`prefetch_reads`].
+#[derive(Debug, Default)]
+pub struct PrefetchedReads {
+    reads: BTreeMap<(ReadSource, u64), Vec<u8>>,
+}
+
+impl PrefetchedReads {
+    /// Returns the data of a read, it fails if it wasn't planned.
+    pub fn get(&self, source: ReadSource, offset: u64, len: usize) -> Result<&[u8]> {
+        self.reads
+            .get(&(source, offset))
+            .filter(|data| data.len() == len)
+            .map(Vec::as_slice)
+            .ok_or_else(|| {
+                anyhow!(
+                    "{} bytes at offset {} of {:?} weren't prefetched",
+                    len,
+                    offset,
+                    source
+                )
+            })
+    }
+}
+
+/// Issues the `reads` with at most `max_outstanding_reads` of them in flight at a time.
+///
+/// `tree_r_last` are the tree-r-last files of the base trees, in the order of their index.
+pub fn prefetch_reads(
+    replica: &dyn ReadAt,
+    tree_r_last: &[Box<dyn ReadAt>],
+    reads: &[PlannedRead],
+    max_outstanding_reads: usize,
+) -> Result<PrefetchedReads> {
+    ensure!(max_outstanding_reads > 0, "no outstanding reads allowed");
+    let workers = max_outstanding_reads.min(reads.len());
+    trace!("prefetching {} reads with {} workers", reads.len(), workers);
+
+    let next_read = AtomicUsize::new(0);
+    let read = |planned: &PlannedRead| -> Result<Vec<u8>> {
+        let file = match planned.source {
+            ReadSource::Replica => replica,
+            ReadSource::TreeRLast(tree_index) => tree_r_last
+                .get(tree_index)
+                .map(|file| &**file)
+                .ok_or_else(|| anyhow!("missing tree-r-last file {}", tree_index))?,
+        };
+        let mut data = vec![0; planned.len];
+        file.read_exact_at(planned.offset, &mut data)
+            .with_context(|| {
+                format!(
+                    "failed to read {} bytes at offset {} of {:?}",
+                    planned.len, planned.offset, planned.source
+                )
+            })?;
+        Ok(data)
+    };
+
+    let results = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next_read.fetch_add(1, Ordering::Relaxed);
+                        match reads.get(index) {
+                            Some(planned) => done.push((planned, read(planned))),
+                            None => return done,
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("prefetch worker panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut prefetched = PrefetchedReads::default();
+    for (planned, data) in results {
+        prefetched
+            .reads
+            .insert((planned.source, planned.offset), data?);
+    }
+    Ok(prefetched)
+}
+
+/// Generates the same vanilla proof as [`vanilla_proof`](super::vanilla_proof), but reads the
+/// data from the buffers of [`prefetch_reads`] for the reads that
+/// [`TreeRLastLayout::plan_reads`] returned for `challenges`.
+pub fn vanilla_proof_from_reads<Tree: MerkleTreeTrait>(
+    sector_id: SectorId,
+    layout: &TreeRLastLayout,
+    reads: &PrefetchedReads,
+    comm_c: <Tree::Hasher as Hasher>::Domain,
+    comm_r_last: <Tree::Hasher as Hasher>::Domain,
+    challenges: &[u64],
+) -> Result<Proof<Tree::Proof>> {
+    let base_roots = base_roots::<Tree>(layout, reads)?;
+
+    let inclusion_proofs = challenges
+        .par_iter()
+        .map(|challenged_leaf| {
+            let challenged_leaf = *challenged_leaf as usize;
+            let proof = inclusion_proof::<Tree>(layout, reads, &base_roots, challenged_leaf)?;
+            let proof = TreeRLastMerkleProof::<Tree>::try_from_proof(proof)?;
+
+            ensure!(
+                proof.validate(challenged_leaf) && proof.root() == comm_r_last,
+                "Generated vanilla proof for sector {} is invalid",
+                sector_id
+            );
+
+            Ok(proof)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Proof {
+        sectors: vec![SectorProof {
+            inclusion_proofs,
+            comm_c,
+            comm_r_last,
+        }],
+    })
+}
+
+/// Returns the inclusion proof of `challenged_leaf` into tree-r-last, from the buffers of
+/// [`prefetch_reads`] for the reads that [`TreeRLastLayout::plan_reads`] returned for it. Unlike
+/// [`vanilla_proof_from_reads`] the proof isn't validated.
+pub fn inclusion_proof_from_reads<Tree: MerkleTreeTrait>(
+    layout: &TreeRLastLayout,
+    reads: &PrefetchedReads,
+    challenged_leaf: u64,
+) -> Result<TreeRLastMerkleProof<Tree>> {
+    let base_roots = base_roots::<Tree>(layout, reads)?;
+    let proof = inclusion_proof::<Tree>(layout, reads, &base_roots, challenged_leaf as usize)?;
+    TreeRLastMerkleProof::<Tree>::try_from_proof(proof)
+}
+
+type TreeRLastMerkleProof<Tree> = MerkleProof<
+    <Tree as MerkleTreeTrait>::Hasher,
+    <Tree as MerkleTreeTrait>::Arity,
+    <Tree as MerkleTreeTrait>::SubTreeArity,
+    <Tree as MerkleTreeTrait>::TopTreeArity,
+>;
+
+/// Returns the roots of the base trees, which are the last nodes of the tree-r-last files.
+fn base_roots<Tree: MerkleTreeTrait>(
+    layout: &TreeRLastLayout,
+    reads: &PrefetchedReads,
+) -> Result<Vec<<Tree::Hasher as Hasher>::Domain>> {
+    let cache_size = layout.cache_size()?;
+    (0..layout.base_tree_count)
+        .map(|tree_index| {
+            let root = reads.get(
+                ReadSource::TreeRLast(tree_index),
+                node_offset(cache_size - 1),
+                NODE_SIZE,
+            )?;
+            <Tree::Hasher as Hasher>::Domain::try_from_bytes(root)
+        })
+        .collect()
+}
+
+type TreeRLastProof<Tree> = merkletree::proof::Proof<
+    <<Tree as MerkleTreeTrait>::Hasher as Hasher>::Domain,
+    <Tree as MerkleTreeTrait>::Arity,
+>;
+
+/// Builds the inclusion proof of a leaf the same way as `MerkleTree::gen_cached_proof`.
+fn inclusion_proof<Tree: MerkleTreeTrait>(
+    layout: &TreeRLastLayout,
+    reads: &PrefetchedReads,
+    base_roots: &[<Tree::Hasher as Hasher>::Domain],
+    challenged_leaf: usize,
+) -> Result<TreeRLastProof<Tree>> {
+    let tree_index = challenged_leaf / layout.base_tree_leafs;
+    let base_proof = base_tree_proof::<Tree>(
+        layout,
+        reads,
+        tree_index,
+        challenged_leaf % layout.base_tree_leafs,
+    )?;
+    if layout.sub_tree_arity == 0 {
+        return Ok(base_proof);
+    }
+
+    let hash_roots = |roots: &[<Tree::Hasher as Hasher>::Domain]| {
+        <Tree::Hasher as Hasher>::Function::default().multi_node(roots, 1)
+    };
+    let layer_proof = |proof, roots: &[_], index: usize| {
+        let mut lemma: Vec<_> = roots
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, root)| *root)
+            .collect();
+        lemma.push(hash_roots(roots));
+        (Some(Box::new(proof)), lemma, vec![index])
+    };
+
+    let sub_tree_arity = layout.sub_tree_arity;
+    let sub_tree_index = tree_index / sub_tree_arity;
+    let sub_tree_roots =
+        &base_roots[sub_tree_index * sub_tree_arity..(sub_tree_index + 1) * sub_tree_arity];
+    let (sub_proof, lemma, path) =
+        layer_proof(base_proof, sub_tree_roots, tree_index % sub_tree_arity);
+    let sub_tree_proof =
+        TreeRLastProof::<Tree>::new::<U0, Tree::SubTreeArity>(sub_proof, lemma, path)?;
+    if layout.top_tree_arity == 0 {
+        return Ok(sub_tree_proof);
+    }
+
+    let top_tree_roots: Vec<_> = base_roots.chunks(sub_tree_arity).map(hash_roots).collect();
+    let (top_proof, lemma, path) = layer_proof(sub_tree_proof, &top_tree_roots, sub_tree_index);
+    TreeRLastProof::<Tree>::new::<Tree::TopTreeArity, Tree::SubTreeArity>(top_proof, lemma, path)
+}
+
+/// Builds the inclusion proof of a leaf into its base tree, from the replica segment of the leaf
+/// and the cached rows of the tree-r-last file.
+fn base_tree_proof<Tree: MerkleTreeTrait>(
+    layout: &TreeRLastLayout,
+    reads: &PrefetchedReads,
+    tree_index: usize,
+    leaf_index: usize,
+) -> Result<TreeRLastProof<Tree>> {
+    let arity = layout.arity;
+    let cache_size = layout.cache_size()?;
+    let segment_width = layout.segment_width()?;
+    let segment_start = leaf_index / segment_width * segment_width;
+
+    // The rows below the cached ones are rebuilt from the leafs of the segment.
+    let segment = reads.get(
+        ReadSource::Replica,
+        node_offset(tree_index * layout.base_tree_leafs + segment_start),
+        segment_width * NODE_SIZE,
+    )?;
+    let leafs = segment
+        .chunks(NODE_SIZE)
+        .map(<Tree::Hasher as Hasher>::Domain::try_from_bytes)
+        .collect::<Result<Vec<_>>>()?;
+    // Without discarded rows the segment is the leaf itself, which is its own root.
+    let (mut lemma, segment_root, mut path) = if segment_width == 1 {
+        (leafs.clone(), leafs[0], Vec::new())
+    } else {
+        let partial_tree = MerkleTree::<
+            <Tree::Hasher as Hasher>::Domain,
+            <Tree::Hasher as Hasher>::Function,
+            VecStore<_>,
+            Tree::Arity,
+        >::new(leafs)?;
+        let partial_proof = partial_tree.gen_proof(leaf_index - segment_start)?;
+
+        let mut lemma = partial_proof.lemma().clone();
+        let segment_root = lemma.pop().expect("lemma contains the root");
+        (lemma, segment_root, partial_proof.path().clone())
+    };
+
+    let mut node = segment_start / segment_width;
+    for row_start in layout.cached_rows()? {
+        let group_start = node / arity * arity;
+        let group = reads.get(
+            ReadSource::TreeRLast(tree_index),
+            node_offset(row_start + group_start),
+            arity * NODE_SIZE,
+        )?;
+        let group = group
+            .chunks(NODE_SIZE)
+            .map(<Tree::Hasher as Hasher>::Domain::try_from_bytes)
+            .collect::<Result<Vec<_>>>()?;
+        if row_start == 0 {
+            ensure!(
+                group[node - group_start] == segment_root,
+                "tree-r-last {} doesn't match the replica",
+                tree_index
+            );
+        }
+        lemma.extend(
+            group
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != node - group_start)
+                .map(|(_, sibling)| *sibling),
+        );
+        path.push(node % arity);
+        node /= arity;
+    }
+
+    let root = reads.get(
+        ReadSource::TreeRLast(tree_index),
+        node_offset(cache_size - 1),
+        NODE_SIZE,
+    )?;
+    lemma.push(<Tree::Hasher as Hasher>::Domain::try_from_bytes(root)?);
+
+    TreeRLastProof::<Tree>::new::<U0, U0>(None, lemma, path)
+}
@@ -18,7 +18,10 @@ use storage_proofs_core::{
     merkle::{MerkleProof, MerkleProofTrait, MerkleTreeTrait, MerkleTreeWrapper},
     parameter_cache::ParameterSetMetadata,
     proof::ProofScheme,
+    proofs_span,
     sector::SectorId,
+    settings::ProofsContext,
+    spans,
     util::{default_rows_to_discard, NODE_SIZE},
 };
 
@@ -376,12 +379,18 @@ impl<'a, Tree: 'a + MerkleTreeTrait> ProofScheme<'a> for FallbackPoSt<'a, Tree>
             .zip(priv_inputs.sectors.chunks(num_sectors_per_chunk))
             .enumerate()
         {
+            let _span = proofs_span!("prove_partition", partition = j);
+            let parent = spans::current();
+            let context = ProofsContext::current();
             let (mut proofs, mut faults) = pub_sectors_chunk
                 .par_iter()
                 .zip(priv_sectors_chunk.par_iter())
                 .enumerate()
                 .map(|(i, (pub_sector, priv_sector))| {
                     let sector_id = pub_sector.id;
+                    let _parent = parent.enter();
+                    let _context = context.enter();
+                    let _span = proofs_span!("prove_sector", sector_id = u64::from(sector_id));
                     let tree = priv_sector.tree;
                     let tree_leafs = tree.leafs();
                     let rows_to_discard =
@@ -157,6 +157,7 @@ fn test_fallback_post<Tree: 'static + MerkleTreeTrait>(
         let instance = FallbackPoStCircuit::<Tree> {
             sectors: circuit_sectors,
             prover_id: Some(prover_id.into()),
+            synthesis_num_cpus: 1,
         };
 
         instance
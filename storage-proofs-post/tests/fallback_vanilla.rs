@@ -1,17 +1,28 @@
+use std::io::Write;
+use std::path::Path;
+
 use filecoin_hashers::{poseidon::PoseidonHasher, Domain, HashFunction, Hasher};
 use generic_array::typenum::{U0, U2, U4, U8};
-use rand::SeedableRng;
+use merkletree::{
+    merkle::{get_merkle_tree_cache_size, MerkleTree},
+    store::VecStore,
+};
+use rand::{Rng, SeedableRng};
 use rand_xorshift::XorShiftRng;
 use storage_proofs_core::{
     api_version::ApiVersion,
     error::Error,
-    merkle::{generate_tree, get_base_tree_count, LCTree, MerkleTreeTrait},
+    merkle::{generate_tree, get_base_tree_count, LCTree, MerkleProofTrait, MerkleTreeTrait},
     proof::ProofScheme,
     sector::SectorId,
+    storage::{MemoryStorage, SectorStorage},
     util::NODE_SIZE,
     TEST_SEED,
 };
-use storage_proofs_post::fallback::{self, FallbackPoSt, PrivateSector, PublicSector};
+use storage_proofs_post::fallback::{
+    self, prefetch_reads, vanilla_proof_from_reads, FallbackPoSt, PrivateSector, PublicSector,
+    TreeRLastLayout,
+};
 use tempfile::tempdir;
 
 #[test]
@@ -422,3 +433,134 @@ fn test_invalid_fallback_post<Tree: MerkleTreeTrait>(
         },
     };
 }
+
+#[test]
+fn test_prefetched_vanilla_proof_poseidon_base_8() {
+    test_prefetched_vanilla_proof::<LCTree<PoseidonHasher, U8, U0, U0>>(None);
+}
+
+#[test]
+fn test_prefetched_vanilla_proof_poseidon_base_8_no_discarded_rows() {
+    test_prefetched_vanilla_proof::<LCTree<PoseidonHasher, U8, U0, U0>>(Some(0));
+}
+
+#[test]
+fn test_prefetched_vanilla_proof_poseidon_sub_8_4() {
+    test_prefetched_vanilla_proof::<LCTree<PoseidonHasher, U8, U4, U0>>(None);
+}
+
+#[test]
+fn test_prefetched_vanilla_proof_poseidon_top_8_4_2() {
+    test_prefetched_vanilla_proof::<LCTree<PoseidonHasher, U8, U4, U2>>(None);
+}
+
+fn test_prefetched_vanilla_proof<Tree: MerkleTreeTrait>(rows_to_discard: Option<usize>)
+where
+    Tree::Store: 'static,
+{
+    let rng = &mut XorShiftRng::from_seed(TEST_SEED);
+
+    let base_tree_count = get_base_tree_count::<Tree>();
+    let leaves = 512 * base_tree_count;
+    let sector_size = (leaves * NODE_SIZE) as u64;
+    let sector_id = SectorId::from(7);
+
+    let temp_dir = tempdir().unwrap();
+    let (data, tree) = generate_tree::<Tree, _>(rng, leaves, Some(temp_dir.path().to_path_buf()));
+    let comm_c = <Tree::Hasher as Hasher>::Domain::random(rng);
+    let comm_r_last = tree.root();
+    let challenges: Vec<u64> = (0..10).map(|_| rng.gen_range(0..leaves as u64)).collect();
+
+    let priv_sectors = [PrivateSector {
+        tree: &tree,
+        comm_c,
+        comm_r_last,
+    }];
+    let expected = fallback::vanilla_proof(
+        sector_id,
+        &fallback::PrivateInputs::<Tree> {
+            sectors: &priv_sectors,
+        },
+        &challenges,
+    )
+    .expect("vanilla proof failed");
+
+    // The tree-r-last files only contain the rows above the discarded ones.
+    let mut layout = TreeRLastLayout::new::<Tree>(sector_size).expect("invalid layout");
+    if let Some(rows_to_discard) = rows_to_discard {
+        layout.rows_to_discard = rows_to_discard;
+    }
+    let storage = MemoryStorage::new();
+    write_file(&storage, Path::new("replica"), &data);
+    let base_tree_bytes = data.len() / base_tree_count;
+    for (tree_index, base_data) in data.chunks(base_tree_bytes).enumerate() {
+        let leafs = base_data
+            .chunks(NODE_SIZE)
+            .map(|leaf| <Tree::Hasher as Hasher>::Domain::try_from_bytes(leaf).unwrap());
+        let base_tree = MerkleTree::<
+            <Tree::Hasher as Hasher>::Domain,
+            <Tree::Hasher as Hasher>::Function,
+            VecStore<_>,
+            Tree::Arity,
+        >::new(leafs)
+        .unwrap();
+        let cache_size = get_merkle_tree_cache_size(
+            layout.base_tree_leafs,
+            layout.arity,
+            layout.rows_to_discard,
+        )
+        .unwrap();
+        let cached_rows = base_tree
+            .read_range(base_tree.len() - cache_size, base_tree.len())
+            .unwrap();
+        let bytes: Vec<u8> = cached_rows
+            .iter()
+            .flat_map(|node| AsRef::<[u8]>::as_ref(node).to_vec())
+            .collect();
+        write_file(&storage, Path::new(&tree_index.to_string()), &bytes);
+    }
+
+    let prove = |storage: &MemoryStorage| {
+        let replica = storage.open_read(Path::new("replica")).unwrap();
+        let tree_r_last: Vec<_> = (0..base_tree_count)
+            .map(|tree_index| {
+                storage
+                    .open_read(Path::new(&tree_index.to_string()))
+                    .unwrap()
+            })
+            .collect();
+        let reads = layout.plan_reads(&challenges).expect("planning failed");
+        let prefetched =
+            prefetch_reads(&*replica, &tree_r_last, &reads, 4).expect("prefetching failed");
+        vanilla_proof_from_reads::<Tree>(
+            sector_id,
+            &layout,
+            &prefetched,
+            comm_c,
+            comm_r_last,
+            &challenges,
+        )
+    };
+
+    let proof = prove(&storage).expect("prefetched vanilla proof failed");
+    let expected = &expected.sectors[0].inclusion_proofs;
+    let actual = &proof.sectors[0].inclusion_proofs;
+    assert_eq!(actual.len(), expected.len());
+    for (actual, expected) in actual.iter().zip(expected.iter()) {
+        assert_eq!(actual.leaf(), expected.leaf());
+        assert_eq!(actual.path(), expected.path());
+        assert_eq!(actual.root(), expected.root());
+    }
+
+    // A corrupted challenged leaf doesn't lead to comm_r_last anymore.
+    let mut corrupted = data;
+    corrupted[challenges[0] as usize * NODE_SIZE] ^= 1;
+    write_file(&storage, Path::new("replica"), &corrupted);
+    assert!(prove(&storage).is_err());
+}
+
+fn write_file(storage: &MemoryStorage, path: &Path, data: &[u8]) {
+    let mut file = storage.create(path).unwrap();
+    file.write_all(data).unwrap();
+    file.flush().unwrap();
+}
@@ -31,7 +31,11 @@ use storage_proofs_core::{
         BinaryMerkleTree, LCTree, MerkleProof, MerkleProofTrait, MerkleTreeTrait,
     },
     parameter_cache::ParameterSetMetadata,
+    progress::{Progress, SealContext, TreeKind},
     proof::ProofScheme,
+    proofs_span,
+    settings::ProofsContext,
+    spans,
 };
 use storage_proofs_porep::stacked::{StackedDrg, TreeRElementData};
 
@@ -355,12 +359,12 @@ pub fn prepare_tree_r_data<Tree: 'static + MerkleTreeTrait>(
     start: usize,
     end: usize,
 ) -> Result<TreeRElementData<Tree>> {
-    use storage_proofs_core::settings::SETTINGS;
+    use storage_proofs_core::settings;
     let tree_data = source
         .read_range(start..end)
         .expect("failed to read from source");
 
-    if SETTINGS.use_gpu_tree_builder::<Tree>() {
+    if settings::current().use_gpu_tree_builder::<Tree>() {
         Ok(TreeRElementData::FrList(
             tree_data.into_par_iter().map(|x| x.into()).collect(),
         ))
@@ -462,9 +466,13 @@ where
         let tree_r_old = Self::instantiate_tree_r(tree_r_old_config, old_replica_path, "TreeROld")?;
         let tree_r_new = Self::instantiate_tree_r(tree_r_new_config, replica_path, "TreeRNew")?;
 
+        let parent = spans::current();
+        let context = ProofsContext::current();
         let vanilla_proofs = (0..partition_count)
             .into_par_iter()
             .map(|k| {
+                let _parent = parent.enter();
+                let _context = context.enter();
                 let pub_inputs = Self::with_partition(pub_inputs.clone(), Some(k));
                 Self::prove_inner(
                     pub_params,
@@ -785,6 +793,7 @@ where
             "Proving EmptySectorUpdate vanilla partition (sector_nodes={}, k={})",
             sector_nodes, k,
         );
+        let _span = proofs_span!("prove_partition", partition = k);
 
         let tree_d_arity = TreeDArity::to_usize();
 
@@ -881,6 +890,36 @@ where
         staged_data_path: &Path,
         h: usize,
     ) -> Result<(TreeRDomain, TreeRDomain, TreeDDomain)> {
+        Self::encode_into_with_context(
+            nodes_count,
+            tree_d_new_config,
+            tree_r_last_new_config,
+            comm_c,
+            comm_r_last_old,
+            new_replica_path,
+            sector_key_path,
+            staged_data_path,
+            h,
+            &SealContext::default(),
+        )
+    }
+
+    /// Like `encode_into`, but reports the built trees to `ctx` and stops with
+    /// `Error::Cancelled` if the cancellation was requested. A cancelled encoding leaves a
+    /// partially written new replica behind, it's overwritten when encoding again.
+    pub fn encode_into_with_context(
+        nodes_count: usize,
+        tree_d_new_config: StoreConfig,
+        tree_r_last_new_config: StoreConfig,
+        comm_c: TreeRDomain,
+        comm_r_last_old: TreeRDomain,
+        new_replica_path: &Path,
+        sector_key_path: &Path,
+        staged_data_path: &Path,
+        h: usize,
+        ctx: &SealContext,
+    ) -> Result<(TreeRDomain, TreeRDomain, TreeDDomain)> {
+        let _span = proofs_span!("encode_into", sector_nodes = nodes_count);
         let tree_count = get_base_tree_count::<TreeR>();
         let base_tree_nodes_count = nodes_count / tree_count;
 
@@ -937,11 +976,15 @@ where
         new_data.ensure_data_of_len(sector_key_path_metadata.len() as usize)?;
 
         // Generate tree_d over the staged_data.
+        ctx.check_cancelled()?;
         let tree_d = create_base_merkle_tree::<BinaryMerkleTree<TreeDHasher>>(
             Some(tree_d_new_config),
             tree_count * base_tree_nodes_count,
             new_data.as_ref(),
         )?;
+        ctx.report(Progress::TreeBuilt {
+            tree: TreeKind::TreeD,
+        });
 
         let comm_d_new = tree_d.root();
 
@@ -964,6 +1007,7 @@ where
             .into_par_iter()
             .zip(new_replica_data.par_chunks_mut(data_block_size))
             .try_for_each(|(chunk_index, replica_data)| -> Result<()> {
+                ctx.check_cancelled()?;
                 for i in (0..data_block_size as u64).step_by(FR_SIZE) {
                     let input_index = (chunk_index as usize) + i as usize;
                     let output_index = i as usize;
@@ -995,6 +1039,7 @@ where
         // This argument is currently unused by this invocation, but required for the API.
         let mut unused_data = Data::empty();
 
+        ctx.check_cancelled()?;
         let tree_r_last = StackedDrg::<TreeR, TreeDHasher>::generate_tree_r_last(
             &mut unused_data,
             base_tree_nodes_count,
@@ -1004,6 +1049,9 @@ where
             &new_replica_store,
             Some(prepare_tree_r_data),
         )?;
+        ctx.report(Progress::TreeBuilt {
+            tree: TreeKind::TreeRLast,
+        });
 
         let comm_r_last_new = tree_r_last.root();
         let comm_r_new = <TreeRHasher as Hasher>::Function::hash2(&comm_c, &comm_r_last_new);
@@ -1023,6 +1071,7 @@ where
         comm_sector_key: TreeRDomain,
         h: usize,
     ) -> Result<()> {
+        let _span = proofs_span!("decode_from", sector_nodes = nodes_count);
         // Sanity check all input path types.
         ensure!(
             metadata(sector_key_cache_path)?.is_dir(),
@@ -1137,6 +1186,7 @@ where
         comm_sector_key: TreeRDomain,
         h: usize,
     ) -> Result<TreeRDomain> {
+        let _span = proofs_span!("remove_encoded_data", sector_nodes = nodes_count);
         // Sanity check all input path types.
         ensure!(
             metadata(sector_key_cache_path)?.is_dir(),